@@ -0,0 +1,202 @@
+// statistical checks, after dudect (Reparaz, Balasch, and Verbauwhede, "Dude,
+// is my code constant time?"), that the routines handling secrets take time
+// independent of the secrets. run with
+//
+//     cargo bench --bench dudect
+//
+// each target is timed on two classes of inputs, a fixed one and random ones,
+// interleaved at random, and Welch's t-test compares the two timing
+// distributions: outright, and cropped at several percentiles to shed the
+// long tail that scheduling adds. a |t| above 10 means the classes are told
+// apart with overwhelming confidence, and fails the run; values below 4.5
+// are consistent with constant time. a deliberately leaky comparison runs
+// first as a control, so that a quiet result means something: if even the
+// control passes, the machine is too noisy and the run is inconclusive.
+//
+// set DUDECT_SAMPLES to change the number of measurements per target
+// (default 200000).
+
+extern crate argon2rs;
+
+use std::env;
+use std::hint::black_box;
+use std::process;
+use std::time::Instant;
+use argon2rs::verifier::{Encoded, constant_eq};
+use argon2rs::{Argon2, Variant};
+
+// |t| beyond which a target fails, and below which it plainly passes.
+const LEAKY: f64 = 10.0;
+const QUIET: f64 = 4.5;
+
+// calls per measurement, so that one measurement is well above the timer's
+// resolution.
+const BATCH: usize = 16;
+
+// percentiles at which measurements are additionally cropped.
+const CROPS: [f64; 5] = [0.5, 0.75, 0.9, 0.95, 0.99];
+
+// xorshift64*, for choosing classes and making random inputs without pulling
+// in a dependency. reproducible runs are a feature here.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    fn bytes(&mut self, n: usize) -> Vec<u8> {
+        (0..n).map(|_| self.next() as u8).collect()
+    }
+}
+
+// Welch's t statistic of two samples.
+fn welch_t(a: &[f64], b: &[f64]) -> f64 {
+    let stats = |xs: &[f64]| {
+        let n = xs.len() as f64;
+        let mean = xs.iter().sum::<f64>() / n;
+        let var = xs.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() /
+                  (n - 1.0);
+        (mean, var, n)
+    };
+    let ((ma, va, na), (mb, vb, nb)) = (stats(a), stats(b));
+    (ma - mb) / (va / na + vb / nb).sqrt()
+}
+
+// The largest |t| over the raw measurements and their cropped versions.
+fn max_t(times: &[(bool, f64)]) -> f64 {
+    let mut sorted: Vec<f64> = times.iter().map(|&(_, t)| t).collect();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mut cutoffs = vec![f64::INFINITY];
+    for &p in CROPS.iter() {
+        cutoffs.push(sorted[((sorted.len() - 1) as f64 * p) as usize]);
+    }
+    cutoffs.iter()
+           .map(|&cut| {
+               let class = |c| {
+                   times.iter()
+                        .filter(|&&(k, t)| k == c && t <= cut)
+                        .map(|&(_, t)| t)
+                        .collect::<Vec<_>>()
+               };
+               let (a, b) = (class(false), class(true));
+               if a.len() < 2 || b.len() < 2 {
+                   0.0
+               } else {
+                   welch_t(&a, &b).abs()
+               }
+           })
+           .fold(0.0, f64::max)
+}
+
+// Times `f` on `samples` inputs, each drawn from the fixed class or the random
+// class at random, and returns the largest |t|. Inputs are made before any
+// timing starts.
+fn measure<I, F>(samples: usize, rng: &mut Rng, mut input: I, f: F) -> f64
+    where I: FnMut(bool, &mut Rng) -> Vec<u8>,
+          F: Fn(&[u8]) -> bool
+{
+    let inputs: Vec<(bool, Vec<u8>)> = (0..samples)
+                                           .map(|_| {
+                                               let c = rng.next() & 1 == 1;
+                                               (c, input(c, rng))
+                                           })
+                                           .collect();
+    // warm caches and branch predictors on both classes.
+    for &(_, ref x) in inputs.iter().take(1000) {
+        black_box(f(black_box(x)));
+    }
+    let times: Vec<(bool, f64)> = inputs.iter()
+                                        .map(|&(c, ref x)| {
+                                            let start = Instant::now();
+                                            for _ in 0..BATCH {
+                                                black_box(f(black_box(x)));
+                                            }
+                                            let t = start.elapsed();
+                                            (c, t.as_nanos() as f64)
+                                        })
+                                        .collect();
+    max_t(&times)
+}
+
+fn main() {
+    let samples = env::var("DUDECT_SAMPLES")
+                      .ok()
+                      .and_then(|s| s.parse().ok())
+                      .unwrap_or(200000);
+    let mut rng = Rng(0x853c49e6748fea9b);
+
+    // comparisons of a 32-byte tag against an equal one, or a random one.
+    let tag = rng.bytes(32);
+    let tag_input = |c: bool, rng: &mut Rng| {
+        if c { rng.bytes(32) } else { tag.clone() }
+    };
+
+    // the tag of an `Unfinalized`, which is what servers compare in server
+    // relief mode.
+    let a2 = Argon2::new(1, 1, 8, Variant::Argon2id).unwrap();
+    let state = a2.hash_unfinalized(32, b"password", b"somesalt", &[], &[]);
+    let mut good = [0; 32];
+    state.finalize(&mut good);
+    let unfinalized_input = |c: bool, rng: &mut Rng| {
+        if c { rng.bytes(32) } else { good.to_vec() }
+    };
+
+    // stored hashes whose tags are fixed or random, for base64 decoding.
+    let a2 = Argon2::new(1, 1, 8, Variant::Argon2id).unwrap();
+    let stored = Encoded::hash(a2, b"password", b"somesalt");
+    let encoded_input = |c: bool, rng: &mut Rng| {
+        let mut enc = stored.to_u8();
+        if c {
+            let at = enc.iter().rposition(|&b| b == b'$').unwrap() + 1;
+            let end = enc.len() - 1;
+            let alphabet = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
+                             abcdefghijklmnopqrstuvwxyz0123456789+/";
+            for b in enc[at..end].iter_mut() {
+                *b = alphabet[rng.next() as usize % 64];
+            }
+        }
+        enc
+    };
+
+    let control = measure(samples, &mut rng, tag_input.clone(), |x| {
+        black_box(&tag[..]) == x
+    });
+    println!("{:<28} max |t| = {:8.2} (control, expected to leak)",
+             "slice ==", control);
+
+    let results = [("verifier::constant_eq",
+                    measure(samples, &mut rng, tag_input, |x| {
+                        constant_eq(&tag, x)
+                    })),
+                   ("Unfinalized::verify",
+                    measure(samples, &mut rng, unfinalized_input, |x| {
+                        state.verify(x)
+                    })),
+                   ("Encoded::from_u8 (tag)",
+                    measure(samples, &mut rng, encoded_input, |x| {
+                        Encoded::from_u8(x).is_ok()
+                    }))];
+
+    let mut failed = false;
+    for &(name, t) in results.iter() {
+        let verdict = match t {
+            t if t > LEAKY => {
+                failed = true;
+                "LEAKS"
+            }
+            t if t > QUIET => "inconclusive, rerun",
+            _ => "ok",
+        };
+        println!("{:<28} max |t| = {:8.2} {}", name, t, verdict);
+    }
+    if control <= LEAKY {
+        println!("the control went undetected; too noisy to judge.");
+    }
+    if failed {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,68 @@
+// computes argon2rs' `attestation::EXPECTED_DIGEST` with rustcrypto's argon2
+// instead of argon2rs, so that the constant doesn't merely restate whatever
+// argon2rs computes. run with
+//
+//     cd benches/ecosystem && cargo run --release --example attestation
+//
+// the battery below must be kept in step with `attestation_digest` in
+// src/attestation.rs.
+
+extern crate argon2;
+extern crate blake2;
+
+use argon2::{Algorithm, Argon2, AssociatedData, ParamsBuilder, Version};
+use blake2::Blake2b;
+use blake2::digest::Digest;
+use blake2::digest::consts::U32;
+
+// (passes, lanes, kib, tag length), each run for every variant and version.
+const CASES: [(u32, u32, u32, usize); 5] = [(1, 1, 8, 4),
+                                            (3, 4, 32, 32),
+                                            (2, 3, 96, 64),
+                                            (1, 1, 2048, 65),
+                                            (2, 4, 4096, 100)];
+
+// with argon2rs' numbering of variants.
+const VARIANTS: [(Algorithm, u32); 3] = [(Algorithm::Argon2d, 0),
+                                         (Algorithm::Argon2i, 1),
+                                         (Algorithm::Argon2id, 2)];
+
+const VERSIONS: [Version; 2] = [Version::V0x10, Version::V0x13];
+
+fn main() {
+    let mut b = Blake2b::<U32>::new();
+    let mut i = 0u8;
+    for &(algorithm, variant) in VARIANTS.iter() {
+        for &version in VERSIONS.iter() {
+            for &(passes, lanes, kib, taglen) in CASES.iter() {
+                let params = ParamsBuilder::new()
+                                 .m_cost(kib)
+                                 .t_cost(passes)
+                                 .p_cost(lanes)
+                                 .output_len(taglen)
+                                 .data(AssociatedData::new(&[4; 12]).unwrap())
+                                 .build()
+                                 .unwrap();
+                let a2 = Argon2::new_with_secret(&[3; 8], algorithm, version,
+                                                 params)
+                             .unwrap();
+                let mut tag = vec![0; taglen];
+                a2.hash_password_into(&vec![i; i as usize + 1], &[2; 16],
+                                      &mut tag)
+                  .unwrap();
+                for &n in [variant, version as u32, passes, lanes, kib,
+                           taglen as u32]
+                              .iter() {
+                    b.update(n.to_le_bytes());
+                }
+                b.update(&tag);
+                i += 1;
+            }
+        }
+    }
+    let hex: Vec<_> = b.finalize()
+                       .iter()
+                       .map(|x| format!("{:#04x}", x))
+                       .collect();
+    println!("pub const EXPECTED_DIGEST: [u8; 32] = [{}];", hex.join(", "));
+}
@@ -0,0 +1,184 @@
+// times argon2rs against the other rust implementations of argon2,
+// rust-argon2 and rustcrypto's argon2, and prints a table of the median time
+// of each, relative to argon2rs'. run with
+//
+//     cd benches/ecosystem && cargo bench [-- filter]
+//
+// where `filter`, if given, selects the cases whose names contain it. the
+// number of timed runs per case defaults to 10 and can be changed with
+// ARGON2_BENCH_SAMPLES. every implementation must produce the same hash for
+// every case, or the comparison is meaningless, so that is checked first.
+//
+// only argon2rs, with its default `threaded` feature, fills lanes in
+// parallel; rust-argon2 and rustcrypto's argon2 fill them one after the
+// other, so lanes > 1 only gains argon2rs time.
+
+extern crate argon2;
+extern crate argon2rs;
+extern crate rust_argon2;
+
+use std::env;
+use std::time::{Duration, Instant};
+
+const PASSWORD: &'static [u8] = b"cd benches/ecosystem && cargo bench";
+const SALT: &'static [u8] = b"cargo test --release";
+const LENGTH: usize = 32;
+const MIB: u32 = 1024;
+
+#[derive(Clone, Copy)]
+enum Variant {
+    I,
+    D,
+    ID,
+}
+
+impl Variant {
+    fn name(self) -> &'static str {
+        match self {
+            Variant::I => "argon2i",
+            Variant::D => "argon2d",
+            Variant::ID => "argon2id",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Case {
+    variant: Variant,
+    kib: u32,
+    passes: u32,
+    lanes: u32,
+}
+
+impl Case {
+    fn name(&self) -> String {
+        format!("{} {}MiB t={} p={}", self.variant.name(), self.kib / MIB,
+                self.passes, self.lanes)
+    }
+}
+
+type Hasher = fn(&Case) -> Vec<u8>;
+
+const IMPLS: &'static [(&'static str, Hasher)] =
+    &[("argon2rs", hash_argon2rs), ("rust-argon2", hash_rust_argon2),
+      ("argon2 (RustCrypto)", hash_rustcrypto)];
+
+fn hash_argon2rs(case: &Case) -> Vec<u8> {
+    let v = match case.variant {
+        Variant::I => argon2rs::Variant::Argon2i,
+        Variant::D => argon2rs::Variant::Argon2d,
+        Variant::ID => argon2rs::Variant::Argon2id,
+    };
+    let a2 = argon2rs::Argon2::new(case.passes, case.lanes, case.kib, v);
+    let mut out = vec![0; LENGTH];
+    a2.unwrap().hash(&mut out, PASSWORD, SALT, &[], &[]);
+    out
+}
+
+fn hash_rust_argon2(case: &Case) -> Vec<u8> {
+    let config = rust_argon2::Config {
+        variant: match case.variant {
+            Variant::I => rust_argon2::Variant::Argon2i,
+            Variant::D => rust_argon2::Variant::Argon2d,
+            Variant::ID => rust_argon2::Variant::Argon2id,
+        },
+        version: rust_argon2::Version::Version13,
+        mem_cost: case.kib,
+        time_cost: case.passes,
+        lanes: case.lanes,
+        hash_length: LENGTH as u32,
+        ..rust_argon2::Config::default()
+    };
+    rust_argon2::hash_raw(PASSWORD, SALT, &config).unwrap()
+}
+
+fn hash_rustcrypto(case: &Case) -> Vec<u8> {
+    let algorithm = match case.variant {
+        Variant::I => argon2::Algorithm::Argon2i,
+        Variant::D => argon2::Algorithm::Argon2d,
+        Variant::ID => argon2::Algorithm::Argon2id,
+    };
+    let params = argon2::Params::new(case.kib, case.passes, case.lanes,
+                                     Some(LENGTH));
+    let a2 = argon2::Argon2::new(algorithm, argon2::Version::V0x13,
+                                 params.unwrap());
+    let mut out = vec![0; LENGTH];
+    a2.hash_password_into(PASSWORD, SALT, &mut out).unwrap();
+    out
+}
+
+fn cases() -> Vec<Case> {
+    let mut rv = vec![];
+    for &variant in [Variant::I, Variant::D, Variant::ID].iter() {
+        for &mib in [8, 64, 256].iter() {
+            rv.push(Case {
+                variant,
+                kib: mib * MIB,
+                passes: 3,
+                lanes: 1,
+            });
+        }
+    }
+    for &lanes in [2, 4, 8].iter() {
+        rv.push(Case {
+            variant: Variant::ID,
+            kib: 64 * MIB,
+            passes: 3,
+            lanes,
+        });
+    }
+    rv
+}
+
+fn median(hash: Hasher, case: &Case, samples: usize) -> Duration {
+    let mut times: Vec<_> = (0..samples).map(|_| {
+                                            let start = Instant::now();
+                                            hash(case);
+                                            start.elapsed()
+                                        })
+                                        .collect();
+    times.sort();
+    times[times.len() / 2]
+}
+
+fn main() {
+    let samples = env::var("ARGON2_BENCH_SAMPLES")
+                      .ok()
+                      .and_then(|n| n.parse().ok())
+                      .unwrap_or(10)
+                      .max(1);
+    // cargo passes `--bench`.
+    let filter = env::args().skip(1).find(|arg| !arg.starts_with("--"));
+    let cases: Vec<_> = cases().into_iter()
+                               .filter(|case| match filter {
+                                   Some(ref f) => case.name().contains(&f[..]),
+                                   None => true,
+                               })
+                               .collect();
+
+    print!("| case |");
+    for &(name, _) in IMPLS {
+        print!(" {} |", name);
+    }
+    println!();
+    println!("|---|{}", "---|".repeat(IMPLS.len()));
+    for case in cases.iter() {
+        // also warms up each implementation.
+        let expected = hash_argon2rs(case);
+        for &(name, hash) in IMPLS {
+            assert!(hash(case) == expected, "{} disagrees on {}", name,
+                    case.name());
+        }
+
+        let times: Vec<_> = IMPLS.iter()
+                                 .map(|&(_, hash)| median(hash, case, samples))
+                                 .collect();
+        let base = times[0].as_secs_f64();
+        print!("| {} |", case.name());
+        for t in times.iter() {
+            let secs = t.as_secs_f64();
+            print!(" {:.1} ms ({:.2}x) |", secs * 1e3, secs / base);
+        }
+        println!();
+    }
+}
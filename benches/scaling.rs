@@ -0,0 +1,86 @@
+// throughput of argon2rs across memory sizes, lane counts, and variants, for
+// catching performance regressions in the simd and threading code. run with
+//
+//     cargo bench --bench scaling [--features=simd|blake2b_simd]
+//
+// the blake2b backend is fixed at compile time, so its name is part of every
+// benchmark id; reports from builds with different backends then sit side by
+// side in target/criterion instead of overwriting one another.
+
+extern crate argon2rs;
+extern crate criterion;
+
+use argon2rs::{Argon2, Variant, defaults};
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group,
+                criterion_main};
+
+const PASSWORD: &'static [u8] = b"cargo bench --bench scaling";
+const SALT: &'static [u8] = b"cargo test --release";
+
+#[cfg(all(not(feature = "blake2b_simd"), not(feature = "simd"),
+          feature = "blake2-rfc", not(feature = "vendored_blake2b")))]
+const BACKEND: &'static str = "blake2-rfc";
+#[cfg(all(not(feature = "blake2b_simd"), feature = "simd",
+          feature = "blake2-rfc", not(feature = "vendored_blake2b")))]
+const BACKEND: &'static str = "blake2-rfc-simd";
+#[cfg(all(feature = "blake2b_simd", not(feature = "vendored_blake2b")))]
+const BACKEND: &'static str = "blake2b_simd";
+#[cfg(any(feature = "vendored_blake2b",
+          all(not(feature = "blake2-rfc"), not(feature = "blake2b_simd"))))]
+const BACKEND: &'static str = "vendored";
+
+const MIB: u32 = 1024;
+
+fn run(c: &mut Criterion, group: &str, cases: &[(String, Argon2)]) {
+    let mut g = c.benchmark_group(format!("{}/{}", group, BACKEND));
+    g.sample_size(10);
+    for &(ref id, ref a2) in cases {
+        let (_, kib, _, _, _) = a2.params();
+        g.throughput(Throughput::Bytes(kib as u64 * 1024));
+        g.bench_function(BenchmarkId::from_parameter(id), |b| {
+            let mut out = [0; defaults::LENGTH];
+            b.iter(|| a2.hash(&mut out, PASSWORD, SALT, &[], &[]))
+        });
+    }
+    g.finish();
+}
+
+fn memory(c: &mut Criterion) {
+    let cases: Vec<_> = [8, 64, 256, 1024]
+                            .iter()
+                            .map(|&mib| {
+                                let a2 = Argon2::new(1, 1, mib * MIB,
+                                                     Variant::Argon2i);
+                                (format!("{}MiB", mib), a2.unwrap())
+                            })
+                            .collect();
+    run(c, "memory", &cases);
+}
+
+fn lanes(c: &mut Criterion) {
+    let cases: Vec<_> = [1, 2, 4, 8]
+                            .iter()
+                            .map(|&lanes| {
+                                let a2 = Argon2::new(1, lanes, 64 * MIB,
+                                                     Variant::Argon2i);
+                                (format!("{}", lanes), a2.unwrap())
+                            })
+                            .collect();
+    run(c, "lanes", &cases);
+}
+
+fn variants(c: &mut Criterion) {
+    let cases: Vec<_> = [("i", Variant::Argon2i), ("d", Variant::Argon2d)]
+                            .iter()
+                            .map(|&(name, v)| {
+                                let a2 = Argon2::new(defaults::PASSES,
+                                                     defaults::LANES,
+                                                     defaults::KIB, v);
+                                (name.to_string(), a2.unwrap())
+                            })
+                            .collect();
+    run(c, "variants", &cases);
+}
+
+criterion_group!(benches, memory, lanes, variants);
+criterion_main!(benches);
@@ -0,0 +1,94 @@
+//! Python bindings for argon2rs, built with `maturin develop` or
+//! `maturin build --release` from this directory. Hashes produced here are
+//! byte-for-byte identical to those of the Rust API, so Python and Rust
+//! services can share one user table and one set of parameters.
+
+use argon2rs::verifier::Encoded;
+use argon2rs::{Argon2, Params, Variant, defaults};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+/// Argon2 cost parameters, validated on construction.
+#[pyclass(name = "Params", frozen)]
+#[derive(Clone, Copy)]
+struct PyParams(Params);
+
+#[pymethods]
+impl PyParams {
+    #[new]
+    #[pyo3(signature = (passes = defaults::PASSES, lanes = defaults::LANES,
+                        kib = defaults::KIB, variant = "argon2i"))]
+    fn new(passes: u32, lanes: u32, kib: u32, variant: &str) -> PyResult<Self> {
+        let variant = match variant {
+            "argon2i" => Variant::Argon2i,
+            "argon2d" => Variant::Argon2d,
+            "argon2id" => Variant::Argon2id,
+            _ => return Err(PyValueError::new_err("unknown variant")),
+        };
+        Params::new(passes, lanes, kib, variant)
+            .map(PyParams)
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    #[getter]
+    fn passes(&self) -> u32 { self.0.passes() }
+
+    #[getter]
+    fn lanes(&self) -> u32 { self.0.lanes() }
+
+    #[getter]
+    fn kib(&self) -> u32 { self.0.kib() }
+
+    #[getter]
+    fn variant(&self) -> &'static str {
+        match self.0.variant() {
+            Variant::Argon2i => "argon2i",
+            Variant::Argon2d => "argon2d",
+            Variant::Argon2id => "argon2id",
+        }
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Params(passes={}, lanes={}, kib={}, variant='{}')",
+                self.passes(), self.lanes(), self.kib(), self.variant())
+    }
+}
+
+/// Hashes `password` with `salt` (at least 8 bytes, e.g. `os.urandom(16)`)
+/// and returns the storable encoding. The GIL is released while hashing.
+#[pyfunction]
+#[pyo3(signature = (password, salt, params = None))]
+fn hash_password(py: Python, password: &[u8], salt: &[u8],
+                 params: Option<PyParams>)
+                 -> PyResult<String> {
+    if salt.len() < 8 {
+        return Err(PyValueError::new_err("salt must be at least 8 bytes"));
+    }
+    let params = match params {
+        Some(p) => p.0,
+        None => Argon2::default(Variant::Argon2i).to_params(),
+    };
+    let enc = py.allow_threads(|| {
+        Encoded::new(Argon2::with_params(params), password, salt, &[], &[])
+            .to_u8()
+    });
+    Ok(String::from_utf8(enc).unwrap())
+}
+
+/// Verifies `password` against a previously stored encoding. Raises
+/// `ValueError` if `encoded` is malformed.
+#[pyfunction]
+fn verify(py: Python, encoded: &str, password: &[u8]) -> PyResult<bool> {
+    let enc = Encoded::from_u8(encoded.as_bytes())
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    Ok(py.allow_threads(|| enc.verify(password)))
+}
+
+#[pymodule]
+#[pyo3(name = "argon2rs")]
+fn init(m: &Bound<PyModule>) -> PyResult<()> {
+    m.add_class::<PyParams>()?;
+    m.add_function(wrap_pyfunction!(hash_password, m)?)?;
+    m.add_function(wrap_pyfunction!(verify, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,26 @@
+import argon2rs
+import pytest
+
+
+def test_roundtrip():
+    params = argon2rs.Params(passes=1, kib=64)
+    enc = argon2rs.hash_password(b"hunter2", b"saltsaltsalt", params)
+    assert enc.startswith("$argon2i$v=19,m=64,t=1,p=1$")
+    assert argon2rs.verify(enc, b"hunter2")
+    assert not argon2rs.verify(enc, b"hunter3")
+
+
+def test_matches_rust():
+    # from argon2rs' own verifier tests.
+    enc = ("$argon2i$v=19,m=4096,t=3,p=1$dG9kbzogZnV6eiB0ZXN0cw"
+           "$AvsXI+N78kGHzeGwzz0VTjfBdl7MmgvBGfJ/XXyqLbA")
+    assert argon2rs.verify(enc, b"argon2i!")
+
+
+def test_bad_input():
+    with pytest.raises(ValueError):
+        argon2rs.Params(passes=0)
+    with pytest.raises(ValueError):
+        argon2rs.hash_password(b"pw", b"short")
+    with pytest.raises(ValueError):
+        argon2rs.verify("$argon2q$", b"pw")
@@ -0,0 +1,38 @@
+// Type definitions for the wasm-bindgen build of argon2rs. wasm-pack emits an
+// equivalent file into pkg/; this copy is kept in the source tree for review
+// and for consumers that vendor the compiled module by hand.
+
+/** Argon2 cost parameters, validated on construction. */
+export class Params {
+  free(): void;
+  /**
+   * Throws if the parameters are out of range or `variant` is not one of
+   * `"argon2i"`, `"argon2d"`, or `"argon2id"`.
+   */
+  constructor(passes: number, lanes: number, kib: number, variant: string);
+  /** The library's default Argon2i parameters. */
+  static defaults(): Params;
+  readonly passes: number;
+  readonly lanes: number;
+  readonly kib: number;
+}
+
+/**
+ * Hashes `password` with `salt` (at least 8 bytes, e.g. from
+ * `crypto.getRandomValues`) and returns the storable encoding.
+ */
+export function hashEncoded(password: string, salt: Uint8Array,
+                            params: Params): string;
+
+/**
+ * Verifies `password` against a previously stored encoding. Throws if
+ * `encoded` is malformed.
+ */
+export function verify(encoded: string, password: string): boolean;
+
+/**
+ * Fills lanes of multi-lane hashes for the rest of the calling worker's life.
+ * Only present in builds with the `threads` feature. Called by
+ * `lane-workers.js`; not for direct use.
+ */
+export function runLaneWorker(): void;
@@ -0,0 +1,54 @@
+// Starts Web Workers that fill the lanes of multi-lane hashes in parallel, for
+// builds with the `threads` feature. Usage, from the worker that hashes:
+//
+//   import init, * as argon2rs from "./pkg/argon2rs_wasm.js";
+//   import { startLaneWorkers } from "./lane-workers.js";
+//
+//   const wasm = await init();
+//   await startLaneWorkers(new URL("./pkg/argon2rs_wasm.js", import.meta.url),
+//                          wasm.memory, 3);
+//   argon2rs.hashEncoded(password, salt, new argon2rs.Params(3, 4, 65536,
+//                                                            "argon2id"));
+//
+// Each worker instantiates the same module over the same shared memory and
+// then runs lanes until it is terminated. A hash with `n` lanes runs at most
+// `n - 1` of them on workers and the rest on the calling thread, so `count` is
+// best set to the number of lanes less one, or to
+// `navigator.hardwareConcurrency - 1` if that is smaller. Hashing works with
+// any number of workers, including none.
+
+const workerSource = `
+self.onmessage = async ({ data: { moduleUrl, module, memory } }) => {
+  const pkg = await import(moduleUrl);
+  await pkg.default({ module_or_path: module, memory });
+  postMessage("ready");
+  pkg.runLaneWorker();
+};
+`;
+
+/**
+ * Starts `count` lane workers for the module at `moduleUrl`, the JS glue
+ * emitted by wasm-pack, sharing `memory`. Resolves to the workers once all of
+ * them are running; terminate them to stop.
+ */
+export async function startLaneWorkers(moduleUrl, memory, count) {
+  const module = await WebAssembly.compileStreaming(
+      fetch(new URL("argon2rs_wasm_bg.wasm", moduleUrl)));
+  const blob = new Blob([workerSource], { type: "text/javascript" });
+  const url = URL.createObjectURL(blob);
+  const workers = [];
+  try {
+    for (let i = 0; i < count; i++) {
+      const w = new Worker(url, { type: "module" });
+      await new Promise((resolve, reject) => {
+        w.onmessage = resolve;
+        w.onerror = reject;
+        w.postMessage({ moduleUrl: moduleUrl.toString(), module, memory });
+      });
+      workers.push(w);
+    }
+  } finally {
+    URL.revokeObjectURL(url);
+  }
+  return workers;
+}
@@ -0,0 +1,85 @@
+//! JavaScript bindings for argon2rs. Build with
+//!
+//! ```text
+//! $ wasm-pack build --target web      # or --target nodejs
+//! ```
+//!
+//! from this directory. `argon2rs.d.ts` documents the resulting API.
+//!
+//! With the `threads` feature (see `Cargo.toml` for the build), multi-lane
+//! hashes fill their lanes in parallel on Web Workers started by
+//! `lane-workers.js`. Hashing then has to happen off the browser's main
+//! thread too, e.g. in another worker, since it waits for the lanes.
+
+use argon2rs::verifier::Encoded;
+use argon2rs::{Argon2, Variant, defaults};
+use wasm_bindgen::prelude::*;
+
+/// Argon2 cost parameters, validated on construction.
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct Params(argon2rs::Params);
+
+#[wasm_bindgen]
+impl Params {
+    /// Throws if the parameters are out of range or `variant` is not one of
+    /// `"argon2i"`, `"argon2d"`, or `"argon2id"`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(passes: u32, lanes: u32, kib: u32, variant: &str)
+               -> Result<Params, JsError> {
+        let variant = match variant {
+            "argon2i" => Variant::Argon2i,
+            "argon2d" => Variant::Argon2d,
+            "argon2id" => Variant::Argon2id,
+            _ => return Err(JsError::new("unknown variant")),
+        };
+        argon2rs::Params::new(passes, lanes, kib, variant)
+            .map(Params)
+            .map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    /// The library's default Argon2i parameters.
+    #[wasm_bindgen(js_name = defaults)]
+    pub fn default_params() -> Params {
+        Params(argon2rs::Params::new(defaults::PASSES, defaults::LANES,
+                                     defaults::KIB, Variant::Argon2i)
+                   .unwrap())
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn passes(&self) -> u32 { self.0.passes() }
+
+    #[wasm_bindgen(getter)]
+    pub fn lanes(&self) -> u32 { self.0.lanes() }
+
+    #[wasm_bindgen(getter)]
+    pub fn kib(&self) -> u32 { self.0.kib() }
+}
+
+/// Hashes `password` with `salt` (at least 8 bytes, e.g. from
+/// `crypto.getRandomValues`) and returns the storable encoding.
+#[wasm_bindgen(js_name = hashEncoded)]
+pub fn hash_encoded(password: &str, salt: &[u8], params: &Params)
+                    -> Result<String, JsError> {
+    if salt.len() < 8 {
+        return Err(JsError::new("salt must be at least 8 bytes"));
+    }
+    let a2 = Argon2::with_params(params.0);
+    let enc = Encoded::new(a2, password.as_bytes(), salt, &[], &[]);
+    Ok(String::from_utf8(enc.to_u8()).unwrap())
+}
+
+/// Verifies `password` against a previously stored encoding. Throws if
+/// `encoded` is malformed.
+#[wasm_bindgen]
+pub fn verify(encoded: &str, password: &str) -> Result<bool, JsError> {
+    Encoded::from_u8(encoded.as_bytes())
+        .map(|enc| enc.verify(password.as_bytes()))
+        .map_err(|e| JsError::new(&e.to_string()))
+}
+
+/// Fills lanes of multi-lane hashes for the rest of the calling worker's life.
+/// Called by `lane-workers.js`; not for direct use.
+#[cfg(all(target_arch = "wasm32", feature = "threads"))]
+#[wasm_bindgen(js_name = runLaneWorker)]
+pub fn run_lane_worker() { argon2rs::lane_worker() }
@@ -0,0 +1,96 @@
+// Registration and login handlers for an async web service. Hashing and
+// verifying are deliberately slow and CPU-bound, so they run on tokio's
+// blocking pool via `spawn_blocking`; calling them directly from a handler
+// would stall every other request scheduled on the same executor thread.
+//
+// Lives in its own crate so that argon2rs itself needn't depend on an async
+// stack or a newer edition. Run it from this directory with
+// `cargo run --release`, then:
+//
+//     curl -d '{"user":"alice","password":"hunter2"}' \
+//          -H 'content-type: application/json' localhost:3000/register
+//     curl -d '{"user":"alice","password":"hunter2"}' \
+//          -H 'content-type: application/json' localhost:3000/login
+
+use argon2rs::verifier::Encoded;
+use argon2rs::{Argon2, Variant};
+use axum::extract::{Json, State};
+use axum::http::StatusCode;
+use axum::routing::post;
+use axum::Router;
+use serde::Deserialize;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::task::spawn_blocking;
+
+// Stands in for a database table of user name -> encoded hash.
+type Users = Arc<Mutex<HashMap<String, String>>>;
+
+#[derive(Deserialize)]
+struct Credentials {
+    user: String,
+    password: String,
+}
+
+// Cost parameters for interactive logins: 64 MiB and three passes over two
+// lanes. Tune these with `examples/cli.rs` on the production hardware.
+fn params() -> Argon2 {
+    Argon2::new(3, 2, 64 * 1024, Variant::Argon2i).unwrap()
+}
+
+async fn register(State(users): State<Users>, Json(creds): Json<Credentials>)
+                  -> StatusCode {
+    // A cheap early answer; the insert below is what settles it.
+    if users.lock().unwrap().contains_key(&creds.user) {
+        return StatusCode::CONFLICT;
+    }
+    let password = creds.password;
+    let enc = spawn_blocking(move || {
+                  Encoded::generate(params().to_params(), password.as_bytes())
+                      .unwrap()
+              })
+              .await
+              .unwrap();
+    // The lock was released while hashing, so another registration for the
+    // same user may have won in the meantime: check again, and insert in the
+    // same critical section.
+    match users.lock().unwrap().entry(creds.user) {
+        Entry::Vacant(e) => {
+            e.insert(enc.to_string());
+            StatusCode::CREATED
+        }
+        Entry::Occupied(_) => StatusCode::CONFLICT,
+    }
+}
+
+async fn login(State(users): State<Users>, Json(creds): Json<Credentials>)
+               -> StatusCode {
+    let stored = users.lock().unwrap().get(&creds.user).cloned();
+    let password = creds.password;
+    let ok = spawn_blocking(move || {
+                 // Unknown users cost as much to reject as wrong passwords, so
+                 // response times don't reveal which user names exist.
+                 match stored.and_then(|s| Encoded::parse(&s).ok()) {
+                     Some(enc) => enc.verify(password.as_bytes()),
+                     None => Encoded::verify_dummy(&params(),
+                                                   password.as_bytes()),
+                 }
+             })
+             .await
+             .unwrap();
+    if ok { StatusCode::OK } else { StatusCode::UNAUTHORIZED }
+}
+
+#[tokio::main]
+async fn main() {
+    let users = Users::default();
+    let app = Router::new()
+                  .route("/register", post(register))
+                  .route("/login", post(login))
+                  .with_state(users);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
+                       .await
+                       .unwrap();
+    axum::serve(listener, app).await.unwrap();
+}
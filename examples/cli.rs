@@ -1,14 +1,21 @@
 extern crate argon2rs;
 
-use argon2rs::{Argon2, Variant};
+use argon2rs::cost::{calibrate, estimate_cost, recommend};
+use argon2rs::{Argon2, Params, Variant};
 use std::string::String;
 use std::env;
+use std::fs::File;
 use std::io::{Read, stdin};
+use std::process::exit;
+use std::time::{Duration, Instant};
 
 const CLI_TOOL_SALT_LEN: usize = 16;
 
-fn that_cli_tool(msg: &[u8], salt: &[u8], passes: u32, lanes: u32, logkib: u32)
-                 -> [u8; argon2rs::defaults::LENGTH] {
+// Also returns the time taken to hash. `k` and `x` are the secret key and
+// associated data.
+fn that_cli_tool(msg: &[u8], salt: &[u8], passes: u32, lanes: u32, logkib: u32,
+                 k: &[u8], x: &[u8])
+                 -> ([u8; argon2rs::defaults::LENGTH], Duration) {
     assert!(salt.len() <= CLI_TOOL_SALT_LEN && passes > 0 && logkib > 0 &&
             lanes > 0);
     let a = Argon2::new(passes, lanes, 1 << logkib, Variant::Argon2i)
@@ -20,21 +27,165 @@ fn that_cli_tool(msg: &[u8], salt: &[u8], passes: u32, lanes: u32, logkib: u32)
     }
 
     let mut out = [0 as u8; argon2rs::defaults::LENGTH];
-    a.hash(&mut out, msg, &s, &[], &[]);
-    out
+    let start = Instant::now();
+    a.hash(&mut out, msg, &s, k, x);
+    (out, start.elapsed())
+}
+
+// Prints the time taken as the reference CLI does, then the memory actually
+// used and the rate at which it was filled, one figure per line.
+fn report(params: &Params, elapsed: Duration) {
+    let secs = elapsed.as_secs_f64();
+    let filled = params.blocks() as f64 * params.passes() as f64;
+    println!("{:.3} seconds", secs);
+    println!("Memory: {} KiB", params.blocks());
+    println!("Throughput: {:.3} GiB/s", filled / (1 << 20) as f64 / secs);
 }
 
 fn to_string(bs: &[u8]) -> String {
     bs.iter().map(|b| format!("{:02x}", b)).collect()
 }
 
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+// Reads a secret key from `path`, less one trailing newline, so that files
+// written with `echo` work. Warns if others may read the file.
+fn read_secret(path: &str) -> Vec<u8> {
+    let mut key = vec![];
+    let read = File::open(path).and_then(|mut f| {
+        warn_if_shared(path, &f);
+        f.read_to_end(&mut key)
+    });
+    if let Err(e) = read {
+        eprintln!("Error: can't read {}: {}", path, e);
+        exit(1);
+    }
+    if key.ends_with(b"\n") {
+        key.pop();
+        if key.ends_with(b"\r") {
+            key.pop();
+        }
+    }
+    if key.len() > argon2rs::limits::MAX_SECRET_LEN {
+        eprintln!("Error: the secret key in {} is longer than {} bytes.",
+                  path, argon2rs::limits::MAX_SECRET_LEN);
+        exit(1);
+    }
+    key
+}
+
+#[cfg(unix)]
+fn warn_if_shared(path: &str, f: &File) {
+    use std::os::unix::fs::PermissionsExt;
+    if let Ok(meta) = f.metadata() {
+        if meta.permissions().mode() & 0o077 != 0 {
+            eprintln!("Warning: {} is readable by other users.", path);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn warn_if_shared(_: &str, _: &File) {}
+
+// Parses sizes like "256M", "1G", or "4096K" into KiB. Bare numbers are KiB.
+fn parse_kib(size: &str) -> Option<u32> {
+    let (digits, scale) = match size.chars().last() {
+        Some('K') | Some('k') => (&size[..size.len() - 1], 1),
+        Some('M') | Some('m') => (&size[..size.len() - 1], 1 << 10),
+        Some('G') | Some('g') => (&size[..size.len() - 1], 1 << 20),
+        _ => (size, 1),
+    };
+    digits.parse::<u32>().ok().and_then(|n| n.checked_mul(scale))
+}
+
+fn bench(prog: &str, opts: &[String]) {
+    let mut target_ms = 250;
+    let mut max_kib = 256 << 10;
+    let mut lanes = 1;
+    for pair in opts.chunks(2) {
+        let val = pair.get(1).map(|v| &v[..]).unwrap_or("");
+        let ok = match &pair[0][..] {
+            "--target-ms" => val.parse().map(|v| target_ms = v).is_ok(),
+            "--max-mem" => parse_kib(val).map(|v| max_kib = v).is_some(),
+            "--lanes" => val.parse().map(|v| lanes = v).is_ok(),
+            _ => false,
+        };
+        if !ok {
+            println!("Usage: {} bench [--target-ms 250] [--max-mem 256M] \
+                      [--lanes 1]", prog);
+            exit(2);
+        }
+    }
+
+    let tput = calibrate();
+    println!("Throughput: {:.0} MiB/s per lane", tput / 1024.0);
+    let target = Duration::from_millis(target_ms);
+    let params = recommend(Variant::Argon2i, target, max_kib, lanes)
+                     .unwrap_or_else(|e| {
+                         println!("Error: {:?}", e);
+                         exit(1)
+                     });
+    let (m, t, p) = (params.kib(), params.passes(), params.lanes());
+    let predicted = estimate_cost(&params).approx_time;
+
+    let a2 = Argon2::with_params(params);
+    let mut out = [0; argon2rs::defaults::LENGTH];
+    let start = Instant::now();
+    a2.hash(&mut out, b"password", b"somesalt", &[], &[]);
+    let measured = start.elapsed();
+
+    println!("Recommended: m={} t={} p={} (predicted {} ms, measured {} ms)",
+             m, t, p, predicted.as_millis(), measured.as_millis());
+    println!("$argon2i$v=19,m={},t={},p={}", m, t, p);
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() != 5 {
-        println!("Usage: {} passes lanes logkib salt", args[0]);
+    if args.len() >= 2 && args[1] == "bench" {
+        return bench(&args[0], &args[2..]);
+    }
+    let mut time = false;
+    let (mut k, mut x) = (vec![], vec![]);
+    let mut ok = args.len() >= 5;
+    let mut opts = args.iter().skip(5);
+    while let Some(opt) = opts.next() {
+        ok = ok && match &opt[..] {
+            "--time" => {
+                time = true;
+                true
+            }
+            "--secret-file" => {
+                opts.next().map(|path| k = read_secret(path)).is_some()
+            }
+            "--ad" => {
+                opts.next()
+                    .and_then(|hex| from_hex(hex))
+                    .map(|ad| x = ad)
+                    .is_some()
+            }
+            _ => false,
+        };
+    }
+    if !ok {
+        println!("Usage: {} passes lanes logkib salt [--time] \
+                  [--secret-file path] [--ad hex]", args[0]);
+        println!("       {} bench [--target-ms 250] [--max-mem 256M] \
+                  [--lanes 1]", args[0]);
         println!("where salt.len() <= {}, memory usage is 2^logkib, and \
-                  plaintext is read from stdin.", CLI_TOOL_SALT_LEN);
+                  plaintext is read from stdin. --time reports how long \
+                  hashing took. --secret-file reads the secret key (pepper) \
+                  from a file, which only its owner should be able to read, \
+                  and --ad gives associated data in hex. bench recommends \
+                  parameters for this machine.", CLI_TOOL_SALT_LEN);
         return;
     }
 
@@ -47,5 +198,10 @@ fn main() {
     stdin().read_to_string(&mut msg).unwrap();
     let p = msg.as_bytes();
 
-    println!("Hash: {}", to_string(&that_cli_tool(p, salt, t, l, logm)));
+    let (hash, elapsed) = that_cli_tool(p, salt, t, l, logm, &k, &x);
+    println!("Hash: {}", to_string(&hash));
+    if time {
+        let params = Params::new(t, l, 1 << logm, Variant::Argon2i).unwrap();
+        report(&params, elapsed);
+    }
 }
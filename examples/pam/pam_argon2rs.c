@@ -0,0 +1,92 @@
+/* pam_argon2rs: authenticates local users against a credential file of
+ * `user:$argon2...` lines, as maintained by `examples/passwd.rs`.
+ *
+ * Usage in a PAM stack:
+ *
+ *     auth required pam_argon2rs.so file=/etc/argon2rs.passwd
+ *
+ * Unknown users are rejected only after a dummy verification, so that they
+ * take as long to reject as wrong passwords. */
+
+#define _GNU_SOURCE
+#define PAM_SM_AUTH
+
+#include <security/pam_modules.h>
+#include <security/pam_ext.h>
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <syslog.h>
+
+#include "argon2rs.h"
+
+#define DEFAULT_FILE "/etc/argon2rs.passwd"
+
+/* Returns the encoded hash of `user` from `path` in a malloc'd buffer, or NULL
+ * if there is no such user. */
+static char *lookup(const char *path, const char *user) {
+    FILE *f = fopen(path, "r");
+    char *line = NULL, *rv = NULL;
+    size_t cap = 0, userlen = strlen(user);
+    ssize_t n;
+
+    if (f == NULL) {
+        return NULL;
+    }
+    while (rv == NULL && (n = getline(&line, &cap, f)) > 0) {
+        if (line[0] == '#' || (size_t)n <= userlen + 1 ||
+            strncmp(line, user, userlen) != 0 || line[userlen] != ':') {
+            continue;
+        }
+        line[strcspn(line, "\r\n")] = '\0';
+        rv = strdup(line + userlen + 1);
+    }
+    free(line);
+    fclose(f);
+    return rv;
+}
+
+PAM_EXTERN int pam_sm_authenticate(pam_handle_t *pamh, int flags, int argc,
+                                   const char **argv) {
+    const char *path = DEFAULT_FILE, *user = NULL, *pwd = NULL;
+    char *encoded;
+    int i, rv;
+
+    (void)flags;
+    for (i = 0; i < argc; i++) {
+        if (strncmp(argv[i], "file=", 5) == 0) {
+            path = argv[i] + 5;
+        }
+    }
+
+    if (pam_get_user(pamh, &user, NULL) != PAM_SUCCESS || user == NULL) {
+        return PAM_USER_UNKNOWN;
+    }
+    if (pam_get_authtok(pamh, PAM_AUTHTOK, &pwd, NULL) != PAM_SUCCESS) {
+        return PAM_AUTH_ERR;
+    }
+
+    encoded = lookup(path, user);
+    rv = argon2rs_verify(encoded, (const uint8_t *)pwd, strlen(pwd));
+    free(encoded);
+
+    switch (rv) {
+    case ARGON2RS_OK:
+        return PAM_SUCCESS;
+    case ARGON2RS_MISMATCH:
+        return PAM_AUTH_ERR;
+    default:
+        pam_syslog(pamh, LOG_ERR, "bad entry for %s in %s", user, path);
+        return PAM_AUTHINFO_UNAVAIL;
+    }
+}
+
+PAM_EXTERN int pam_sm_setcred(pam_handle_t *pamh, int flags, int argc,
+                              const char **argv) {
+    (void)pamh;
+    (void)flags;
+    (void)argc;
+    (void)argv;
+    return PAM_SUCCESS;
+}
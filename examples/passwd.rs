@@ -0,0 +1,57 @@
+extern crate argon2rs;
+
+use argon2rs::passwd::CredFile;
+use argon2rs::{Argon2, Variant};
+use std::env;
+use std::io::{Read, stdin};
+use std::process::exit;
+
+fn read_password() -> String {
+    let mut pw = String::new();
+    stdin().read_to_string(&mut pw).unwrap();
+    pw.trim_end_matches(&['\r', '\n'][..]).to_string()
+}
+
+fn usage(prog: &str) -> ! {
+    println!("Usage: {} file set user", prog);
+    println!("       {} file verify user", prog);
+    println!("       {} file delete user", prog);
+    println!("       {} file list", prog);
+    println!("where passwords are read from stdin.");
+    exit(2);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        usage(&args[0]);
+    }
+    let creds = CredFile::new(&args[1]);
+
+    match (&args[2][..], args.get(3)) {
+        ("set", Some(user)) => {
+            let a2 = Argon2::default(Variant::Argon2i);
+            creds.set(user, read_password().as_bytes(), a2).unwrap();
+        }
+        ("verify", Some(user)) => {
+            if creds.verify(user, read_password().as_bytes()).unwrap() {
+                println!("ok");
+            } else {
+                println!("mismatch");
+                exit(1);
+            }
+        }
+        ("delete", Some(user)) => {
+            if !creds.remove(user).unwrap() {
+                println!("no such user: {}", user);
+                exit(1);
+            }
+        }
+        ("list", None) => {
+            for user in creds.list().unwrap() {
+                println!("{}", user);
+            }
+        }
+        _ => usage(&args[0]),
+    }
+}
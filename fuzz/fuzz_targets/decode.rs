@@ -0,0 +1,33 @@
+//! Every decoder of stored hashes must return an error, never panic, on any
+//! input. Whatever parses must survive a round trip, and verify without
+//! panicking.
+
+#![no_main]
+
+use argon2rs::verifier::Encoded;
+use libfuzzer_sys::fuzz_target;
+
+// Verifies `enc`, unless its costs would make the fuzzer crawl or run out of
+// memory.
+fn verify(enc: &Encoded) {
+    let (_, kib, passes, _, _) = enc.params();
+    if kib <= 1024 && passes <= 4 {
+        let _ = enc.verify(b"password");
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(enc) = Encoded::from_u8(data) {
+        let re = enc.to_u8();
+        assert!(Encoded::from_u8(&re).unwrap() == enc);
+        verify(&enc);
+    }
+    if let Ok(enc) = Encoded::from_u8_strict(data) {
+        assert!(Encoded::from_u8(&enc.to_u8()).unwrap() == enc);
+        verify(&enc);
+    }
+    if let Ok(enc) = Encoded::from_bytes(data) {
+        assert!(Encoded::from_bytes(&enc.to_bytes()).unwrap() == enc);
+        verify(&enc);
+    }
+});
@@ -0,0 +1,42 @@
+/* C interface to argon2rs. Build the library with
+ *
+ *     cargo rustc --release --features capi --crate-type staticlib
+ *
+ * and link against target/release/libargon2rs.a (plus -lpthread -ldl -lm).
+ * See src/capi.rs for full documentation. */
+
+#ifndef ARGON2RS_H
+#define ARGON2RS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define ARGON2RS_OK 0
+#define ARGON2RS_MISMATCH 1
+#define ARGON2RS_EINVAL (-1)
+#define ARGON2RS_EDECODE (-2)
+#define ARGON2RS_ETOOSMALL (-3)
+#define ARGON2RS_EINTERNAL (-4)
+
+uint32_t argon2rs_abi_version(void);
+
+/* Returns ARGON2RS_OK if `pwd` matches the NUL-terminated encoded hash
+ * `encoded`, ARGON2RS_MISMATCH if not. A null `encoded` performs a dummy
+ * verification and returns ARGON2RS_MISMATCH. */
+int argon2rs_verify(const char *encoded, const uint8_t *pwd, size_t pwdlen);
+
+/* Hashes `pwd` with `salt` (at least 8 bytes) under default Argon2i
+ * parameters, writing the NUL-terminated encoding to `out`. */
+int argon2rs_hash_encoded(const uint8_t *pwd, size_t pwdlen,
+                          const uint8_t *salt, size_t saltlen,
+                          char *out, size_t outlen);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -1,30 +1,82 @@
-extern crate blake2_rfc;
-
-use std::{fmt, mem};
+use std::{fmt, mem, ptr};
+use std::any::TypeId;
 use std::error::Error;
-use self::blake2_rfc::blake2b::Blake2b;
+use std::ops::BitOr;
+use std::sync::{Arc, Condvar, Mutex};
+#[cfg(feature = "metrics")]
+use std::time::Instant;
+use blake2::Blake2b;
 use octword::u64x2;
-use block::{ARGON2_BLOCK_BYTES, Block, Matrix};
+use block::{self, ARGON2_BLOCK_BYTES, Block, FixedMatrix, Matrix};
+use compression::{BlaMka, Compression};
+use indexing::{Indexing, Position, Spec};
+use limits::{MAX_INPUT_LEN, MAX_SECRET_LEN, MAX_TAG_LEN, MIN_SALT_LEN,
+             MIN_TAG_LEN};
+use memcheck::{self, InsufficientMemory};
+use params::Params;
+use verifier::constant_eq;
 use workers::Workers;
 
 #[derive(Eq, PartialEq, Copy, Clone, Debug)]
 pub enum Variant {
     Argon2d = 0,
     Argon2i = 1,
+    /// Argon2i's data-independent indexing for the first half of the first
+    /// pass, then Argon2d's. RFC 9106's recommended variant.
+    Argon2id = 2,
 }
 
 const DEF_B2HASH_LEN: usize = 64;
-const SLICES_PER_LANE: u32 = 4;
-
+pub(crate) const SLICES_PER_LANE: u32 = 4;
+
+/// The parameters of `Argon2::default` and the `*_simple` functions. These
+/// are those of the reference implementation's `run.c` (4 MiB, 3 passes, 1
+/// lane) unless the build enables one of the `defaults-interactive`,
+/// `defaults-moderate`, or `defaults-rfc9106` features, which select the
+/// cost parameters of the `Preset` of the same name. If several are enabled,
+/// the most expensive one wins.
 pub mod defaults {
-    // from run.c
-    pub const PASSES: u32 = 3;
-    pub const KIB: u32 = 4096;
+    pub const PASSES: u32 = preset::PASSES;
+    pub const KIB: u32 = preset::KIB;
     /// Default level of parallelism.
-    pub const LANES: u32 = 1;
+    pub const LANES: u32 = preset::LANES;
     /// The size of Argon2's hash output is adjustable. This is the default
     /// length.
     pub const LENGTH: usize = 32;
+
+    #[cfg(not(any(feature = "defaults-interactive",
+                  feature = "defaults-moderate",
+                  feature = "defaults-rfc9106")))]
+    mod preset {
+        // from run.c
+        pub const PASSES: u32 = 3;
+        pub const KIB: u32 = 4096;
+        pub const LANES: u32 = 1;
+    }
+
+    #[cfg(all(feature = "defaults-interactive",
+              not(any(feature = "defaults-moderate",
+                      feature = "defaults-rfc9106"))))]
+    mod preset {
+        pub const PASSES: u32 = 2;
+        pub const KIB: u32 = 64 << 10;
+        pub const LANES: u32 = 1;
+    }
+
+    #[cfg(all(feature = "defaults-moderate",
+              not(feature = "defaults-rfc9106")))]
+    mod preset {
+        pub const PASSES: u32 = 3;
+        pub const KIB: u32 = 256 << 10;
+        pub const LANES: u32 = 1;
+    }
+
+    #[cfg(feature = "defaults-rfc9106")]
+    mod preset {
+        pub const PASSES: u32 = 1;
+        pub const KIB: u32 = 2 << 20;
+        pub const LANES: u32 = 4;
+    }
 }
 
 fn split_u64(n: u64) -> (u32, u32) {
@@ -33,7 +85,7 @@ fn split_u64(n: u64) -> (u32, u32) {
 
 fn as32le(k: u32) -> [u8; 4] { unsafe { mem::transmute(k.to_le()) } }
 
-fn len32(t: &[u8]) -> [u8; 4] { as32le(t.len() as u32) }
+pub(crate) fn len32(t: &[u8]) -> [u8; 4] { as32le(t.len() as u32) }
 
 macro_rules! b2hash {
     ($($bytes: expr),*) => {
@@ -47,11 +99,17 @@ macro_rules! b2hash {
         {
             let mut b = Blake2b::new($out.len());
             $(b.update($bytes));*;
-            $out.clone_from_slice(b.finalize().as_bytes());
+            b.finalize_into($out);
         }
     };
 }
 
+// The parameter prefix is absorbed afresh on every call on purpose. Caching
+// the Blake2b state after it, per `Argon2`, was measured to save 5 to 8 ns a
+// hash: the 24-byte prefix is less than one Blake2b block, so no compression
+// is saved, only the state's setup, against 20 µs or more for even the
+// smallest (8 KiB) hash. The prefix also includes the tag length, which
+// varies by call.
 #[cfg_attr(rustfmt, rustfmt_skip)]
 fn h0(lanes: u32, hash_length: u32, memory_kib: u32, passes: u32, version: u32,
       variant: Variant, p: &[u8], s: &[u8], k: &[u8], x: &[u8])
@@ -76,6 +134,60 @@ pub struct Argon2 {
     kib: u32,
     variant: Variant,
     version: Version,
+    flags: Flags,
+    pool: Option<Arc<Pool>>,
+    stack_size: Option<usize>,
+    prefault: bool,
+    on_segment: Option<fn()>,
+    // `SLICES_PER_LANE` except under `with_slices`.
+    slices: u32,
+}
+
+/// Which buffers to wipe after hashing, after the `ARGON2_FLAG_CLEAR_*` flags
+/// of the reference implementation. Combine with `|`. The default is
+/// `Flags::CLEAR_MEMORY`: the block matrix is always wiped unless requested
+/// otherwise, while the caller's buffers are only wiped by
+/// `Argon2::hash_clearing`, and only if asked to.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Flags(u32);
+
+impl Flags {
+    /// Wipe the password passed to `Argon2::hash_clearing`.
+    pub const CLEAR_PASSWORD: Flags = Flags(1 << 0);
+    /// Wipe the secret passed to `Argon2::hash_clearing`.
+    pub const CLEAR_SECRET: Flags = Flags(1 << 1);
+    /// Wipe the block matrix before freeing it.
+    pub const CLEAR_MEMORY: Flags = Flags(1 << 2);
+
+    /// No flags at all.
+    pub fn empty() -> Flags { Flags(0) }
+
+    /// All of the above.
+    pub fn all() -> Flags { Flags(7) }
+
+    pub fn bits(&self) -> u32 { self.0 }
+
+    /// Returns `None` if `bits` has any unknown flags set.
+    pub fn from_bits(bits: u32) -> Option<Flags> {
+        match bits & !Flags::all().0 {
+            0 => Some(Flags(bits)),
+            _ => None,
+        }
+    }
+
+    /// Whether all flags set in `other` are set in `self`.
+    pub fn contains(&self, other: Flags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl Default for Flags {
+    fn default() -> Flags { Flags::CLEAR_MEMORY }
+}
+
+impl BitOr for Flags {
+    type Output = Flags;
+    fn bitor(self, rhs: Flags) -> Flags { Flags(self.0 | rhs.0) }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -90,6 +202,10 @@ pub enum ParamErr {
     TooFewLanes,
     TooManyLanes,
     MinKiB(u64),
+    MaxKiB(u64),
+    /// The memory size isn't a multiple of the given number of KiB, four per
+    /// lane, under `Rounding::Error`.
+    UnalignedKiB(u64),
 }
 
 impl fmt::Display for ParamErr {
@@ -100,12 +216,18 @@ impl fmt::Display for ParamErr {
                 write!(f, "{}", self.description())
             }
             MinKiB(k) => write!(f, "Memory parameter must be >= {} KiB.", k),
+            MaxKiB(k) => write!(f, "Memory parameter must be <= {} KiB.", k),
+            UnalignedKiB(k) => {
+                write!(f, "Memory parameter must be a multiple of {} KiB.", k)
+            }
         }
     }
 }
 
-impl Error for ParamErr {
-    fn description(&self) -> &str {
+impl ParamErr {
+    // `Error::description`, for `Params::valid`, which can't call trait
+    // methods.
+    pub(crate) const fn reason(&self) -> &'static str {
         use ParamErr::*;
         match *self {
             TooFewPasses => "Argon2 requires one or more passes to be run.",
@@ -113,10 +235,37 @@ impl Error for ParamErr {
                 "The number of lanes must be between one and 2^24 - 1."
             }
             MinKiB(_) => "Specified size of block matrix was too small.",
+            MaxKiB(_) => "Specified size of block matrix was too large.",
+            UnalignedKiB(_) => {
+                "Specified size of block matrix wasn't a whole number of \
+                 segments."
+            }
         }
     }
 }
 
+impl Error for ParamErr {
+    fn description(&self) -> &str { self.reason() }
+}
+
+/// Returned by `Argon2::hash_checked` when a block of the matrix no longer
+/// matched its checksum: the block at column `column` of lane `lane` changed
+/// after it was written.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct CorruptionErr {
+    pub lane: u32,
+    pub column: u32,
+}
+
+impl fmt::Display for CorruptionErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Block matrix corrupted at lane {}, column {}.", self.lane,
+               self.column)
+    }
+}
+
+impl Error for CorruptionErr {}
+
 impl Argon2 {
     /// Returns an `Argon2` set to default input parameters. See below for a
     /// description of these parameters.
@@ -139,9 +288,11 @@ impl Argon2 {
     ///
     /// `kib`: Desired total size of block matrix, in kibibytes (1 KiB = 1024
     /// bytes). Increasing this forces hashing to use more memory in order to
-    /// thwart ASIC-based attacks. Must be >= 8 * lanes.
+    /// thwart ASIC-based attacks. Must be >= 8 * lanes, and at most
+    /// `Memory::max()`. `Params::with_memory` takes the size in other units.
     ///
-    /// `variant`: Set this to `Variant::Argon2i` when hashing passwords.
+    /// `variant`: Set this to `Variant::Argon2i` or `Variant::Argon2id` when
+    /// hashing passwords.
     pub fn new(passes: u32, lanes: u32, kib: u32, variant: Variant)
                -> Result<Argon2, ParamErr> {
         Argon2::with_version(passes, lanes, kib, variant, Version::_0x13)
@@ -152,26 +303,182 @@ impl Argon2 {
     pub(crate) fn with_version(passes: u32, lanes: u32, kib: u32,
                                variant: Variant, version: Version)
                                -> Result<Argon2, ParamErr> {
-        if passes < 1 {
-            Result::Err(ParamErr::TooFewPasses)
-        } else if lanes < 1 {
-            Result::Err(ParamErr::TooFewLanes)
-        } else if 0x00ffffff < lanes {
-            Result::Err(ParamErr::TooManyLanes)
-        } else if (kib as u64) < 8 * lanes as u64 {
-            Result::Err(ParamErr::MinKiB(8 * lanes as u64))
-        } else {
-            Result::Ok(Argon2 {
-                passes: passes,
-                lanes: lanes,
-                lanelen: kib / (4 * lanes) * 4,
-                kib: kib,
-                variant: variant,
-                version: version,
-            })
+        Params::with_version(passes, lanes, kib, variant, version)
+            .map(Argon2::with_params)
+    }
+
+    /// Creates an `Argon2` from previously validated parameters.
+    pub fn with_params(params: Params) -> Argon2 {
+        Argon2 {
+            passes: params.passes(),
+            lanes: params.lanes(),
+            lanelen: params.lanelen(),
+            kib: params.kib(),
+            variant: params.variant(),
+            version: params.version(),
+            flags: Flags::default(),
+            pool: None,
+            stack_size: None,
+            prefault: false,
+            on_segment: None,
+            slices: SLICES_PER_LANE,
+        }
+    }
+
+    /// Returns this `Argon2` with each lane cut into `slices` slices instead
+    /// of the four that the Argon2 spec fixes, for exploring how the number
+    /// of synchronization points affects scheduling and tradeoff attacks.
+    /// Enabled by the `research` feature.
+    ///
+    /// The results are not Argon2 hashes (except with `slices` = 4) and can't
+    /// be told apart from them, since H0 doesn't cover the slice count: never
+    /// store them, nor use this `Argon2` with `verifier::Encoded`, which only
+    /// records the standard parameters. Lanes are rounded down to a multiple
+    /// of `slices` blocks, of which there must be at least two per slice;
+    /// `slices` must be at least 2.
+    #[cfg(feature = "research")]
+    pub fn with_slices(self, slices: u32) -> Result<Argon2, ParamErr> {
+        assert!(slices >= 2, "Argon2 needs at least two slices per lane");
+        let min_kib = 2 * slices as u64 * self.lanes as u64;
+        if (self.kib as u64) < min_kib {
+            return Err(ParamErr::MinKiB(min_kib));
+        }
+        let lanelen = self.kib / self.lanes / slices * slices;
+        Ok(Argon2 {
+            lanelen,
+            slices,
+            ..self
+        })
+    }
+
+    /// Returns this `Argon2` with its wiping behavior set to `flags`. Cf.
+    /// `Builder::flags`.
+    pub fn with_flags(self, flags: Flags) -> Argon2 {
+        Argon2 { flags, ..self }
+    }
+
+    /// Returns this `Argon2` with `hook` called on the hashing thread between
+    /// segments, once every lane has filled its segment of a slice, which is
+    /// when lanes synchronize: `4 * passes` times per hash. (`hash_fixed`
+    /// calls it after the segment of every lane.)
+    ///
+    /// This is for single-core systems where a hash would otherwise hold the
+    /// processor for its whole duration: the hook can yield to a scheduler or
+    /// feed a watchdog. It can't change the result. To abandon a hash, it can
+    /// panic; the block matrix is still wiped as it unwinds, and a pooled one
+    /// goes back to the pool.
+    pub fn on_segment(self, hook: fn()) -> Argon2 {
+        Argon2 {
+            on_segment: Some(hook),
+            ..self
         }
     }
 
+    // Calls the `on_segment` hook, if any.
+    #[inline(always)]
+    fn segment_done(&self) {
+        if let Some(hook) = self.on_segment {
+            hook();
+        }
+    }
+
+    /// Returns this `Argon2` with the threads that fill lanes 1 and up spawned
+    /// with `bytes` of stack, instead of the platform's default of 2 to 8
+    /// MiB. (Lane 0 is filled on the hashing thread.) Filling a lane needs
+    /// little stack, so a service running many hashes at once can save the
+    /// address space and commit charge of the rest: 64 KiB is plenty in
+    /// optimized builds, and 128 KiB in debug builds. The platform may round
+    /// `bytes` up to its minimum.
+    ///
+    /// Lane workers are spawned for every hash, unless this `Argon2` also has
+    /// a pool: see `Argon2::with_pool`, whose matrices keep their workers.
+    pub fn with_stack_size(self, bytes: usize) -> Argon2 {
+        Argon2 {
+            stack_size: Some(bytes),
+            ..self
+        }
+    }
+
+    /// Returns this `Argon2` with the pages of each block matrix faulted in as
+    /// it is allocated, so that the first pass doesn't take a page fault per
+    /// 4 KiB of memory. That costs a write per block up front, and makes the
+    /// time a hash takes less variable, which matters when timing hashes, as
+    /// `cost::calibrate` does. Off by default. Applies to the matrices of a
+    /// pool only if set before `Argon2::with_pool`.
+    pub fn with_prefault(self) -> Argon2 {
+        Argon2 { prefault: true, ..self }
+    }
+
+    /// Returns this `Argon2` with `size` block matrices allocated up front and
+    /// reused by every subsequent hash, instead of one being allocated per
+    /// hash. An `Argon2` is `Sync`, so that many threads can hash through one
+    /// `&Argon2`; with a pool, at most `size` of them do so at a time while
+    /// the rest wait, which caps the memory used for hashing at `size` times
+    /// `Params::blocks` KiB. `size` must be at least 1. Matrices wait in the
+    /// pool wiped if `Flags::CLEAR_MEMORY` is set, and are freed when the last
+    /// `Argon2` sharing them is dropped.
+    pub fn with_pool(self, size: usize) -> Argon2 {
+        assert!(size >= 1);
+        let wipe = self.flags.contains(Flags::CLEAR_MEMORY);
+        let free = (0..size)
+                       .map(|_| {
+                           Scratch::allocated(self.lanes, self.lanelen, wipe,
+                                              self.stack_size, self.prefault)
+                       })
+                       .collect();
+        let pool = Pool {
+            free: Mutex::new(free),
+            returned: Condvar::new(),
+        };
+        Argon2 { pool: Some(Arc::new(pool)), ..self }
+    }
+
+    /// Pays the one-time costs of hashing ahead of serving traffic, so that
+    /// they don't land on the first requests: runs a throwaway hash in every
+    /// matrix of the pool, which faults in its pages and gets its lane
+    /// workers' threads running. Without a pool, runs one throwaway hash,
+    /// which still covers the self-test (with the `self-test` feature) and
+    /// growing the allocator's heap; matrices and workers are then made anew
+    /// for every hash, so it is `Argon2::with_pool` that keeps them warm.
+    ///
+    /// Hashes started meanwhile wait for the pool's matrices to come back.
+    pub fn warm_up(&self) {
+        let mut out = [0; defaults::LENGTH];
+        let mut warm = |scratch: &mut Scratch| {
+            self.hash_in(&mut out, b"warm-up", b"warm-up salt", &[], &[],
+                         scratch)
+        };
+        match self.pool {
+            None => warm(&mut Scratch::new()),
+            Some(ref pool) => {
+                // leased, so that a panicking `on_segment` hook doesn't take
+                // the pool's matrices with it.
+                let all = mem::take(&mut *pool.free.lock().unwrap());
+                let mut leases: Vec<_> = all.into_iter()
+                                            .map(|scratch| {
+                                                Lease {
+                                                    pool,
+                                                    scratch: Some(scratch),
+                                                }
+                                            })
+                                            .collect();
+                for lease in leases.iter_mut() {
+                    warm(lease.scratch.as_mut().unwrap());
+                }
+            }
+        }
+    }
+
+    /// The wiping behavior of this `Argon2`.
+    pub fn flags(&self) -> Flags { self.flags }
+
+    /// The parameters of this `Argon2`, as a `Params`.
+    pub fn to_params(&self) -> Params {
+        Params::with_version(self.passes, self.lanes, self.kib, self.variant,
+                             self.version)
+            .unwrap()
+    }
+
     /// Runs the selected Argon2 variant over provided inputs, writing the final
     /// hash to the byte slice `out`. Note that the output length is assumed to
     /// be `out.len()` and must be between 4 and 2^32 - 1. The inputs are:
@@ -188,45 +495,344 @@ impl Argon2 {
         self.hash_impl(out, p, s, k, x, |_| {}, |_, _| {});
     }
 
-    #[cfg_attr(rustfmt, rustfmt_skip)]
-    fn hash_impl<F, G>(&self, out: &mut [u8], p: &[u8], s: &[u8], k: &[u8],
-                       x: &[u8], mut h0_fn: F, mut pass_fn: G)
+    /// Same as `Argon2::hash`, but with `variant` and `version` in place of
+    /// those of this `Argon2`, whose costs, flags, and pool are kept. A
+    /// service verifying a mix of legacy hashes can thus share one pool of
+    /// matrices across variants, rather than keep an `Argon2` and a pool per
+    /// variant.
+    #[allow(clippy::too_many_arguments)]
+    pub fn hash_with(&self, variant: Variant, version: Version, out: &mut [u8],
+                     p: &[u8], s: &[u8], k: &[u8], x: &[u8]) {
+        let a2 = Argon2 {
+            variant,
+            version,
+            pool: self.pool.clone(),
+            ..*self
+        };
+        a2.hash(out, p, s, k, x);
+    }
+
+    /// Same as `Argon2::hash`, but afterwards wipes `p` if this `Argon2` has
+    /// `Flags::CLEAR_PASSWORD` set and `k` if it has `Flags::CLEAR_SECRET` set,
+    /// as the reference implementation does.
+    pub fn hash_clearing(&self, out: &mut [u8], p: &mut [u8], s: &[u8],
+                         k: &mut [u8], x: &[u8]) {
+        self.hash(out, p, s, k, x);
+        if self.flags.contains(Flags::CLEAR_PASSWORD) {
+            wipe(p);
+        }
+        if self.flags.contains(Flags::CLEAR_SECRET) {
+            wipe(k);
+        }
+    }
+
+    /// Runs the memory-hard portion of Argon2 over the provided inputs,
+    /// stopping just short of the final H′ compression. The result can be sent
+    /// to a server holding the expected tag, which then needs only a cheap
+    /// `Unfinalized::verify` to check it (the "server relief" mode described in
+    /// the Argon2 paper). `taglen` is the length of the tag that will
+    /// eventually be produced, subject to the same bounds as `out.len()` in
+    /// `Argon2::hash`; the other inputs are as in `Argon2::hash`.
+    pub fn hash_unfinalized(&self, taglen: usize, p: &[u8], s: &[u8], k: &[u8],
+                            x: &[u8])
+                            -> Unfinalized {
+        let h0 = self.h0(taglen, p, s, k, x);
+        let block = self.with_scratch(|scratch| {
+            self.fill_matrix::<BlaMka, Spec, _>(h0, |_, _| {}, None, scratch)
+        });
+        Unfinalized {
+            taglen: taglen as u32,
+            block,
+        }
+    }
+
+    pub(crate) fn hash_impl<F, G>(&self, out: &mut [u8], p: &[u8], s: &[u8],
+                                  k: &[u8], x: &[u8], mut h0_fn: F,
+                                  pass_fn: G)
         where F: FnMut(&[u8]),
               G: FnMut(u32, &Matrix)
     {
-        assert!(4 <= out.len() && out.len() <= 0xffffffff);
-        assert!(p.len() <= 0xffffffff);
-        assert!(8 <= s.len()  && s.len() <= 0xffffffff);
-        assert!(k.len() <= 32);
-        assert!(x.len() <= 0xffffffff);
-
-        let mut blocks = Matrix::new(self.lanes, self.lanelen);
-        let h0 = h0(self.lanes, out.len() as u32, self.kib, self.passes,
-                    self.version as u32, self.variant, p, s, k, x);
+        let h0 = self.h0(out.len(), p, s, k, x);
         h0_fn(&h0);  // kats
+        let last = self.with_scratch(|scratch| {
+            self.fill_matrix::<BlaMka, Spec, _>(h0, pass_fn, None, scratch)
+        });
+        h_prime(out, last.as_u8());
+    }
+
+    // Runs `f` with a scratch from the pool, if there is one, waiting for one
+    // to be returned if need be.
+    fn with_scratch<T, F>(&self, f: F) -> T
+        where F: FnOnce(&mut Scratch) -> T
+    {
+        match self.pool {
+            None => f(&mut Scratch::new()),
+            Some(ref pool) => {
+                let mut lease = Lease {
+                    pool,
+                    scratch: Some(pool.take()),
+                };
+                f(lease.scratch.as_mut().unwrap())
+            }
+        }
+    }
 
-        let mut workers = Workers::new(self.lanes);
+    /// Same as `Argon2::hash`, but keeps a checksum of every block as it is
+    /// written, and verifies the checksums of the blocks it reads and, before
+    /// finalizing, of the whole matrix. This detects memory corruption, such
+    /// as bit flips in non-ECC RAM during a multi-gigabyte hash, that would
+    /// otherwise silently produce a wrong tag. On a mismatch, `out` is left
+    /// untouched and the first bad block is reported; hashing again is the
+    /// only remedy.
+    ///
+    /// Checksums take 8 bytes per KiB of memory, and the checks slow hashing
+    /// down by roughly a third. `Argon2::with_pool` is not used.
+    pub fn hash_checked(&self, out: &mut [u8], p: &[u8], s: &[u8], k: &[u8],
+                        x: &[u8])
+                        -> Result<(), CorruptionErr> {
+        let h0 = self.h0(out.len(), p, s, k, x);
+        let mut scratch = Scratch::checking();
+        let last = self.fill_matrix::<BlaMka, Spec, _>(h0, |_, _| {}, None,
+                                                       &mut scratch);
+        if let Some((lane, column)) = scratch.corrupt {
+            return Err(CorruptionErr { lane, column });
+        }
+        h_prime(out, last.as_u8());
+        Ok(())
+    }
 
-        workers.map(&mut blocks,
-                    &|bref, lane| self.fill_first_slice(bref, h0, lane));
+    /// Same as `Argon2::hash`, but first checks that the block matrix fits in
+    /// the memory available to this process, including under cgroup limits,
+    /// and fails without allocating if it doesn't. See `memcheck`.
+    pub fn try_hash(&self, out: &mut [u8], p: &[u8], s: &[u8], k: &[u8],
+                    x: &[u8])
+                    -> Result<(), InsufficientMemory> {
+        memcheck::check(&self.to_params())?;
+        self.hash(out, p, s, k, x);
+        Ok(())
+    }
 
-        // finish first pass. slices have to be filled in sync.
-        for slice in 1..SLICES_PER_LANE {
-            workers.map(&mut blocks,
-                        &|bref, lane| self.fill_slice(bref, 0, lane, slice, 0));
+    /// Same as `Argon2::hash`, but with the block matrix in `blocks` instead of
+    /// on the heap, and filled on the calling thread. This is for firmware
+    /// with small, fixed parameters; `BLOCKS` must be at least
+    /// `Params::blocks()` of this `Argon2`, or this panics. The blocks used
+    /// are wiped afterwards if `Flags::CLEAR_MEMORY` is set.
+    pub fn hash_fixed<const BLOCKS: usize>(&self, out: &mut [u8], p: &[u8],
+                                           s: &[u8], k: &[u8], x: &[u8],
+                                           blocks: &mut FixedMatrix<BLOCKS>) {
+        #[cfg(feature = "self-test")]
+        ::selftest::ensure();
+
+        assert!(self.lanes as usize * self.lanelen as usize <= BLOCKS,
+                "FixedMatrix is too small for these parameters");
+        #[cfg(feature = "metrics")]
+        let start = Instant::now();
+        let h0 = self.h0(out.len(), p, s, k, x);
+        let wipe = self.flags.contains(Flags::CLEAR_MEMORY);
+        // dropped, and so wiped, before `blocks` is released.
+        let mut matrix = unsafe {
+            Matrix::borrowed(blocks.blocks_mut(), self.lanes, self.lanelen,
+                             wipe)
+        };
+        self.fill_sequential::<BlaMka, Spec, _>(&mut matrix, h0, &mut ());
+        let last = matrix.xor_column(self.lanelen - 1);
+        drop(matrix);
+        #[cfg(feature = "metrics")]
+        ::telemetry::hashed(self.variant, start.elapsed());
+        h_prime(out, last.as_u8());
+    }
+
+    // Same as `hash`, but reusing the matrix and lane workers of `scratch`
+    // across calls. Cf. `batch`.
+    pub(crate) fn hash_in(&self, out: &mut [u8], p: &[u8], s: &[u8],
+                          k: &[u8], x: &[u8], scratch: &mut Scratch) {
+        let h0 = self.h0(out.len(), p, s, k, x);
+        let last = self.fill_matrix::<BlaMka, Spec, _>(h0, |_, _| {}, None,
+                                                       scratch);
+        h_prime(out, last.as_u8());
+    }
+
+    // Same as `hash`, but reports every block computation to `rec`. Lanes are
+    // filled one after the other so that `rec` sees a deterministic order.
+    #[cfg_attr(not(feature = "instrumentation"), allow(dead_code))]
+    pub(crate) fn hash_recorded(&self, out: &mut [u8], p: &[u8], s: &[u8],
+                                k: &[u8], x: &[u8], rec: &mut dyn Recorder) {
+        let h0 = self.h0(out.len(), p, s, k, x);
+        let last = self.fill_matrix::<BlaMka, Spec, _>(h0, |_, _| {},
+                                                       Some(rec),
+                                                       &mut Scratch::new());
+        h_prime(out, last.as_u8());
+    }
+
+    /// Same as `Argon2::hash`, but with `C` in place of the compression
+    /// function G of the Argon2 spec when computing blocks. Argon2i's index
+    /// generation still uses BlaMka. See `compression::Compression`.
+    #[cfg(feature = "internals")]
+    pub fn hash_with_compression<C>(&self, out: &mut [u8], p: &[u8], s: &[u8],
+                                    k: &[u8], x: &[u8])
+        where C: Compression
+    {
+        let h0 = self.h0(out.len(), p, s, k, x);
+        let last = self.fill_matrix::<C, Spec, _>(h0, |_, _| {}, None,
+                                                  &mut Scratch::new());
+        h_prime(out, last.as_u8());
+    }
+
+    /// Same as `Argon2::hash`, but with `I` in place of the indexing of the
+    /// Argon2 spec when choosing reference blocks. See `indexing::Indexing`.
+    #[cfg(feature = "research")]
+    pub fn hash_with_indexing<I>(&self, out: &mut [u8], p: &[u8], s: &[u8],
+                                 k: &[u8], x: &[u8])
+        where I: Indexing
+    {
+        let h0 = self.h0(out.len(), p, s, k, x);
+        let last = self.fill_matrix::<BlaMka, I, _>(h0, |_, _| {}, None,
+                                                    &mut Scratch::new());
+        h_prime(out, last.as_u8());
+    }
+
+    // Fills the block matrix and returns the xor of its last column, which is
+    // everything short of the final H′. The matrix and lane workers are taken
+    // from `scratch` if it holds suitable ones, and are left in it afterwards.
+    #[cfg_attr(rustfmt, rustfmt_skip)]
+    fn fill_matrix<C, I, G>(&self, h0: [u8; 72], mut pass_fn: G,
+                            rec: Option<&mut dyn Recorder>,
+                            scratch: &mut Scratch)
+                            -> Block
+        where C: Compression,
+              I: Indexing,
+              G: FnMut(u32, &Matrix)
+    {
+        #[cfg(feature = "self-test")]
+        ::selftest::ensure();
+        #[cfg(feature = "metrics")]
+        let start = Instant::now();
+
+        let wipe = self.flags.contains(Flags::CLEAR_MEMORY);
+        let (mut blocks, mut workers) = scratch.take(self.lanes, self.lanelen,
+                                                     wipe, self.stack_size,
+                                                     self.prefault);
+        if scratch.checked {
+            blocks.enable_checks();
         }
-        pass_fn(0, &blocks);  // kats
 
-        for p in 1..self.passes {
-            for slice in 0..SLICES_PER_LANE {
+        if let Some(rec) = rec {
+            // tracing is for research, not speed, so go lane by lane.
+            self.fill_sequential::<C, I, _>(&mut blocks, h0, rec);
+        } else {
+            #[cfg(feature = "metrics")]
+            let lane_times = ::telemetry::LaneTimes::new(self.lanes);
+
+            workers.map(&mut blocks, &|bref, lane| {
+                #[cfg(feature = "metrics")]
+                let _timer = lane_times.start(lane);
+                self.fill_first_slice::<C, I, _>(bref, h0, lane, &mut ())
+            });
+            blocks.check_canaries();
+            self.segment_done();
+
+            // finish first pass. slices have to be filled in sync.
+            for slice in 1..self.slices {
                 workers.map(&mut blocks, &|bref, lane| {
-                    self.fill_slice(bref, p, lane, slice, 0)
+                    #[cfg(feature = "metrics")]
+                    let _timer = lane_times.start(lane);
+                    self.fill_slice::<C, I, _>(bref, 0, lane, slice, 0,
+                                               &mut ())
                 });
+                blocks.check_canaries();
+                self.segment_done();
             }
-            pass_fn(p, &blocks);  // kats
+            pass_fn(0, &blocks);  // kats
+
+            for p in 1..self.passes {
+                for slice in 0..self.slices {
+                    workers.map(&mut blocks, &|bref, lane| {
+                        #[cfg(feature = "metrics")]
+                        let _timer = lane_times.start(lane);
+                        self.fill_slice::<C, I, _>(bref, p, lane, slice, 0,
+                                                   &mut ())
+                    });
+                    blocks.check_canaries();
+                    self.segment_done();
+                }
+                pass_fn(p, &blocks);  // kats
+            }
+            #[cfg(feature = "metrics")]
+            lane_times.report();
+        }
+        #[cfg(feature = "wipe_hooks")]
+        ::wipe_hooks::maybe_panic();
+
+        // serial on purpose: xoring a column costs about 40 ns per lane, while
+        // a `workers.map` costs microseconds per lane, so splitting the xor
+        // across workers was slower at every lane count up to 256 (measured
+        // with the threadpool; a tree reduction needs several maps).
+        if blocks.is_checked() {
+            blocks.check_all();
+            scratch.corrupt = blocks.corruption();
         }
+        let rv = blocks.xor_column(self.lanelen - 1);
+        scratch.put(blocks, workers, (self.lanes, self.stack_size));
+        #[cfg(feature = "metrics")]
+        ::telemetry::hashed(self.variant, start.elapsed());
+        rv
+    }
 
-        h_prime(out, &blocks.xor_column(self.lanelen - 1).as_u8());
+    // Fills the block matrix on the calling thread, one lane after the other.
+    fn fill_sequential<C, I, R>(&self, blocks: &mut Matrix, h0: [u8; 72],
+                                rec: &mut R)
+        where C: Compression,
+              I: Indexing,
+              R: Recorder + ?Sized
+    {
+        for lane in 0..self.lanes {
+            self.fill_first_slice::<C, I, _>(blocks, h0, lane, rec);
+            self.segment_done();
+        }
+        for slice in 1..self.slices {
+            for lane in 0..self.lanes {
+                self.fill_slice::<C, I, _>(blocks, 0, lane, slice, 0, rec);
+                self.segment_done();
+            }
+        }
+        rec.pass_done(0, blocks);
+        for p in 1..self.passes {
+            for slice in 0..self.slices {
+                for lane in 0..self.lanes {
+                    self.fill_slice::<C, I, _>(blocks, p, lane, slice, 0,
+                                               rec);
+                    self.segment_done();
+                }
+            }
+            rec.pass_done(p, blocks);
+        }
+    }
+
+    // H0 of the given inputs, with room for the block indices that
+    // `fill_first_slice` appends.
+    pub(crate) fn h0(&self, taglen: usize, p: &[u8], s: &[u8], k: &[u8],
+                     x: &[u8])
+                     -> [u8; 72] {
+        assert!(MIN_TAG_LEN <= taglen && taglen <= MAX_TAG_LEN);
+        assert!(p.len() <= MAX_INPUT_LEN);
+        assert!(MIN_SALT_LEN <= s.len() && s.len() <= MAX_INPUT_LEN);
+        assert!(k.len() <= MAX_SECRET_LEN);
+        assert!(x.len() <= MAX_INPUT_LEN);
+        h0(self.lanes, taglen as u32, self.kib, self.passes,
+           self.version as u32, self.variant, p, s, k, x)
+    }
+
+    // Fills segment (`lane`, `slice`) of pass `pass`, the same way that
+    // `fill_matrix` does. For `low_level`.
+    pub(crate) fn fill_segment(&self, blks: &mut Matrix, h0: [u8; 72],
+                               pass: u32, lane: u32, slice: u32) {
+        if pass == 0 && slice == 0 {
+            self.fill_first_slice::<BlaMka, Spec, _>(blks, h0, lane,
+                                                     &mut ());
+        } else {
+            self.fill_slice::<BlaMka, Spec, _>(blks, pass, lane, slice, 0,
+                                               &mut ());
+        }
     }
 
     // `Matrix` is an array of 1-KiB blocks and organized as follows:
@@ -245,10 +851,16 @@ impl Argon2 {
     //  where each `b` represents a 1-KiB block.
     //
     //  Some invariants:
-    //  - There are always four slices.
+    //  - There are always four slices (`self.slices`, which only differs
+    //    under `with_slices`).
     //  - `lanelen * lane = self.kib`.
     //  - Filling is done segment-by-segment.
-    fn fill_first_slice(&self, blks: &mut Matrix, mut h0: [u8; 72], lane: u32) {
+    fn fill_first_slice<C, I, R>(&self, blks: &mut Matrix, mut h0: [u8; 72],
+                                 lane: u32, rec: &mut R)
+        where C: Compression,
+              I: Indexing,
+              R: Recorder + ?Sized
+    {
         // fill the first (of four) slice
         h0[68..72].clone_from_slice(&as32le(lane));
 
@@ -257,47 +869,101 @@ impl Argon2 {
 
         h0[64..68].clone_from_slice(&as32le(1));
         h_prime(blks[(lane, 1)].as_u8_mut(), &h0);
+        if blks.is_checked() {
+            blks.seal((lane, 0));
+            blks.seal((lane, 1));
+        }
 
         // finish rest of first slice
-        self.fill_slice(blks, 0, lane, 0, 2);
+        self.fill_slice::<C, I, _>(blks, 0, lane, 0, 2, rec);
     }
 
     #[cfg_attr(rustfmt, rustfmt_skip)]
-    fn fill_slice(&self, blks: &mut Matrix, pass: u32, lane: u32, slice: u32,
-                  offset: u32) {
-        let mut jgen = Gen2i::new(offset as usize, pass, lane, slice,
-                                  self.lanes * self.lanelen, self.passes);
-        let slicelen = self.lanelen / SLICES_PER_LANE;
-
-        for idx in offset..slicelen {
-            let (j1, j2) = if self.variant == Variant::Argon2i {
-                jgen.nextj()
-            } else {
+    fn fill_slice<C, I, R>(&self, blks: &mut Matrix, pass: u32, lane: u32,
+                           slice: u32, offset: u32, rec: &mut R)
+        where C: Compression,
+              I: Indexing,
+              R: Recorder + ?Sized
+    {
+        let slicelen = self.lanelen / self.slices;
+        let independent = I::data_independent(self.variant, pass, slice,
+                                              self.slices);
+
+        if independent {
+            let mut jgen = Gen2i::new(offset as usize, pass, lane, slice,
+                                      self.lanes * self.lanelen, self.passes,
+                                      self.variant);
+            let mut idx = offset;
+            while idx < slicelen {
+                let pairs = jgen.next_pairs();
+                let n = pairs.len().min((slicelen - idx) as usize);
+                for &(j1, j2) in &pairs[..n] {
+                    let (wr, pre, zth) = self.fill_block::<C, I>(blks, pass,
+                                                                 lane, slice,
+                                                                 idx, j1, j2);
+                    rec.record(pass, wr, pre, zth);
+                    idx += 1;
+                }
+            }
+        } else {
+            for idx in offset..slicelen {
                 let col = self.prev(slice * slicelen + idx);
-                split_u64((blks[(lane, col)])[0].0)
-            };
-            self.fill_block(blks, pass, lane, slice, idx, j1, j2);
+                let (j1, j2) = split_u64((blks[(lane, col)])[0].0);
+                let (wr, pre, zth) = self.fill_block::<C, I>(blks, pass, lane,
+                                                             slice, idx, j1,
+                                                             j2);
+                rec.record(pass, wr, pre, zth);
+            }
         }
     }
 
-    fn fill_block(&self, blks: &mut Matrix, pass: u32, lane: u32, slice: u32,
-                  idx: u32, j1: u32, j2: u32) {
-        let slicelen = self.lanelen / SLICES_PER_LANE;
-        let ls = self.lanes;
-        let z = index_alpha(pass, lane, slice, ls, idx, slicelen, j1, j2);
-
-        let zth = match (pass, slice) {
-            (0, 0) => (lane, z),
-            _ => (j2 % self.lanes, z),
+    // Returns the coordinates of the written, previous, and reference blocks.
+    #[inline(always)]
+    fn fill_block<C, I>(&self, blks: &mut Matrix, pass: u32, lane: u32,
+                        slice: u32, idx: u32, j1: u32, j2: u32)
+                        -> ((u32, u32), (u32, u32), (u32, u32))
+        where C: Compression,
+              I: Indexing
+    {
+        let slicelen = self.lanelen / self.slices;
+        let pos = Position {
+            pass,
+            lane,
+            slice,
+            idx,
+            lanes: self.lanes,
+            slicelen,
+            slices: self.slices,
         };
+        let zth = I::reference(&pos, (j1, j2));
+        // lanes are filled concurrently, so a reference into a segment being
+        // written would be a data race. The spec's never are.
+        if TypeId::of::<I>() != TypeId::of::<Spec>() {
+            assert!(pos.may_reference(zth),
+                    "block {:?} may not reference {:?}",
+                    (lane, pos.column()), zth);
+        }
 
         let cur = (lane, slice * slicelen + idx);
         let pre = (lane, self.prev(cur.1));
+        let checked = blks.is_checked();
+        if checked {
+            blks.check(pre);
+            blks.check(zth);
+            if pass > 0 {
+                blks.check(cur);
+            }
+        }
         let (wr, rd, refblk) = blks.get3(cur, pre, zth);
+        let (x, y) = (rd.as_words(), refblk.as_words());
         match self.version {
-            Version::_0x10 => g(wr, rd, refblk),
-            Version::_0x13 => g_xor(wr, rd, refblk),
+            Version::_0x10 => C::compress(wr.as_words_mut(), x, y),
+            Version::_0x13 => C::compress_xor(wr.as_words_mut(), x, y),
+        }
+        if checked {
+            blks.seal(cur);
         }
+        (cur, pre, zth)
     }
 
     fn prev(&self, n: u32) -> u32 {
@@ -311,6 +977,137 @@ impl Argon2 {
     }
 }
 
+// A block matrix and lane workers kept between hashes, so that hashing many
+// times with the same dimensions allocates (and spawns threads) only once.
+// Plain `Argon2::hash` uses a fresh one each time, which keeps nothing.
+pub(crate) struct Scratch {
+    keep: bool,
+    // Whether to checksum blocks, and where the first bad one was found.
+    checked: bool,
+    corrupt: Option<(u32, u32)>,
+    blocks: Option<Matrix>,
+    // and the lanes and stack size they were spawned for.
+    workers: Option<((u32, Option<usize>), Workers)>,
+}
+
+impl Scratch {
+    pub fn new() -> Scratch {
+        Scratch {
+            keep: false,
+            checked: false,
+            corrupt: None,
+            blocks: None,
+            workers: None,
+        }
+    }
+
+    // A scratch that keeps what it is given.
+    pub fn keeping() -> Scratch { Scratch { keep: true, ..Scratch::new() } }
+
+    // A scratch that checksums every block. See `Argon2::hash_checked`.
+    fn checking() -> Scratch { Scratch { checked: true, ..Scratch::new() } }
+
+    // A scratch that keeps what it is given, starting out with a matrix and
+    // workers for the given dimensions.
+    fn allocated(lanes: u32, lanelen: u32, wipe: bool,
+                 stack_size: Option<usize>, prefault: bool)
+                 -> Scratch {
+        let mut blocks = Matrix::new(lanes, lanelen, wipe);
+        if prefault {
+            blocks.prefault();
+        }
+        Scratch {
+            blocks: Some(blocks),
+            workers: Some(((lanes, stack_size),
+                           Workers::new(lanes, stack_size))),
+            ..Scratch::keeping()
+        }
+    }
+
+    fn take(&mut self, lanes: u32, lanelen: u32, wipe: bool,
+            stack_size: Option<usize>, prefault: bool)
+            -> (Matrix, Workers) {
+        let blocks = match self.blocks.take() {
+            Some(ref b) if !b.fits(lanes, lanelen, wipe) => None,
+            b => b,
+        };
+        let workers = match self.workers.take() {
+            Some((key, w)) if key == (lanes, stack_size) => w,
+            _ => Workers::new(lanes, stack_size),
+        };
+        let blocks = blocks.unwrap_or_else(|| {
+            let mut blocks = Matrix::new(lanes, lanelen, wipe);
+            if prefault {
+                blocks.prefault();
+            }
+            blocks
+        });
+        (blocks, workers)
+    }
+
+    // Keeps `blocks` and `workers` if this scratch keeps anything. Kept
+    // matrices are wiped now if they would be on drop.
+    fn put(&mut self, mut blocks: Matrix, workers: Workers,
+           key: (u32, Option<usize>)) {
+        if self.keep {
+            blocks.clear_if_wiping();
+            self.blocks = Some(blocks);
+            self.workers = Some((key, workers));
+        }
+    }
+}
+
+// The matrices of `Argon2::with_pool`.
+struct Pool {
+    free: Mutex<Vec<Scratch>>,
+    returned: Condvar,
+}
+
+impl Pool {
+    fn take(&self) -> Scratch {
+        let mut free = self.free.lock().unwrap();
+        loop {
+            match free.pop() {
+                Some(scratch) => return scratch,
+                None => free = self.returned.wait(free).unwrap(),
+            }
+        }
+    }
+}
+
+// Returns its scratch to the pool on drop, even if hashing panicked.
+struct Lease<'a> {
+    pool: &'a Pool,
+    scratch: Option<Scratch>,
+}
+
+impl<'a> Drop for Lease<'a> {
+    fn drop(&mut self) {
+        if let Some(scratch) = self.scratch.take() {
+            // never poisoned: nothing panics while holding the lock.
+            self.pool.free.lock().unwrap().push(scratch);
+            self.pool.returned.notify_one();
+        }
+    }
+}
+
+// Observer of block computations, for `Argon2::hash_recorded`. Receives the
+// pass and the (lane, column) coordinates of the written, previous, and
+// reference blocks, then the matrix at the end of each pass. The unit impl
+// compiles away.
+pub(crate) trait Recorder {
+    fn record(&mut self, pass: u32, wr: (u32, u32), prev: (u32, u32),
+              refblk: (u32, u32));
+
+    #[inline(always)]
+    fn pass_done(&mut self, _pass: u32, _blocks: &Matrix) {}
+}
+
+impl Recorder for () {
+    #[inline(always)]
+    fn record(&mut self, _: u32, _: (u32, u32), _: (u32, u32), _: (u32, u32)) {}
+}
+
 /// Convenience wrapper around Argon2i for the majority of use cases where only
 /// a password and salt are supplied. Note that a salt between 8 and 2^32 - 1
 /// bytes must be provided.
@@ -331,7 +1128,95 @@ pub fn argon2d_simple(password: &str, salt: &str) -> [u8; defaults::LENGTH] {
     out
 }
 
-fn h_prime(out: &mut [u8], input: &[u8]) {
+/// The xor of the final column of the block matrix, as produced by
+/// `Argon2::hash_unfinalized`. This is the complete state of an Argon2 run
+/// prior to the final H′ compression that yields the tag, and is as sensitive
+/// as the tag itself. It is wiped on drop.
+pub struct Unfinalized {
+    taglen: u32,
+    block: Block,
+}
+
+impl Unfinalized {
+    /// Length of the serialized form returned by `Unfinalized::to_bytes`.
+    pub const ENCODED_LEN: usize = 4 + ARGON2_BLOCK_BYTES;
+
+    /// The length of the tag that this state finalizes to.
+    pub fn tag_len(&self) -> usize { self.taglen as usize }
+
+    /// Computes the final tag, writing it to `out`. `out.len()` must equal
+    /// `self.tag_len()`.
+    pub fn finalize(&self, out: &mut [u8]) {
+        assert_eq!(out.len(), self.tag_len());
+        h_prime(out, self.block.as_u8());
+    }
+
+    // Like `finalize`, but over `label` followed by the block, so that outputs
+    // under different labels are independent of each other and of the tag.
+    // `out` may be of any length permitted for tags.
+    pub(crate) fn finalize_labeled(&self, label: &[u8], out: &mut [u8]) {
+        assert!(MIN_TAG_LEN <= out.len() && out.len() <= MAX_TAG_LEN);
+        let mut input = Vec::with_capacity(label.len() + ARGON2_BLOCK_BYTES);
+        input.extend_from_slice(label);
+        input.extend_from_slice(self.block.as_u8());
+        h_prime(out, &input);
+        wipe(&mut input);
+    }
+
+    /// Finalizes this state and compares the result against `tag` in constant
+    /// time.
+    pub fn verify(&self, tag: &[u8]) -> bool {
+        if tag.len() != self.tag_len() {
+            return false;
+        }
+        let mut out = vec![0; self.tag_len()];
+        self.finalize(&mut out);
+        let rv = constant_eq(&out, tag);
+        wipe(&mut out);
+        #[cfg(feature = "metrics")]
+        ::telemetry::verified(rv);
+        rv
+    }
+
+    /// Serializes this state as the tag length (4 bytes, little endian)
+    /// followed by the 1 KiB block.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut rv = Vec::with_capacity(Self::ENCODED_LEN);
+        rv.extend_from_slice(&as32le(self.taglen));
+        rv.extend_from_slice(self.block.as_u8());
+        rv
+    }
+
+    /// Inverse of `Unfinalized::to_bytes`. Returns `None` if `bytes` is of the
+    /// wrong length or encodes an invalid tag length.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Unfinalized> {
+        if bytes.len() != Self::ENCODED_LEN {
+            return None;
+        }
+        let mut taglen = [0; 4];
+        taglen.clone_from_slice(&bytes[..4]);
+        let taglen = u32::from_le_bytes(taglen);
+        if taglen < 4 {
+            return None;
+        }
+        let mut block = block::zero();
+        block.as_u8_mut().clone_from_slice(&bytes[4..]);
+        Some(Unfinalized { taglen, block })
+    }
+}
+
+impl Drop for Unfinalized {
+    fn drop(&mut self) { wipe(self.block.as_u8_mut()); }
+}
+
+// Zeroes `bytes` in a way that the optimizer won't elide.
+pub(crate) fn wipe(bytes: &mut [u8]) {
+    for b in bytes.iter_mut() {
+        unsafe { ptr::write_volatile(b, 0) };
+    }
+}
+
+pub(crate) fn h_prime(out: &mut [u8], input: &[u8]) {
     if out.len() <= DEF_B2HASH_LEN {
         b2hash!(out; &len32(out), input);
     } else {
@@ -350,11 +1235,21 @@ fn h_prime(out: &mut [u8], input: &[u8]) {
     }
 }
 
+// The (lane, column) of the block referenced from `pos`, given pseudo-random
+// values `j1` and `j2`.
+pub(crate) fn reference(pos: &Position, (j1, j2): (u32, u32)) -> (u32, u32) {
+    let z = index_alpha(pos, j1, j2);
+    match (pos.pass, pos.slice) {
+        (0, 0) => (pos.lane, z),
+        _ => (j2 % pos.lanes, z),
+    }
+}
+
 // from opt.c
-fn index_alpha(pass: u32, lane: u32, slice: u32, lanes: u32, sliceidx: u32,
-               slicelen: u32, j1: u32, j2: u32)
-               -> u32 {
-    let lanelen = slicelen * SLICES_PER_LANE;
+pub(crate) fn index_alpha(pos: &Position, j1: u32, j2: u32) -> u32 {
+    let Position { pass, lane, slice, idx: sliceidx, lanes, slicelen, slices } =
+        *pos;
+    let lanelen = slicelen * slices;
     // All quotes below taken from Section 3.3 ("Indexing") of the Argon2 spec.
     let r: u32 = match (pass, slice, j2 % lanes == lane) {
         // "If we work with the first slice and the first pass, then l is the
@@ -378,52 +1273,79 @@ fn index_alpha(pass: u32, lane: u32, slice: u32, lanes: u32, sliceidx: u32,
     let (r_, j1_) = (r as u64, j1 as u64);
     let relpos = (r_ - 1 - (r_ * (j1_ * j1_ >> 32) >> 32)) as u32;
 
-    match (pass, slice) {
-        (0, _) | (_, 3) => relpos % lanelen,
+    match pass {
+        0 => relpos % lanelen,
         _ => (slicelen * (slice + 1) + relpos) % lanelen,
     }
 }
 
-struct Gen2i {
-    arg: Block,
-    pseudos: Block,
+// Argon2i's generator of pseudo-random values. Its input block is all zeros
+// past the seven words set in `new`, so only those are kept; see `g_two`. Each
+// address block is split into `(j1, j2)` pairs as soon as it is generated, so
+// that `fill_slice` can run through them without a branch per block.
+pub(crate) struct Gen2i {
+    arg: [u64x2; ARG_OCTWORDS],
+    pairs: [(u32, u32); per_kib!(u64)],
+    // the next pair to hand out, `pairs.len()` once all have been.
     idx: usize,
 }
 
+// The leading words of `Gen2i`'s input block that can be nonzero, in pairs.
+const ARG_OCTWORDS: usize = 4;
+
 impl Gen2i {
     #[cfg_attr(rustfmt, rustfmt_skip)]
-    fn new(start_at: usize, pass: u32, lane: u32, slice: u32, totblocks: u32,
-           totpasses: u32)
-           -> Gen2i {
-        use block::zero;
-
-        let mut rv = Gen2i { arg: zero(), pseudos: zero(), idx: start_at };
+    pub(crate) fn new(start_at: usize, pass: u32, lane: u32, slice: u32,
+                      totblocks: u32, totpasses: u32, variant: Variant)
+                      -> Gen2i {
+        let mut rv = Gen2i {
+            arg: [u64x2(0, 0); ARG_OCTWORDS],
+            pairs: [(0, 0); per_kib!(u64)],
+            idx: 0,
+        };
         let args = [(pass, lane), (slice, totblocks),
-                    (totpasses, Variant::Argon2i as u32)];
+                    (totpasses, variant as u32)];
         for (k, &(lo, hi)) in rv.arg.iter_mut().zip(args.into_iter()) {
             *k = u64x2(lo as u64, hi as u64);
         }
         rv.more();
+        rv.idx = start_at;
         rv
     }
 
     fn more(&mut self) {
         self.arg[3].0 += 1;
-        g_two(&mut self.pseudos, &self.arg);
+        let mut pseudos = block::zero();
+        g_two(&mut pseudos, &self.arg);
+        for (p, &w) in self.pairs.iter_mut().zip(pseudos.as_u64()) {
+            *p = split_u64(w);
+        }
+        self.idx = 0;
     }
 
-    fn nextj(&mut self) -> (u32, u32) {
-        let rv = split_u64(self.pseudos.as_u64()[self.idx]);
-        self.idx = (self.idx + 1) % per_kib!(u64);
-        if self.idx == 0 {
+    // One pair at a time, for `internals::IndexGen`.
+    #[cfg_attr(not(feature = "internals"), allow(dead_code))]
+    pub(crate) fn nextj(&mut self) -> (u32, u32) {
+        if self.idx == self.pairs.len() {
             self.more();
         }
-        rv
+        self.idx += 1;
+        self.pairs[self.idx - 1]
+    }
+
+    // The pairs left of the current address block, all of which count as
+    // handed out. The next call starts on a new block.
+    pub(crate) fn next_pairs(&mut self) -> &[(u32, u32)] {
+        if self.idx == self.pairs.len() {
+            self.more();
+        }
+        let start = mem::replace(&mut self.idx, self.pairs.len());
+        &self.pairs[start..]
     }
 }
 
 // g x y = let r = x `xor` y in p_col (p_row r) `xor` r,
-fn g(dest: &mut Block, lhs: &Block, rhs: &Block) {
+pub(crate) fn g(dest: &mut Block, lhs: &Block, rhs: &Block) {
     for (d, (l, r)) in dest.iter_mut().zip(lhs.iter().zip(rhs.iter())) {
         *d = *l ^ *r;
     }
@@ -441,7 +1363,7 @@ fn g(dest: &mut Block, lhs: &Block, rhs: &Block) {
 
 // Identical to `g`, except that instead of overwriting the old block with the
 // new one, they are xor-ed together.
-fn g_xor(dest: &mut Block, lhs: &Block, rhs: &Block) {
+pub(crate) fn g_xor(dest: &mut Block, lhs: &Block, rhs: &Block) {
     let mut tmp: Block = unsafe { mem::uninitialized() };
     let lr = lhs.iter().zip(rhs.iter());
     for ((d, t), (l, r)) in dest.iter_mut().zip(tmp.iter_mut()).zip(lr) {
@@ -461,9 +1383,13 @@ fn g_xor(dest: &mut Block, lhs: &Block, rhs: &Block) {
 }
 
 /// ``` g2 y = let g' y = g 0 y in g' . g' ```
-/// Used for data-independent index generation.
-fn g_two(dest: &mut Block, src: &Block) {
-    *dest = src.clone();
+/// Used for data-independent index generation. `arg` holds the leading words
+/// of `y`, and the rest are zero.
+fn g_two(dest: &mut Block, arg: &[u64x2; ARG_OCTWORDS]) {
+    *dest = block::zero();
+    for (i, a) in arg.iter().enumerate() {
+        dest[i] = *a;
+    }
 
     for row in 0..8 {
         p_row(row, dest);
@@ -472,7 +1398,9 @@ fn g_two(dest: &mut Block, src: &Block) {
         p_col(col, dest);
     }
 
-    *dest ^= src;
+    for (i, a) in arg.iter().enumerate() {
+        dest[i] = dest[i] ^ *a;
+    }
 
     let tmp: Block = dest.clone();
 
@@ -537,13 +1465,40 @@ fn p_col(col: usize, b: &mut Block) {
        b[8 * 4 + col], b[8 * 5 + col], b[8 * 6 + col], b[8 * 7 + col]);
 }
 
+// The permutation P of the Argon2 spec over 16 words (8 16-byte registers).
+#[cfg_attr(not(feature = "primitives"), allow(dead_code))]
+pub(crate) fn p_words(w: &mut [u64; 16]) {
+    let mut v = [u64x2(0, 0); 8];
+    for (v, w) in v.iter_mut().zip(w.chunks(2)) {
+        *v = u64x2(w[0], w[1]);
+    }
+    p!(v[0], v[1], v[2], v[3], v[4], v[5], v[6], v[7]);
+    for (v, w) in v.iter().zip(w.chunks_mut(2)) {
+        w[0] = v.0;
+        w[1] = v.1;
+    }
+}
+
+// P applied to each row and then each column of `b`, which is G without the
+// feed-forward.
+#[cfg_attr(not(feature = "primitives"), allow(dead_code))]
+pub(crate) fn p_block(b: &mut Block) {
+    for row in 0..8 {
+        p_row(row, b);
+    }
+    for col in 0..8 {
+        p_col(col, b);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs::File;
     use std::io::Read;
-    use super::Argon2;
+    use super::{Argon2, CorruptionErr, Unfinalized};
     use super::{Variant, Version};
-    use block;
+    use block::{self, FixedMatrix, Matrix};
+    use octword::u64x2;
     use std::fmt::Write;
 
     // from genkat.c
@@ -567,14 +1522,6 @@ mod tests {
 
     }
 
-    fn block_info(i: usize, b: &block::Block) -> String {
-        let blk = b.as_u64();
-        blk.iter().enumerate().fold(String::new(), |xs, (j, octword)| {
-            xs + "Block " + &format!("{:004} ", i) + &format!("[{:>3}]: ", j) +
-            &format!("{:0016x}", octword) + "\n"
-        })
-    }
-
     fn run_and_collect(arg: &Argon2, out: &mut [u8], p: &[u8], s: &[u8],
                        k: &[u8], x: &[u8])
                        -> (String, String) {
@@ -591,9 +1538,9 @@ mod tests {
 
             let passfn = |p: u32, matrix: &block::Matrix| {
                 wl!(&mut blockoutput, "\n After pass {}:", p);
-                for (i, block) in matrix.iter().enumerate() {
-                    w!(&mut blockoutput, "{}", block_info(i, block));
-                }
+                let mut dump = vec![];
+                matrix.dump(&mut dump).unwrap();
+                w!(&mut blockoutput, "{}", String::from_utf8(dump).unwrap());
             };
 
             arg.hash_impl(out, p, s, k, x, h0fn, passfn);
@@ -633,12 +1580,321 @@ mod tests {
         }
     }
 
+    #[test]
+    fn unfinalized_roundtrip() {
+        let a2 = Argon2::new(1, 2, 16, Variant::Argon2i).unwrap();
+        let (p, s) = (b"password", b"somesalt");
+        let mut expected = [0; 40];
+        a2.hash(&mut expected, p, s, &[], &[]);
+
+        let state = a2.hash_unfinalized(expected.len(), p, s, &[], &[]);
+        let bytes = state.to_bytes();
+        assert_eq!(bytes.len(), Unfinalized::ENCODED_LEN);
+        let state = Unfinalized::from_bytes(&bytes).unwrap();
+        let mut out = [0; 40];
+        state.finalize(&mut out);
+        assert_eq!(&out[..], &expected[..]);
+        assert!(state.verify(&expected));
+        assert!(!state.verify(&expected[..32]));
+
+        assert!(Unfinalized::from_bytes(&bytes[1..]).is_none());
+    }
+
+    #[test]
+    fn small_stack() {
+        // Filling a lane takes a few KiB of stack in optimized builds: the
+        // pairs of an address block in `Gen2i`, the block they are split from,
+        // and one temporary in `g_xor` or `g_two`, none of which grows with
+        // the parameters. Unoptimized builds spill much
+        // more, about 100 KiB in all.
+        let stack = if cfg!(debug_assertions) { 128 << 10 } else { 16 << 10 };
+        for &v in [Variant::Argon2i, Variant::Argon2id].iter() {
+            let a2 = Argon2::new(2, 1, 64, v).unwrap();
+            let mut exp = [0; 32];
+            a2.hash(&mut exp, b"password", b"somesalt", &[], &[]);
+            let out = ::std::thread::Builder::new()
+                          .stack_size(stack)
+                          .spawn(move || {
+                              let mut out = [0; 32];
+                              a2.hash(&mut out, b"password", b"somesalt", &[],
+                                      &[]);
+                              out
+                          })
+                          .unwrap()
+                          .join()
+                          .unwrap();
+            assert_eq!(out, exp);
+        }
+    }
+
+    #[test]
+    fn block_hex() {
+        let mut b = block::zero();
+        b.as_u8_mut()[0] = 0xab;
+        b.as_u8_mut()[1023] = 0x01;
+        let hex = format!("{:x}", b);
+        assert_eq!(hex.len(), 128 * 17 - 1);
+        assert!(hex.starts_with("00000000000000ab 0000000000000000 "));
+        assert!(hex.ends_with(" 0100000000000000"));
+        assert_eq!(format!("{:?}", b), format!("Block({})", hex));
+    }
+
+    #[test]
+    fn fixed() {
+        let mut blocks = FixedMatrix::<32>::new();
+        for &(lanes, kib) in [(1, 8), (2, 16), (4, 32)].iter() {
+            let a2 = Argon2::new(2, lanes, kib, Variant::Argon2id).unwrap();
+            let (mut exp, mut out) = ([0; 32], [0; 32]);
+            a2.hash(&mut exp, b"password", b"somesalt", &[], &[]);
+            a2.hash_fixed(&mut out, b"password", b"somesalt", &[], &[],
+                          &mut blocks);
+            assert_eq!(out, exp);
+        }
+        // wiped afterwards.
+        let zero = block::zero();
+        assert!(blocks.blocks_mut().iter().all(|b| b.as_u8() == zero.as_u8()));
+    }
+
+    #[test]
+    #[should_panic(expected = "too small")]
+    fn fixed_too_small() {
+        let a2 = Argon2::new(1, 1, 16, Variant::Argon2id).unwrap();
+        a2.hash_fixed(&mut [0; 32], b"password", b"somesalt", &[], &[],
+                      &mut FixedMatrix::<8>::new());
+    }
+
+    #[test]
+    fn pooled() {
+        fn assert_sync<T: Sync>() {}
+        assert_sync::<Argon2>();
+
+        let a2 = Argon2::new(1, 2, 16, Variant::Argon2id).unwrap();
+        let pooled = Argon2::new(1, 2, 16, Variant::Argon2id).unwrap()
+                         .with_pool(2);
+        ::std::thread::scope(|sc| {
+            for i in 0..4u8 {
+                let (a2, pooled) = (&a2, &pooled);
+                sc.spawn(move || {
+                    let (mut exp, mut out) = ([0; 32], [0; 32]);
+                    a2.hash(&mut exp, &[i], b"somesalt", &[], &[]);
+                    pooled.hash(&mut out, &[i], b"somesalt", &[], &[]);
+                    assert_eq!(out, exp);
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn stack_size() {
+        let a2 = Argon2::new(2, 4, 32, Variant::Argon2id).unwrap();
+        let (mut exp, mut out) = ([0; 32], [0; 32]);
+        a2.hash(&mut exp, b"password", b"somesalt", &[], &[]);
+        let small = Argon2::new(2, 4, 32, Variant::Argon2id).unwrap()
+                        .with_stack_size(128 * 1024);
+        small.hash(&mut out, b"password", b"somesalt", &[], &[]);
+        assert_eq!(out, exp);
+        let pooled = small.with_pool(1);
+        for _ in 0..2 {
+            out = [0; 32];
+            pooled.hash(&mut out, b"password", b"somesalt", &[], &[]);
+            assert_eq!(out, exp);
+        }
+    }
+
+    #[test]
+    fn prefault() {
+        let a2 = Argon2::new(2, 2, 32, Variant::Argon2id).unwrap();
+        let (mut exp, mut out) = ([0; 32], [0; 32]);
+        a2.hash(&mut exp, b"password", b"somesalt", &[], &[]);
+        assert!(!a2.prefault);
+        let a2 = a2.with_prefault();
+        a2.hash(&mut out, b"password", b"somesalt", &[], &[]);
+        assert_eq!(out, exp);
+        let pooled = a2.with_pool(1);
+        for _ in 0..2 {
+            out = [0; 32];
+            pooled.hash(&mut out, b"password", b"somesalt", &[], &[]);
+            assert_eq!(out, exp);
+        }
+    }
+
+    #[test]
+    fn warm_up() {
+        let a2 = Argon2::new(1, 2, 16, Variant::Argon2id).unwrap();
+        let (mut exp, mut out) = ([0; 32], [0; 32]);
+        a2.warm_up();
+        a2.hash(&mut exp, b"password", b"somesalt", &[], &[]);
+        let pooled = Argon2::new(1, 2, 16, Variant::Argon2id).unwrap()
+                         .with_pool(3);
+        pooled.warm_up();
+        assert_eq!(pooled.pool.as_ref().unwrap().free.lock().unwrap().len(),
+                   3);
+        pooled.hash(&mut out, b"password", b"somesalt", &[], &[]);
+        assert_eq!(out, exp);
+    }
+
+    #[test]
+    fn hash_with() {
+        let pooled = Argon2::new(1, 2, 16, Variant::Argon2i).unwrap()
+                         .with_pool(1);
+        for &v in [Variant::Argon2d, Variant::Argon2i, Variant::Argon2id]
+                      .iter() {
+            for &vers in [Version::_0x10, Version::_0x13].iter() {
+                let a2 = Argon2::with_version(1, 2, 16, v, vers).unwrap();
+                let (mut exp, mut out) = ([0; 32], [0; 32]);
+                a2.hash(&mut exp, b"password", b"somesalt", &[], &[]);
+                pooled.hash_with(v, vers, &mut out, b"password", b"somesalt",
+                                 &[], &[]);
+                assert_eq!(out, exp);
+            }
+        }
+        assert_eq!(pooled.pool.as_ref().unwrap().free.lock().unwrap().len(),
+                   1);
+    }
+
+    #[cfg(feature = "research")]
+    #[test]
+    fn with_slices() {
+        let a2 = || Argon2::new(2, 2, 36, Variant::Argon2id).unwrap();
+        let hash = |a2: &Argon2| {
+            let mut out = [0; 32];
+            a2.hash(&mut out, b"password", b"somesalt", &[], &[]);
+            out
+        };
+        assert_eq!(hash(&a2().with_slices(4).unwrap()), hash(&a2()));
+        for &slices in [2, 3, 6, 9].iter() {
+            let research = a2().with_slices(slices).unwrap();
+            assert_eq!(research.lanelen, 18 / slices * slices);
+            assert!(hash(&research) != hash(&a2()));
+        }
+        assert_eq!(a2().with_slices(10).err(),
+                   Some(super::ParamErr::MinKiB(40)));
+    }
+
+    #[test]
+    fn on_segment() {
+        use std::cell::Cell;
+        thread_local!(static SEGMENTS: Cell<u32> = const { Cell::new(0) });
+        fn count() { SEGMENTS.with(|s| s.set(s.get() + 1)); }
+
+        let a2 = Argon2::new(3, 2, 16, Variant::Argon2id).unwrap();
+        let (mut exp, mut out) = ([0; 32], [0; 32]);
+        a2.hash(&mut exp, b"password", b"somesalt", &[], &[]);
+        let a2 = a2.on_segment(count);
+        a2.hash(&mut out, b"password", b"somesalt", &[], &[]);
+        assert_eq!(out, exp);
+        assert_eq!(SEGMENTS.with(|s| s.get()), 3 * 4);
+
+        let mut blocks = FixedMatrix::<16>::new();
+        a2.hash_fixed(&mut out, b"password", b"somesalt", &[], &[],
+                      &mut blocks);
+        assert_eq!(out, exp);
+        assert_eq!(SEGMENTS.with(|s| s.get()), 3 * 4 + 3 * 4 * 2);
+    }
+
+    #[test]
+    fn abandoned() {
+        use std::cell::Cell;
+        use std::panic::{self, AssertUnwindSafe};
+        thread_local!(static ABANDON: Cell<bool> = const { Cell::new(true) });
+        fn abandon() {
+            if ABANDON.with(|a| a.get()) {
+                panic!("abandoned");
+            }
+        }
+
+        let pooled = Argon2::new(1, 2, 16, Variant::Argon2id).unwrap()
+                         .on_segment(abandon)
+                         .with_pool(2);
+        let mut out = [0; 32];
+        let hash = || {
+            pooled.hash(&mut out, b"password", b"somesalt", &[], &[])
+        };
+        assert!(panic::catch_unwind(AssertUnwindSafe(hash)).is_err());
+        let warm = || pooled.warm_up();
+        assert!(panic::catch_unwind(AssertUnwindSafe(warm)).is_err());
+        let free = || pooled.pool.as_ref().unwrap().free.lock().unwrap().len();
+        assert_eq!(free(), 2);
+
+        ABANDON.with(|a| a.set(false));
+        let mut exp = [0; 32];
+        Argon2::new(1, 2, 16, Variant::Argon2id)
+            .unwrap()
+            .hash(&mut exp, b"password", b"somesalt", &[], &[]);
+        pooled.hash(&mut out, b"password", b"somesalt", &[], &[]);
+        assert_eq!(out, exp);
+        assert_eq!(free(), 2);
+    }
+
+    #[test]
+    fn hash_checked() {
+        for &v in [Variant::Argon2i, Variant::Argon2d, Variant::Argon2id]
+                      .iter() {
+            let a2 = Argon2::new(2, 2, 16, v).unwrap();
+            let (mut exp, mut out) = ([0; 32], [0; 32]);
+            a2.hash(&mut exp, b"password", b"somesalt", &[], &[]);
+            a2.hash_checked(&mut out, b"password", b"somesalt", &[], &[])
+                .unwrap();
+            assert_eq!(out, exp);
+        }
+    }
+
+    #[test]
+    fn corrupted_block() {
+        let mut m = Matrix::new(2, 8, false);
+        m.enable_checks();
+        for lane in 0..2 {
+            for col in 0..8 {
+                m[(lane, col)][3] = u64x2(lane as u64, col as u64);
+                m.seal((lane, col));
+            }
+        }
+        m.check_all();
+        assert_eq!(m.corruption(), None);
+
+        m[(1, 5)][7].0 ^= 1 << 40;
+        m[(1, 6)][0].1 ^= 1;
+        m.check((0, 2));
+        assert_eq!(m.corruption(), None);
+        m.check_all();
+        assert_eq!(m.corruption(), Some((1, 5)));
+        let err = CorruptionErr { lane: 1, column: 5 };
+        assert_eq!(err.to_string(),
+                   "Block matrix corrupted at lane 1, column 5.");
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "canary after lane 1")]
+    fn overrun_lane() {
+        let mut m = Matrix::new(3, 8, false);
+        m.check_canaries();
+        // as a lane that writes one block past its end would.
+        let last: *mut block::Block = &mut m[(1, 7)];
+        unsafe { *last.add(1) = block::zero() };
+        m.check_canaries();
+    }
+
     #[test]
     fn argon2i_kat() {
         compare_kats("kats/0x10/argon2i", Variant::Argon2i, Version::_0x10);
         compare_kats("kats/0x13/argon2i", Variant::Argon2i, Version::_0x13);
     }
 
+    #[test]
+    fn argon2id_kat() {
+        // RFC 9106, section 5.3. Same inputs as `compare_kats`.
+        let a2 = Argon2::new(3, 4, 32, Variant::Argon2id).unwrap();
+        let mut out = [0; TEST_OUTLEN];
+        a2.hash(&mut out, &[1; TEST_PWDLEN], &[2; TEST_SALTLEN],
+                &[3; TEST_SECRETLEN], &[4; TEST_ADLEN]);
+        assert_eq!(out,
+                   [0x0d, 0x64, 0x0d, 0xf5, 0x8d, 0x78, 0x76, 0x6c, 0x08, 0xc0,
+                    0x37, 0xa3, 0x4a, 0x8b, 0x53, 0xc9, 0xd0, 0x1e, 0xf0, 0x45,
+                    0x2d, 0x75, 0xb6, 0x5e, 0xb5, 0x25, 0x20, 0xe9, 0x6b, 0x01,
+                    0xe6, 0x59]);
+    }
+
     #[test]
     fn argon2d_kat() {
         compare_kats("kats/0x10/argon2d", Variant::Argon2d, Version::_0x10);
@@ -0,0 +1,129 @@
+//! Attestation that this build of the crate computes Argon2 exactly as the
+//! reference implementation does.
+//!
+//! The backend that fills memory is chosen at compile time: SIMD or scalar
+//! kernels, threaded or sequential lanes, and one of several Blake2b
+//! implementations. `attestation_digest` runs a fixed battery of hashes
+//! through whichever was compiled in and condenses the tags into one digest,
+//! so that a deployment can compare it against `EXPECTED_DIGEST` (or against
+//! the same battery run through another implementation) before taking
+//! traffic.
+//!
+//! The battery covers every variant, both versions, one to four lanes,
+//! Argon2i address blocks spanning several segments, and tags shorter than,
+//! equal to, and longer than one Blake2b digest. For case `i` of `CASES`, in
+//! order, the password is `i + 1` bytes of `i`, the salt 16 bytes of `0x02`,
+//! the secret 8 bytes of `0x03` and the associated data 12 bytes of `0x04`.
+//! The digest is the 32-byte Blake2b hash of, for each case, the variant
+//! (0 for Argon2d, 1 for Argon2i, 2 for Argon2id), version, passes, lanes,
+//! KiB, and tag length as little-endian 32-bit integers, followed by the tag.
+//!
+//! The battery takes tens of milliseconds, which is fine once per deployment
+//! but not for a probe that runs every few seconds. `health_check` instead
+//! computes one known hash of 8 KiB and a single pass, in well under a
+//! millisecond.
+
+use std::error::Error;
+use std::fmt;
+use argon2::{Argon2, Variant, Version};
+use blake2::Blake2b;
+
+/// `attestation_digest()` of a correct implementation, as computed by
+/// RustCrypto's `argon2` crate: see benches/ecosystem/attestation.rs.
+pub const EXPECTED_DIGEST: [u8; 32] =
+    [0x8b, 0x90, 0x33, 0x5b, 0x29, 0x32, 0x69, 0x41, 0x5a, 0x66,
+     0x25, 0x59, 0xc8, 0xca, 0x93, 0x08, 0x2f, 0xee, 0x1f, 0xf1,
+     0x0f, 0xe6, 0xba, 0x36, 0x2d, 0x8e, 0x11, 0x70, 0x99, 0xfc,
+     0x73, 0x5f];
+
+// Argon2id of "password" and "somesalt", with 8 KiB, one pass and one lane.
+const HEALTH_TAG: [u8; 32] =
+    [0xf1, 0x37, 0xf8, 0xe1, 0x86, 0xa4, 0x03, 0xa6, 0x79, 0xcc, 0xd0,
+     0x60, 0x6e, 0x5a, 0xb5, 0xdc, 0xda, 0xfe, 0x43, 0xc1, 0x64, 0x08,
+     0x55, 0xac, 0x8c, 0x6e, 0x33, 0xe9, 0xbd, 0x63, 0xee, 0xb3];
+
+// (passes, lanes, kib, tag length), each run for every variant and version.
+const CASES: [(u32, u32, u32, usize); 5] = [(1, 1, 8, 4),
+                                            (3, 4, 32, 32),
+                                            (2, 3, 96, 64),
+                                            (1, 1, 2048, 65),
+                                            (2, 4, 4096, 100)];
+
+const VARIANTS: [Variant; 3] = [Variant::Argon2d, Variant::Argon2i,
+                                Variant::Argon2id];
+
+const VERSIONS: [Version; 2] = [Version::_0x10, Version::_0x13];
+
+/// Runs the attestation battery and returns its digest. This hashes about
+/// 37 MiB in total, which takes tens of milliseconds.
+pub fn attestation_digest() -> [u8; 32] {
+    let mut b = Blake2b::new(32);
+    let mut i = 0u8;
+    for &variant in VARIANTS.iter() {
+        for &version in VERSIONS.iter() {
+            for &(passes, lanes, kib, taglen) in CASES.iter() {
+                let a2 = Argon2::with_version(passes, lanes, kib, variant,
+                                              version)
+                             .unwrap();
+                let mut tag = vec![0; taglen];
+                a2.hash(&mut tag, &vec![i; i as usize + 1], &[2; 16], &[3; 8],
+                        &[4; 12]);
+                for &n in [variant as u32, version as u32, passes, lanes, kib,
+                           taglen as u32]
+                              .iter() {
+                    b.update(&n.to_le_bytes());
+                }
+                b.update(&tag);
+                i += 1;
+            }
+        }
+    }
+    let mut out = [0; 32];
+    b.finalize_into(&mut out);
+    out
+}
+
+/// Whether `attestation_digest()` is `EXPECTED_DIGEST`.
+pub fn attest() -> bool { attestation_digest() == EXPECTED_DIGEST }
+
+/// Returned by `health_check` when hashing gave a wrong result, or with the
+/// `self-test` feature, when the power-on self-test failed.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct HealthCheckFailed;
+
+impl fmt::Display for HealthCheckFailed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Argon2 health check failed.")
+    }
+}
+
+impl Error for HealthCheckFailed {}
+
+/// Computes a tiny known-answer hash, for the readiness probes of services
+/// whose core function is verifying passwords. Never panics.
+pub fn health_check() -> Result<(), HealthCheckFailed> {
+    // hashing would panic on a failed self-test.
+    #[cfg(feature = "self-test")]
+    ::selftest::status().map_err(|_| HealthCheckFailed)?;
+
+    let a2 = Argon2::new(1, 1, 8, Variant::Argon2id).unwrap();
+    let mut out = [0; 32];
+    a2.hash(&mut out, b"password", b"somesalt", &[], &[]);
+    match out == HEALTH_TAG {
+        true => Ok(()),
+        false => Err(HealthCheckFailed),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EXPECTED_DIGEST, attestation_digest, health_check};
+
+    #[test]
+    fn attestation() {
+        assert_eq!(attestation_digest(), EXPECTED_DIGEST);
+    }
+
+    #[test]
+    fn healthy() { assert_eq!(health_check(), Ok(())); }
+}
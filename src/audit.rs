@@ -0,0 +1,126 @@
+//! A process-wide callback on verification outcomes, for streaming
+//! authentication events into a SIEM or audit log without wrapping every call
+//! site.
+//!
+//! Once `set_hook` has installed a hook, it is called after every
+//! verification of an `Encoded` (including through `KeyRing`,
+//! `VersionPolicy`, and `batch::verify_many`) with a `VerifyEvent`. Events
+//! describe the stored hash and the outcome; they never contain the password,
+//! the tag, or the salt. Verifications rejected before hashing, such as those
+//! of hashes made by `Encoded::wrap_legacy` through `Encoded::verify`, are
+//! reported too, with `hashed` unset. So is `Encoded::verify_dummy`, as a
+//! failure without a key id, so that hooks cost unknown accounts as much time
+//! as known ones.
+//!
+//! Hooks run on the verifying thread, after the comparison, and should be
+//! quick: a slow hook delays the answer to the login it reports.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use params::Params;
+
+/// One verification, as reported to the hook.
+#[derive(Debug, Clone)]
+pub struct VerifyEvent<'a> {
+    /// The parameters of the stored hash.
+    pub params: Params,
+    /// The key id of the stored hash, empty if it has none.
+    pub keyid: &'a [u8],
+    /// The legacy scheme that the stored hash wraps, if any.
+    pub legacy_scheme: Option<&'a str>,
+    /// Whether the password matched.
+    pub success: bool,
+    /// Whether the password was hashed at all.
+    pub hashed: bool,
+    /// The time taken, from the start of the verification to the comparison.
+    pub duration: Duration,
+}
+
+type Hook = Arc<dyn Fn(&VerifyEvent) + Send + Sync>;
+
+static HOOK: RwLock<Option<Hook>> = RwLock::new(None);
+// Whether `HOOK` is set, so that verifications only read the clock if they
+// will be reported (it panics on some wasm targets).
+static HOOKED: AtomicBool = AtomicBool::new(false);
+
+/// Installs `hook`, replacing any previous one, to be called after every
+/// verification from now on.
+pub fn set_hook<F>(hook: F)
+    where F: Fn(&VerifyEvent) + Send + Sync + 'static
+{
+    *HOOK.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(hook));
+    HOOKED.store(true, Ordering::SeqCst);
+}
+
+/// Removes the hook, if any.
+pub fn clear_hook() {
+    *HOOK.write().unwrap_or_else(|e| e.into_inner()) = None;
+    HOOKED.store(false, Ordering::SeqCst);
+}
+
+// Times a verification if there is a hook to report it to.
+pub(crate) struct Timer(Option<Instant>);
+
+impl Timer {
+    pub(crate) fn start() -> Timer {
+        Timer(match HOOKED.load(Ordering::Relaxed) {
+            true => Some(Instant::now()),
+            false => None,
+        })
+    }
+
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.0.map_or(Duration::from_secs(0), |t| t.elapsed())
+    }
+}
+
+pub(crate) fn report(event: &VerifyEvent) {
+    if !HOOKED.load(Ordering::Relaxed) {
+        return;
+    }
+    // cloned out so that a hook may itself verify, or replace the hook.
+    let hook = HOOK.read().unwrap_or_else(|e| e.into_inner()).clone();
+    if let Some(hook) = hook {
+        hook(event);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use super::{clear_hook, set_hook};
+    use argon2::{Argon2, Variant};
+    use keyring::KeyRing;
+    use verifier::Encoded;
+
+    #[test]
+    fn events() {
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2i).unwrap();
+        let params = a2.to_params();
+        let ring = KeyRing::new(b"audit-test", b"pepper").unwrap();
+        let enc = ring.encode(a2, b"password", b"somesalt", &[]);
+
+        // other tests verify concurrently, so only keep events of this one.
+        let dummy = Argon2::new(1, 1, 24, Variant::Argon2d).unwrap();
+        let dummy_params = dummy.to_params();
+        let seen = Arc::new(Mutex::new(vec![]));
+        let sink = seen.clone();
+        set_hook(move |e| {
+            if e.keyid == b"audit-test" ||
+               e.keyid.is_empty() && e.params == dummy_params {
+                sink.lock().unwrap().push((e.params, e.success, e.hashed));
+            }
+        });
+        assert!(ring.verify(&enc, b"password"));
+        assert!(!ring.verify(&enc, b"wrong"));
+        assert!(!Encoded::verify_dummy(&dummy, b"password"));
+        clear_hook();
+        assert!(ring.verify(&enc, b"password"));
+
+        assert_eq!(*seen.lock().unwrap(),
+                   vec![(params, true, true),
+                        (params, false, true),
+                        (dummy_params, false, true)]);
+    }
+}
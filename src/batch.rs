@@ -0,0 +1,76 @@
+//! Verifying large numbers of hashes at once, as credential migrations and
+//! audits do.
+//!
+//! `verify_many` runs verifications on a fixed number of threads, each of
+//! which keeps its block matrix and lane workers from one hash to the next
+//! whenever the parameters stay the same, so that a table of uniformly hashed
+//! passwords costs one allocation per thread rather than one per hash.
+//! Results are streamed back as they complete.
+
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, IntoIter};
+use std::thread;
+use argon2::Scratch;
+use verifier::Encoded;
+
+/// Verifies each `(encoded, password)` pair of `pairs` on `concurrency`
+/// threads (at least one), as `Encoded::verify` would. Returns an iterator
+/// over `(index, verified)`, where `index` is the position of the pair in
+/// `pairs`. Results arrive in order of completion, not necessarily in input
+/// order. Dropping the iterator early stops the remaining verifications.
+pub fn verify_many<I, P>(pairs: I, concurrency: usize) -> IntoIter<(usize, bool)>
+    where I: IntoIterator<Item = (Encoded, P)>,
+          I::IntoIter: Send + 'static,
+          P: AsRef<[u8]>
+{
+    let pairs = Arc::new(Mutex::new(pairs.into_iter().enumerate()));
+    let (tx, rx) = mpsc::channel();
+    for _ in 0..concurrency.max(1) {
+        let (pairs, tx) = (pairs.clone(), tx.clone());
+        thread::spawn(move || {
+            let mut scratch = Scratch::keeping();
+            loop {
+                let next = pairs.lock().unwrap().next();
+                let (i, (enc, p)) = match next {
+                    Some(pair) => pair,
+                    None => break,
+                };
+                let ok = enc.verify_in(p.as_ref(), &mut scratch);
+                if tx.send((i, ok)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    rx.into_iter()
+}
+
+#[cfg(test)]
+mod test {
+    use super::verify_many;
+    use argon2::{Argon2, Variant};
+    use verifier::Encoded;
+
+    #[test]
+    fn matches_verify() {
+        let mut pairs = vec![];
+        for i in 0..12 {
+            let lanes = 1 + i % 3 / 2;
+            let a2 = Argon2::new(1, lanes, 16, Variant::Argon2i).unwrap();
+            let pw = format!("password {}", i);
+            let enc = Encoded::hash(a2, pw.as_bytes(), b"saltsalt");
+            // every third password is wrong.
+            let attempt = if i % 3 == 0 { "wrong".to_string() } else { pw };
+            pairs.push((enc, attempt));
+        }
+        let exp: Vec<bool> = pairs.iter()
+                                  .map(|(enc, p)| enc.verify(p.as_bytes()))
+                                  .collect();
+
+        let mut got: Vec<(usize, bool)> = verify_many(pairs, 3).collect();
+        got.sort();
+        assert_eq!(got.into_iter().map(|(_, ok)| ok).collect::<Vec<_>>(),
+                   exp);
+        assert_eq!(exp.iter().filter(|&&ok| ok).count(), 8);
+    }
+}
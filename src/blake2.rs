@@ -0,0 +1,73 @@
+//! The Blake2b implementation behind H0 and H′, selected at compile time:
+//! blake2b_simd with the `blake2b_simd` feature, and blake2-rfc otherwise or
+//! with `minimal`.
+//! Initial block generation runs H′ to 1 KiB twice per lane, which is a
+//! noticeable share of hashes with little memory, so the faster backend can
+//! pay off there. All produce identical digests.
+//!
+//! The `vendored_blake2b` feature overrides both with the plain RFC 7693
+//! implementation in `vendored`, as does building without the default
+//! `blake2-rfc` dependency, so that the crate can be audited and built without
+//! outside cryptographic code.
+
+pub use self::backend::Blake2b;
+
+#[cfg(any(test, feature = "vendored_blake2b",
+          all(not(feature = "blake2-rfc"),
+              any(not(feature = "blake2b_simd"), feature = "minimal"))))]
+mod vendored;
+
+#[cfg(all(feature = "blake2-rfc", not(feature = "vendored_blake2b"),
+          any(not(feature = "blake2b_simd"), feature = "minimal")))]
+mod backend {
+    extern crate blake2_rfc;
+
+    pub struct Blake2b(blake2_rfc::blake2b::Blake2b);
+
+    impl Blake2b {
+        #[inline(always)]
+        pub fn new(outlen: usize) -> Blake2b {
+            Blake2b(blake2_rfc::blake2b::Blake2b::new(outlen))
+        }
+
+        #[inline(always)]
+        pub fn update(&mut self, bytes: &[u8]) { self.0.update(bytes) }
+
+        // Writes the digest to `out`, which must be of the output length.
+        #[inline(always)]
+        pub fn finalize_into(self, out: &mut [u8]) {
+            out.clone_from_slice(self.0.finalize().as_bytes())
+        }
+    }
+}
+
+#[cfg(all(feature = "blake2b_simd", not(feature = "minimal"),
+          not(feature = "vendored_blake2b")))]
+mod backend {
+    extern crate blake2b_simd;
+
+    pub struct Blake2b(blake2b_simd::State);
+
+    impl Blake2b {
+        #[inline(always)]
+        pub fn new(outlen: usize) -> Blake2b {
+            Blake2b(blake2b_simd::Params::new().hash_length(outlen).to_state())
+        }
+
+        #[inline(always)]
+        pub fn update(&mut self, bytes: &[u8]) { self.0.update(bytes); }
+
+        // Writes the digest to `out`, which must be of the output length.
+        #[inline(always)]
+        pub fn finalize_into(self, out: &mut [u8]) {
+            out.clone_from_slice(self.0.finalize().as_bytes())
+        }
+    }
+}
+
+#[cfg(any(feature = "vendored_blake2b",
+          all(not(feature = "blake2-rfc"),
+              any(not(feature = "blake2b_simd"), feature = "minimal"))))]
+mod backend {
+    pub use super::vendored::Blake2b;
+}
@@ -0,0 +1,183 @@
+// Unkeyed Blake2b with digests of 1 to 64 bytes, after RFC 7693. Written for
+// reading rather than speed: Argon2 spends nearly all of its time in the
+// compression function G, not in Blake2b.
+
+const IV: [u64; 8] = [0x6a09e667f3bcc908, 0xbb67ae8584caa73b,
+                      0x3c6ef372fe94f82b, 0xa54ff53a5f1d36f1,
+                      0x510e527fade682d1, 0x9b05688c2b3e6c1f,
+                      0x1f83d9abfb41bd6b, 0x5be0cd19137e2179];
+
+const SIGMA: [[usize; 16]; 12] =
+    [[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+     [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+     [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+     [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+     [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+     [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+     [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+     [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+     [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+     [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+     [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+     [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3]];
+
+const BLOCK: usize = 128;
+
+pub struct Blake2b {
+    h: [u64; 8],
+    // bytes compressed so far.
+    t: u128,
+    buf: [u8; BLOCK],
+    buflen: usize,
+    outlen: usize,
+}
+
+impl Blake2b {
+    pub fn new(outlen: usize) -> Blake2b {
+        assert!((1..=64).contains(&outlen));
+        let mut h = IV;
+        h[0] ^= 0x01010000 ^ outlen as u64;
+        Blake2b {
+            h,
+            t: 0,
+            buf: [0; BLOCK],
+            buflen: 0,
+            outlen,
+        }
+    }
+
+    pub fn update(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            // the last block must be compressed by `finalize_into`, so a full
+            // buffer is only compressed once more input arrives.
+            if self.buflen == BLOCK {
+                self.t += BLOCK as u128;
+                let buf = self.buf;
+                self.compress(&buf, false);
+                self.buflen = 0;
+            }
+            let n = (BLOCK - self.buflen).min(bytes.len());
+            self.buf[self.buflen..self.buflen + n].copy_from_slice(&bytes[..n]);
+            self.buflen += n;
+            bytes = &bytes[n..];
+        }
+    }
+
+    // Writes the digest to `out`, which must be of the output length.
+    pub fn finalize_into(mut self, out: &mut [u8]) {
+        assert_eq!(out.len(), self.outlen);
+        self.t += self.buflen as u128;
+        for b in self.buf[self.buflen..].iter_mut() {
+            *b = 0;
+        }
+        let buf = self.buf;
+        self.compress(&buf, true);
+        for (chunk, h) in out.chunks_mut(8).zip(self.h.iter()) {
+            chunk.copy_from_slice(&h.to_le_bytes()[..chunk.len()]);
+        }
+    }
+
+    fn compress(&mut self, block: &[u8; BLOCK], last: bool) {
+        let mut m = [0u64; 16];
+        for (w, b) in m.iter_mut().zip(block.chunks(8)) {
+            let mut le = [0; 8];
+            le.copy_from_slice(b);
+            *w = u64::from_le_bytes(le);
+        }
+        let mut v = [0u64; 16];
+        v[..8].copy_from_slice(&self.h);
+        v[8..].copy_from_slice(&IV);
+        v[12] ^= self.t as u64;
+        v[13] ^= (self.t >> 64) as u64;
+        if last {
+            v[14] = !v[14];
+        }
+        for s in SIGMA.iter() {
+            g(&mut v, 0, 4, 8, 12, m[s[0]], m[s[1]]);
+            g(&mut v, 1, 5, 9, 13, m[s[2]], m[s[3]]);
+            g(&mut v, 2, 6, 10, 14, m[s[4]], m[s[5]]);
+            g(&mut v, 3, 7, 11, 15, m[s[6]], m[s[7]]);
+            g(&mut v, 0, 5, 10, 15, m[s[8]], m[s[9]]);
+            g(&mut v, 1, 6, 11, 12, m[s[10]], m[s[11]]);
+            g(&mut v, 2, 7, 8, 13, m[s[12]], m[s[13]]);
+            g(&mut v, 3, 4, 9, 14, m[s[14]], m[s[15]]);
+        }
+        for i in 0..8 {
+            self.h[i] ^= v[i] ^ v[i + 8];
+        }
+    }
+}
+
+// The mixing function G of RFC 7693, section 3.1.
+#[inline(always)]
+fn g(v: &mut [u64; 16], a: usize, b: usize, c: usize, d: usize, x: u64,
+     y: u64) {
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+    v[d] = (v[d] ^ v[a]).rotate_right(32);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(24);
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+    v[d] = (v[d] ^ v[a]).rotate_right(16);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(63);
+}
+
+#[cfg(test)]
+mod test {
+    use super::Blake2b;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn digest(input: &[u8], outlen: usize) -> String {
+        let mut b = Blake2b::new(outlen);
+        b.update(input);
+        let mut out = vec![0; outlen];
+        b.finalize_into(&mut out);
+        hex(&out)
+    }
+
+    #[test]
+    fn rfc7693_vector() {
+        // appendix A.
+        assert_eq!(digest(b"abc", 64),
+                   "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6f\
+                    dbffa2d17d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925a\
+                    b92386edd4009923");
+        assert_eq!(digest(b"", 64),
+                   "786a02f742015903c6c6fd852552d272912f4740e15847618a86e217\
+                    f71f5419d25e1031afee585313896444934eb04b903a685b1448b755\
+                    d56f701afe9be2ce");
+    }
+
+    #[test]
+    fn block_boundaries() {
+        let input: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+        let cases = [(127, 64,
+                      "b6292669ccd38d5f01caae96ba272c76a879a45743afa0725d83b9\
+                       ebb26665b731f1848c52f11972b6644f554c064fa90780dbbbf3a8\
+                       9d4fc31f67df3e5857ef"),
+                     (128, 64,
+                      "2319e3789c47e2daa5fe807f61bec2a1a6537fa03f19ff32e87eec\
+                       bfd64b7e0e8ccff439ac333b040f19b0c4ddd11a61e24ac1fe0f10\
+                       a039806c5dcc0da3d115"),
+                     (129, 32,
+                      "f7f3c46ba2564ff4c4c162da1f5b605f9f1c4aa6a20652a9f9a337\
+                       c1a2f5b9c9"),
+                     (1000, 17, "825409d4f8f96ba454bab52cc819d5e720"),
+                     (255, 1, "77")];
+        for &(n, outlen, expected) in cases.iter() {
+            assert_eq!(digest(&input[..n], outlen), expected);
+
+            // the same, fed in uneven pieces.
+            let mut b = Blake2b::new(outlen);
+            for piece in input[..n].chunks(37) {
+                b.update(piece);
+            }
+            let mut out = vec![0; outlen];
+            b.finalize_into(&mut out);
+            assert_eq!(hex(&out), expected);
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use octword::u64x2;
-use std::mem;
+use std::{fmt, io, mem, ptr, slice};
+use std::sync::atomic::{AtomicUsize, Ordering, compiler_fence};
 use std::ops::{BitXorAssign, Index, IndexMut};
 use std::slice::{Iter, IterMut};
 
@@ -13,6 +14,9 @@ macro_rules! per_kib {
 
 pub struct Block([u64x2; per_kib!(u64x2)]);
 
+/// A block viewed as 128 little-endian words, as in the Argon2 spec.
+pub type Words = [u64; per_kib!(u64)];
+
 impl Clone for Block {
     #[inline(always)]
     fn clone(&self) -> Self {
@@ -41,6 +45,49 @@ impl Block {
         let rv: &[u64; per_kib!(u64)] = unsafe { mem::transmute(&self.0) };
         rv
     }
+
+    pub fn as_words(&self) -> &Words {
+        unsafe { &*(self as *const Block as *const Words) }
+    }
+
+    pub fn as_words_mut(&mut self) -> &mut Words {
+        unsafe { &mut *(self as *mut Block as *mut Words) }
+    }
+
+    // Inverse of `as_words`, if `w` is suitably aligned.
+    pub fn from_words(w: &Words) -> Option<&Block> {
+        match w.as_ptr() as usize % mem::align_of::<Block>() {
+            0 => Some(unsafe { &*(w as *const Words as *const Block) }),
+            _ => None,
+        }
+    }
+
+    // Inverse of `as_words_mut`, if `w` is suitably aligned.
+    pub fn from_words_mut(w: &mut Words) -> Option<&mut Block> {
+        match w.as_ptr() as usize % mem::align_of::<Block>() {
+            0 => Some(unsafe { &mut *(w as *mut Words as *mut Block) }),
+            _ => None,
+        }
+    }
+}
+
+// The 128 words of the block in hex, separated by spaces.
+impl fmt::LowerHex for Block {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, w) in self.as_u64().iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{:016x}", w)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Block {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Block({:x})", self)
+    }
 }
 
 impl<'a> BitXorAssign<&'a Block> for Block {
@@ -76,12 +123,56 @@ impl IndexMut<usize> for Block {
     }
 }
 
-pub fn zero() -> Block { Block([u64x2(0, 0); per_kib!(u64x2)]) }
+const ZERO: Block = Block([u64x2(0, 0); per_kib!(u64x2)]);
+
+pub fn zero() -> Block { ZERO }
+
+/// Storage for a block matrix of up to `BLOCKS` 1-KiB blocks, for hashing on
+/// targets without a heap, or where the matrix must live at a fixed address.
+/// `new` is a `const fn`, so the storage can be a `static`. Hash into it with
+/// `Argon2::hash_fixed`, which takes `Params::blocks()` of its blocks.
+pub struct FixedMatrix<const BLOCKS: usize>([Block; BLOCKS]);
+
+impl<const BLOCKS: usize> FixedMatrix<BLOCKS> {
+    pub const fn new() -> Self { FixedMatrix([ZERO; BLOCKS]) }
+
+    pub(crate) fn blocks_mut(&mut self) -> &mut [Block] { &mut self.0 }
+}
+
+impl<const BLOCKS: usize> Default for FixedMatrix<BLOCKS> {
+    fn default() -> Self { FixedMatrix::new() }
+}
+
+// In debug builds, matrices that `Matrix::new` allocates have a canary block
+// after each lane, which no lane should ever write. Lanes are filled in
+// parallel through aliases of the matrix (`mut_ref`, `get3`), so a bug there
+// could otherwise write into the next lane and silently change the hash;
+// `check_canaries` turns it into a panic at the end of the slice.
+const CANARIES: bool = cfg!(debug_assertions);
+const CANARY: u64 = 0xca4a_21e5_ca4a_21e5;
 
 pub struct Matrix {
-    blocks: Vec<Block>,
+    // The blocks, either those of `heap` or borrowed ones (cf. `borrowed`).
+    ptr: *mut Block,
+    len: usize,
+    #[allow(dead_code)]  // only owns them.
+    heap: Vec<Block>,
     lanes: u32,
     lanelen: u32,
+    // The distance between the starts of lanes: `lanelen`, plus one for the
+    // canary if there is one.
+    stride: usize,
+    wipe: bool,
+    // A checksum per block for `Argon2::hash_checked`, or empty. See `seal`.
+    sums: Vec<u64>,
+    // The index of the first block found not to match its checksum, or
+    // `usize::MAX`.
+    corrupt: AtomicUsize,
+    // Under loom, every block access goes through a tracked cell so that the
+    // model checker sees the reads and writes that lanes make of each other's
+    // blocks. See `workers.rs`.
+    #[cfg(loom)]
+    track: Vec<::loom::cell::UnsafeCell<()>>,
 }
 
 impl Index<(u32, u32)> for Matrix {
@@ -89,34 +180,70 @@ impl Index<(u32, u32)> for Matrix {
 
     #[inline(always)]
     fn index(&self, idx: (u32, u32)) -> &Block {
-        let (row, col) = idx;
-        debug_assert!(row < self.lanes && col < self.lanelen);
-        unsafe {
-            self.blocks.get_unchecked(row as usize * self.lanelen as usize +
-                                      col as usize)
-        }
+        let i = self.offset(idx);
+        #[cfg(loom)]
+        self.track[i].with(|_| ());
+        unsafe { &*self.ptr.add(i) }
     }
 }
 
 impl IndexMut<(u32, u32)> for Matrix {
     #[inline(always)]
     fn index_mut(&mut self, idx: (u32, u32)) -> &mut Block {
-        let (row, col) = idx;
-        debug_assert!(row < self.lanes && col < self.lanelen);
-        unsafe {
-            self.blocks.get_unchecked_mut(row as usize * self.lanelen as usize +
-                                          col as usize)
-        }
+        let i = self.offset(idx);
+        #[cfg(loom)]
+        self.track[i].with_mut(|_| ());
+        unsafe { &mut *self.ptr.add(i) }
     }
 }
 
 impl Matrix {
-    pub fn new(lanes: u32, lanelen: u32) -> Self {
+    // `wipe` is whether to zero all blocks on drop.
+    pub fn new(lanes: u32, lanelen: u32, wipe: bool) -> Self {
         debug_assert!(lanes > 0 && lanelen > 0);
-        Matrix {
-            blocks: vec![zero(); lanelen as usize * lanes as usize],
+        let stride = lanelen as usize + CANARIES as usize;
+        let n = stride * lanes as usize;
+        let mut heap = vec![zero(); n];
+        #[cfg(feature = "metrics")]
+        ::telemetry::allocated(lanes as usize * lanelen as usize *
+                               ARGON2_BLOCK_BYTES);
+        let mut rv = Matrix {
+            ptr: heap.as_mut_ptr(),
+            len: n,
+            heap,
             lanes: lanes,
             lanelen: lanelen,
+            stride,
+            wipe,
+            sums: vec![],
+            corrupt: AtomicUsize::new(usize::MAX),
+            #[cfg(loom)]
+            track: (0..n).map(|_| ::loom::cell::UnsafeCell::new(())).collect(),
+        };
+        rv.plant_canaries();
+        rv
+    }
+
+    // A matrix over the first `lanes * lanelen` of `blocks`, which must not be
+    // used otherwise while the matrix exists. Unsafe since the matrix doesn't
+    // borrow `blocks`; callers drop it before returning.
+    pub unsafe fn borrowed(blocks: &mut [Block], lanes: u32, lanelen: u32,
+                           wipe: bool)
+                           -> Self {
+        let n = lanelen as usize * lanes as usize;
+        assert!(lanes > 0 && lanelen > 0 && n <= blocks.len());
+        Matrix {
+            ptr: blocks.as_mut_ptr(),
+            len: n,
+            heap: vec![],
+            lanes,
+            lanelen,
+            stride: lanelen as usize,
+            wipe,
+            sums: vec![],
+            corrupt: AtomicUsize::new(usize::MAX),
+            #[cfg(loom)]
+            track: (0..n).map(|_| ::loom::cell::UnsafeCell::new(())).collect(),
         }
     }
 
@@ -141,13 +268,206 @@ impl Matrix {
         rv
     }
 
-    pub fn iter(&self) -> Iter<Block> { self.blocks.iter() }
+    // The blocks, lane by lane.
+    pub fn iter(&self) -> impl Iterator<Item = &Block> + '_ {
+        self.lanes().flat_map(|lane| lane.iter())
+    }
+
+    // Starts keeping a checksum of every block, which costs 8 bytes per block.
+    // From then on, every block must be `seal`ed after it is written, and may
+    // be `check`ed whenever it is read.
+    pub fn enable_checks(&mut self) {
+        self.sums = vec![0; self.len];
+        self.corrupt.store(usize::MAX, Ordering::Relaxed);
+    }
+
+    #[inline(always)]
+    pub fn is_checked(&self) -> bool { !self.sums.is_empty() }
+
+    // Records the checksum of the block at `idx`. Like block writes, seals of
+    // distinct blocks may happen concurrently on aliases of this matrix.
+    #[inline(always)]
+    pub fn seal(&mut self, idx: (u32, u32)) {
+        let sum = checksum(&self[idx]);
+        let i = self.offset(idx);
+        self.sums[i] = sum;
+    }
+
+    // Compares the block at `idx` with its checksum, noting a mismatch.
+    #[inline(always)]
+    pub fn check(&self, idx: (u32, u32)) {
+        let i = self.offset(idx);
+        if checksum(&self[idx]) != unsafe { *self.sums.as_ptr().add(i) } {
+            let none = usize::MAX;
+            let _ = self.corrupt.compare_exchange(none, i, Ordering::Relaxed,
+                                                  Ordering::Relaxed);
+        }
+    }
+
+    // Checks every block.
+    pub fn check_all(&self) {
+        for lane in 0..self.lanes {
+            for col in 0..self.lanelen {
+                self.check((lane, col));
+            }
+        }
+    }
+
+    // The (lane, column) of the first block found corrupted, if any.
+    pub fn corruption(&self) -> Option<(u32, u32)> {
+        match self.corrupt.load(Ordering::Relaxed) {
+            usize::MAX => None,
+            i => Some(((i / self.stride) as u32, (i % self.stride) as u32)),
+        }
+    }
+
+    // Panics if a lane has written past its end, into the canary that
+    // follows it. A no-op for matrices without canaries.
+    pub fn check_canaries(&self) {
+        if self.stride == self.lanelen as usize {
+            return;
+        }
+        for lane in 0..self.lanes as usize {
+            let canary = &self.blocks()[lane * self.stride + self.stride - 1];
+            assert!(canary.as_u64().iter().all(|&w| w == CANARY),
+                    "the canary after lane {} was overwritten", lane);
+        }
+    }
+
+    fn plant_canaries(&mut self) {
+        if self.stride == self.lanelen as usize {
+            return;
+        }
+        let stride = self.stride;
+        for lane in self.blocks_mut().chunks_mut(stride) {
+            for w in lane[stride - 1].iter_mut() {
+                *w = u64x2(CANARY, CANARY);
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn offset(&self, (row, col): (u32, u32)) -> usize {
+        debug_assert!(row < self.lanes && col < self.lanelen);
+        row as usize * self.stride + col as usize
+    }
+
+    // (lanes, lanelen).
+    #[cfg_attr(not(feature = "instrumentation"), allow(dead_code))]
+    pub fn dims(&self) -> (u32, u32) { (self.lanes, self.lanelen) }
+
+    // The blocks of each lane in turn. Lanes are contiguous rows.
+    #[cfg_attr(not(feature = "instrumentation"), allow(dead_code))]
+    pub fn lanes(&self) -> impl Iterator<Item = &[Block]> + '_ {
+        let lanelen = self.lanelen as usize;
+        self.blocks().chunks(self.stride).map(move |lane| &lane[..lanelen])
+    }
+
+    #[cfg_attr(not(feature = "instrumentation"), allow(dead_code))]
+    pub fn lane(&self, lane: u32) -> &[Block] {
+        assert!(lane < self.lanes);
+        let start = lane as usize * self.stride;
+        &self.blocks()[start..start + self.lanelen as usize]
+    }
+
+    // The blocks of column `col`, from lane 0 on.
+    #[cfg_attr(not(feature = "instrumentation"), allow(dead_code))]
+    pub fn column(&self, col: u32) -> impl Iterator<Item = &Block> + '_ {
+        assert!(col < self.lanelen);
+        self.lanes().map(move |lane| &lane[col as usize])
+    }
+
+    // Writes every block, one word per line, as the reference implementation's
+    // genkat does after each pass.
+    #[cfg_attr(not(feature = "instrumentation"), allow(dead_code))]
+    pub fn dump(&self, w: &mut dyn io::Write) -> io::Result<()> {
+        for (i, block) in self.iter().enumerate() {
+            for (j, word) in block.as_u64().iter().enumerate() {
+                writeln!(w, "Block {:04} [{:>3}]: {:016x}", i, j, word)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn blocks(&self) -> &[Block] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    fn blocks_mut(&mut self) -> &mut [Block] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+
+    // Whether this matrix can be reused for a hash with the given dimensions
+    // and wiping behavior.
+    pub fn fits(&self, lanes: u32, lanelen: u32, wipe: bool) -> bool {
+        (self.lanes, self.lanelen, self.wipe) == (lanes, lanelen, wipe)
+    }
+
+    // Writes to every block, so that the OS maps all of the matrix's pages
+    // now rather than as hashing first touches them. `new` zeroes the blocks
+    // already, but the allocator may hand out zeroed pages that it hasn't
+    // touched, and the compiler may turn the zeroing into such an allocation.
+    pub fn prefault(&mut self) {
+        for blk in self.blocks_mut().iter_mut() {
+            unsafe { ptr::write_volatile(&mut blk.0[0], blk.0[0]) };
+        }
+    }
+
+    // Zeroes all blocks if this matrix was created with `wipe`. Checksums are
+    // dropped either way.
+    pub fn clear_if_wiping(&mut self) {
+        self.clear_all();
+        if self.wipe {
+            self.plant_canaries();
+        }
+    }
+
+    // `clear_if_wiping`, canaries included. The stores are volatile so that
+    // zeroing a matrix that is about to be freed isn't optimized away as a
+    // dead store. They write a word at a time, rather than a byte at a time
+    // as `argon2::wipe` does, since matrices can be gigabytes.
+    fn clear_all(&mut self) {
+        if self.wipe {
+            for blk in self.blocks_mut().iter_mut() {
+                for w in blk.iter_mut() {
+                    unsafe { ptr::write_volatile(w, u64x2(0, 0)) };
+                }
+            }
+            for sum in self.sums.iter_mut() {
+                unsafe { ptr::write_volatile(sum, 0) };
+            }
+            compiler_fence(Ordering::SeqCst);
+        }
+        self.sums = vec![];
+    }
 }
 
+// A cheap checksum for `Matrix::seal`, which catches any single flipped bit:
+// the xor of all words, combined with their wrapping sum.
+#[inline(always)]
+fn checksum(b: &Block) -> u64 {
+    let (mut x, mut sum) = (0u64, 0u64);
+    for &w in b.as_u64() {
+        x ^= w;
+        sum = sum.wrapping_add(w);
+    }
+    x ^ sum.rotate_left(32)
+}
+
+// The blocks are owned or exclusively borrowed, as by a `Vec`.
+unsafe impl Send for Matrix {}
+unsafe impl Sync for Matrix {}
+
 impl Drop for Matrix {
     fn drop(&mut self) {
-        for blk in self.blocks.iter_mut() {
-            *blk = zero();
+        self.clear_all();
+        #[cfg(feature = "wipe_hooks")]
+        {
+            let bytes = unsafe {
+                slice::from_raw_parts(self.ptr as *const u8,
+                                      self.len * ARGON2_BLOCK_BYTES)
+            };
+            ::wipe_hooks::release(self.ptr as usize, bytes, self.wipe);
         }
     }
 }
@@ -0,0 +1,262 @@
+//! Step-by-step construction of `Argon2`, for settings beyond the cost
+//! parameters taken by `Argon2::new`.
+
+use std::fmt;
+use argon2::{Argon2, Flags, ParamErr, Variant, Version, defaults};
+use cost;
+use params::{Memory, Params, Rounding};
+
+// The least memory that the OWASP Password Storage Cheat Sheet recommends for
+// Argon2id with 1, 2, 3, 4, and 5 or more passes, in KiB.
+const OWASP_MIN_KIB: [u32; 5] = [46 << 10, 19 << 10, 12 << 10, 9 << 10,
+                                 7 << 10];
+
+/// A weakness of parameters that are valid nonetheless, as reported by
+/// `Builder::validate`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ParamWarning {
+    /// Less memory than OWASP's guidance for this number of passes: 46 MiB
+    /// for one pass, 19 MiB for two, down to 7 MiB for five or more.
+    LowMemory { kib: u32, recommended: u32 },
+    /// A single pass of Argon2i, which time-memory tradeoff attacks cut down
+    /// to a fraction of its intended cost. Use at least 3 passes, or
+    /// Argon2id.
+    SinglePassArgon2i,
+    /// More lanes than this machine can fill at once, so that hashing takes
+    /// longer without costing an attacker with more cores any more.
+    LanesExceedCores { lanes: u32, cores: u32 },
+}
+
+impl fmt::Display for ParamWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParamWarning::LowMemory { kib, recommended } => {
+                write!(f, "{} KiB of memory is below the recommended {} KiB.",
+                       kib, recommended)
+            }
+            ParamWarning::SinglePassArgon2i => {
+                write!(f, "Argon2i with a single pass is open to tradeoff \
+                           attacks.")
+            }
+            ParamWarning::LanesExceedCores { lanes, cores } => {
+                write!(f, "{} lanes exceed the {} that can run at once.",
+                       lanes, cores)
+            }
+        }
+    }
+}
+
+/// Builds an `Argon2`, starting from the defaults of `Argon2::default`, e.g.
+/// `Builder::new(Variant::Argon2i).passes(4).flags(Flags::all()).build()`.
+/// Unless set, the number of lanes is `Params::suggest_lanes` for the memory
+/// size and this machine's cores, rather than `defaults::LANES`.
+#[derive(Debug, Clone, Copy)]
+pub struct Builder {
+    variant: Variant,
+    version: Version,
+    passes: u32,
+    lanes: Option<u32>,
+    memory: Memory,
+    rounding: Rounding,
+    flags: Flags,
+}
+
+impl Builder {
+    pub fn new(variant: Variant) -> Builder {
+        Builder {
+            variant,
+            version: Version::_0x13,
+            passes: defaults::PASSES,
+            lanes: None,
+            memory: Memory::kib(defaults::KIB as u64),
+            rounding: Rounding::default(),
+            flags: Flags::default(),
+        }
+    }
+
+    /// Cf. `Argon2::new`.
+    pub fn passes(mut self, passes: u32) -> Builder {
+        self.passes = passes;
+        self
+    }
+
+    /// Cf. `Argon2::new`.
+    pub fn lanes(mut self, lanes: u32) -> Builder {
+        self.lanes = Some(lanes);
+        self
+    }
+
+    /// Cf. `Argon2::new`.
+    pub fn kib(mut self, kib: u32) -> Builder {
+        self.memory = Memory::kib(kib as u64);
+        self
+    }
+
+    /// Same as `Builder::kib`, e.g. `.memory(Memory::mib(64))`.
+    pub fn memory(mut self, memory: Memory) -> Builder {
+        self.memory = memory;
+        self
+    }
+
+    /// What to do if the memory size isn't a multiple of `4 * lanes` KiB.
+    /// Defaults to `Rounding::Down`, i.e. to using a little less memory than
+    /// asked for; deployments with exact budgets or mandated minimums may
+    /// prefer `Rounding::Error` or `Rounding::Up`.
+    pub fn rounding(mut self, rounding: Rounding) -> Builder {
+        self.rounding = rounding;
+        self
+    }
+
+    /// The Argon2 version to hash with. Only needed for interoperating with
+    /// legacy (0x10) hashes.
+    pub fn version(mut self, version: Version) -> Builder {
+        self.version = version;
+        self
+    }
+
+    /// Which buffers to wipe after hashing. Defaults to
+    /// `Flags::CLEAR_MEMORY`.
+    pub fn flags(mut self, flags: Flags) -> Builder {
+        self.flags = flags;
+        self
+    }
+
+    /// Validates the parameters, failing as `Argon2::new` does, or with
+    /// `ParamErr::UnalignedKiB` under `Rounding::Error`.
+    pub fn build(&self) -> Result<Argon2, ParamErr> {
+        let kib = self.memory.to_kib32()?;
+        let lanes = self.lanes.unwrap_or_else(|| {
+            Params::suggest_lanes(kib, cost::cores())
+        });
+        let params = Params::with_version(self.passes, lanes, kib,
+                                          self.variant, self.version)?;
+        self.rounding
+            .apply(params)
+            .map(|p| Argon2::with_params(p).with_flags(self.flags))
+    }
+
+    /// Fails as `Builder::build` does, and otherwise lists the weaknesses of
+    /// the parameters that it would build with, if any, for the application
+    /// to log or refuse as its policy has it. The number of cores that
+    /// `ParamWarning::LanesExceedCores` compares with is that of this
+    /// machine, and is 1 without the `threaded` feature.
+    pub fn validate(&self) -> Result<Vec<ParamWarning>, ParamErr> {
+        let params = self.build()?.to_params();
+        let mut rv = vec![];
+        let passes = params.passes().min(OWASP_MIN_KIB.len() as u32);
+        let recommended = OWASP_MIN_KIB[passes as usize - 1];
+        if params.kib() < recommended {
+            rv.push(ParamWarning::LowMemory {
+                kib: params.kib(),
+                recommended,
+            });
+        }
+        if params.variant() == Variant::Argon2i && params.passes() == 1 {
+            rv.push(ParamWarning::SinglePassArgon2i);
+        }
+        let cores = cost::cores();
+        if params.lanes() > cores {
+            rv.push(ParamWarning::LanesExceedCores {
+                lanes: params.lanes(),
+                cores,
+            });
+        }
+        Ok(rv)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Builder, ParamWarning};
+    use argon2::{Argon2, Flags, ParamErr, Variant};
+    use cost;
+    use params::{Memory, Rounding};
+
+    #[test]
+    fn builds_like_new() {
+        let a2 = Builder::new(Variant::Argon2d).passes(2).kib(64).build();
+        let exp = Argon2::new(2, 1, 64, Variant::Argon2d).unwrap();
+        assert_eq!(a2.unwrap().params(), exp.params());
+        assert_eq!(Builder::new(Variant::Argon2i).lanes(0).build().err(),
+                   Some(ParamErr::TooFewLanes));
+        let a2 = Builder::new(Variant::Argon2d).memory(Memory::mib(1)).build();
+        assert_eq!(a2.unwrap().params().1, 1024);
+    }
+
+    #[test]
+    fn rounding() {
+        let b = Builder::new(Variant::Argon2i).passes(1).lanes(3).kib(50);
+        let kib = |b: Builder| b.build().map(|a2| a2.params().1);
+        assert_eq!(kib(b), Ok(50));
+        assert_eq!(kib(b.rounding(Rounding::Down)), Ok(50));
+        assert_eq!(kib(b.rounding(Rounding::Up)), Ok(60));
+        assert_eq!(kib(b.rounding(Rounding::Error)),
+                   Err(ParamErr::UnalignedKiB(12)));
+        for &r in [Rounding::Error, Rounding::Down, Rounding::Up].iter() {
+            assert_eq!(kib(b.kib(48).rounding(r)), Ok(48));
+        }
+        let max = b.lanes(1).memory(Memory::max()).rounding(Rounding::Up);
+        assert_eq!(kib(max), Err(ParamErr::MaxKiB(Memory::max().as_kib())));
+    }
+
+    #[test]
+    fn suggested_lanes() {
+        let cores = cost::cores();
+        let a2 = Builder::new(Variant::Argon2id).kib(64 << 10).build();
+        assert_eq!(a2.unwrap().params().3, cores.min(64));
+        let a2 = Builder::new(Variant::Argon2id).kib(64).build();
+        assert_eq!(a2.unwrap().params().3, 1);
+    }
+
+    #[test]
+    fn clear_flags() {
+        let tiny = Builder::new(Variant::Argon2i).passes(1).kib(8);
+        let (mut exp, mut out) = ([0; 32], [0; 32]);
+        tiny.build()
+            .unwrap()
+            .hash(&mut exp, b"password", b"saltsalt", b"key", &[]);
+
+        let (mut p, mut k) = (*b"password", *b"key");
+        let a2 = tiny.flags(Flags::empty()).build().unwrap();
+        a2.hash_clearing(&mut out, &mut p, b"saltsalt", &mut k, &[]);
+        assert_eq!((out, &p, &k), (exp, b"password", b"key"));
+
+        let a2 = tiny.flags(Flags::all()).build().unwrap();
+        a2.hash_clearing(&mut out, &mut p, b"saltsalt", &mut k, &[]);
+        assert_eq!((out, p, k), (exp, [0; 8], [0; 3]));
+
+        let a2 = tiny.flags(Flags::CLEAR_SECRET).build().unwrap();
+        let (mut p, mut k) = (*b"password", *b"key");
+        a2.hash_clearing(&mut out, &mut p, b"saltsalt", &mut k, &[]);
+        assert_eq!((out, &p, k), (exp, b"password", [0; 3]));
+
+        assert_eq!(Flags::from_bits(8), None);
+        assert_eq!(Flags::from_bits(3),
+                   Some(Flags::CLEAR_PASSWORD | Flags::CLEAR_SECRET));
+    }
+
+    #[test]
+    fn validate() {
+        let owasp = Builder::new(Variant::Argon2id).passes(2).lanes(1)
+                                                   .memory(Memory::mib(19));
+        assert_eq!(owasp.validate(), Ok(vec![]));
+        assert_eq!(owasp.passes(5).kib(7 << 10).validate(), Ok(vec![]));
+        assert_eq!(owasp.passes(0).validate(), Err(ParamErr::TooFewPasses));
+
+        let (cores, kib) = (cost::cores(), 64 * (cost::cores() + 1));
+        let weak = Builder::new(Variant::Argon2i).passes(1).lanes(cores + 1)
+                                                 .kib(kib);
+        assert_eq!(weak.validate(),
+                   Ok(vec![ParamWarning::LowMemory {
+                               kib,
+                               recommended: 46 << 10,
+                           },
+                           ParamWarning::SinglePassArgon2i,
+                           ParamWarning::LanesExceedCores {
+                               lanes: cores + 1,
+                               cores,
+                           }]));
+        assert_eq!(weak.validate().unwrap()[1].to_string(),
+                   "Argon2i with a single pass is open to tradeoff attacks.");
+    }
+}
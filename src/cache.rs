@@ -0,0 +1,166 @@
+//! An opt-in cache of successful verifications, for devices that verify the
+//! same credential over and over, such as a screen unlock.
+//!
+//! A `VerifyCache` remembers, for a limited time, that a password matched an
+//! encoded hash, so that verifying the same pair again costs a Blake2b hash
+//! instead of an Argon2 one. Entries are 32-byte Blake2b digests of the pair
+//! under a random key drawn when the cache is created; neither the password
+//! nor anything Argon2 computed from it is kept. Only matches are cached:
+//! wrong passwords are always verified in full.
+//!
+//! This trades away some of Argon2's protection, and is therefore never used
+//! unless asked for. While an entry lives, anyone who can read the process's
+//! memory finds the key next to the digest, and can test guesses for that
+//! password at the speed of Blake2b. Keep the time to live short, and `clear`
+//! the cache when the device locks for good or the password changes. Hits
+//! aren't verified, so they are neither reported to `audit` nor counted by
+//! `telemetry`.
+
+use std::io;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use argon2::{len32, wipe};
+use blake2::Blake2b;
+use rng::{OsRng, RngProvider};
+use verifier::{Encoded, constant_eq};
+
+const KEY_LEN: usize = 32;
+const DIGEST_LEN: usize = 32;
+
+/// A bounded cache of successful verifications, each kept for a fixed time
+/// to live. Keys and entries are wiped when dropped.
+pub struct VerifyCache {
+    key: [u8; KEY_LEN],
+    capacity: usize,
+    ttl: Duration,
+    entries: Mutex<Vec<Entry>>,
+}
+
+struct Entry {
+    digest: [u8; DIGEST_LEN],
+    expires: Instant,
+}
+
+impl Drop for Entry {
+    fn drop(&mut self) { wipe(&mut self.digest); }
+}
+
+impl VerifyCache {
+    /// Creates a cache of at most `capacity` entries, each kept for `ttl`
+    /// after the verification that added it. Fails only if the operating
+    /// system's random number generator does.
+    pub fn new(capacity: usize, ttl: Duration) -> io::Result<VerifyCache> {
+        VerifyCache::with_rng(capacity, ttl, &mut OsRng)
+    }
+
+    /// Same as `VerifyCache::new`, but draws the key from `rng`.
+    pub fn with_rng(capacity: usize, ttl: Duration, rng: &mut dyn RngProvider)
+                    -> io::Result<VerifyCache> {
+        let mut key = [0; KEY_LEN];
+        rng.fill(&mut key)?;
+        Ok(VerifyCache {
+            key,
+            capacity,
+            ttl,
+            entries: Mutex::new(vec![]),
+        })
+    }
+
+    /// Same as `Encoded::verify`, but answers from the cache if `p` matched
+    /// `enc` less than the time to live ago, and otherwise caches a match,
+    /// evicting the oldest entry if the cache is full.
+    pub fn verify(&self, enc: &Encoded, p: &[u8]) -> bool {
+        let mut digest = self.digest(enc, p);
+        let now = Instant::now();
+        let hit = {
+            let mut entries = self.lock();
+            entries.retain(|e| e.expires > now);
+            // every entry is compared, so that the time taken doesn't tell
+            // which one matched.
+            entries.iter()
+                   .fold(false, |hit, e| constant_eq(&e.digest, &digest) | hit)
+        };
+        let rv = hit || enc.verify(p);
+        if rv && !hit && self.capacity > 0 {
+            let mut entries = self.lock();
+            if entries.len() >= self.capacity {
+                entries.remove(0);
+            }
+            entries.push(Entry {
+                digest,
+                expires: now + self.ttl,
+            });
+        }
+        wipe(&mut digest);
+        rv
+    }
+
+    /// Forgets every entry.
+    pub fn clear(&self) { self.lock().clear(); }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Entry>> {
+        // entries are only ever pushed and removed whole.
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn digest(&self, enc: &Encoded, p: &[u8]) -> [u8; DIGEST_LEN] {
+        let enc = enc.to_u8();
+        let mut b = Blake2b::new(DIGEST_LEN);
+        for part in [&self.key[..], &enc, p].iter() {
+            b.update(&len32(part));
+            b.update(part);
+        }
+        let mut rv = [0; DIGEST_LEN];
+        b.finalize_into(&mut rv);
+        rv
+    }
+}
+
+impl Drop for VerifyCache {
+    fn drop(&mut self) { wipe(&mut self.key); }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use super::VerifyCache;
+    use argon2::{Argon2, Variant};
+    use verifier::Encoded;
+
+    fn encoded(p: &[u8]) -> Encoded {
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2id).unwrap();
+        Encoded::new(a2, p, b"somesalt", &[], &[])
+    }
+
+    #[test]
+    fn caches_matches() {
+        let cache = VerifyCache::new(2, Duration::from_secs(60)).unwrap();
+        let (a, b, c) = (encoded(b"a"), encoded(b"b"), encoded(b"c"));
+        assert!(!cache.verify(&a, b"wrong"));
+        assert_eq!(cache.lock().len(), 0);
+        assert!(cache.verify(&a, b"a"));
+        assert!(cache.verify(&a, b"a"));
+        assert_eq!(cache.lock().len(), 1);
+        assert!(!cache.verify(&a, b"b"));
+
+        assert!(cache.verify(&b, b"b"));
+        assert!(cache.verify(&c, b"c"));
+        assert_eq!(cache.lock().len(), 2);
+        let digests: Vec<_> = cache.lock().iter().map(|e| e.digest).collect();
+        assert_eq!(digests,
+                   vec![cache.digest(&b, b"b"), cache.digest(&c, b"c")]);
+
+        cache.clear();
+        assert_eq!(cache.lock().len(), 0);
+    }
+
+    #[test]
+    fn expires() {
+        let cache = VerifyCache::new(4, Duration::from_secs(0)).unwrap();
+        let enc = encoded(b"password");
+        assert!(cache.verify(&enc, b"password"));
+        assert!(cache.verify(&enc, b"password"));
+        // each verification dropped the last one's entry, expired on arrival.
+        assert_eq!(cache.lock().len(), 1);
+    }
+}
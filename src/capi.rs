@@ -0,0 +1,166 @@
+//! A minimal C ABI for linking argon2rs into C programs, chiefly
+//! `examples/pam/pam_argon2rs.c`. The exported symbols, their signatures, and
+//! the return codes below are stable: new functionality gets new symbols. See
+//! `include/argon2rs.h` for the C declarations.
+//!
+//! Build a static library to link against with
+//!
+//! ```text
+//! $ cargo rustc --release --features capi --crate-type staticlib
+//! ```
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::{ptr, slice};
+use argon2::{Argon2, Variant};
+use verifier::Encoded;
+
+/// Success, or for `argon2rs_verify`, a matching password.
+pub const ARGON2RS_OK: c_int = 0;
+/// The password didn't match.
+pub const ARGON2RS_MISMATCH: c_int = 1;
+/// A null pointer or out-of-range length was passed.
+pub const ARGON2RS_EINVAL: c_int = -1;
+/// The encoded hash couldn't be parsed.
+pub const ARGON2RS_EDECODE: c_int = -2;
+/// The output buffer is too small.
+pub const ARGON2RS_ETOOSMALL: c_int = -3;
+/// An internal error occurred. Panics never unwind across this ABI.
+pub const ARGON2RS_EINTERNAL: c_int = -4;
+
+/// Revision of this ABI. Bumped when symbols are added, never when they change.
+#[no_mangle]
+pub extern "C" fn argon2rs_abi_version() -> u32 { 1 }
+
+/// Verifies the `pwdlen`-byte password `pwd` against the NUL-terminated
+/// encoded hash `encoded`, returning `ARGON2RS_OK` on a match and
+/// `ARGON2RS_MISMATCH` otherwise.
+///
+/// If `encoded` is null, a dummy verification with default parameters is
+/// performed and `ARGON2RS_MISMATCH` returned, so that callers can reject
+/// unknown users in the same time as wrong passwords.
+///
+/// # Safety
+///
+/// `encoded` must be null or point to a NUL-terminated string, and `pwd` must
+/// point to `pwdlen` readable bytes (or be null if `pwdlen` is zero).
+#[no_mangle]
+pub unsafe extern "C" fn argon2rs_verify(encoded: *const c_char,
+                                         pwd: *const u8, pwdlen: usize)
+                                         -> c_int {
+    guard(|| verify(encoded, pwd, pwdlen))
+}
+
+unsafe fn verify(encoded: *const c_char, pwd: *const u8, pwdlen: usize)
+                 -> c_int {
+    let pwd = match bytes(pwd, pwdlen) {
+        Some(pwd) => pwd,
+        None => return ARGON2RS_EINVAL,
+    };
+    let enc = if encoded.is_null() {
+        None
+    } else {
+        match Encoded::from_u8(CStr::from_ptr(encoded).to_bytes()) {
+            Ok(enc) => Some(enc),
+            Err(_) => return ARGON2RS_EDECODE,
+        }
+    };
+    match Encoded::verify_opt(enc.as_ref(), pwd) {
+        true => ARGON2RS_OK,
+        false => ARGON2RS_MISMATCH,
+    }
+}
+
+/// Hashes the `pwdlen`-byte password `pwd` with the `saltlen`-byte salt `salt`
+/// using default Argon2i parameters, and writes the NUL-terminated encoding to
+/// the `outlen`-byte buffer `out`. `saltlen` must be at least 8. 128 bytes of
+/// output suffice for salts of up to 32 bytes.
+///
+/// # Safety
+///
+/// `pwd` and `salt` must point to `pwdlen` and `saltlen` readable bytes
+/// respectively (or be null if their length is zero), and `out` must point to
+/// `outlen` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn argon2rs_hash_encoded(pwd: *const u8, pwdlen: usize,
+                                               salt: *const u8,
+                                               saltlen: usize,
+                                               out: *mut c_char, outlen: usize)
+                                               -> c_int {
+    guard(|| hash_encoded(pwd, pwdlen, salt, saltlen, out, outlen))
+}
+
+unsafe fn hash_encoded(pwd: *const u8, pwdlen: usize, salt: *const u8,
+                       saltlen: usize, out: *mut c_char, outlen: usize)
+                       -> c_int {
+    let (pwd, salt) = match (bytes(pwd, pwdlen), bytes(salt, saltlen)) {
+        (Some(p), Some(s)) if 8 <= s.len() && s.len() <= 0xffffffff => (p, s),
+        _ => return ARGON2RS_EINVAL,
+    };
+    if out.is_null() || pwd.len() > 0xffffffff {
+        return ARGON2RS_EINVAL;
+    }
+    let a2 = Argon2::default(Variant::Argon2i);
+    let enc = Encoded::new(a2, pwd, salt, &[], &[]).to_u8();
+    if enc.len() + 1 > outlen {
+        return ARGON2RS_ETOOSMALL;
+    }
+    ptr::copy_nonoverlapping(enc.as_ptr() as *const c_char, out, enc.len());
+    *out.add(enc.len()) = 0;
+    ARGON2RS_OK
+}
+
+fn guard<F: FnOnce() -> c_int>(f: F) -> c_int {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(ARGON2RS_EINTERNAL)
+}
+
+unsafe fn bytes<'a>(p: *const u8, len: usize) -> Option<&'a [u8]> {
+    match (p.is_null(), len) {
+        (true, 0) => Some(&[]),
+        (true, _) => None,
+        (false, _) => Some(slice::from_raw_parts(p, len)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::CStr;
+    use std::os::raw::c_char;
+    use std::ptr;
+    use super::*;
+
+    #[test]
+    fn hash_then_verify() {
+        let (pwd, salt) = (b"hunter2", b"saltsaltsalt");
+        let mut out = [0 as c_char; 128];
+        unsafe {
+            assert_eq!(argon2rs_hash_encoded(pwd.as_ptr(), pwd.len(),
+                                             salt.as_ptr(), salt.len(),
+                                             out.as_mut_ptr(), 10),
+                       ARGON2RS_ETOOSMALL);
+            assert_eq!(argon2rs_hash_encoded(pwd.as_ptr(), pwd.len(),
+                                             salt.as_ptr(), 4,
+                                             out.as_mut_ptr(), out.len()),
+                       ARGON2RS_EINVAL);
+            assert_eq!(argon2rs_hash_encoded(pwd.as_ptr(), pwd.len(),
+                                             salt.as_ptr(), salt.len(),
+                                             out.as_mut_ptr(), out.len()),
+                       ARGON2RS_OK);
+            let enc = CStr::from_ptr(out.as_ptr());
+            assert!(enc.to_bytes().starts_with(b"$argon2i$v=19,m=4096"));
+
+            assert_eq!(argon2rs_verify(out.as_ptr(), pwd.as_ptr(), pwd.len()),
+                       ARGON2RS_OK);
+            assert_eq!(argon2rs_verify(out.as_ptr(), pwd.as_ptr(), 3),
+                       ARGON2RS_MISMATCH);
+            assert_eq!(argon2rs_verify(ptr::null(), pwd.as_ptr(), pwd.len()),
+                       ARGON2RS_MISMATCH);
+            assert_eq!(argon2rs_verify(b"$argon2q$\0".as_ptr() as *const c_char,
+                                       pwd.as_ptr(), pwd.len()),
+                       ARGON2RS_EDECODE);
+            assert_eq!(argon2rs_verify(out.as_ptr(), ptr::null(), 1),
+                       ARGON2RS_EINVAL);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! CBOR representations of `Params` and `Encoded` for constrained devices and
+//! protocols that already speak CBOR. The schema is that of the `json` module:
+//! a map with the same keys and values, so that one document can be
+//! transcoded into the other without loss.
+
+use std::io;
+use ciborium::{de, ser};
+use params::Params;
+use verifier::Encoded;
+
+/// The ways in which decoding CBOR can fail: malformed input, or a well-formed
+/// document that doesn't describe valid parameters or hashes.
+pub type Error = de::Error<io::Error>;
+
+fn to_vec<T: ::serde::Serialize>(val: &T) -> Vec<u8> {
+    let mut rv = vec![];
+    ser::into_writer(val, &mut rv).expect("writes to a Vec never fail");
+    rv
+}
+
+impl Params {
+    /// Serializes these parameters as CBOR.
+    pub fn to_cbor(&self) -> Vec<u8> { to_vec(self) }
+
+    /// Parses and validates parameters serialized by `Params::to_cbor`.
+    pub fn from_cbor(bytes: &[u8]) -> Result<Params, Error> {
+        de::from_reader(bytes)
+    }
+}
+
+impl Encoded {
+    /// Serializes this hashing session as CBOR.
+    pub fn to_cbor(&self) -> Vec<u8> { to_vec(self) }
+
+    /// Reconstructs a hashing session serialized by `Encoded::to_cbor`.
+    pub fn from_cbor(bytes: &[u8]) -> Result<Encoded, Error> {
+        de::from_reader(bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use argon2::Variant;
+    use params::Params;
+    use verifier::Encoded;
+
+    #[test]
+    fn params_roundtrip() {
+        let p = Params::new(3, 4, 4096, Variant::Argon2d).unwrap();
+        let cbor = p.to_cbor();
+        // A five-entry map whose first key is "variant".
+        assert_eq!(&cbor[..9], b"\xa5\x67variant");
+        assert_eq!(Params::from_cbor(&cbor).unwrap(), p);
+        assert!(Params::from_cbor(&cbor[..cbor.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn encoded_roundtrip() {
+        let enc = Encoded::from_u8(b"$argon2d$v=16,m=8,t=1,p=1\
+                                     ,keyid=a2V5,wrap=sha1,data=eA\
+                                     $c2FsdHNhbHQ$aGFzaA")
+                      .unwrap();
+        let dec = Encoded::from_cbor(&enc.to_cbor()).unwrap();
+        assert_eq!(dec.to_u8(), enc.to_u8());
+
+        let json = Encoded::from_json(&enc.to_json()).unwrap();
+        assert_eq!(json.to_cbor(), enc.to_cbor());
+        assert!(Encoded::from_cbor(b"\xa0").is_err());
+
+        let short = enc.to_json().replace("c2FsdHNhbHQ", "c2FsdA");
+        let short: ::serde_json::Value = ::serde_json::from_str(&short)
+                                             .unwrap();
+        assert!(Encoded::from_cbor(&super::to_vec(&short)).is_err());
+    }
+}
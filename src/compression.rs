@@ -0,0 +1,119 @@
+//! The compression function G, as a trait so that alternative permutations or
+//! instrumented versions can be run under Argon2's scheduling, indexing, and
+//! threading (see `Argon2::hash_with_compression`). Public with the
+//! `internals` feature. Hashes computed with anything but `BlaMka` are not
+//! Argon2 hashes.
+
+use argon2::{g, g_xor};
+use block::{self, Block};
+
+pub use block::Words;
+
+/// A compression function mapping two 1-KiB blocks to one. Blocks are viewed
+/// as 128 words, i.e. as the little-endian decoding of their bytes.
+pub trait Compression {
+    /// Sets `dest` to G(`x`, `y`). Used by Argon2 version 0x10.
+    fn compress(dest: &mut Words, x: &Words, y: &Words);
+
+    /// Sets `dest` to `dest` ⊕ G(`x`, `y`). Used by Argon2 version 0x13.
+    fn compress_xor(dest: &mut Words, x: &Words, y: &Words) {
+        let mut tmp = [0; 128];
+        Self::compress(&mut tmp, x, y);
+        for (d, t) in dest.iter_mut().zip(tmp.iter()) {
+            *d ^= *t;
+        }
+    }
+}
+
+/// The compression function of the Argon2 spec, built on the BlaMka variant
+/// of Blake2b's round function.
+pub struct BlaMka;
+
+impl Compression for BlaMka {
+    #[inline(always)]
+    fn compress(dest: &mut Words, x: &Words, y: &Words) {
+        with_blocks(dest, x, y, g)
+    }
+
+    #[inline(always)]
+    fn compress_xor(dest: &mut Words, x: &Words, y: &Words) {
+        with_blocks(dest, x, y, g_xor)
+    }
+}
+
+// Applies `f` to the blocks underlying `dest`, `x`, and `y`, going through
+// copies if they aren't aligned as blocks. They always are when called by
+// `Argon2`.
+#[inline(always)]
+fn with_blocks<F>(dest: &mut Words, x: &Words, y: &Words, f: F)
+    where F: Fn(&mut Block, &Block, &Block)
+{
+    let aligned = (Block::from_words(x), Block::from_words(y));
+    if let (Some(x), Some(y)) = aligned {
+        if let Some(dest) = Block::from_words_mut(dest) {
+            return f(dest, x, y);
+        }
+    }
+    let (mut d, mut x_, mut y_) = (block::zero(), block::zero(), block::zero());
+    d.as_words_mut().clone_from(dest);
+    x_.as_words_mut().clone_from(x);
+    y_.as_words_mut().clone_from(y);
+    f(&mut d, &x_, &y_);
+    dest.clone_from(d.as_words());
+}
+
+#[cfg(all(test, feature = "internals"))]
+mod test {
+    use super::{BlaMka, Compression, Words};
+    use argon2::{Argon2, Variant, Version};
+
+    // BlaMka through the trait's default `compress_xor`.
+    struct Plain;
+
+    impl Compression for Plain {
+        fn compress(dest: &mut Words, x: &Words, y: &Words) {
+            BlaMka::compress(dest, x, y)
+        }
+    }
+
+    // Not a compression function at all.
+    struct Xor;
+
+    impl Compression for Xor {
+        fn compress(dest: &mut Words, x: &Words, y: &Words) {
+            for (d, (x, y)) in dest.iter_mut().zip(x.iter().zip(y.iter())) {
+                *d = x ^ y;
+            }
+        }
+    }
+
+    #[test]
+    fn custom_compression() {
+        for &vers in [Version::_0x10, Version::_0x13].iter() {
+            let a2 = Argon2::with_version(2, 2, 16, Variant::Argon2i, vers)
+                         .unwrap();
+            let hash = |f: &dyn Fn(&mut [u8])| {
+                let mut out = [0; 32];
+                f(&mut out);
+                out
+            };
+            let exp = hash(&|out| a2.hash(out, b"password", b"saltsalt", &[],
+                                          &[]));
+            let blamka = hash(&|out| {
+                a2.hash_with_compression::<BlaMka>(out, b"password",
+                                                   b"saltsalt", &[], &[])
+            });
+            let plain = hash(&|out| {
+                a2.hash_with_compression::<Plain>(out, b"password",
+                                                  b"saltsalt", &[], &[])
+            });
+            let xor = hash(&|out| {
+                a2.hash_with_compression::<Xor>(out, b"password", b"saltsalt",
+                                                &[], &[])
+            });
+            assert_eq!(blamka, exp);
+            assert_eq!(plain, exp);
+            assert!(xor != exp);
+        }
+    }
+}
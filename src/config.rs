@@ -0,0 +1,175 @@
+//! Cost parameters as a section of a service's own configuration, so that they
+//! are parsed and validated along with the rest of it. `ParamsConfig`
+//! deserializes from a section such as
+//!
+//! ```toml
+//! [password_hashing]
+//! preset = "interactive"
+//! memory_kib = 131072
+//! ```
+//!
+//! where `preset` is one of `interactive`, `moderate`, `sensitive`,
+//! `rfc9106`, or `rfc9106-low-memory` (cf. `Preset`), and `memory_kib`,
+//! `passes`, `lanes`, and `variant` override the values of the preset.
+//! Without a preset, unset values are those of `defaults` and the variant is
+//! Argon2id. A preset name can stand in for the whole section, as in
+//! `password_hashing = "moderate"`. Unknown keys are rejected, so that a
+//! misspelled key doesn't silently leave a weaker default in place.
+//!
+//! With the `toml` feature, `Params::from_toml` parses such a section by
+//! itself.
+
+use std::fmt;
+use serde::de::{self, Deserialize, Deserializer, MapAccess, Visitor};
+use serde::de::value::MapAccessDeserializer;
+use argon2::{Variant, defaults};
+use json;
+use params::{Params, Preset};
+
+/// Validated parameters, deserialized from a configuration section. See the
+/// module documentation for the format.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ParamsConfig {
+    params: Params,
+}
+
+impl ParamsConfig {
+    pub fn params(&self) -> Params { self.params }
+}
+
+impl From<ParamsConfig> for Params {
+    fn from(c: ParamsConfig) -> Params { c.params }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Section {
+    preset: Option<String>,
+    memory_kib: Option<u32>,
+    passes: Option<u32>,
+    lanes: Option<u32>,
+    variant: Option<String>,
+}
+
+const PRESETS: &[&str] = &["interactive", "moderate", "sensitive", "rfc9106",
+                           "rfc9106-low-memory"];
+
+fn preset<E: de::Error>(name: &str) -> Result<Preset, E> {
+    match name {
+        "interactive" => Ok(Preset::Interactive),
+        "moderate" => Ok(Preset::Moderate),
+        "sensitive" => Ok(Preset::Sensitive),
+        "rfc9106" => Ok(Preset::Rfc9106),
+        "rfc9106-low-memory" => Ok(Preset::Rfc9106LowMemory),
+        _ => Err(E::unknown_variant(name, PRESETS)),
+    }
+}
+
+impl Section {
+    fn params<E: de::Error>(&self) -> Result<Params, E> {
+        let (passes, lanes, kib, variant) = match self.preset {
+            Some(ref name) => {
+                let p = preset::<E>(name)?.params();
+                (p.passes(), p.lanes(), p.kib(), p.variant())
+            }
+            None => {
+                (defaults::PASSES, defaults::LANES, defaults::KIB,
+                 Variant::Argon2id)
+            }
+        };
+        let variant = match self.variant {
+            Some(ref name) => json::variant(name)?,
+            None => variant,
+        };
+        Params::new(self.passes.unwrap_or(passes),
+                    self.lanes.unwrap_or(lanes),
+                    self.memory_kib.unwrap_or(kib),
+                    variant)
+            .map_err(E::custom)
+    }
+}
+
+struct ConfigVisitor;
+
+impl<'de> Visitor<'de> for ConfigVisitor {
+    type Value = Params;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a preset name or a table of Argon2 parameters")
+    }
+
+    fn visit_str<E: de::Error>(self, name: &str) -> Result<Params, E> {
+        preset(name).map(Preset::params)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Params, A::Error> {
+        Section::deserialize(MapAccessDeserializer::new(map))?.params()
+    }
+}
+
+impl<'de> Deserialize<'de> for ParamsConfig {
+    fn deserialize<D>(d: D) -> Result<ParamsConfig, D::Error>
+        where D: Deserializer<'de>
+    {
+        let params = d.deserialize_any(ConfigVisitor)?;
+        Ok(ParamsConfig { params })
+    }
+}
+
+#[cfg(feature = "toml")]
+impl Params {
+    /// Parses and validates the contents of a configuration section, e.g.
+    /// `preset = "moderate"`. See the `config` module for the format.
+    pub fn from_toml(toml: &str) -> Result<Params, ::toml::de::Error> {
+        ::toml::from_str::<ParamsConfig>(toml).map(Params::from)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json;
+    use super::ParamsConfig;
+    use argon2::{Variant, defaults};
+    use params::{Params, Preset};
+
+    fn parse(json: &str) -> Result<Params, serde_json::Error> {
+        serde_json::from_str::<ParamsConfig>(json).map(Params::from)
+    }
+
+    #[test]
+    fn sections() {
+        assert_eq!(parse(r#""moderate""#).unwrap(), Preset::Moderate.params());
+        assert_eq!(parse(r#"{"preset": "rfc9106-low-memory"}"#).unwrap(),
+                   Preset::Rfc9106LowMemory.params());
+        assert_eq!(parse(r#"{"preset": "interactive", "passes": 4,
+                             "variant": "argon2i"}"#)
+                       .unwrap(),
+                   Params::new(4, 1, 64 << 10, Variant::Argon2i).unwrap());
+        assert_eq!(parse(r#"{"memory_kib": 8192}"#).unwrap(),
+                   Params::new(defaults::PASSES, defaults::LANES, 8192,
+                               Variant::Argon2id)
+                       .unwrap());
+
+        let bad = [r#""fast""#,
+                   r#"{"preset": "fast"}"#,
+                   r#"{"variant": "scrypt"}"#,
+                   r#"{"passes": 0}"#,
+                   r#"{"memory_kb": 8192}"#,
+                   r#"4096"#];
+        for json in bad.iter() {
+            assert!(parse(json).is_err(), "{}", json);
+        }
+        let err = parse(r#"{"lanes": 4, "memory_kib": 16}"#).unwrap_err();
+        assert!(err.to_string().contains(">= 32 KiB"));
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn from_toml() {
+        let p = Params::from_toml("preset = \"moderate\"\nlanes = 2\n");
+        assert_eq!(p.unwrap(), Params::new(3, 2, 256 << 10, Variant::Argon2id)
+                                   .unwrap());
+        assert!(Params::from_toml("preset = \"moderate\"\nlane = 2\n")
+                    .is_err());
+    }
+}
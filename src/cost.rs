@@ -0,0 +1,324 @@
+//! Up-front cost prediction, for admission control layers that want to turn
+//! away oversized hash requests before any memory is allocated, and parameter
+//! selection: `recommend` for a single point, and `ParamSearch` for a measured
+//! sweep of the trade-offs available on a machine.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use argon2::{Argon2, ParamErr, Variant};
+use block::ARGON2_BLOCK_BYTES;
+use limits::{MAX_LANES, MIN_MEMORY_PER_LANE_KIB};
+use params::Params;
+
+/// Predicted resource usage of a single hash.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct CostEstimate {
+    /// Exact size of the block matrix in bytes, which dominates memory usage.
+    pub bytes: u64,
+    /// Approximate wall-clock time, extrapolated from this machine's measured
+    /// block throughput.
+    pub approx_time: Duration,
+}
+
+// Single-lane throughput in blocks per second, stored as `f64` bits. Zero if
+// not yet measured.
+static THROUGHPUT: AtomicU64 = AtomicU64::new(0);
+
+// Size of the hash timed by `calibrate`. Large enough to amortize setup and
+// small enough to be unnoticeable at startup.
+const CALIBRATION_KIB: u32 = 2048;
+
+/// Predicts the memory and time needed to hash with `params`. The first call
+/// measures this machine's throughput with a small hash (a few milliseconds);
+/// subsequent calls reuse that figure.
+pub fn estimate_cost(params: &Params) -> CostEstimate {
+    CostEstimate {
+        bytes: params.blocks() * ARGON2_BLOCK_BYTES as u64,
+        approx_time: Duration::from_secs_f64(seconds(params, throughput())),
+    }
+}
+
+fn seconds(params: &Params, blocks_per_sec: f64) -> f64 {
+    let filled = params.blocks() as f64 * params.passes() as f64;
+    filled / (blocks_per_sec * parallelism(params.lanes()))
+}
+
+/// Returns the cached single-lane throughput, in blocks per second, measuring
+/// it first if necessary.
+pub fn throughput() -> f64 {
+    match f64::from_bits(THROUGHPUT.load(Ordering::Relaxed)) {
+        t if t > 0.0 => t,
+        _ => calibrate(),
+    }
+}
+
+/// Overrides the cached throughput figure, e.g. with one measured offline on
+/// production hardware. `blocks_per_sec` must be positive.
+pub fn set_throughput(blocks_per_sec: f64) {
+    assert!(blocks_per_sec > 0.0);
+    THROUGHPUT.store(blocks_per_sec.to_bits(), Ordering::Relaxed);
+}
+
+/// Re-measures throughput and caches the result.
+pub fn calibrate() -> f64 {
+    // pooled so that allocating and faulting in the matrix isn't timed.
+    let a2 = Argon2::new(1, 1, CALIBRATION_KIB, Variant::Argon2i).unwrap();
+    let a2 = a2.with_prefault().with_pool(1);
+    let mut out = [0; 32];
+    let start = Instant::now();
+    a2.hash(&mut out, b"calibration", b"calibration salt", &[], &[]);
+    let elapsed = start.elapsed().as_secs_f64().max(1e-6);
+    let rv = CALIBRATION_KIB as f64 / elapsed;
+    set_throughput(rv);
+    rv
+}
+
+/// Recommends parameters for hashes taking about `target` on this machine,
+/// following RFC 9106: use as much memory as allowed, up to `max_kib`, then as
+/// many passes as fit the time budget. Memory is halved from `max_kib` only if
+/// a single pass over it would already overrun `target`. The prediction uses
+/// `estimate_cost`, so callers wanting certainty should time a hash with the
+/// result.
+pub fn recommend(variant: Variant, target: Duration, max_kib: u32, lanes: u32)
+                 -> Result<Params, ParamErr> {
+    recommend_at(throughput(), variant, target, max_kib, lanes)
+}
+
+fn recommend_at(blocks_per_sec: f64, variant: Variant, target: Duration,
+                max_kib: u32, lanes: u32)
+                -> Result<Params, ParamErr> {
+    let budget = target.as_secs_f64();
+    let mut kib = max_kib;
+    loop {
+        let one = Params::new(1, lanes, kib, variant)?;
+        let secs = seconds(&one, blocks_per_sec);
+        if secs <= budget || kib / 2 < MIN_MEMORY_PER_LANE_KIB * lanes {
+            let passes = (budget / secs.max(1e-9)).max(1.0)
+                                                  .min(u32::MAX as f64);
+            return Params::new(passes as u32, lanes, kib, variant);
+        }
+        kib /= 2;
+    }
+}
+
+/// A sweep over memory sizes, pass counts, and lane counts, within caps on
+/// memory, latency, and lanes, that benchmarks every candidate on this machine
+/// and keeps the Pareto-optimal ones: those for which no other candidate has
+/// at least as much memory and as many passes while being no slower. This is
+/// the table to put in a parameter-selection document; `recommend` picks a
+/// single point instead.
+///
+/// Memory sizes are the powers of two from the minimum (1 MiB unless set) up
+/// to the cap, plus the cap itself; lane counts are the powers of two up to
+/// the cap (the number of cores unless set); and passes run from one up to
+/// the cap (10 unless set). Timings grow with memory and passes, so a sweep
+/// stops along either axis at the first candidate over the latency cap, and
+/// is far quicker than the grid's size suggests. Still, each candidate is a
+/// real hash, so a sweep up to gigabytes takes minutes.
+#[derive(Debug, Clone, Copy)]
+pub struct ParamSearch {
+    variant: Variant,
+    max_kib: u32,
+    max_time: Duration,
+    min_kib: u32,
+    max_lanes: u32,
+    max_passes: u32,
+    samples: u32,
+}
+
+/// A benchmarked point found by `ParamSearch`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Candidate {
+    pub params: Params,
+    /// The fastest of the timed hashes with `params`.
+    pub time: Duration,
+}
+
+impl ParamSearch {
+    /// A sweep for `variant`, using at most `max_kib` of memory and `max_time`
+    /// per hash.
+    pub fn new(variant: Variant, max_kib: u32, max_time: Duration)
+               -> ParamSearch {
+        ParamSearch {
+            variant,
+            max_kib,
+            max_time,
+            min_kib: 1 << 10,
+            max_lanes: cores().min(MAX_LANES),
+            max_passes: 10,
+            samples: 1,
+        }
+    }
+
+    /// Starts the memory sweep at `kib` rather than 1 MiB.
+    pub fn min_kib(self, kib: u32) -> ParamSearch {
+        ParamSearch { min_kib: kib, ..self }
+    }
+
+    /// Tries at most `lanes` lanes, e.g. the cores set aside for hashing.
+    pub fn max_lanes(self, lanes: u32) -> ParamSearch {
+        ParamSearch { max_lanes: lanes, ..self }
+    }
+
+    /// Tries at most `passes` passes.
+    pub fn max_passes(self, passes: u32) -> ParamSearch {
+        ParamSearch { max_passes: passes, ..self }
+    }
+
+    /// Times each candidate `samples` times and keeps the fastest, which
+    /// steadies the results on a busy machine. Defaults to 1.
+    pub fn samples(self, samples: u32) -> ParamSearch {
+        ParamSearch { samples: samples.max(1), ..self }
+    }
+
+    /// Runs the sweep, returning the Pareto-optimal candidates by increasing
+    /// memory, then passes.
+    pub fn run(&self) -> Vec<Candidate> {
+        self.run_with(|params| {
+            let a2 = Argon2::with_params(*params).with_prefault()
+                                                 .with_pool(1);
+            let mut out = [0; 32];
+            let start = Instant::now();
+            a2.hash(&mut out, b"param search", b"param search salt", &[], &[]);
+            start.elapsed()
+        })
+    }
+
+    fn run_with<F>(&self, mut time: F) -> Vec<Candidate>
+        where F: FnMut(&Params) -> Duration
+    {
+        let mut found = vec![];
+        let mut lanes = 1;
+        while lanes <= self.max_lanes {
+            let min = MIN_MEMORY_PER_LANE_KIB * lanes;
+            let mut kib = self.min_kib.max(min);
+            while kib <= self.max_kib {
+                let mut fits = false;
+                for passes in 1..self.max_passes + 1 {
+                    let params = match Params::new(passes, lanes, kib,
+                                                   self.variant) {
+                        Ok(params) => params,
+                        Err(_) => break,
+                    };
+                    let t = (0..self.samples).map(|_| time(&params))
+                                             .min()
+                                             .unwrap();
+                    if t > self.max_time {
+                        break;
+                    }
+                    fits = true;
+                    found.push(Candidate { params, time: t });
+                }
+                if !fits || kib == self.max_kib {
+                    break;
+                }
+                kib = kib.saturating_mul(2).min(self.max_kib);
+            }
+            match lanes.checked_mul(2) {
+                Some(l) => lanes = l,
+                None => break,
+            }
+        }
+        pareto(found)
+    }
+}
+
+// The candidates not dominated by another, by increasing memory and passes.
+// Of equally good ones, the one with the fewest lanes is kept.
+fn pareto(mut found: Vec<Candidate>) -> Vec<Candidate> {
+    let key = |c: &Candidate| (c.params.kib(), c.params.passes());
+    let dominates = |a: &Candidate, b: &Candidate| {
+        let (ka, kb) = (key(a), key(b));
+        ka.0 >= kb.0 && ka.1 >= kb.1 && a.time <= b.time &&
+        (ka != kb || a.time < b.time || a.params.lanes() < b.params.lanes())
+    };
+    let keep: Vec<bool> = found.iter()
+                               .map(|b| !found.iter().any(|a| dominates(a, b)))
+                               .collect();
+    let mut keep = keep.into_iter();
+    found.retain(|_| keep.next().unwrap());
+    found.sort_by_key(|c| (key(c), c.params.lanes()));
+    found
+}
+
+fn parallelism(lanes: u32) -> f64 { lanes.min(cores()) as f64 }
+
+// The number of lanes that can run at once.
+#[cfg(all(feature = "threaded", not(feature = "minimal")))]
+pub(crate) fn cores() -> u32 {
+    use std::thread;
+    let cores = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    cores.min(u32::MAX as usize) as u32
+}
+
+#[cfg(any(not(feature = "threaded"), feature = "minimal"))]
+pub(crate) fn cores() -> u32 { 1 }
+
+#[cfg(test)]
+mod test {
+    use super::{ParamSearch, estimate_cost, recommend_at};
+    use argon2::{ParamErr, Variant};
+    use std::time::Duration;
+    use params::Params;
+
+    #[test]
+    fn exact_bytes() {
+        let p = Params::new(3, 1, 4096, Variant::Argon2i).unwrap();
+        assert_eq!(estimate_cost(&p).bytes, 4096 * 1024);
+        // rounded down to a multiple of 4 * lanes.
+        let p = Params::new(1, 3, 100, Variant::Argon2d).unwrap();
+        assert_eq!(estimate_cost(&p).bytes, 96 * 1024);
+    }
+
+    #[test]
+    fn time_scales_with_passes() {
+        let one = Params::new(1, 1, 4096, Variant::Argon2i).unwrap();
+        let ten = Params::new(10, 1, 4096, Variant::Argon2i).unwrap();
+        assert!(estimate_cost(&one).approx_time <
+                estimate_cost(&ten).approx_time);
+    }
+
+    #[test]
+    fn recommendations() {
+        // at one block per microsecond, one pass over 1 GiB takes ~1.05 s.
+        let ms = Duration::from_millis;
+        let rec = |t, m, l| recommend_at(1e6, Variant::Argon2i, ms(t), m, l);
+        let p = rec(3200, 1 << 20, 1).unwrap();
+        assert_eq!((p.kib(), p.passes()), (1 << 20, 3));
+        // 1 GiB overruns 300 ms, but 256 MiB fits once.
+        let p = rec(300, 1 << 20, 1).unwrap();
+        assert_eq!((p.kib(), p.passes()), (1 << 18, 1));
+        // the minimum memory is kept even if it overruns.
+        let p = rec(0, 1 << 20, 1).unwrap();
+        assert_eq!((p.kib(), p.passes()), (8, 1));
+        assert_eq!(rec(1, 8, 2).err(), Some(ParamErr::MinKiB(16)));
+    }
+
+    #[test]
+    fn param_search() {
+        // one block per microsecond per lane, on up to two cores.
+        let model = |p: &Params| {
+            let blocks = p.blocks() * p.passes() as u64;
+            Duration::from_micros(blocks / p.lanes().min(2) as u64)
+        };
+        let search = ParamSearch::new(Variant::Argon2id, 1 << 20,
+                                      Duration::from_millis(300))
+                         .min_kib(1 << 16)
+                         .max_lanes(4)
+                         .max_passes(3);
+        let found = search.run_with(model);
+        let points: Vec<_> = found.iter()
+                                  .map(|c| {
+                                      let p = c.params;
+                                      (p.kib() >> 10, p.passes(), p.lanes())
+                                  })
+                                  .collect();
+        // one lane is slower than two, and four no faster; 1 GiB takes over
+        // half a second, and 512 MiB fits only once.
+        assert_eq!(points,
+                   [(64, 1, 2), (64, 2, 2), (64, 3, 2), (128, 1, 2),
+                    (128, 2, 2), (128, 3, 2), (256, 1, 2), (256, 2, 2),
+                    (512, 1, 2)]);
+        assert!(found.iter().all(|c| c.time <= Duration::from_millis(300)));
+    }
+}
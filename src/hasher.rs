@@ -0,0 +1,116 @@
+//! A trait for what applications usually need of a password hash: making an
+//! encoded hash for storage, and checking a password against one.
+//!
+//! Code written against `PasswordHasher` rather than `Argon2` can be given
+//! `Insecure` in its tests, which hashes in microseconds instead of the
+//! hundreds of milliseconds that production parameters should take:
+//!
+//! ```ignore
+//! struct Accounts<H: PasswordHasher> { hasher: H, ... }
+//!
+//! // in production
+//! Accounts { hasher: Argon2::with_params(Preset::Moderate.params()), ... }
+//! // in tests
+//! Accounts { hasher: hasher::Insecure, ... }
+//! ```
+
+use std::io;
+use argon2::{Argon2, Variant};
+use limits::{MIN_LANES, MIN_MEMORY_PER_LANE_KIB, MIN_PASSES};
+use params::Params;
+use verifier::Encoded;
+
+/// Hashes passwords into PHC strings, and verifies passwords against them.
+pub trait PasswordHasher {
+    /// Hashes `p` with a fresh random salt, as `Encoded::generate` does, and
+    /// returns the encoded hash. Fails only if the system's random number
+    /// generator does.
+    fn hash_encoded(&self, p: &[u8]) -> io::Result<String>;
+
+    /// Whether `p` matches `encoded`, which is false if `encoded` doesn't
+    /// parse.
+    fn verify(&self, encoded: &str, p: &[u8]) -> bool;
+}
+
+impl<H: PasswordHasher + ?Sized> PasswordHasher for &H {
+    fn hash_encoded(&self, p: &[u8]) -> io::Result<String> {
+        (**self).hash_encoded(p)
+    }
+
+    fn verify(&self, encoded: &str, p: &[u8]) -> bool {
+        (**self).verify(encoded, p)
+    }
+}
+
+/// Hashes with this instance's parameters. Verification uses those encoded in
+/// the hash, except that hashes that don't parse cost as much as a hash with
+/// this instance's parameters, as with `Encoded::verify_dummy`.
+impl PasswordHasher for Argon2 {
+    fn hash_encoded(&self, p: &[u8]) -> io::Result<String> {
+        Encoded::generate(self.to_params(), p).map(|enc| enc.to_string())
+    }
+
+    fn verify(&self, encoded: &str, p: &[u8]) -> bool {
+        match encoded.parse::<Encoded>() {
+            Ok(enc) => enc.verify(p),
+            Err(_) => Encoded::verify_dummy(self, p),
+        }
+    }
+}
+
+/// Argon2id with the least parameters allowed: one pass over 8 KiB, in one
+/// lane. For tests only; these hashes give next to no protection.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Insecure;
+
+impl Insecure {
+    /// The parameters that `Insecure` hashes with.
+    pub fn params() -> Params {
+        Params::valid(MIN_PASSES, MIN_LANES, MIN_MEMORY_PER_LANE_KIB,
+                      Variant::Argon2id)
+    }
+}
+
+impl PasswordHasher for Insecure {
+    fn hash_encoded(&self, p: &[u8]) -> io::Result<String> {
+        Encoded::generate(Insecure::params(), p).map(|enc| enc.to_string())
+    }
+
+    fn verify(&self, encoded: &str, p: &[u8]) -> bool {
+        encoded.parse::<Encoded>().map(|enc| enc.verify(p)).unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Insecure, PasswordHasher};
+    use argon2::{Argon2, Variant};
+
+    // as application code would be written.
+    fn round_trip<H: PasswordHasher>(hasher: &H) -> String {
+        let enc = hasher.hash_encoded(b"hunter2").unwrap();
+        assert!(hasher.verify(&enc, b"hunter2"));
+        assert!(!hasher.verify(&enc, b"hunter3"));
+        assert!(!hasher.verify("$argon2id$garbage", b"hunter2"));
+        // a 4-byte salt.
+        assert!(!hasher.verify("$argon2i$v=19,m=8,t=1,p=1$c2FsdA$\
+                                AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA",
+                               b"hunter2"));
+        enc
+    }
+
+    #[test]
+    fn hashers() {
+        let a2 = Argon2::new(2, 1, 16, Variant::Argon2i).unwrap();
+        let enc = round_trip(&a2);
+        assert!(enc.starts_with("$argon2i$v=19,m=16,t=2,p=1$"));
+        let enc = round_trip(&Insecure);
+        assert!(enc.starts_with("$argon2id$v=19,m=8,t=1,p=1$"));
+        assert!(a2.verify(&enc, b"hunter2"));
+
+        let hashers: [&dyn PasswordHasher; 2] = [&a2, &Insecure];
+        for h in hashers.iter() {
+            round_trip(h);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! Argon2's choice of reference blocks, as a trait so that alternative
+//! distributions (uniform, windowed, ...) can be run under Argon2's
+//! scheduling, compression, and threading (see `Argon2::hash_with_indexing`),
+//! e.g. to measure their resistance to tradeoff attacks. Public with the
+//! `research` feature. Hashes computed with anything but `Spec` are not Argon2
+//! hashes.
+
+use argon2::{self, Variant};
+
+/// Where a block being computed lies in the block matrix, which has `lanes`
+/// rows of `slices * slicelen` columns. Cf. the terminology of `internals`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub pass: u32,
+    pub lane: u32,
+    pub slice: u32,
+    /// The index of the block within its segment.
+    pub idx: u32,
+    pub lanes: u32,
+    pub slicelen: u32,
+    /// Four, except under `Argon2::with_slices`.
+    pub slices: u32,
+}
+
+impl Position {
+    /// The column of the block.
+    pub fn column(&self) -> u32 { self.slice * self.slicelen + self.idx }
+
+    /// Whether block (`lane`, `col`) may be referenced from here: it must
+    /// have been computed, and not be written concurrently. Within the
+    /// block's own lane, that is any other block, or in the first pass, any
+    /// earlier one. In other lanes, it is any block outside of the current
+    /// slice, or in the first pass, any block of an earlier slice.
+    pub fn may_reference(&self, (lane, col): (u32, u32)) -> bool {
+        let lanelen = self.slices * self.slicelen;
+        if lane >= self.lanes || col >= lanelen {
+            return false;
+        }
+        match (lane == self.lane, self.pass) {
+            (true, 0) => col < self.column(),
+            (true, _) => col != self.column(),
+            (false, 0) => col < self.slice * self.slicelen,
+            (false, _) => col / self.slicelen != self.slice,
+        }
+    }
+}
+
+/// A way of choosing the block that each new block is compressed with, in
+/// addition to its predecessor. The provided methods are those of the Argon2
+/// spec.
+pub trait Indexing: 'static {
+    /// Whether the pseudo-random values `(j1, j2)` of segment (`lane`,
+    /// `slice`) in pass `pass` are drawn from Argon2i's generator, rather
+    /// than taken from the first word of the previous block.
+    fn data_independent(variant: Variant, pass: u32, slice: u32, slices: u32)
+                        -> bool {
+        match variant {
+            Variant::Argon2i => true,
+            Variant::Argon2d => false,
+            Variant::Argon2id => pass == 0 && slice < slices / 2,
+        }
+    }
+
+    /// The (lane, column) of the block to reference from `pos`, given
+    /// pseudo-random values `j`. Hashing panics unless it satisfies
+    /// `pos.may_reference`.
+    fn reference(pos: &Position, j: (u32, u32)) -> (u32, u32) {
+        argon2::reference(pos, j)
+    }
+}
+
+/// The indexing of the Argon2 spec (section 3.3).
+pub struct Spec;
+
+impl Indexing for Spec {}
+
+#[cfg(all(test, feature = "research"))]
+mod test {
+    use super::{Indexing, Position, Spec};
+    use argon2::{Argon2, Variant};
+
+    // References one of the `slicelen` blocks before the previous one, in the
+    // same lane, whatever `j2`.
+    struct Windowed;
+
+    impl Indexing for Windowed {
+        fn reference(pos: &Position, (j1, _): (u32, u32)) -> (u32, u32) {
+            let (col, lanelen) = (pos.column(), pos.slices * pos.slicelen);
+            let window = match pos.pass {
+                0 => pos.slicelen.min(col - 1),
+                _ => pos.slicelen,
+            };
+            (pos.lane, (col + lanelen - 2 - j1 % window) % lanelen)
+        }
+    }
+
+    // References a block of another lane that is being written.
+    struct Racy;
+
+    impl Indexing for Racy {
+        fn reference(pos: &Position, _: (u32, u32)) -> (u32, u32) {
+            ((pos.lane + 1) % pos.lanes, pos.column())
+        }
+    }
+
+    fn hash<I: Indexing>(a2: &Argon2) -> [u8; 32] {
+        let mut out = [0; 32];
+        a2.hash_with_indexing::<I>(&mut out, b"password", b"somesalt", &[],
+                                   &[]);
+        out
+    }
+
+    #[test]
+    fn custom_indexing() {
+        for &v in [Variant::Argon2d, Variant::Argon2i, Variant::Argon2id]
+                      .iter() {
+            let a2 = Argon2::new(2, 2, 16, v).unwrap();
+            let mut exp = [0; 32];
+            a2.hash(&mut exp, b"password", b"somesalt", &[], &[]);
+            assert_eq!(hash::<Spec>(&a2), exp);
+            assert!(hash::<Windowed>(&a2) != exp);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn racy_indexing() {
+        let a2 = Argon2::new(1, 2, 16, Variant::Argon2d).unwrap();
+        hash::<Racy>(&a2);
+    }
+
+    #[test]
+    fn may_reference() {
+        let pos = Position {
+            pass: 0,
+            lane: 1,
+            slice: 2,
+            idx: 1,
+            lanes: 2,
+            slicelen: 4,
+            slices: 4,
+        };
+        assert!(pos.may_reference((1, 8)));
+        assert!(!pos.may_reference((1, 9)));
+        assert!(pos.may_reference((0, 7)));
+        assert!(!pos.may_reference((0, 8)));
+        assert!(!pos.may_reference((2, 0)));
+        let later = Position { pass: 1, ..pos };
+        assert!(later.may_reference((1, 10)));
+        assert!(later.may_reference((0, 12)));
+        assert!(!later.may_reference((0, 11)));
+        assert!(!later.may_reference((1, 16)));
+    }
+}
@@ -0,0 +1,197 @@
+//! Argon2i's data-independent indexing, for researchers analyzing tradeoff
+//! attacks on concrete parameters. Enabled by the `internals` feature. Nothing
+//! here is needed to compute hashes.
+//!
+//! Terminology follows the layout documented in `argon2.rs`: the block matrix
+//! has `lanes` rows of `lanelen` columns, and each lane is cut into four
+//! slices of `slicelen = lanelen / 4` columns. The part of a lane within a
+//! slice is a segment, and blocks are addressed within their segment by
+//! `idx`.
+
+use argon2::{self, Gen2i, SLICES_PER_LANE, Variant};
+use indexing::Position;
+use params::Params;
+
+/// Maps the pseudo-random values `(j1, j2)` of block `idx` of segment
+/// (`lane`, `slice`) in pass `pass` to the column of its reference block
+/// within the reference lane, as specified in section 3.3 of the Argon2 spec.
+/// This is the raw indexing function: the reference lane is `lane` in the
+/// first slice of the first pass and `j2 % lanes` otherwise. Cf. `references`
+/// for both coordinates at once.
+pub fn index_alpha(pass: u32, lane: u32, slice: u32, lanes: u32, idx: u32,
+                   slicelen: u32, (j1, j2): (u32, u32))
+                   -> u32 {
+    let pos = Position {
+        pass,
+        lane,
+        slice,
+        idx,
+        lanes,
+        slicelen,
+        slices: SLICES_PER_LANE,
+    };
+    argon2::index_alpha(&pos, j1, j2)
+}
+
+/// The stream of `(j1, j2)` pairs that Argon2i draws from for one segment,
+/// starting at `idx` 0. The stream is endless, but only its first `slicelen`
+/// items are used.
+pub struct IndexGen(Gen2i);
+
+impl IndexGen {
+    /// The stream for segment (`lane`, `slice`) in pass `pass` under `params`.
+    /// The variant of `params` is ignored.
+    pub fn new(params: &Params, pass: u32, lane: u32, slice: u32) -> IndexGen {
+        let totblocks = params.lanes() * params.lanelen();
+        IndexGen(Gen2i::new(0, pass, lane, slice, totblocks, params.passes(),
+                            Variant::Argon2i))
+    }
+}
+
+impl Iterator for IndexGen {
+    type Item = (u32, u32);
+
+    fn next(&mut self) -> Option<(u32, u32)> { Some(self.0.nextj()) }
+}
+
+/// A block computation: the block at `idx` of segment (`lane`, `slice`) in
+/// pass `pass` is computed from its predecessor in the same lane and from the
+/// block at (`ref_lane`, `ref_col`), both as matrix coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reference {
+    pub pass: u32,
+    pub lane: u32,
+    pub slice: u32,
+    pub idx: u32,
+    pub ref_lane: u32,
+    pub ref_col: u32,
+}
+
+/// Enumerates every block computation of an Argon2i hash under `params`, in an
+/// order consistent with the dependencies between them: pass by pass, slice by
+/// slice, and lane by lane within each slice. The first two blocks of each
+/// lane are derived from H0 rather than computed, and are skipped. Returns
+/// `None` for Argon2d and Argon2id, whose references depend on the data being
+/// hashed (for Argon2id, past the first half of the first pass).
+pub fn references(params: &Params) -> Option<References> {
+    match params.variant() {
+        Variant::Argon2d | Variant::Argon2id => None,
+        Variant::Argon2i => {
+            let mut rv = References {
+                params: *params,
+                slicelen: params.lanelen() / SLICES_PER_LANE,
+                pass: 0,
+                slice: 0,
+                lane: 0,
+                idx: 0,
+                gen: IndexGen::new(params, 0, 0, 0),
+            };
+            rv.start_segment();
+            Some(rv)
+        }
+    }
+}
+
+/// Iterator returned by `references`.
+pub struct References {
+    params: Params,
+    slicelen: u32,
+    pass: u32,
+    slice: u32,
+    lane: u32,
+    idx: u32,
+    gen: IndexGen,
+}
+
+impl References {
+    fn start_segment(&mut self) {
+        self.gen = IndexGen::new(&self.params, self.pass, self.lane,
+                                 self.slice);
+        self.idx = 0;
+        if self.pass == 0 && self.slice == 0 {
+            self.gen.nth(1);
+            self.idx = 2;
+        }
+    }
+}
+
+impl Iterator for References {
+    type Item = Reference;
+
+    fn next(&mut self) -> Option<Reference> {
+        while self.idx == self.slicelen {
+            self.lane += 1;
+            if self.lane == self.params.lanes() {
+                self.lane = 0;
+                self.slice += 1;
+                if self.slice == SLICES_PER_LANE {
+                    self.slice = 0;
+                    self.pass += 1;
+                }
+            }
+            if self.pass == self.params.passes() {
+                return None;
+            }
+            self.start_segment();
+        }
+
+        let j = self.gen.next().unwrap();
+        let pos = Position {
+            pass: self.pass,
+            lane: self.lane,
+            slice: self.slice,
+            idx: self.idx,
+            lanes: self.params.lanes(),
+            slicelen: self.slicelen,
+            slices: SLICES_PER_LANE,
+        };
+        let (ref_lane, ref_col) = argon2::reference(&pos, j);
+        let rv = Reference {
+            pass: self.pass,
+            lane: self.lane,
+            slice: self.slice,
+            idx: self.idx,
+            ref_lane,
+            ref_col,
+        };
+        self.idx += 1;
+        Some(rv)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{IndexGen, references};
+    use argon2::Variant;
+    use params::Params;
+
+    #[test]
+    fn covers_every_block() {
+        let params = Params::new(2, 2, 64, Variant::Argon2i).unwrap();
+        let (lanes, lanelen) = (params.lanes(), params.lanelen());
+        let refs: Vec<_> = references(&params).unwrap().collect();
+        assert_eq!(refs.len() as u32, 2 * lanes * lanelen - 2 * lanes);
+
+        for r in &refs {
+            let col = r.slice * lanelen / 4 + r.idx;
+            assert!(r.ref_lane < lanes && r.ref_col < lanelen);
+            assert!((r.ref_lane, r.ref_col) != (r.lane, col));
+            if r.pass == 0 && r.slice == 0 {
+                assert!(r.ref_lane == r.lane && r.ref_col < col);
+            }
+        }
+
+        let d = Params::new(2, 2, 64, Variant::Argon2d).unwrap();
+        assert!(references(&d).is_none());
+    }
+
+    #[test]
+    fn index_stream_is_deterministic() {
+        let params = Params::new(1, 1, 8, Variant::Argon2i).unwrap();
+        let xs: Vec<_> = IndexGen::new(&params, 0, 0, 1).take(200).collect();
+        let ys: Vec<_> = IndexGen::new(&params, 0, 0, 1).take(200).collect();
+        let zs: Vec<_> = IndexGen::new(&params, 0, 0, 2).take(200).collect();
+        assert_eq!(xs, ys);
+        assert!(xs != zs);
+    }
+}
@@ -0,0 +1,244 @@
+//! JSON representations of `Params` and `Encoded`, for REST APIs and audit
+//! tooling that exchange hash metadata rather than PHC strings. Both types also
+//! implement serde's `Serialize` and `Deserialize` with the same schema, so
+//! they can be embedded in other serde-encoded structures.
+//!
+//! Parameters are represented as
+//!
+//! ```json
+//! {"variant": "argon2i", "version": 19, "m": 4096, "t": 3, "p": 1}
+//! ```
+//!
+//! where `version` is 16 (0x10) or 19 (0x13). Encoded hashes add the fields
+//! `salt_b64` and `hash_b64`, plus `keyid_b64`, `data_b64`, `wrap`, and
+//! `extra` when present. Binary fields use the standard base64 alphabet
+//! without padding, as in PHC strings. `extra` holds `Encoded::extra_params`
+//! as `[name, value]` pairs.
+
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use serde_json;
+use argon2::{Argon2, Variant, Version};
+use limits::MIN_SALT_LEN;
+use params::Params;
+use verifier::{Encoded, base64_no_pad, debase64_no_pad, is_extra_param};
+
+#[derive(Serialize, Deserialize)]
+struct ParamsRepr {
+    variant: String,
+    version: u32,
+    m: u32,
+    t: u32,
+    p: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncodedRepr {
+    #[serde(flatten)]
+    params: ParamsRepr,
+    salt_b64: String,
+    hash_b64: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    keyid_b64: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    data_b64: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    wrap: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    extra: Vec<(String, String)>,
+}
+
+impl<'a> From<&'a Params> for ParamsRepr {
+    fn from(p: &'a Params) -> ParamsRepr {
+        let variant = match p.variant() {
+            Variant::Argon2i => "argon2i",
+            Variant::Argon2d => "argon2d",
+            Variant::Argon2id => "argon2id",
+        };
+        ParamsRepr {
+            variant: variant.to_string(),
+            version: p.version() as u32,
+            m: p.kib(),
+            t: p.passes(),
+            p: p.lanes(),
+        }
+    }
+}
+
+// Parses a variant by its name in PHC strings.
+pub(crate) fn variant<E: de::Error>(name: &str) -> Result<Variant, E> {
+    match name {
+        "argon2i" => Ok(Variant::Argon2i),
+        "argon2d" => Ok(Variant::Argon2d),
+        "argon2id" => Ok(Variant::Argon2id),
+        v => Err(E::unknown_variant(v, &["argon2i", "argon2d", "argon2id"])),
+    }
+}
+
+impl ParamsRepr {
+    fn params<E: de::Error>(&self) -> Result<Params, E> {
+        let variant = variant(&self.variant)?;
+        let version = match self.version {
+            0x10 => Version::_0x10,
+            0x13 => Version::_0x13,
+            v => return Err(E::custom(format!("unknown version {}", v))),
+        };
+        Params::with_version(self.t, self.p, self.m, variant, version)
+            .map_err(E::custom)
+    }
+}
+
+fn b64(bytes: &[u8]) -> String {
+    String::from_utf8(base64_no_pad(bytes)).unwrap()
+}
+
+fn unb64<E: de::Error>(field: &'static str, s: &str) -> Result<Vec<u8>, E> {
+    match s {
+        "" => Ok(vec![]),
+        _ => debase64_no_pad(s.as_bytes()).ok_or_else(|| {
+            E::custom(format!("invalid base64 in {}", field))
+        }),
+    }
+}
+
+impl Serialize for Params {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        ParamsRepr::from(self).serialize(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for Params {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Params, D::Error> {
+        ParamsRepr::deserialize(d)?.params()
+    }
+}
+
+impl Serialize for Encoded {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        EncodedRepr {
+            params: ParamsRepr::from(&self.params.to_params()),
+            salt_b64: b64(&self.salt),
+            hash_b64: b64(&self.hash),
+            keyid_b64: b64(&self.key),
+            data_b64: b64(&self.data),
+            wrap: self.wrap.clone(),
+            extra: self.extra.clone(),
+        }
+        .serialize(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for Encoded {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Encoded, D::Error> {
+        let repr = EncodedRepr::deserialize(d)?;
+        let params = repr.params.params()?;
+        if let Some(ref w) = repr.wrap {
+            let ok = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() ||
+                               c == '-';
+            if w.is_empty() || !w.chars().all(ok) {
+                return Err(de::Error::custom("invalid wrap scheme"));
+            }
+        }
+        if !repr.extra.iter().all(|(n, v)| is_extra_param(n, v)) {
+            return Err(de::Error::custom("invalid extra parameter"));
+        }
+        let salt = unb64("salt_b64", &repr.salt_b64)?;
+        if salt.len() < MIN_SALT_LEN {
+            return Err(de::Error::custom("salt too short"));
+        }
+        Ok(Encoded {
+            params: Argon2::with_params(params),
+            salt,
+            hash: unb64("hash_b64", &repr.hash_b64)?,
+            key: unb64("keyid_b64", &repr.keyid_b64)?,
+            data: unb64("data_b64", &repr.data_b64)?,
+            wrap: repr.wrap,
+            extra: repr.extra,
+        })
+    }
+}
+
+impl Params {
+    /// Serializes these parameters as JSON. See the `json` module for the
+    /// schema.
+    pub fn to_json(&self) -> String { serde_json::to_string(self).unwrap() }
+
+    /// Parses and validates parameters serialized by `Params::to_json`.
+    pub fn from_json(json: &str) -> Result<Params, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+impl Encoded {
+    /// Serializes this hashing session as JSON. See the `json` module for the
+    /// schema.
+    pub fn to_json(&self) -> String { serde_json::to_string(self).unwrap() }
+
+    /// Reconstructs a hashing session serialized by `Encoded::to_json`.
+    pub fn from_json(json: &str) -> Result<Encoded, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use argon2::Variant;
+    use params::Params;
+    use verifier::Encoded;
+
+    #[test]
+    fn params_roundtrip() {
+        let p = Params::new(3, 4, 4096, Variant::Argon2d).unwrap();
+        let json = p.to_json();
+        assert_eq!(json,
+                   r#"{"variant":"argon2d","version":19,"m":4096,"t":3,"p":4}"#);
+        assert_eq!(Params::from_json(&json).unwrap(), p);
+
+        let bad = [r#"{"variant":"argon2x","version":19,"m":8,"t":1,"p":1}"#,
+                   r#"{"variant":"argon2i","version":17,"m":8,"t":1,"p":1}"#,
+                   r#"{"variant":"argon2i","version":19,"m":8,"t":0,"p":1}"#,
+                   r#"{"variant":"argon2i","version":19,"m":8,"t":1}"#];
+        for json in bad.iter() {
+            assert!(Params::from_json(json).is_err());
+        }
+    }
+
+    #[test]
+    fn encoded_roundtrip() {
+        let phc = b"$argon2i$v=19,m=4096,t=3,p=1$dG9kbzogZnV6eiB0ZXN0cw\
+                    $AvsXI+N78kGHzeGwzz0VTjfBdl7MmgvBGfJ/XXyqLbA";
+        let enc = Encoded::from_u8(phc).unwrap();
+        let json = enc.to_json();
+        assert_eq!(json,
+                   r#"{"variant":"argon2i","version":19,"m":4096,"t":3,"p":1,"#
+                   .to_string() +
+                   r#""salt_b64":"dG9kbzogZnV6eiB0ZXN0cw","# +
+                   r#""hash_b64":"AvsXI+N78kGHzeGwzz0VTjfBdl7MmgvBGfJ/XXyqLbA"}"#);
+        let dec = Encoded::from_json(&json).unwrap();
+        assert_eq!(&dec.to_u8()[..], &phc[..]);
+        assert!(dec.verify(b"argon2i!"));
+
+        let with_extras = Encoded::from_u8(b"$argon2d$v=16,m=8,t=1,p=1\
+                                             ,keyid=a2V5,wrap=sha1,data=eA\
+                                             $c2FsdHNhbHQ$aGFzaA")
+                              .unwrap();
+        let json = with_extras.to_json();
+        assert!(json.contains(r#""keyid_b64":"a2V5","data_b64":"eA","#));
+        assert!(json.contains(r#""wrap":"sha1""#));
+        let dec = Encoded::from_json(&json).unwrap();
+        assert_eq!(dec.to_u8(), with_extras.to_u8());
+
+        assert!(Encoded::from_json(&json.replace("a2V5", "a2V5*")).is_err());
+        // a 4-byte salt, which `verify` couldn't hash with.
+        let short = json.replace("c2FsdHNhbHQ", "c2FsdA");
+        assert!(Encoded::from_json(&short).is_err());
+
+        let unknown = Encoded::from_u8(b"$argon2d$v=19,m=8,t=1,p=1,x-new=1.5\
+                                         $c2FsdHNhbHQ$aGFzaA")
+                          .unwrap();
+        let json = unknown.to_json();
+        assert!(json.contains(r#""extra":[["x-new","1.5"]]"#));
+        assert_eq!(Encoded::from_json(&json).unwrap().to_u8(), unknown.to_u8());
+        assert!(Encoded::from_json(&json.replace("x-new", "m")).is_err());
+    }
+}
@@ -0,0 +1,252 @@
+//! Key derivation from passphrases, as opposed to password storage.
+//!
+//! Deriving an encryption key (say, for a password manager's vault) from a
+//! passphrase needs the same salt every time, yet there is often nowhere to
+//! keep a random one before the key is available. `derive_salt` computes a
+//! salt from a context string naming the application and purpose, plus an
+//! identifier that is unique per passphrase, such as an account id. Distinct
+//! (context, id) pairs give unrelated salts, so precomputed attacks must still
+//! target one account at a time.
+//!
+//! These salts are predictable by design and must not be used for password
+//! hashes kept in a database, where `verifier::Encoded` with a random salt is
+//! the right tool.
+//!
+//! `derive_key` turns a passphrase into a 256-bit key for disk or file
+//! encryption. The key depends on the passphrase, the salt, and every one of
+//! the cost parameters, so an encryption tool must store the salt and the
+//! resolved `Params` (not just a preset name, nor "whatever the defaults
+//! are") alongside the ciphertext, e.g. in the volume header, and derive the
+//! key from exactly those on every unlock with `derive_key_with_params`.
+//! Raising the cost later means re-deriving with new parameters and
+//! re-encrypting, or re-wrapping, the data key.
+//!
+//! Keys are returned as `SecretOutput`s, which wipe themselves when dropped.
+//! Callers that manage their own key buffers can hash into them directly with
+//! `Argon2::hash` instead.
+//!
+//! Applications that both authenticate a user and decrypt their data with the
+//! same passphrase can get a verification tag and an encryption key out of a
+//! single Argon2 run with `derive_tag_and_key`, rather than paying for two.
+//! Where the tag is stored as an ordinary `Encoded` hash, use
+//! `Encoded::verify_with_output` to check the passphrase and get the same key.
+//!
+//! Argon2 is far too slow to run per device or per session. To log in once
+//! and key many sessions, derive one master key as above and expand it with
+//! `derive_session_key`, which is cheap and gives unrelated keys for distinct
+//! session ids.
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use blake2::Blake2b;
+use argon2::{Argon2, h_prime, len32, wipe};
+use params::{Params, Preset};
+use limits::{MAX_TAG_LEN, MIN_TAG_LEN};
+use verifier::constant_eq;
+
+/// Length of the salts returned by `derive_salt`.
+pub const SALT_LEN: usize = 16;
+
+/// Length of the keys returned by `derive_key`.
+pub const KEY_LEN: usize = 32;
+
+// Separates these salts from other uses of Blake2b on the same inputs.
+const SALT_DOMAIN: &[u8] = b"argon2rs kdf salt";
+
+// Separates session keys from other uses of H′ on the same inputs.
+const SESSION_DOMAIN: &[u8] = b"argon2rs kdf session key";
+
+// Prepended to the final block by `derive_tag_and_key`, and for the key, by
+// `Encoded::verify_with_output`.
+const TAG_LABEL: &[u8] = b"argon2rs kdf tag";
+pub(crate) const KEY_LABEL: &[u8] = b"argon2rs kdf key";
+
+/// Secret bytes, such as a derived key, that are wiped when dropped. Derefs
+/// to `[u8]`. Formatting with `{:?}` shows only the length, and comparisons
+/// take constant time.
+pub struct SecretOutput(Vec<u8>);
+
+impl SecretOutput {
+    /// `len` zero bytes, to be filled in place, e.g. by `Argon2::hash`.
+    pub fn zeroed(len: usize) -> SecretOutput { SecretOutput(vec![0; len]) }
+}
+
+impl Deref for SecretOutput {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] { &self.0[..] }
+}
+
+impl DerefMut for SecretOutput {
+    fn deref_mut(&mut self) -> &mut [u8] { &mut self.0[..] }
+}
+
+impl AsRef<[u8]> for SecretOutput {
+    fn as_ref(&self) -> &[u8] { &self.0[..] }
+}
+
+impl PartialEq for SecretOutput {
+    fn eq(&self, other: &SecretOutput) -> bool {
+        constant_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for SecretOutput {}
+
+impl fmt::Debug for SecretOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretOutput({} bytes)", self.0.len())
+    }
+}
+
+impl Drop for SecretOutput {
+    fn drop(&mut self) { wipe(&mut self.0); }
+}
+
+/// Derives a deterministic salt for key derivation. `context` should be fixed
+/// for an application and purpose, e.g. `"example.com vault key"`, and `id`
+/// must be unique per passphrase, e.g. the user's account id. See the module
+/// documentation.
+pub fn derive_salt(context: &str, id: &[u8]) -> [u8; SALT_LEN] {
+    let mut rv = [0; SALT_LEN];
+    let mut b = Blake2b::new(SALT_LEN);
+    for &field in [SALT_DOMAIN, context.as_bytes(), id].iter() {
+        b.update(&len32(field));
+        b.update(field);
+    }
+    b.finalize_into(&mut rv);
+    rv
+}
+
+/// Derives a key from `passphrase` and `salt` under one of the Argon2id
+/// presets. `Preset::Sensitive` or `Preset::Rfc9106` suit disk encryption,
+/// where unlocking is rare and may take seconds. Store `preset.params()`
+/// with the encrypted data; see the module documentation.
+pub fn derive_key(passphrase: &[u8], salt: &[u8], preset: Preset)
+                  -> SecretOutput {
+    derive_key_with_params(passphrase, salt, &preset.params())
+}
+
+/// Derives a `KEY_LEN`-byte key from `passphrase` and `salt` under previously
+/// stored `params`. The salt must be at least 8 bytes.
+pub fn derive_key_with_params(passphrase: &[u8], salt: &[u8], params: &Params)
+                              -> SecretOutput {
+    let mut rv = SecretOutput::zeroed(KEY_LEN);
+    Argon2::with_params(*params).hash(&mut rv, passphrase, salt, &[], &[]);
+    rv
+}
+
+/// Runs `argon` once over `passphrase` and `salt`, and derives from the result
+/// both a verification tag, written to `tag`, and an encryption key, written
+/// to `key`. Each is the final H′ compression over a distinct label followed
+/// by the last block, so knowing the tag (e.g. as stored on a server) reveals
+/// nothing about the key. Both lengths must be between 4 and 2^32 - 1.
+///
+/// The tag differs from the output of `Argon2::hash` on the same inputs, and
+/// is to be compared with `verifier::constant_eq`.
+pub fn derive_tag_and_key(argon: &Argon2, passphrase: &[u8], salt: &[u8],
+                          tag: &mut [u8], key: &mut [u8]) {
+    let state = argon.hash_unfinalized(tag.len(), passphrase, salt, &[], &[]);
+    state.finalize_labeled(TAG_LABEL, tag);
+    state.finalize_labeled(KEY_LABEL, key);
+}
+
+/// Expands `master`, a secret key derived with Argon2 (e.g. by `derive_key`,
+/// or as the key of `derive_tag_and_key`), into a `len`-byte key for the
+/// session or device `session_id`. This is a single keyed hash (Argon2's H′
+/// over the length-prefixed domain, master key and id), so it adds nothing to
+/// the cost of guessing the passphrase: `master` must never be stored or sent
+/// where a verification tag would be. `len` must be between 4 and 2^32 - 1.
+pub fn derive_session_key(master: &[u8], session_id: &[u8], len: usize)
+                          -> SecretOutput {
+    assert!((MIN_TAG_LEN..=MAX_TAG_LEN).contains(&len));
+    let mut input = vec![];
+    for &field in [SESSION_DOMAIN, master, session_id].iter() {
+        input.extend_from_slice(&len32(field));
+        input.extend_from_slice(field);
+    }
+    let mut rv = SecretOutput::zeroed(len);
+    h_prime(&mut rv, &input);
+    wipe(&mut input);
+    rv
+}
+
+#[cfg(test)]
+mod test {
+    use super::{derive_key_with_params, derive_salt, derive_session_key,
+                derive_tag_and_key};
+    use argon2::{Argon2, Variant};
+    use params::{Params, Preset};
+
+    #[test]
+    fn test_derive_salt() {
+        let salt = derive_salt("example.com vault key", b"alice@example.com");
+        assert_eq!(salt,
+                   [0x62, 0xa8, 0xd5, 0xb2, 0xf4, 0x3d, 0xb3, 0xd6, 0xcd, 0x87,
+                    0x34, 0x28, 0x48, 0x32, 0xc0, 0x06]);
+        // fields are length-prefixed, so moving bytes between them matters.
+        assert!(derive_salt("ab", b"c") != derive_salt("a", b"bc"));
+        assert!(derive_salt("ctx", b"alice") != derive_salt("ctx", b"bob"));
+    }
+
+    #[test]
+    fn test_derive_key() {
+        let params = Params::new(1, 1, 8, Variant::Argon2id).unwrap();
+        let salt = derive_salt("test", b"id");
+        let key = derive_key_with_params(b"passphrase", &salt, &params);
+        let mut exp = [0; 32];
+        Argon2::with_params(params).hash(&mut exp, b"passphrase", &salt, &[],
+                                         &[]);
+        assert_eq!(&key[..], &exp[..]);
+        assert!(key != derive_key_with_params(b"passphrasf", &salt, &params));
+        assert_eq!(format!("{:?}", key), "SecretOutput(32 bytes)");
+    }
+
+    #[test]
+    fn presets() {
+        let p = Preset::Rfc9106.params();
+        assert_eq!((p.variant(), p.kib(), p.passes(), p.lanes()),
+                   (Variant::Argon2id, 2 << 20, 1, 4));
+        let p = Preset::Interactive.params();
+        assert_eq!((p.variant(), p.kib(), p.passes(), p.lanes()),
+                   (Variant::Argon2id, 64 << 10, 2, 1));
+    }
+
+    #[test]
+    fn tag_and_key() {
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2id).unwrap();
+        let (mut tag, mut key) = ([0; 32], [0; 32]);
+        derive_tag_and_key(&a2, b"passphrase", b"somesalt", &mut tag, &mut key);
+        assert!(tag != key);
+
+        let mut plain = [0; 32];
+        a2.hash(&mut plain, b"passphrase", b"somesalt", &[], &[]);
+        assert!(tag != plain && key != plain);
+
+        let (mut tag2, mut key2) = ([0; 32], [0; 64]);
+        derive_tag_and_key(&a2, b"passphrase", b"somesalt", &mut tag2,
+                           &mut key2);
+        assert_eq!(tag, tag2);
+        assert!(key[..] != key2[..32]);
+    }
+
+    #[test]
+    fn session_keys() {
+        let master = [7; 32];
+        let k1 = derive_session_key(&master, b"laptop", 32);
+        assert_eq!(k1, derive_session_key(&master, b"laptop", 32));
+        assert!(k1 != derive_session_key(&master, b"phone", 32));
+        assert!(k1 != derive_session_key(&[8; 32], b"laptop", 32));
+        // fields are length-prefixed.
+        assert!(derive_session_key(b"ab", b"c", 32) !=
+                derive_session_key(b"a", b"bc", 32));
+
+        let long = derive_session_key(&master, b"laptop", 100);
+        assert_eq!(long.len(), 100);
+        assert!(long[..32] != k1[..]);
+        assert_eq!(&k1[..],
+                   &[0xc5, 0x2e, 0x59, 0x73, 0x9f, 0x80, 0x32, 0x1a, 0x46, 0x1a,
+                     0x72, 0xab, 0xb4, 0x08, 0xb7, 0x46, 0x6c, 0xb7, 0xea, 0x3a,
+                     0x2e, 0xc8, 0x87, 0xe8, 0xae, 0xbd, 0x9f, 0x99, 0x4f, 0x57,
+                     0xf6, 0x8b]);
+    }
+}
@@ -0,0 +1,180 @@
+//! Pepper rotation.
+//!
+//! A pepper is a secret value mixed into every hash (Argon2's `k` input) but
+//! stored apart from the hashes themselves, so that a leaked user table alone
+//! can't be attacked offline. Peppers should be rotated like any other key. A
+//! `KeyRing` holds the current pepper along with retired ones, each under a
+//! short, non-secret key id. New hashes are made with the active pepper and
+//! record its key id in their encoding; old hashes keep verifying against the
+//! pepper named by their key id, and can be rehashed at the next successful
+//! login (see `KeyRing::needs_rekey`) instead of all at once.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use argon2::{Argon2, wipe};
+use limits::MAX_SECRET_LEN;
+use verifier::Encoded;
+
+/// Returned for a pepper that can't be added to a `KeyRing`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum KeyErr {
+    EmptyKeyId,
+    SecretTooLong,
+}
+
+impl fmt::Display for KeyErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::KeyErr::*;
+        match *self {
+            EmptyKeyId => write!(f, "Key ids must be non-empty."),
+            SecretTooLong => {
+                write!(f, "Peppers must be <= {} bytes.", MAX_SECRET_LEN)
+            }
+        }
+    }
+}
+
+impl Error for KeyErr {}
+
+/// Maps key ids to peppers, one of which is active. Peppers are wiped on drop.
+pub struct KeyRing {
+    keys: HashMap<Vec<u8>, Vec<u8>>,
+    active: Vec<u8>,
+}
+
+impl KeyRing {
+    /// Creates a key ring whose active pepper is `secret`, identified by
+    /// `keyid`. `secret` must be at most `limits::MAX_SECRET_LEN` bytes, as
+    /// with `Argon2::hash`; `keyid` must be non-empty, since an empty key id
+    /// denotes an unpeppered hash.
+    pub fn new(keyid: &[u8], secret: &[u8]) -> Result<KeyRing, KeyErr> {
+        let mut rv = KeyRing {
+            keys: HashMap::new(),
+            active: keyid.to_vec(),
+        };
+        rv.insert(keyid, secret)?;
+        Ok(rv)
+    }
+
+    /// Adds (or replaces) the pepper known as `keyid`, without changing which
+    /// one is active. Fails under the same conditions as `KeyRing::new`.
+    pub fn insert(&mut self, keyid: &[u8], secret: &[u8])
+                  -> Result<(), KeyErr> {
+        if keyid.is_empty() {
+            return Err(KeyErr::EmptyKeyId);
+        } else if secret.len() > MAX_SECRET_LEN {
+            return Err(KeyErr::SecretTooLong);
+        }
+        let old = self.keys.insert(keyid.to_vec(), secret.to_vec());
+        if let Some(mut old) = old {
+            wipe(&mut old);
+        }
+        Ok(())
+    }
+
+    /// Makes the pepper known as `keyid` the one used for new hashes. Returns
+    /// `false`, leaving the active key unchanged, if no such pepper exists.
+    pub fn set_active(&mut self, keyid: &[u8]) -> bool {
+        if self.keys.contains_key(keyid) {
+            self.active = keyid.to_vec();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The key id of the active pepper.
+    pub fn active(&self) -> &[u8] { &self.active[..] }
+
+    /// Same as `Encoded::new`, except that the password is hashed with the
+    /// active pepper and only its key id is recorded in the result.
+    pub fn encode(&self, argon: Argon2, p: &[u8], s: &[u8], x: &[u8])
+                  -> Encoded {
+        let secret = &self.keys[&self.active];
+        Encoded::with_keyid(argon, p, s, secret, &self.active, x)
+    }
+
+    /// Verifies `p` against `enc` using the pepper named by its key id. Hashes
+    /// without a key id are verified without a pepper. Returns `false` if the
+    /// named pepper isn't in this key ring.
+    pub fn verify(&self, enc: &Encoded, p: &[u8]) -> bool {
+        match enc.keyid() {
+            [] => enc.verify(p),
+            keyid => match self.keys.get(keyid) {
+                Some(secret) => enc.verify_with_secret(p, secret),
+                None => enc.rejected(),
+            },
+        }
+    }
+
+    /// Whether `enc` was made with anything other than the active pepper, and
+    /// should therefore be rehashed once its password is known.
+    pub fn needs_rekey(&self, enc: &Encoded) -> bool {
+        enc.keyid() != self.active()
+    }
+}
+
+impl Drop for KeyRing {
+    fn drop(&mut self) {
+        for secret in self.keys.values_mut() {
+            wipe(secret);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{KeyErr, KeyRing};
+    use argon2::{Argon2, Variant};
+    use verifier::Encoded;
+
+    #[test]
+    fn rotation() {
+        let mut ring = KeyRing::new(b"2016", b"old pepper").unwrap();
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2i).unwrap();
+        let old = ring.encode(a2, b"hunter2", b"saltsalt", b"");
+        assert!(ring.verify(&old, b"hunter2"));
+        assert!(!ring.needs_rekey(&old));
+        // the pepper itself must never end up in the encoding.
+        let enc = String::from_utf8(old.to_u8()).unwrap();
+        assert!(enc.contains(",keyid=MjAxNg$"));
+        let old = Encoded::from_u8(enc.as_bytes()).unwrap();
+
+        ring.insert(b"2017", b"new pepper").unwrap();
+        assert!(ring.set_active(b"2017"));
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2i).unwrap();
+        let new = ring.encode(a2, b"hunter2", b"saltsalt", b"");
+        assert!(ring.verify(&old, b"hunter2"));
+        assert!(ring.verify(&new, b"hunter2"));
+        assert!(!ring.verify(&new, b"hunter3"));
+        assert!(ring.needs_rekey(&old));
+        assert!(!ring.needs_rekey(&new));
+
+        assert!(!ring.set_active(b"2018"));
+        assert_eq!(ring.active(), b"2017");
+    }
+
+    #[test]
+    fn unknown_and_missing_keyids() {
+        let ring = KeyRing::new(b"a", b"pepper").unwrap();
+        let other = KeyRing::new(b"b", b"pepper").unwrap();
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2i).unwrap();
+        let enc = other.encode(a2, b"hunter2", b"saltsalt", b"");
+        assert!(!ring.verify(&enc, b"hunter2"));
+
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2i).unwrap();
+        let plain = Encoded::new(a2, b"hunter2", b"saltsalt", b"", b"");
+        assert!(ring.verify(&plain, b"hunter2"));
+        assert!(ring.needs_rekey(&plain));
+    }
+
+    #[test]
+    fn bad_peppers() {
+        assert_eq!(KeyRing::new(b"", b"pepper").err(),
+                   Some(KeyErr::EmptyKeyId));
+        let mut ring = KeyRing::new(b"a", &[0; 32]).unwrap();
+        assert_eq!(ring.insert(b"b", &[0; 33]), Err(KeyErr::SecretTooLong));
+        assert!(!ring.set_active(b"b"));
+    }
+}
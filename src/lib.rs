@@ -1,11 +1,107 @@
 #![cfg_attr(feature = "simd", feature(repr_simd, platform_intrinsics))]
+#![cfg_attr(all(target_arch = "wasm32", feature = "wasm-threads"),
+            feature(stdarch_wasm_atomic_wait))]
+
+// `minimal` overrides `threaded` and `blake2b_simd`, which may be enabled by
+// default or by another crate, but `simd` only ever by explicit choice.
+#[cfg(all(feature = "minimal", feature = "simd"))]
+compile_error!("The `minimal` and `simd` features are mutually exclusive.");
+
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+#[cfg(feature = "cbor")]
+extern crate ciborium;
+#[cfg(feature = "toml")]
+extern crate toml;
+#[cfg(feature = "metrics")]
+#[macro_use]
+extern crate metrics;
+extern crate getrandom;
+#[cfg(all(feature = "subprocess", unix))]
+extern crate libc;
+#[cfg(feature = "pbe")]
+extern crate chacha20poly1305;
+#[cfg(loom)]
+extern crate loom;
+#[cfg(test)]
+extern crate proptest;
+#[cfg(test)]
+extern crate base64;
 
 mod octword;
 #[macro_use]
 mod block;
 mod argon2;
+mod blake2;
+#[cfg(feature = "internals")]
+pub mod compression;
+#[cfg(not(feature = "internals"))]
+mod compression;
+#[cfg(feature = "research")]
+pub mod indexing;
+#[cfg(not(feature = "research"))]
+mod indexing;
+mod params;
+mod builder;
 mod workers;
 pub mod verifier;
+pub mod pow;
+pub mod cost;
+pub mod keyring;
+pub mod policy;
+pub mod batch;
+pub mod passwd;
+pub mod kdf;
+pub mod limits;
+pub mod memcheck;
+pub mod attestation;
+pub mod audit;
+pub mod cache;
+pub mod hasher;
+pub mod stepwise;
+pub mod salt;
+pub mod rng;
+#[cfg(all(feature = "subprocess", unix))]
+pub mod subprocess;
+#[cfg(feature = "pbe")]
+pub mod pbe;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "serde")]
+pub mod json;
+#[cfg(feature = "cbor")]
+pub mod cbor;
+#[cfg(feature = "serde")]
+pub mod config;
+#[cfg(feature = "internals")]
+pub mod internals;
+#[cfg(feature = "instrumentation")]
+pub mod trace;
+#[cfg(feature = "primitives")]
+pub mod primitives;
+#[cfg(feature = "low_level")]
+pub mod low_level;
+#[cfg(not(feature = "low_level"))]
+mod low_level;
+#[cfg(feature = "self-test")]
+pub mod selftest;
+#[cfg(feature = "wipe_hooks")]
+pub mod wipe_hooks;
+#[cfg(feature = "metrics")]
+pub mod telemetry;
 
-pub use argon2::{Argon2, ParamErr, Variant, argon2d_simple, argon2i_simple,
-                 defaults};
+pub use argon2::{Argon2, CorruptionErr, Flags, ParamErr, Unfinalized,
+                 Variant, Version, argon2d_simple, argon2i_simple, defaults};
+pub use attestation::health_check;
+pub use block::FixedMatrix;
+pub use builder::{Builder, ParamWarning};
+pub use hasher::PasswordHasher;
+pub use params::{EnvErr, Memory, Params, Preset, Rounding};
+pub use salt::Salt;
+pub use cost::{CostEstimate, estimate_cost};
+#[cfg(all(target_arch = "wasm32", feature = "wasm-threads",
+          not(feature = "minimal")))]
+pub use workers::lane_worker;
@@ -0,0 +1,50 @@
+//! The bounds that this crate enforces on Argon2 inputs and parameters, and
+//! the sizes it works in, for validation layers and user interfaces that need
+//! to agree with it. These are the limits of the Argon2 specification (RFC
+//! 9106); `Params::new` and `Argon2::hash` check against exactly these values.
+//!
+//! Some limits are tighter in practice: memory is capped by `Memory::max()`
+//! on platforms with a small address space, and `Salt` accepts at most
+//! `salt::MAX_LEN` bytes so that salts fit in PHC strings.
+
+use argon2::SLICES_PER_LANE;
+use block::ARGON2_BLOCK_BYTES;
+
+/// The size of an Argon2 memory block. The `kib` parameter counts these.
+pub const BLOCK_BYTES: usize = ARGON2_BLOCK_BYTES;
+
+/// The number of slices each lane is split into, which are the points at
+/// which lanes synchronize.
+pub const SYNC_POINTS: u32 = SLICES_PER_LANE;
+
+/// The fewest passes over memory.
+pub const MIN_PASSES: u32 = 1;
+
+/// The fewest lanes.
+pub const MIN_LANES: u32 = 1;
+
+/// The most lanes, 2^24 - 1.
+pub const MAX_LANES: u32 = 0x00ff_ffff;
+
+/// The least memory per lane, two blocks per slice. The `kib` parameter must
+/// be at least this times the number of lanes.
+pub const MIN_MEMORY_PER_LANE_KIB: u32 = 2 * SYNC_POINTS;
+
+/// The most memory that can be encoded, 2^32 - 1 KiB. See also
+/// `Memory::max()`.
+pub const MAX_MEMORY_KIB: u32 = u32::MAX;
+
+/// The shortest salt.
+pub const MIN_SALT_LEN: usize = 8;
+
+/// The longest salt, password, or associated data, 2^32 - 1 bytes.
+pub const MAX_INPUT_LEN: usize = 0xffff_ffff;
+
+/// The longest secret key.
+pub const MAX_SECRET_LEN: usize = 32;
+
+/// The shortest tag.
+pub const MIN_TAG_LEN: usize = 4;
+
+/// The longest tag, 2^32 - 1 bytes.
+pub const MAX_TAG_LEN: usize = 0xffff_ffff;
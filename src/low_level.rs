@@ -0,0 +1,204 @@
+//! Segment-by-segment filling of the block matrix, for callers that schedule
+//! the work themselves, e.g. on their own thread pool or a few segments at a
+//! time between other tasks. Public with the `low_level` feature; see
+//! `stepwise` for the simpler case of filling a slice at a time.
+//!
+//! An Argon2 run fills its matrix one slice at a time, over every pass. Within
+//! a slice, each lane has a segment that depends only on segments of earlier
+//! slices, so the segments of a slice can be filled in any order or all at
+//! once (cf. `workers.rs`). `Instance::segments` hands out the segments of
+//! the next slice as `Send` values; all of them must be filled before the
+//! next slice is requested, which is checked. Once `is_done()`,
+//! `Instance::finalize` computes the tag, which is identical to the output of
+//! `Argon2::hash` on the same inputs.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use argon2::{Argon2, Flags, SLICES_PER_LANE, h_prime};
+use block::Matrix;
+use params::Params;
+
+/// The state of an Argon2 run that is being filled segment by segment.
+pub struct Instance {
+    argon: Argon2,
+    blocks: Matrix,
+    h0: [u8; 72],
+    taglen: usize,
+    lanes: u32,
+    lanelen: u32,
+    passes: u32,
+    // The pass and slice that `segments` hands out next.
+    pass: u32,
+    slice: u32,
+    unfilled: AtomicUsize,
+}
+
+/// The position of a segment in the fill order: slice `slice` of lane `lane`
+/// in pass `pass`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Position {
+    pub pass: u32,
+    pub lane: u32,
+    pub slice: u32,
+}
+
+/// A segment of the slice currently being filled, as handed out by
+/// `Instance::segments`.
+pub struct Segment<'a> {
+    argon: &'a Argon2,
+    blocks: *mut Matrix,
+    h0: [u8; 72],
+    pos: Position,
+    unfilled: &'a AtomicUsize,
+}
+
+// Segments of one slice write disjoint blocks, and read only blocks of earlier
+// slices, which no segment of this slice writes. See `workers.rs`.
+unsafe impl<'a> Send for Segment<'a> {}
+unsafe impl<'a> Sync for Segment<'a> {}
+
+impl Instance {
+    /// Starts a run over the given inputs, which are as in `Argon2::hash`, for
+    /// a tag of `taglen` bytes. The block matrix is allocated here; no blocks
+    /// are computed until segments are filled.
+    pub fn new(params: Params, taglen: usize, p: &[u8], s: &[u8], k: &[u8],
+               x: &[u8])
+               -> Instance {
+        #[cfg(feature = "self-test")]
+        ::selftest::ensure();
+
+        let argon = Argon2::with_params(params);
+        let h0 = argon.h0(taglen, p, s, k, x);
+        let wipe = argon.flags().contains(Flags::CLEAR_MEMORY);
+        Instance {
+            blocks: Matrix::new(params.lanes(), params.lanelen(), wipe),
+            argon,
+            h0,
+            taglen,
+            lanes: params.lanes(),
+            lanelen: params.lanelen(),
+            passes: params.passes(),
+            pass: 0,
+            slice: 0,
+            unfilled: AtomicUsize::new(0),
+        }
+    }
+
+    /// The pass and slice whose segments `segments` returns next, or `None`
+    /// once the last slice of the last pass has been handed out.
+    pub fn next_slice(&self) -> Option<(u32, u32)> {
+        if self.is_done() {
+            None
+        } else {
+            Some((self.pass, self.slice))
+        }
+    }
+
+    /// Whether every slice has been handed out.
+    pub fn is_done(&self) -> bool { self.pass == self.passes }
+
+    /// Hands out the segments of the next slice, one per lane. Panics if a
+    /// segment of the previous slice was dropped without being filled, or if
+    /// `is_done()`.
+    pub fn segments(&mut self) -> Vec<Segment<'_>> {
+        assert!(!self.is_done(), "all slices have been filled");
+        self.check_filled();
+        let (pass, slice) = (self.pass, self.slice);
+        self.slice += 1;
+        if self.slice == SLICES_PER_LANE {
+            self.slice = 0;
+            self.pass += 1;
+        }
+        self.unfilled.store(self.lanes as usize, Ordering::Relaxed);
+
+        let blocks: *mut Matrix = &mut self.blocks;
+        let (argon, h0, unfilled) = (&self.argon, self.h0, &self.unfilled);
+        (0..self.lanes)
+            .map(move |lane| {
+                Segment {
+                    argon,
+                    blocks,
+                    h0,
+                    pos: Position { pass, lane, slice },
+                    unfilled,
+                }
+            })
+            .collect()
+    }
+
+    /// Computes the tag once every segment has been filled, writing it to
+    /// `out`. `out.len()` must be the `taglen` passed to `Instance::new`.
+    pub fn finalize(&self, out: &mut [u8]) {
+        assert!(self.is_done(), "not all slices have been filled");
+        self.check_filled();
+        assert_eq!(out.len(), self.taglen);
+        h_prime(out, self.blocks.xor_column(self.lanelen - 1).as_u8());
+    }
+
+    fn check_filled(&self) {
+        assert!(self.unfilled.load(Ordering::Acquire) == 0,
+                "segments of the previous slice were left unfilled");
+        self.blocks.check_canaries();
+    }
+}
+
+impl<'a> Segment<'a> {
+    #[cfg_attr(not(feature = "low_level"), allow(dead_code))]
+    pub fn position(&self) -> Position { self.pos }
+
+    /// Computes the blocks of this segment.
+    pub fn fill(self) {
+        let blocks = unsafe { &mut *self.blocks };
+        let Position { pass, lane, slice } = self.pos;
+        self.argon.fill_segment(blocks, self.h0, pass, lane, slice);
+        self.unfilled.fetch_sub(1, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use super::Instance;
+    use argon2::{Argon2, Variant};
+    use params::Params;
+
+    #[test]
+    fn matches_hash() {
+        for &v in [Variant::Argon2i, Variant::Argon2d, Variant::Argon2id]
+                      .iter() {
+            let params = Params::new(2, 3, 48, v).unwrap();
+            let mut exp = [0; 32];
+            Argon2::with_params(params).hash(&mut exp, b"password",
+                                             b"somesalt", b"key", b"data");
+
+            let mut inst = Instance::new(params, 32, b"password", b"somesalt",
+                                         b"key", b"data");
+            assert_eq!(inst.next_slice(), Some((0, 0)));
+            while !inst.is_done() {
+                // lanes in reverse, on threads of their own.
+                let mut segs = inst.segments();
+                segs.reverse();
+                thread::scope(|sc| {
+                    for seg in segs {
+                        sc.spawn(move || seg.fill());
+                    }
+                });
+            }
+            assert_eq!(inst.next_slice(), None);
+            let mut out = [0; 32];
+            inst.finalize(&mut out);
+            assert_eq!(out, exp);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "left unfilled")]
+    fn unfilled_segments() {
+        let params = Params::new(1, 2, 16, Variant::Argon2i).unwrap();
+        let mut inst = Instance::new(params, 32, b"password", b"somesalt",
+                                     &[], &[]);
+        let mut segs = inst.segments();
+        segs.pop().unwrap().fill();
+        drop(segs);
+        inst.segments();
+    }
+}
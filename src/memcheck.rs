@@ -0,0 +1,193 @@
+//! Checking that the block matrix will fit in the memory that is actually
+//! available before allocating it, so that a hash too large for its machine
+//! or container fails with `InsufficientMemory` instead of waking the OOM
+//! killer. See `Argon2::try_hash`.
+//!
+//! The memory available is the least of the kernel's estimate of what can be
+//! allocated without swapping (`MemAvailable` in /proc/meminfo), and the room
+//! left under the memory limit of this process's cgroup and of each of its
+//! ancestors, for both cgroup v1 and v2. This is Linux only: elsewhere the
+//! amount is unknown, and the check always passes.
+//!
+//! The answer is a snapshot, not a reservation: other processes may allocate
+//! in the meantime, so a passing check makes running out of memory unlikely,
+//! not impossible.
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use params::Params;
+
+/// The block matrix needs `required` bytes, but only `available` are free.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct InsufficientMemory {
+    pub required: u64,
+    pub available: u64,
+}
+
+impl fmt::Display for InsufficientMemory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Argon2 needs {} bytes of memory, but only {} are available.",
+               self.required, self.available)
+    }
+}
+
+impl Error for InsufficientMemory {}
+
+/// The number of bytes that can be allocated without swapping or exceeding a
+/// cgroup limit, or `None` where that is unknown.
+pub fn available() -> Option<u64> {
+    if cfg!(target_os = "linux") {
+        available_under(Path::new("/"))
+    } else {
+        None
+    }
+}
+
+/// Whether the block matrix of `params` fits in `available()` memory. Passes
+/// if the memory available is unknown.
+pub fn check(params: &Params) -> Result<(), InsufficientMemory> {
+    let required = params.blocks() * 1024;
+    match available() {
+        Some(available) if available < required => {
+            Err(InsufficientMemory { required, available })
+        }
+        _ => Ok(()),
+    }
+}
+
+// `available`, with /proc and /sys under `root`.
+fn available_under(root: &Path) -> Option<u64> {
+    let meminfo = fs::read_to_string(root.join("proc/meminfo")).ok();
+    let mut rv = meminfo.as_ref().and_then(|info| {
+        info.lines()
+            .find(|l| l.starts_with("MemAvailable:"))
+            .and_then(|l| l.split_whitespace().nth(1))
+            .and_then(|kib| kib.parse::<u64>().ok())
+            .map(|kib| kib * 1024)
+    });
+    let cgroups = fs::read_to_string(root.join("proc/self/cgroup"))
+                      .unwrap_or_default();
+    for line in cgroups.lines() {
+        // hierarchy-id:controllers:path
+        let mut fields = line.splitn(3, ':');
+        let (id, controllers, path) = match (fields.next(), fields.next(),
+                                             fields.next()) {
+            (Some(id), Some(c), Some(path)) => (id, c, path),
+            _ => continue,
+        };
+        let (dir, limit, usage) = if id == "0" && controllers.is_empty() {
+            ("sys/fs/cgroup", "memory.max", "memory.current")
+        } else if controllers.split(',').any(|c| c == "memory") {
+            ("sys/fs/cgroup/memory", "memory.limit_in_bytes",
+             "memory.usage_in_bytes")
+        } else {
+            continue;
+        };
+        let mut cg = root.join(dir).join(path.trim_start_matches('/'));
+        loop {
+            if let Some(room) = headroom(&cg, limit, usage) {
+                rv = Some(rv.map_or(room, |r| r.min(room)));
+            }
+            if cg == root.join(dir) || !cg.pop() {
+                break;
+            }
+        }
+    }
+    rv
+}
+
+// The room left under the cgroup at `cg`, if it has a limit.
+fn headroom(cg: &Path, limit: &str, usage: &str) -> Option<u64> {
+    let read = |name| {
+        fs::read_to_string(cg.join(name))
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+    };
+    // "max" under v2 and a huge number under v1 mean no limit; the latter is
+    // harmless, as MemAvailable is smaller.
+    let limit = read(limit)?;
+    Some(limit.saturating_sub(read(usage).unwrap_or(0)))
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use super::{InsufficientMemory, available_under, check};
+    use argon2::{Argon2, Variant};
+    use params::Params;
+
+    fn fixture(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root = env::temp_dir().join(format!("argon2rs-memcheck-{}-{}",
+                                                name, ::std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for &(path, contents) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        root
+    }
+
+    const MEMINFO: &str = "MemTotal:       16384000 kB\n\
+                           MemFree:         1024000 kB\n\
+                           MemAvailable:    8192000 kB\n";
+
+    #[test]
+    fn sources() {
+        let bare = fixture("bare", &[("proc/meminfo", MEMINFO)]);
+        assert_eq!(available_under(&bare), Some(8192000 * 1024));
+
+        // v2, limited in the parent, which leaves less room than MemAvailable
+        let v2 = fixture("v2",
+                         &[("proc/meminfo", MEMINFO),
+                           ("proc/self/cgroup", "0::/pod/app\n"),
+                           ("sys/fs/cgroup/pod/memory.max", "1000000\n"),
+                           ("sys/fs/cgroup/pod/memory.current", "400000\n"),
+                           ("sys/fs/cgroup/pod/app/memory.max", "max\n"),
+                           ("sys/fs/cgroup/pod/app/memory.current", "1\n")]);
+        assert_eq!(available_under(&v2), Some(600000));
+
+        let v1 = fixture("v1",
+                         &[("proc/self/cgroup", "4:memory:/docker/abc\n\
+                                                 1:cpu:/\n"),
+                           ("sys/fs/cgroup/memory/docker/abc/\
+                             memory.limit_in_bytes",
+                            "2000000"),
+                           ("sys/fs/cgroup/memory/docker/abc/\
+                             memory.usage_in_bytes",
+                            "500000")]);
+        assert_eq!(available_under(&v1), Some(1500000));
+
+        let none = fixture("none", &[]);
+        assert_eq!(available_under(&none), None);
+        for root in [bare, v2, v1].iter() {
+            fs::remove_dir_all(root).unwrap();
+        }
+    }
+
+    #[test]
+    fn try_hash() {
+        let small = Params::new(1, 1, 8, Variant::Argon2i).unwrap();
+        assert_eq!(check(&small), Ok(()));
+        let a2 = Argon2::with_params(small);
+        let (mut out, mut exp) = ([0; 32], [0; 32]);
+        a2.try_hash(&mut out, b"password", b"somesalt", &[], &[]).unwrap();
+        a2.hash(&mut exp, b"password", b"somesalt", &[], &[]);
+        assert_eq!(out, exp);
+
+        let huge = Params::new(1, 1, u32::MAX, Variant::Argon2i).unwrap();
+        if super::available().is_some() {
+            match check(&huge) {
+                Err(InsufficientMemory { required, available }) => {
+                    assert_eq!(required, huge.blocks() * 1024);
+                    assert!(available < required);
+                }
+                Ok(()) => panic!("4 TiB available"),
+            }
+        }
+    }
+}
@@ -0,0 +1,463 @@
+use std::{env, fmt};
+use std::error::Error;
+use argon2::{Argon2, ParamErr, Variant, Version, SLICES_PER_LANE};
+use block::ARGON2_BLOCK_BYTES;
+use limits::{MAX_LANES, MIN_LANES, MIN_MEMORY_PER_LANE_KIB, MIN_PASSES};
+
+/// A size of block matrix. The constructors save converting units by hand,
+/// and the size is carried in KiB as a `u64`, so that large sizes don't
+/// overflow before they can be checked. Sizes are validated against
+/// `Memory::max()` when turned into `Params`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct Memory(u64);
+
+impl Memory {
+    pub const fn kib(kib: u64) -> Memory { Memory(kib) }
+
+    pub const fn mib(mib: u64) -> Memory { Memory(mib.saturating_mul(1 << 10)) }
+
+    pub const fn gib(gib: u64) -> Memory { Memory(gib.saturating_mul(1 << 20)) }
+
+    /// The largest size usable on this platform: 2^32 - 1 KiB, the most that
+    /// Argon2 can encode, or less where the address space can't hold that
+    /// many bytes.
+    pub const fn max() -> Memory {
+        let addressable = isize::MAX as u64 / ARGON2_BLOCK_BYTES as u64;
+        if addressable < u32::MAX as u64 {
+            Memory(addressable)
+        } else {
+            Memory(u32::MAX as u64)
+        }
+    }
+
+    pub const fn as_kib(&self) -> u64 { self.0 }
+
+    pub const fn bytes(&self) -> u64 { self.0.saturating_mul(1024) }
+
+    // The size as the `kib` parameter of `Params::new`.
+    pub(crate) const fn to_kib32(self) -> Result<u32, ParamErr> {
+        if self.0 > Memory::max().0 {
+            Err(ParamErr::MaxKiB(Memory::max().0))
+        } else {
+            Ok(self.0 as u32)
+        }
+    }
+}
+
+/// What to do with a memory size that isn't a multiple of `4 * lanes` KiB,
+/// which Argon2 can't use all of. See `Builder::rounding`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Rounding {
+    /// Fail with `ParamErr::UnalignedKiB`.
+    Error,
+    /// Keep the size as given, of which Argon2 uses the largest multiple of
+    /// `4 * lanes` KiB, as `Params::blocks` reports. This is what the
+    /// reference implementation does, and the default.
+    #[default]
+    Down,
+    /// Raise the size to the next multiple of `4 * lanes` KiB, so that at
+    /// least as much memory as was asked for is used. The raised size is the
+    /// one hashed with and recorded in encoded hashes.
+    Up,
+}
+
+impl Rounding {
+    // `params` with its memory size rounded this way.
+    pub(crate) fn apply(self, params: Params) -> Result<Params, ParamErr> {
+        let unit = SLICES_PER_LANE as u64 * params.lanes as u64;
+        let kib = params.kib as u64;
+        let rounded = match self {
+            _ if kib.is_multiple_of(unit) => return Ok(params),
+            Rounding::Error => return Err(ParamErr::UnalignedKiB(unit)),
+            Rounding::Down => return Ok(params),
+            Rounding::Up => (kib / unit + 1) * unit,
+        };
+        Ok(Params {
+            kib: Memory::kib(rounded).to_kib32()?,
+            ..params
+        })
+    }
+}
+
+/// A validated set of Argon2 cost parameters, detached from any particular
+/// hashing session. Unlike `Argon2`, this is `Copy` and comparable, which makes
+/// it suitable for configuration and policy code.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Params {
+    variant: Variant,
+    version: Version,
+    kib: u32,
+    passes: u32,
+    lanes: u32,
+}
+
+impl Params {
+    /// Validates the given parameters, which have the same meaning and bounds
+    /// as those of `Argon2::new`. This is a `const fn`, as are the other
+    /// constructors; see `Params::valid` for checking constants at build
+    /// time.
+    pub const fn new(passes: u32, lanes: u32, kib: u32, variant: Variant)
+                     -> Result<Params, ParamErr> {
+        Params::with_version(passes, lanes, kib, variant, Version::_0x13)
+    }
+
+    /// Same as `Params::new`, but panics if the parameters are invalid. In a
+    /// constant, that fails the build instead:
+    ///
+    /// ```ignore
+    /// const POLICY: Params = Params::valid(3, 1, 1 << 16, Variant::Argon2id);
+    /// ```
+    pub const fn valid(passes: u32, lanes: u32, kib: u32, variant: Variant)
+                       -> Params {
+        match Params::new(passes, lanes, kib, variant) {
+            Ok(params) => params,
+            Err(e) => panic!("{}", e.reason()),
+        }
+    }
+
+    /// Same as `Params::new`, but with the memory size given as a `Memory`,
+    /// e.g. `Params::with_memory(3, 1, Memory::mib(64), Variant::Argon2id)`.
+    pub const fn with_memory(passes: u32, lanes: u32, memory: Memory,
+                             variant: Variant)
+                             -> Result<Params, ParamErr> {
+        match memory.to_kib32() {
+            Ok(kib) => Params::new(passes, lanes, kib, variant),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Same as `Params::new`, but for an explicit Argon2 version. Only needed
+    /// for interoperating with legacy (0x10) hashes.
+    pub const fn with_version(passes: u32, lanes: u32, kib: u32,
+                              variant: Variant, version: Version)
+                              -> Result<Params, ParamErr> {
+        let min_kib = MIN_MEMORY_PER_LANE_KIB as u64 * lanes as u64;
+        if passes < MIN_PASSES {
+            Err(ParamErr::TooFewPasses)
+        } else if lanes < MIN_LANES {
+            Err(ParamErr::TooFewLanes)
+        } else if MAX_LANES < lanes {
+            Err(ParamErr::TooManyLanes)
+        } else if (kib as u64) < min_kib {
+            Err(ParamErr::MinKiB(min_kib))
+        } else if kib as u64 > Memory::max().0 {
+            Err(ParamErr::MaxKiB(Memory::max().0))
+        } else {
+            Ok(Params {
+                variant,
+                version,
+                kib,
+                passes,
+                lanes,
+            })
+        }
+    }
+
+    pub const fn variant(&self) -> Variant { self.variant }
+
+    pub const fn version(&self) -> Version { self.version }
+
+    /// The requested memory size in KiB. See also `Params::blocks`.
+    pub const fn kib(&self) -> u32 { self.kib }
+
+    /// The requested memory size as a `Memory`.
+    pub const fn memory(&self) -> Memory { Memory::kib(self.kib as u64) }
+
+    pub const fn passes(&self) -> u32 { self.passes }
+
+    pub const fn lanes(&self) -> u32 { self.lanes }
+
+    /// The number of 1-KiB blocks in each lane of the block matrix.
+    pub fn lanelen(&self) -> u32 {
+        self.kib / (SLICES_PER_LANE * self.lanes) * SLICES_PER_LANE
+    }
+
+    /// The number of 1-KiB blocks that are actually allocated, which is `kib`
+    /// rounded down to a multiple of `4 * lanes`.
+    pub fn blocks(&self) -> u64 { self.lanelen() as u64 * self.lanes as u64 }
+
+    /// Whether hashes made with these parameters are at least as costly to
+    /// attack as those made with `other`: they are of the same variant, and
+    /// have at least the same version, memory, and passes, and at most the
+    /// same number of lanes, which an attacker may compute in parallel. This
+    /// is a partial order: parameters of different variants, or that trade
+    /// one cost against another, are incomparable, and neither is at least
+    /// the other.
+    pub fn is_at_least(&self, other: &Params) -> bool {
+        self.variant == other.variant &&
+        self.version as u32 >= other.version as u32 &&
+        self.kib >= other.kib && self.passes >= other.passes &&
+        self.lanes <= other.lanes
+    }
+
+    // Unvalidated parameters, only for comparing others against with
+    // `is_at_least`.
+    pub(crate) fn floor(passes: u32, lanes: u32, kib: u32, variant: Variant,
+                        version: Version)
+                        -> Params {
+        Params {
+            variant,
+            version,
+            kib,
+            passes,
+            lanes,
+        }
+    }
+
+    /// Suggests a lane count for hashing with `memory_kib` of memory on a
+    /// machine that can give `available_cores` cores to each hash. Following
+    /// RFC 9106, that is as many lanes as there are threads to run them, but
+    /// no more than one per MiB of memory: lanes synchronize four times a
+    /// pass, and below that size the synchronization costs more than the
+    /// parallelism saves. Always at least 1.
+    ///
+    /// The result differs between machines, so the chosen lane count must be
+    /// stored with the hash or the parameters, as `Encoded` does.
+    pub fn suggest_lanes(memory_kib: u32, available_cores: u32) -> u32 {
+        let by_memory = memory_kib / SUGGESTED_KIB_PER_LANE;
+        available_cores.min(by_memory).clamp(MIN_LANES, MAX_LANES)
+    }
+}
+
+// The least memory per lane for `Params::suggest_lanes`.
+const SUGGESTED_KIB_PER_LANE: u32 = 1 << 10;
+
+impl Params {
+    /// Reads parameters from the environment, so that they can be tuned
+    /// without rebuilding. With `prefix` `"ARGON2"`, the variables are
+    /// `ARGON2_MEMORY_KIB`, `ARGON2_PASSES`, `ARGON2_LANES`, and
+    /// `ARGON2_VARIANT` (one of `argon2i`, `argon2d`, `argon2id`). Unset
+    /// variables take their values from `fallback`; set ones must parse, and
+    /// the resulting parameters must be valid.
+    pub fn from_env(prefix: &str, fallback: Preset) -> Result<Params, EnvErr> {
+        Params::from_vars(prefix, fallback, |name| env::var(name).ok())
+    }
+
+    fn from_vars<F>(prefix: &str, fallback: Preset, var: F)
+                    -> Result<Params, EnvErr>
+        where F: Fn(&str) -> Option<String>
+    {
+        let read = |suffix: &str| {
+            let name = format!("{}_{}", prefix, suffix);
+            var(&name).map(|value| (name, value))
+        };
+        let number = |suffix: &str, default: u32| match read(suffix) {
+            None => Ok(default),
+            Some((name, value)) => {
+                value.trim().parse().map_err(|_| EnvErr::Invalid(name, value))
+            }
+        };
+
+        let fb = fallback.params();
+        let kib = number("MEMORY_KIB", fb.kib())?;
+        let passes = number("PASSES", fb.passes())?;
+        let lanes = number("LANES", fb.lanes())?;
+        let variant = match read("VARIANT") {
+            None => fb.variant(),
+            Some((name, value)) => {
+                match value.trim() {
+                    "argon2i" => Variant::Argon2i,
+                    "argon2d" => Variant::Argon2d,
+                    "argon2id" => Variant::Argon2id,
+                    _ => return Err(EnvErr::Invalid(name, value)),
+                }
+            }
+        };
+        Params::new(passes, lanes, kib, variant).map_err(EnvErr::Params)
+    }
+}
+
+/// Errors from `Params::from_env`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum EnvErr {
+    /// The named variable was set to the given value, which doesn't parse.
+    Invalid(String, String),
+    /// The variables parsed, but describe invalid parameters.
+    Params(ParamErr),
+}
+
+impl fmt::Display for EnvErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EnvErr::Invalid(ref name, ref value) => {
+                write!(f, "Invalid value for {}: {:?}.", name, value)
+            }
+            EnvErr::Params(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for EnvErr {}
+
+impl<'a> From<&'a Argon2> for Params {
+    fn from(a2: &'a Argon2) -> Params { a2.to_params() }
+}
+
+/// Named sets of Argon2id parameters. The values behind each name are fixed
+/// and will not change in later releases, so that keys derived under a preset
+/// stay derivable.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Preset {
+    /// 64 MiB, 2 passes, 1 lane, as libsodium's interactive limits. For
+    /// online logins.
+    Interactive,
+    /// 256 MiB, 3 passes, 1 lane, as libsodium's moderate limits.
+    Moderate,
+    /// 1 GiB, 4 passes, 1 lane, as libsodium's sensitive limits. For keys
+    /// protecting high-value data; takes seconds on typical hardware.
+    Sensitive,
+    /// 2 GiB, 1 pass, 4 lanes: the first recommendation of RFC 9106.
+    Rfc9106,
+    /// 64 MiB, 3 passes, 4 lanes: the second recommendation of RFC 9106, for
+    /// when 2 GiB is too much.
+    Rfc9106LowMemory,
+}
+
+impl Preset {
+    pub fn params(self) -> Params {
+        let (passes, lanes, kib) = match self {
+            Preset::Interactive => (2, 1, 64 << 10),
+            Preset::Moderate => (3, 1, 256 << 10),
+            Preset::Sensitive => (4, 1, 1 << 20),
+            Preset::Rfc9106 => (1, 4, 2 << 20),
+            Preset::Rfc9106LowMemory => (3, 4, 64 << 10),
+        };
+        Params::new(passes, lanes, kib, Variant::Argon2id).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EnvErr, Memory, Params, Preset};
+    use argon2::{Argon2, ParamErr, Variant, Version};
+
+    #[test]
+    fn memory() {
+        assert_eq!(Memory::mib(64), Memory::kib(64 << 10));
+        assert_eq!(Memory::gib(2).bytes(), 2 << 30);
+        let p = Params::with_memory(1, 1, Memory::gib(1), Variant::Argon2id);
+        assert_eq!(p.unwrap().kib(), 1 << 20);
+
+        let too_big = Memory::kib(u32::MAX as u64 + 1);
+        let err = Params::with_memory(1, 1, too_big, Variant::Argon2id);
+        assert_eq!(err, Err(ParamErr::MaxKiB(Memory::max().as_kib())));
+        assert_eq!(Memory::gib(u64::MAX).to_kib32(),
+                   Err(ParamErr::MaxKiB(Memory::max().as_kib())));
+        if cfg!(target_pointer_width = "64") {
+            assert_eq!(Memory::max().as_kib(), u32::MAX as u64);
+        }
+    }
+
+    #[test]
+    fn is_at_least() {
+        let base = Params::new(2, 2, 64, Variant::Argon2id).unwrap();
+        let more = |passes, lanes, kib| {
+            Params::new(passes, lanes, kib, Variant::Argon2id).unwrap()
+        };
+        assert!(base.is_at_least(&base));
+        assert!(more(3, 1, 128).is_at_least(&base));
+        assert!(!base.is_at_least(&more(3, 1, 128)));
+        for &p in [more(1, 2, 64), more(2, 4, 64), more(2, 2, 32)].iter() {
+            assert!(base.is_at_least(&p) && !p.is_at_least(&base));
+        }
+        // trades memory for passes
+        let other = more(4, 2, 32);
+        assert!(!base.is_at_least(&other) && !other.is_at_least(&base));
+
+        let argon2i = Params::new(2, 2, 64, Variant::Argon2i).unwrap();
+        assert!(!base.is_at_least(&argon2i) && !argon2i.is_at_least(&base));
+        let legacy = Params::with_version(2, 2, 64, Variant::Argon2id,
+                                          Version::_0x10)
+                         .unwrap();
+        assert!(base.is_at_least(&legacy) && !legacy.is_at_least(&base));
+    }
+
+    #[test]
+    fn const_params() {
+        const POLICY: Params = Params::valid(3, 1, 1 << 16, Variant::Argon2id);
+        const BIG: Result<Params, ParamErr> =
+            Params::with_memory(1, 1, Memory::gib(1), Variant::Argon2id);
+        assert_eq!(Ok(POLICY), Params::new(3, 1, 1 << 16, Variant::Argon2id));
+        assert_eq!(BIG.map(|p| p.kib()), Ok(1 << 20));
+    }
+
+    #[test]
+    #[should_panic(expected = "Argon2 requires one or more passes")]
+    fn invalid_const_params() {
+        Params::valid(0, 1, 8, Variant::Argon2id);
+    }
+
+    #[test]
+    fn limits() {
+        use limits::*;
+        let id = Variant::Argon2id;
+        let min = MIN_MEMORY_PER_LANE_KIB;
+        assert!(Params::new(MIN_PASSES, MIN_LANES, min, id).is_ok());
+        assert_eq!(Params::new(MIN_PASSES - 1, 1, min, id),
+                   Err(ParamErr::TooFewPasses));
+        assert_eq!(Params::new(1, MIN_LANES - 1, min, id),
+                   Err(ParamErr::TooFewLanes));
+        assert_eq!(Params::new(1, MAX_LANES + 1, u32::MAX, id),
+                   Err(ParamErr::TooManyLanes));
+        assert_eq!(Params::new(1, 2, 2 * min - 1, id),
+                   Err(ParamErr::MinKiB(2 * min as u64)));
+        assert_eq!(MIN_MEMORY_PER_LANE_KIB as usize * BLOCK_BYTES, 8 << 10);
+    }
+
+    #[test]
+    fn suggest_lanes() {
+        assert_eq!(Params::suggest_lanes(64 << 10, 8), 8);
+        assert_eq!(Params::suggest_lanes(2 << 20, 4), 4);
+        assert_eq!(Params::suggest_lanes(4 << 10, 8), 4);
+        assert_eq!(Params::suggest_lanes(64, 8), 1);
+        assert_eq!(Params::suggest_lanes(64 << 10, 0), 1);
+        assert_eq!(Params::suggest_lanes(u32::MAX, u32::MAX), (1 << 22) - 1);
+    }
+
+    #[test]
+    fn from_vars() {
+        let vars = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter().find(|v| v.0 == name).map(|v| v.1.to_string())
+            }
+        };
+        let p = Params::from_vars("A2", Preset::Interactive, vars(&[]));
+        assert_eq!(p, Ok(Preset::Interactive.params()));
+
+        let p = Params::from_vars("A2", Preset::Interactive,
+                                  vars(&[("A2_MEMORY_KIB", "1024"),
+                                         ("A2_PASSES", " 4 "),
+                                         ("A2_VARIANT", "argon2i"),
+                                         ("ARGON2_LANES", "2")]));
+        assert_eq!(p, Ok(Params::new(4, 1, 1024, Variant::Argon2i).unwrap()));
+
+        let p = Params::from_vars("A2", Preset::Interactive,
+                                  vars(&[("A2_LANES", "four")]));
+        assert_eq!(p, Err(EnvErr::Invalid("A2_LANES".into(), "four".into())));
+        let p = Params::from_vars("A2", Preset::Interactive,
+                                  vars(&[("A2_VARIANT", "scrypt")]));
+        assert_eq!(p.unwrap_err().to_string(),
+                   "Invalid value for A2_VARIANT: \"scrypt\".");
+        let p = Params::from_vars("A2", Preset::Interactive,
+                                  vars(&[("A2_PASSES", "0")]));
+        assert_eq!(p, Err(EnvErr::Params(ParamErr::TooFewPasses)));
+    }
+
+    #[test]
+    fn default_preset() {
+        let d = Argon2::default(Variant::Argon2id).to_params();
+        let preset = if cfg!(feature = "defaults-rfc9106") {
+            Some(Preset::Rfc9106)
+        } else if cfg!(feature = "defaults-moderate") {
+            Some(Preset::Moderate)
+        } else if cfg!(feature = "defaults-interactive") {
+            Some(Preset::Interactive)
+        } else {
+            None
+        };
+        match preset {
+            Some(preset) => assert_eq!(d, preset.params()),
+            None => assert_eq!((d.kib(), d.passes(), d.lanes()), (4096, 3, 1)),
+        }
+    }
+}
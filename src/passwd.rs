@@ -0,0 +1,309 @@
+//! Flat credential files of `user:$argon2...` lines, the Argon2 analogue of
+//! htpasswd for small services. See `examples/passwd.rs` for a command-line
+//! front end.
+//!
+//! Every modification rewrites the whole file: the new contents are written to
+//! a temporary sibling, flushed to disk, and renamed over the original, so
+//! readers always see either the old or the new file, never a torn one. The
+//! new file keeps the permissions of the old, and a file created by the first
+//! `set` is readable by its owner only (on Unix).
+//! Writers serialize on an exclusive lock of a sibling `.lock` file. Lines that
+//! aren't entries (blank lines, `#` comments) are preserved as-is.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use argon2::{Argon2, Variant};
+use salt::Salt;
+use verifier::Encoded;
+
+/// A credential file at a given path. No I/O happens until one of its methods
+/// is called, and the file itself is only created by the first `set`.
+pub struct CredFile {
+    path: PathBuf,
+}
+
+enum Line {
+    Entry(String, String),
+    Other(String),
+}
+
+impl CredFile {
+    pub fn new<P: AsRef<Path>>(path: P) -> CredFile {
+        CredFile { path: path.as_ref().to_path_buf() }
+    }
+
+    /// The users that have entries, in file order. A missing file has none.
+    pub fn list(&self) -> io::Result<Vec<String>> {
+        let lines = self.read()?;
+        Ok(lines.into_iter()
+                .filter_map(|l| match l {
+                    Line::Entry(user, _) => Some(user),
+                    Line::Other(_) => None,
+                })
+                .collect())
+    }
+
+    /// Returns the stored hash of `user`, if any.
+    pub fn get(&self, user: &str) -> io::Result<Option<Encoded>> {
+        for line in self.read()? {
+            match line {
+                Line::Entry(ref u, ref enc) if u == user => {
+                    return Encoded::from_u8(enc.as_bytes())
+                        .map(Some)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData,
+                                                    e));
+                }
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Verifies `password` against the entry of `user`. Unknown users take as
+    /// long to reject as wrong passwords: they are checked with
+    /// `Encoded::verify_dummy` under the parameters of the file's first entry.
+    pub fn verify(&self, user: &str, password: &[u8]) -> io::Result<bool> {
+        match self.get(user)? {
+            Some(enc) => Ok(enc.verify(password)),
+            None => Ok(Encoded::verify_dummy(&self.dummy()?, password)),
+        }
+    }
+
+    // The parameters to reject unknown users under: those of the first entry,
+    // which the others most likely share, or `Argon2::default` for Argon2i if
+    // there is none.
+    fn dummy(&self) -> io::Result<Argon2> {
+        for line in self.read()? {
+            if let Line::Entry(_, enc) = line {
+                if let Ok(enc) = Encoded::from_u8(enc.as_bytes()) {
+                    return Ok(Argon2::with_params(enc.argon2().to_params()));
+                }
+            }
+        }
+        Ok(Argon2::default(Variant::Argon2i))
+    }
+
+    /// Adds an entry for `user`, or replaces the existing one, hashing
+    /// `password` with `argon` and a fresh random salt.
+    pub fn set(&self, user: &str, password: &[u8], argon: Argon2)
+               -> io::Result<()> {
+        check_user(user)?;
+        let salt = Salt::generate()?;
+        let enc = Encoded::new(argon, password, &salt, &[], &[]);
+        let enc = String::from_utf8(enc.to_u8()).unwrap();
+        self.modify(|lines| {
+            let mut enc = Some(enc);
+            for line in lines.iter_mut() {
+                if let Line::Entry(ref u, ref mut e) = *line {
+                    if u == user {
+                        *e = enc.take().unwrap();
+                        break;
+                    }
+                }
+            }
+            if let Some(enc) = enc {
+                lines.push(Line::Entry(user.to_string(), enc));
+            }
+        })
+    }
+
+    /// Removes the entry of `user`, returning whether there was one.
+    pub fn remove(&self, user: &str) -> io::Result<bool> {
+        let mut found = false;
+        self.modify(|lines| {
+            let before = lines.len();
+            lines.retain(|l| match *l {
+                Line::Entry(ref u, _) => u != user,
+                Line::Other(_) => true,
+            });
+            found = lines.len() != before;
+        })?;
+        Ok(found)
+    }
+
+    fn read(&self) -> io::Result<Vec<Line>> {
+        match File::open(&self.path) {
+            Ok(f) => parse(f),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn modify<F>(&self, f: F) -> io::Result<()>
+        where F: FnOnce(&mut Vec<Line>)
+    {
+        let lock = OpenOptions::new()
+                       .write(true)
+                       .create(true)
+                       .truncate(false)
+                       .open(self.sibling(".lock"))?;
+        lock.lock()?;
+
+        let mut lines = self.read()?;
+        f(&mut lines);
+
+        let tmp = self.sibling(".tmp");
+        {
+            // created afresh, so that the mode applies: readable by the owner
+            // only until it takes on the permissions of the file it replaces.
+            let _ = fs::remove_file(&tmp);
+            let mut opts = OpenOptions::new();
+            opts.write(true).create_new(true);
+            #[cfg(unix)]
+            ::std::os::unix::fs::OpenOptionsExt::mode(&mut opts, 0o600);
+            let mut out = opts.open(&tmp)?;
+            match fs::metadata(&self.path) {
+                Ok(meta) => out.set_permissions(meta.permissions())?,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+            for line in &lines {
+                match *line {
+                    Line::Entry(ref user, ref enc) => {
+                        writeln!(out, "{}:{}", user, enc)?
+                    }
+                    Line::Other(ref l) => writeln!(out, "{}", l)?,
+                }
+            }
+            out.sync_all()?;
+        }
+        fs::rename(&tmp, &self.path)?;
+        lock.unlock()
+    }
+
+    fn sibling(&self, suffix: &str) -> PathBuf {
+        let mut name = self.path.file_name().unwrap_or_default().to_owned();
+        name.push(suffix);
+        self.path.with_file_name(name)
+    }
+}
+
+fn parse<R: Read>(r: R) -> io::Result<Vec<Line>> {
+    let mut rv = vec![];
+    for line in BufReader::new(r).lines() {
+        let line = line?;
+        let entry = match line.find(':') {
+            Some(i) if !line.starts_with('#') => {
+                Some((line[..i].to_string(), line[i + 1..].to_string()))
+            }
+            _ => None,
+        };
+        rv.push(match entry {
+            Some((user, enc)) => Line::Entry(user, enc),
+            None => Line::Other(line),
+        });
+    }
+    Ok(rv)
+}
+
+fn check_user(user: &str) -> io::Result<()> {
+    let bad = |c| c == ':' || c == '\n' || c == '\r';
+    if user.is_empty() || user.starts_with('#') || user.contains(bad) {
+        Err(io::Error::new(io::ErrorKind::InvalidInput,
+                           "user names must be non-empty, must not start with \
+                            '#', and must not contain ':' or newlines"))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+    use std::path::PathBuf;
+    use super::CredFile;
+    use argon2::{Argon2, Variant};
+
+    fn scratch(name: &str) -> PathBuf {
+        let mut rv = env::temp_dir();
+        rv.push(format!("argon2rs-passwd-{}-{}", name, ::std::process::id()));
+        let _ = fs::remove_file(&rv);
+        rv
+    }
+
+    #[test]
+    fn set_verify_remove() {
+        let path = scratch("svr");
+        let creds = CredFile::new(&path);
+        assert_eq!(creds.list().unwrap(), Vec::<String>::new());
+        assert!(!creds.verify("alice", b"hunter2").unwrap());
+
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2i).unwrap();
+        creds.set("alice", b"hunter2", a2).unwrap();
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2i).unwrap();
+        creds.set("bob", b"correct horse", a2).unwrap();
+        assert_eq!(creds.list().unwrap(), vec!["alice", "bob"]);
+        assert!(creds.verify("alice", b"hunter2").unwrap());
+        assert!(!creds.verify("alice", b"correct horse").unwrap());
+
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2i).unwrap();
+        creds.set("alice", b"hunter3", a2).unwrap();
+        assert_eq!(creds.list().unwrap(), vec!["alice", "bob"]);
+        assert!(creds.verify("alice", b"hunter3").unwrap());
+        assert!(!creds.verify("alice", b"hunter2").unwrap());
+
+        assert!(creds.remove("alice").unwrap());
+        assert!(!creds.remove("alice").unwrap());
+        assert_eq!(creds.list().unwrap(), vec!["bob"]);
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2i).unwrap();
+        assert!(creds.set("a:b", b"pw", a2).is_err());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn dummy_params() {
+        let path = scratch("dummy");
+        let creds = CredFile::new(&path);
+        let default = Argon2::default(Variant::Argon2i).to_params();
+        assert_eq!(creds.dummy().unwrap().to_params(), default);
+
+        let argon = Argon2::new(2, 2, 64, Variant::Argon2id).unwrap();
+        let params = argon.to_params();
+        creds.set("erin", b"pw", argon).unwrap();
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2i).unwrap();
+        creds.set("frank", b"pw", a2).unwrap();
+        assert_eq!(creds.dummy().unwrap().to_params(), params);
+        assert!(!creds.verify("mallory", b"pw").unwrap());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn preserves_other_lines() {
+        let path = scratch("other");
+        File::create(&path).unwrap().write_all(b"# users\n\n").unwrap();
+        let creds = CredFile::new(&path);
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2i).unwrap();
+        creds.set("carol", b"pw", a2).unwrap();
+
+        let mut contents = String::new();
+        File::open(&path).unwrap().read_to_string(&mut contents).unwrap();
+        assert!(contents.starts_with("# users\n\ncarol:$argon2i$v=19,"));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = scratch("mode");
+        let mode = |path: &PathBuf| {
+            fs::metadata(path).unwrap().permissions().mode() & 0o777
+        };
+        let creds = CredFile::new(&path);
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2i).unwrap();
+        creds.set("dave", b"pw", a2).unwrap();
+        assert_eq!(mode(&path), 0o600);
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640))
+            .unwrap();
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2i).unwrap();
+        creds.set("erin", b"pw", a2).unwrap();
+        assert_eq!(mode(&path), 0o640);
+        assert!(creds.remove("dave").unwrap());
+        assert_eq!(mode(&path), 0o640);
+        let _ = fs::remove_file(&path);
+    }
+}
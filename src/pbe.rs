@@ -0,0 +1,216 @@
+//! Password-based encryption: `seal` encrypts a message under a password,
+//! and `open` decrypts it given the same password. With the `pbe` feature.
+//!
+//! The key is derived with Argon2id from the password and a random salt, and
+//! the message encrypted with ChaCha20-Poly1305 (RFC 8439), as implemented by
+//! the `chacha20poly1305` crate. The output is
+//! self-contained: a header holding the format version, the cost parameters,
+//! and the salt, followed by the ciphertext and its 16-byte tag. The header
+//! is authenticated along with the ciphertext, so tampering with either makes
+//! `open` fail.
+//!
+//! ```text
+//! "A2PE" | version (1) | passes | lanes | kib | salt (16) | ciphertext | tag
+//! ```
+//!
+//! with `passes`, `lanes`, and `kib` little endian u32s. Every message gets
+//! its own salt, and therefore its own key, so the nonce is always zero.
+//!
+//! `open` spends whatever memory and time the header asks for. Where sealed
+//! messages may come from an attacker, check `pbe::params` first.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use argon2::{Argon2, ParamErr, Variant};
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce, Tag};
+use kdf::SecretOutput;
+use params::Params;
+use rng::{OsRng, RngProvider};
+
+const MAGIC: &[u8] = b"A2PE";
+const VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+
+/// Length of the header that precedes the ciphertext.
+pub const HEADER_LEN: usize = 4 + 1 + 3 * 4 + SALT_LEN;
+
+/// How much longer sealed messages are than their plaintext.
+pub const OVERHEAD: usize = HEADER_LEN + TAG_LEN;
+
+/// Errors from `open` and `params`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum OpenErr {
+    /// The input is too short, or doesn't begin with the magic bytes.
+    Malformed,
+    /// The input is in a later format version than this one.
+    Version(u8),
+    /// The header holds invalid parameters.
+    Params(ParamErr),
+    /// The password is wrong, or the message was altered.
+    Mismatch,
+}
+
+impl fmt::Display for OpenErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OpenErr::Malformed => write!(f, "Not a sealed message."),
+            OpenErr::Version(v) => {
+                write!(f, "Unsupported sealed message version {}.", v)
+            }
+            OpenErr::Params(ref e) => write!(f, "{}", e),
+            OpenErr::Mismatch => {
+                write!(f, "Wrong password, or the message was altered.")
+            }
+        }
+    }
+}
+
+impl Error for OpenErr {}
+
+/// Encrypts `plaintext` under `password`, with a key derived by Argon2id at
+/// the costs (memory, passes, and lanes) of `params`. Fails if the operating
+/// system's random number generator does, or with `InvalidInput` if
+/// `plaintext` is longer than ChaCha20 can encrypt under one nonce (256 GiB).
+pub fn seal(password: &[u8], plaintext: &[u8], params: &Params)
+            -> io::Result<Vec<u8>> {
+    seal_with(password, plaintext, params, &mut OsRng)
+}
+
+/// Same as `seal`, but drawing the salt from `rng`.
+pub fn seal_with(password: &[u8], plaintext: &[u8], params: &Params,
+                 rng: &mut dyn RngProvider)
+                 -> io::Result<Vec<u8>> {
+    let params = Params::new(params.passes(), params.lanes(), params.kib(),
+                             Variant::Argon2id)
+                     .unwrap();
+    let mut rv = Vec::with_capacity(OVERHEAD + plaintext.len());
+    rv.extend_from_slice(MAGIC);
+    rv.push(VERSION);
+    for &n in [params.passes(), params.lanes(), params.kib()].iter() {
+        rv.extend_from_slice(&n.to_le_bytes());
+    }
+    let mut salt = [0; SALT_LEN];
+    rng.fill(&mut salt)?;
+    rv.extend_from_slice(&salt);
+    rv.extend_from_slice(plaintext);
+
+    let key = derive(password, params, &salt);
+    let (header, body) = rv.split_at_mut(HEADER_LEN);
+    let tag = cipher(&key)
+                  .encrypt_in_place_detached(&Nonce::default(), header, body)
+                  .map_err(|_| {
+                      io::Error::new(io::ErrorKind::InvalidInput,
+                                     "plaintext too long")
+                  })?;
+    rv.extend_from_slice(&tag);
+    Ok(rv)
+}
+
+/// Decrypts a message sealed under `password`.
+pub fn open(password: &[u8], sealed: &[u8]) -> Result<SecretOutput, OpenErr> {
+    let params = params(sealed)?;
+    let (header, body) = sealed.split_at(HEADER_LEN);
+    let (ct, tag) = body.split_at(body.len() - TAG_LEN);
+    let key = derive(password, params, &header[HEADER_LEN - SALT_LEN..]);
+    let mut rv = SecretOutput::zeroed(ct.len());
+    rv.clone_from_slice(ct);
+    cipher(&key)
+        .decrypt_in_place_detached(&Nonce::default(), header, &mut rv,
+                                   Tag::from_slice(tag))
+        .map(|()| rv)
+        .map_err(|_| OpenErr::Mismatch)
+}
+
+/// The parameters that `open` would derive the key of `sealed` with.
+pub fn params(sealed: &[u8]) -> Result<Params, OpenErr> {
+    if sealed.len() < OVERHEAD || &sealed[..MAGIC.len()] != MAGIC {
+        return Err(OpenErr::Malformed);
+    }
+    if sealed[4] != VERSION {
+        return Err(OpenErr::Version(sealed[4]));
+    }
+    let num = |i: usize| {
+        let mut n = [0; 4];
+        n.clone_from_slice(&sealed[5 + 4 * i..9 + 4 * i]);
+        u32::from_le_bytes(n)
+    };
+    Params::new(num(0), num(1), num(2), Variant::Argon2id)
+        .map_err(OpenErr::Params)
+}
+
+fn cipher(key: &[u8]) -> ChaCha20Poly1305 {
+    ChaCha20Poly1305::new_from_slice(key).expect("keys are KEY_LEN bytes")
+}
+
+fn derive(password: &[u8], params: Params, salt: &[u8]) -> SecretOutput {
+    let mut key = SecretOutput::zeroed(KEY_LEN);
+    Argon2::with_params(params).hash(&mut key, password, salt, &[], &[]);
+    key
+}
+
+#[cfg(test)]
+mod test {
+    use super::{OVERHEAD, OpenErr, open, params, seal, seal_with};
+    use argon2::{ParamErr, Variant};
+    use params::Params;
+    use rng::test::Counter;
+
+    #[test]
+    fn round_trip() {
+        let cheap = Params::new(1, 1, 8, Variant::Argon2i).unwrap();
+        let msg = b"attack at dawn";
+        let sealed = seal(b"password", msg, &cheap).unwrap();
+        assert_eq!(sealed.len(), msg.len() + OVERHEAD);
+        assert_eq!(params(&sealed).unwrap().variant(), Variant::Argon2id);
+        assert_eq!(&open(b"password", &sealed).unwrap()[..], &msg[..]);
+        assert_eq!(open(b"passw0rd", &sealed), Err(OpenErr::Mismatch));
+        assert!(seal(b"password", msg, &cheap).unwrap() != sealed);
+
+        let empty = seal(b"password", b"", &cheap).unwrap();
+        assert_eq!(open(b"password", &empty).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn known_answer() {
+        let cheap = Params::new(1, 1, 8, Variant::Argon2i).unwrap();
+        let sealed = seal_with(b"password", b"attack at dawn", &cheap,
+                               &mut Counter(0))
+                         .unwrap();
+        let hex: String = sealed.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(hex,
+                   "41325045010100000001000000080000000001020304050607\
+                    08090a0b0c0d0e0f61a16f52ac1d9d8baf1f760b880b5d8c439d\
+                    183ca1718354af05fe3f9396");
+    }
+
+    #[test]
+    fn tampering() {
+        let cheap = Params::new(1, 1, 8, Variant::Argon2i).unwrap();
+        let sealed = seal_with(b"password", b"attack at dawn", &cheap,
+                               &mut Counter(0))
+                         .unwrap();
+        // the header, including the salt, is authenticated.
+        for &i in [13, 20, sealed.len() - 20, sealed.len() - 1].iter() {
+            let mut altered = sealed.clone();
+            altered[i] ^= 1;
+            assert_eq!(open(b"password", &altered), Err(OpenErr::Mismatch));
+        }
+
+        let mut altered = sealed.clone();
+        altered[0] = b'B';
+        assert_eq!(open(b"password", &altered), Err(OpenErr::Malformed));
+        altered = sealed.clone();
+        altered[4] = 2;
+        assert_eq!(open(b"password", &altered), Err(OpenErr::Version(2)));
+        altered = sealed.clone();
+        altered[5] = 0;
+        assert_eq!(open(b"password", &altered),
+                   Err(OpenErr::Params(ParamErr::TooFewPasses)));
+        assert_eq!(open(b"password", &sealed[..OVERHEAD - 1]),
+                   Err(OpenErr::Malformed));
+    }
+}
@@ -0,0 +1,244 @@
+//! Verification across Argon2 versions, and cost requirements that change at
+//! runtime.
+//!
+//! Hashes made before Argon2 1.3 (version 0x13) use version 1.0 (0x10), whose
+//! compression function overwrites blocks instead of XOR-ing into them on
+//! later passes. A database may hold both kinds for years. A `VersionPolicy`
+//! names the legacy versions that are still accepted at login, always hashes
+//! new passwords with the newest version, and reports which stored hashes
+//! should be rehashed once their password is known (cf. `KeyRing`, which does
+//! the same for peppers).
+//!
+//! A `CostPolicy` does the same for costs: it holds the parameters that new
+//! hashes are made with and the least memory and passes that stored hashes
+//! must have. A `PolicyHandle` shares one between threads and lets it be
+//! replaced while the service runs, e.g. when a configuration file is
+//! reloaded, so that raising costs doesn't take a redeploy.
+
+use std::io;
+use std::sync::{Arc, RwLock};
+use argon2::{Argon2, Version};
+use params::Params;
+use verifier::Encoded;
+
+/// The version that new hashes are made with.
+pub const NEWEST: Version = Version::_0x13;
+
+/// The Argon2 versions accepted at verification time. The newest version is
+/// always accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionPolicy {
+    legacy: Vec<Version>,
+}
+
+impl VersionPolicy {
+    /// A policy accepting only `NEWEST`.
+    pub fn new() -> VersionPolicy { VersionPolicy { legacy: vec![] } }
+
+    /// Returns this policy, additionally accepting hashes of `version`.
+    pub fn accept(mut self, version: Version) -> VersionPolicy {
+        if version != NEWEST && !self.legacy.contains(&version) {
+            self.legacy.push(version);
+        }
+        self
+    }
+
+    /// Whether hashes of `version` are accepted.
+    pub fn accepts(&self, version: Version) -> bool {
+        version == NEWEST || self.legacy.contains(&version)
+    }
+
+    /// Same as `Encoded::new`, except that the hash is made with `NEWEST`
+    /// whatever the version of `argon`, e.g. when rehashing with the
+    /// parameters of `Encoded::argon2` of a legacy hash.
+    pub fn encode(&self, argon: &Argon2, p: &[u8], s: &[u8], k: &[u8],
+                  x: &[u8])
+                  -> Encoded {
+        let (variant, kib, passes, lanes, _) = argon.params();
+        let params = Params::with_version(passes, lanes, kib, variant, NEWEST)
+                         .unwrap();
+        let argon = Argon2::with_params(params).with_flags(argon.flags());
+        Encoded::new(argon, p, s, k, x)
+    }
+
+    /// Verifies `p` against `enc`, returning `false` without hashing if the
+    /// version of `enc` isn't accepted.
+    pub fn verify(&self, enc: &Encoded, p: &[u8]) -> bool {
+        match self.accepts(enc.version()) {
+            true => enc.verify(p),
+            false => enc.rejected(),
+        }
+    }
+
+    /// Whether `enc` was made with a version other than `NEWEST`, and should
+    /// therefore be rehashed (with `VersionPolicy::encode`) once its password
+    /// is known.
+    pub fn needs_upgrade(&self, enc: &Encoded) -> bool {
+        enc.version() != NEWEST
+    }
+}
+
+impl Default for VersionPolicy {
+    fn default() -> VersionPolicy { VersionPolicy::new() }
+}
+
+/// The parameters of new hashes and the least costs of stored ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CostPolicy {
+    params: Params,
+    min_kib: u32,
+    min_passes: u32,
+}
+
+impl CostPolicy {
+    /// A policy that makes new hashes with `params`, and requires stored
+    /// hashes to cost at least as much memory and as many passes.
+    pub fn new(params: Params) -> CostPolicy {
+        CostPolicy {
+            params,
+            min_kib: params.kib(),
+            min_passes: params.passes(),
+        }
+    }
+
+    /// Returns this policy, accepting stored hashes with as little as `kib`
+    /// of memory, e.g. to phase in a higher cost without rehashing every
+    /// account at once.
+    pub fn min_kib(self, kib: u32) -> CostPolicy {
+        CostPolicy { min_kib: kib, ..self }
+    }
+
+    /// Returns this policy, accepting stored hashes with as few as `passes`.
+    pub fn min_passes(self, passes: u32) -> CostPolicy {
+        CostPolicy {
+            min_passes: passes,
+            ..self
+        }
+    }
+
+    /// The parameters that new hashes are made with.
+    pub fn params(&self) -> Params { self.params }
+
+    /// Hashes `p` with this policy's parameters and a fresh random salt, as
+    /// `Encoded::generate` does.
+    pub fn encode(&self, p: &[u8]) -> io::Result<Encoded> {
+        Encoded::generate(self.params, p)
+    }
+
+    /// Whether `enc` should be rehashed with `CostPolicy::encode` once its
+    /// password is known: its parameters aren't `Params::is_at_least` those
+    /// of new hashes, lowered to the least memory and passes accepted. That
+    /// is, it has too little memory, too few passes, more lanes than new
+    /// hashes, a different variant, or a legacy version.
+    pub fn needs_rehash(&self, enc: &Encoded) -> bool {
+        let floor = Params::floor(self.min_passes, self.params.lanes(),
+                                  self.min_kib, self.params.variant(), NEWEST);
+        !enc.argon2().to_params().is_at_least(&floor)
+    }
+}
+
+/// A `CostPolicy` that can be replaced while in use. Clones share the same
+/// policy, so a service can hand one to every worker and update them all with
+/// `PolicyHandle::store`. Hashes in progress finish under the policy they
+/// started with.
+#[derive(Debug, Clone)]
+pub struct PolicyHandle(Arc<RwLock<Arc<CostPolicy>>>);
+
+impl PolicyHandle {
+    /// A handle on `policy`, shared with its clones.
+    pub fn new(policy: CostPolicy) -> PolicyHandle {
+        PolicyHandle(Arc::new(RwLock::new(Arc::new(policy))))
+    }
+
+    /// The current policy. Holding on to it doesn't block `store`.
+    pub fn load(&self) -> Arc<CostPolicy> {
+        // the lock is only held to clone or swap an `Arc`, so it can't be
+        // poisoned halfway through an update.
+        self.0.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Replaces the policy for every clone of this handle.
+    pub fn store(&self, policy: CostPolicy) {
+        *self.0.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(policy);
+    }
+
+    /// `CostPolicy::encode` under the current policy.
+    pub fn encode(&self, p: &[u8]) -> io::Result<Encoded> {
+        self.load().encode(p)
+    }
+
+    /// `CostPolicy::needs_rehash` under the current policy.
+    pub fn needs_rehash(&self, enc: &Encoded) -> bool {
+        self.load().needs_rehash(enc)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CostPolicy, NEWEST, PolicyHandle, VersionPolicy};
+    use argon2::{Argon2, Variant, Version};
+    use params::Params;
+    use verifier::Encoded;
+
+    fn legacy() -> Encoded {
+        let a2 = Argon2::with_version(1, 1, 8, Variant::Argon2i,
+                                      Version::_0x10);
+        Encoded::new(a2.unwrap(), b"hunter2", b"saltsalt", &[], &[])
+    }
+
+    #[test]
+    fn mixed_versions() {
+        let strict = VersionPolicy::new();
+        let lenient = VersionPolicy::new().accept(Version::_0x10);
+        let old = legacy();
+        assert_eq!(old.version(), Version::_0x10);
+        assert!(!strict.verify(&old, b"hunter2"));
+        assert!(lenient.verify(&old, b"hunter2"));
+        assert!(!lenient.verify(&old, b"hunter3"));
+        assert!(lenient.needs_upgrade(&old));
+
+        let new = lenient.encode(old.argon2(), b"hunter2", b"saltsalt", &[],
+                                 &[]);
+        assert_eq!(new.version(), NEWEST);
+        assert_eq!(new.params().1, old.params().1);
+        assert!(strict.verify(&new, b"hunter2"));
+        assert!(!lenient.needs_upgrade(&new));
+    }
+
+    #[test]
+    fn hot_reload() {
+        let weak = Params::new(1, 1, 8, Variant::Argon2id).unwrap();
+        let strong = Params::new(2, 1, 16, Variant::Argon2id).unwrap();
+        let handle = PolicyHandle::new(CostPolicy::new(weak));
+        let worker = handle.clone();
+
+        let old = worker.encode(b"hunter2").unwrap();
+        assert_eq!(old.params(), (Variant::Argon2id, 8, 1, 1, NEWEST));
+        assert!(!worker.needs_rehash(&old));
+
+        let current = worker.load();
+        handle.store(CostPolicy::new(strong).min_kib(8));
+        assert_eq!(current.params(), weak);
+        assert!(worker.needs_rehash(&old));
+        let new = worker.encode(b"hunter2").unwrap();
+        assert_eq!(new.params(), (Variant::Argon2id, 16, 2, 1, NEWEST));
+        assert!(!worker.needs_rehash(&new));
+
+        handle.store(CostPolicy::new(strong).min_passes(1));
+        assert!(worker.needs_rehash(&old));
+        handle.store(CostPolicy::new(strong).min_kib(8).min_passes(1));
+        assert!(!worker.needs_rehash(&old));
+
+        let argon2i = Params::new(1, 1, 8, Variant::Argon2i).unwrap();
+        handle.store(CostPolicy::new(argon2i));
+        assert!(worker.needs_rehash(&old));
+        assert!(CostPolicy::new(weak).needs_rehash(&legacy()));
+
+        let two_lanes = Params::new(1, 2, 16, Variant::Argon2id).unwrap();
+        handle.store(CostPolicy::new(two_lanes).min_kib(8));
+        assert!(!worker.needs_rehash(&old));
+        handle.store(CostPolicy::new(weak));
+        let parallel = Encoded::generate(two_lanes, b"hunter2").unwrap();
+        assert!(worker.needs_rehash(&parallel));
+    }
+}
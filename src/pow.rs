@@ -0,0 +1,180 @@
+//! Memory-hard client puzzles built on Argon2d.
+//!
+//! A server hands out a `Challenge` (cost parameters, a unique seed, and a
+//! difficulty), the client searches for a nonce whose Argon2d hash begins with
+//! `difficulty` zero bits, and the server checks the answer with a single hash.
+//! Argon2d is used since puzzle inputs are public, so its data-dependent
+//! indexing costs nothing in side-channel resistance and makes time-memory
+//! tradeoffs harder for the solver.
+
+use std::error::Error;
+use std::fmt;
+use argon2::{Argon2, ParamErr, Variant, defaults};
+use limits::{MAX_INPUT_LEN, MIN_SALT_LEN};
+use verifier::constant_eq;
+
+/// A memory-hard puzzle. See the module documentation.
+pub struct Challenge {
+    argon: Argon2,
+    seed: Vec<u8>,
+    difficulty: u32,
+}
+
+/// Returned by `Challenge::new` for arguments that make no puzzle.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ChallengeErr {
+    /// More leading zero bits than a hash has.
+    TooDifficult,
+    /// A seed too short or too long to be a salt.
+    SeedLength,
+    InvalidParams(ParamErr),
+}
+
+impl fmt::Display for ChallengeErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ChallengeErr::*;
+        match *self {
+            TooDifficult => {
+                write!(f, "Difficulty must be <= {} bits.",
+                       8 * defaults::LENGTH)
+            }
+            SeedLength => {
+                write!(f, "Seeds must be between {} and {} bytes.",
+                       MIN_SALT_LEN, MAX_INPUT_LEN)
+            }
+            InvalidParams(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for ChallengeErr {}
+
+/// A nonce that solves a `Challenge`, along with its hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Solution {
+    pub nonce: u64,
+    pub hash: [u8; defaults::LENGTH],
+}
+
+impl Challenge {
+    /// Creates a new puzzle, failing if any of the parameters is out of the
+    /// bounds below. Parameters are:
+    ///
+    /// `difficulty`: The number of leading zero bits required of a solution's
+    /// hash. Each additional bit doubles the expected number of hashes needed
+    /// to solve the puzzle. Must be at most `8 * defaults::LENGTH`.
+    ///
+    /// `params`: The memory and time cost of each attempt. Only the passes,
+    /// lanes, memory size, and version are used; puzzles are always Argon2d.
+    ///
+    /// `seed`: Used as the salt of every attempt, and therefore must be between
+    /// 8 and 2^32 - 1 bytes. It should be unique per challenge so that
+    /// solutions can't be precomputed or replayed.
+    pub fn new(difficulty: u32, params: Argon2, seed: &[u8])
+               -> Result<Challenge, ChallengeErr> {
+        if difficulty as usize > 8 * defaults::LENGTH {
+            return Err(ChallengeErr::TooDifficult);
+        }
+        if seed.len() < MIN_SALT_LEN || seed.len() > MAX_INPUT_LEN {
+            return Err(ChallengeErr::SeedLength);
+        }
+        let (_, kib, passes, lanes, version) = params.params();
+        let argon = Argon2::with_version(passes, lanes, kib, Variant::Argon2d,
+                                         version)
+                        .map_err(ChallengeErr::InvalidParams)?;
+        Ok(Challenge {
+            argon,
+            seed: seed.to_vec(),
+            difficulty,
+        })
+    }
+
+    /// Tries each nonce produced by `nonces` in turn, returning the first that
+    /// solves this puzzle, or `None` if the iterator runs dry first.
+    pub fn solve<I>(&self, nonces: I) -> Option<Solution>
+        where I: IntoIterator<Item = u64>
+    {
+        for nonce in nonces {
+            let hash = self.attempt(nonce);
+            if leading_zeros(&hash) >= self.difficulty {
+                return Some(Solution { nonce, hash });
+            }
+        }
+        None
+    }
+
+    /// Checks a purported solution by recomputing its hash. Costs exactly one
+    /// Argon2d invocation.
+    pub fn verify(&self, solution: &Solution) -> bool {
+        let hash = self.attempt(solution.nonce);
+        let matches = constant_eq(&hash, &solution.hash);
+        matches && leading_zeros(&hash) >= self.difficulty
+    }
+
+    /// The number of leading zero bits required of a solution.
+    pub fn difficulty(&self) -> u32 { self.difficulty }
+
+    /// The seed of this puzzle, which must be sent to the solver.
+    pub fn seed(&self) -> &[u8] { &self.seed[..] }
+
+    fn attempt(&self, nonce: u64) -> [u8; defaults::LENGTH] {
+        let mut out = [0; defaults::LENGTH];
+        let p = nonce.to_le_bytes();
+        self.argon.hash(&mut out, &p, &self.seed[..], &[], &[]);
+        out
+    }
+}
+
+fn leading_zeros(bytes: &[u8]) -> u32 {
+    let mut rv = 0;
+    for &b in bytes {
+        rv += b.leading_zeros();
+        if b != 0 {
+            break;
+        }
+    }
+    rv
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Challenge, ChallengeErr, leading_zeros};
+    use argon2::{Argon2, Variant};
+
+    #[test]
+    fn test_leading_zeros() {
+        assert_eq!(leading_zeros(&[0xff, 0]), 0);
+        assert_eq!(leading_zeros(&[0x01, 0]), 7);
+        assert_eq!(leading_zeros(&[0, 0, 0x20]), 18);
+        assert_eq!(leading_zeros(&[0, 0]), 16);
+    }
+
+    #[test]
+    fn solve_and_verify() {
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2i).unwrap();
+        let c = Challenge::new(6, a2, b"unique challenge seed").unwrap();
+        let mut s = c.solve(0..).unwrap();
+        assert!(leading_zeros(&s.hash) >= 6);
+        assert!(c.verify(&s));
+
+        s.nonce += 1;
+        assert!(!c.verify(&s));
+    }
+
+    #[test]
+    fn exhausted_nonces() {
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2i).unwrap();
+        let c = Challenge::new(8 * 32, a2, b"unique challenge seed").unwrap();
+        assert!(c.solve(0..4).is_none());
+    }
+
+    #[test]
+    fn bad_args() {
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2i).unwrap();
+        assert_eq!(Challenge::new(8 * 32 + 1, a2, b"unique seed").err(),
+                   Some(ChallengeErr::TooDifficult));
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2i).unwrap();
+        assert_eq!(Challenge::new(6, a2, b"seed").err(),
+                   Some(ChallengeErr::SeedLength));
+    }
+}
@@ -0,0 +1,108 @@
+//! Argon2's building blocks, for use in other memory-hard or sponge-like
+//! constructions. Enabled by the `primitives` feature.
+//!
+//! Words are the little-endian decoding of bytes, as in the Argon2 spec. A
+//! 1-KiB block is 128 words, arranged as an 8x8 matrix of 16-byte registers:
+//! register (i, j) comprises words `16 * i + 2 * j` and `16 * i + 2 * j + 1`.
+
+use argon2;
+use block::{Block, Words};
+
+/// The variable-length hash function H′ of section 3.3 of the Argon2 spec
+/// (`blake2b_long` in the reference implementation), filling `out` with a
+/// hash of `input`. Outputs of up to 64 bytes are plain Blake2b digests of
+/// the output length (as 4 little-endian bytes) followed by `input`; longer
+/// ones are chained from 64-byte digests. `out.len()` must be between 1 and
+/// 2^32 - 1.
+pub fn blake2b_long(out: &mut [u8], input: &[u8]) {
+    assert!(!out.is_empty() && out.len() as u64 <= 0xffffffff);
+    argon2::h_prime(out, input)
+}
+
+/// The permutation P of section 3.6 of the Argon2 spec: one round of Blake2b
+/// over eight 16-byte registers (16 words), with each addition `a + b`
+/// hardened to `a + b + 2 * lo32(a) * lo32(b)` (the "BlaMka" round).
+pub fn permute(state: &mut [u64; 16]) { argon2::p_words(state) }
+
+/// Applies `permute` to each row of registers of `block`, and then to each
+/// column. This is the compression function G of Argon2 without its
+/// feed-forward: G(X, Y) = `permute_block(X ⊕ Y)` ⊕ X ⊕ Y.
+pub fn permute_block(block: &mut Words) {
+    match Block::from_words_mut(block) {
+        Some(b) => argon2::p_block(b),
+        None => {
+            let mut b = ::block::zero();
+            b.as_words_mut().clone_from(block);
+            argon2::p_block(&mut b);
+            block.clone_from(b.as_words());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{blake2b_long, permute, permute_block};
+    use compression::{BlaMka, Compression};
+
+    #[test]
+    fn p_is_g_without_feedforward() {
+        let mut x = [0; 128];
+        for (i, w) in x.iter_mut().enumerate() {
+            *w = (i as u64).wrapping_mul(0x9e3779b97f4a7c15);
+        }
+        let (zero, mut g) = ([0; 128], [0; 128]);
+        BlaMka::compress(&mut g, &x, &zero);
+
+        let mut p = x;
+        permute_block(&mut p);
+        for (p, x) in p.iter_mut().zip(x.iter()) {
+            *p ^= *x;
+        }
+        assert_eq!(&p[..], &g[..]);
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    // Computed independently from the definition of H′ with Python's hashlib.
+    #[test]
+    fn blake2b_long_vectors() {
+        let cases: [(usize, &str); 5] =
+            [(4, "bc8c6d5f"),
+             (32, "baa91a7e734fe1b82a8549cdb960bd63010114f6c57cac789fdefb8c\
+                   04bae489"),
+             (64, "c7b51d2e3b13318e1dfe2f9ccd9d851a80a5d9f83f4fbd90f0e5cbf3\
+                   bc28c5866cc2854a2025022b5d6ed3a1d8b000efc174985852f5bee1\
+                   40f23821f10575df"),
+             (65, "ab6e33a31dcde6f7cbbab61bcb7e4a3b72d9ed6c2f1141c419fb439a\
+                   b16b1285aec1502602554f66b2c435848aee15f73ecb87b17aba0ccf\
+                   5c10b41d3144965c31"),
+             (100, "86772cbb3e423f74105006482885c9f421b4142b2f9500672bc1e02\
+                    23f49131a4fee3d4d6fe4f1d66a57faff65ac92704405159b10608f\
+                    4dc5448343752cdacc62bc9dde08aa8690bbbc40352c134c808dcbb\
+                    349eee90495e6dd69e653c99786b28b31cd")];
+        for &(len, exp) in cases.iter() {
+            let mut out = vec![0; len];
+            blake2b_long(&mut out, b"argon2rs");
+            assert_eq!(hex(&out), exp);
+        }
+
+        let mut block = [0; 1024];
+        blake2b_long(&mut block, b"argon2rs");
+        assert_eq!(hex(&block[992..]),
+                   "48e6dc8cde4cd2f6becfca42df2a736e280f240cd3bf612c41291ccb\
+                    85ae267f");
+    }
+
+    #[test]
+    fn permutation() {
+        let mut state = [0; 16];
+        permute(&mut state);
+        assert_eq!(state, [0; 16]);
+
+        state[0] = 1;
+        permute(&mut state);
+        assert!(state.iter().filter(|&&w| w != 0).count() > 8);
+    }
+}
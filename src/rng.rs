@@ -0,0 +1,54 @@
+//! Sources of randomness for salts.
+//!
+//! Everything in this crate that needs random bytes draws them from an
+//! `RngProvider`. The `_with` variants of the generating functions, such as
+//! `Salt::generate_with` and `Encoded::generate_with`, take one explicitly;
+//! the others use `OsRng`. Targets without an operating system RNG can supply
+//! their hardware RNG this way, and tests can supply fixed bytes.
+
+use std::io;
+use getrandom;
+
+/// A source of random bytes. Implementations used for salts must be
+/// cryptographically secure.
+pub trait RngProvider {
+    /// Fills `buf` entirely with random bytes.
+    fn fill(&mut self, buf: &mut [u8]) -> io::Result<()>;
+}
+
+/// The operating system's random number generator, via the `getrandom` crate.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OsRng;
+
+impl RngProvider for OsRng {
+    fn fill(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        getrandom::getrandom(buf).map_err(io::Error::from)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::io;
+    use super::{OsRng, RngProvider};
+
+    // Yields consecutive bytes from the given one on, for reproducible salts.
+    pub struct Counter(pub u8);
+
+    impl RngProvider for Counter {
+        fn fill(&mut self, buf: &mut [u8]) -> io::Result<()> {
+            for b in buf.iter_mut() {
+                *b = self.0;
+                self.0 = self.0.wrapping_add(1);
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn os_rng() {
+        let (mut a, mut b) = ([0; 32], [0; 32]);
+        OsRng.fill(&mut a).unwrap();
+        OsRng.fill(&mut b).unwrap();
+        assert!(a != b);
+    }
+}
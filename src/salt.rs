@@ -0,0 +1,189 @@
+//! Validated salts.
+//!
+//! Hashing takes salts as plain slices, so a short or constant one slips by
+//! easily. A `Salt` is bounds-checked when created, and `Salt::generate` draws
+//! one from the OS (cf. `rng`). It derefs to `[u8]`, so `&salt` goes wherever
+//! a salt slice does.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::ops::Deref;
+use std::str;
+use limits;
+use rng::{OsRng, RngProvider};
+use verifier::{base64_no_pad, debase64_no_pad};
+
+/// The shortest salt accepted, per the Argon2 specification.
+pub const MIN_LEN: usize = limits::MIN_SALT_LEN;
+/// The longest salt accepted. Its base64 encoding is 64 characters, the most
+/// that PHC strings allow.
+pub const MAX_LEN: usize = 48;
+/// The length of salts made by `Salt::generate`, as recommended for password
+/// hashing.
+pub const DEFAULT_LEN: usize = 16;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SaltErr {
+    TooShort,
+    TooLong,
+    InvalidBase64,
+}
+
+impl fmt::Display for SaltErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::SaltErr::*;
+        match *self {
+            TooShort => write!(f, "Salts must be >= {} bytes.", MIN_LEN),
+            TooLong => write!(f, "Salts must be <= {} bytes.", MAX_LEN),
+            InvalidBase64 => write!(f, "Salt is not valid unpadded base64."),
+        }
+    }
+}
+
+impl Error for SaltErr {}
+
+/// A salt between `MIN_LEN` and `MAX_LEN` bytes long.
+#[derive(Clone, Copy)]
+pub struct Salt {
+    bytes: [u8; MAX_LEN],
+    len: usize,
+}
+
+impl Salt {
+    /// Copies `bytes` into a new salt, checking its length.
+    pub fn new(bytes: &[u8]) -> Result<Salt, SaltErr> {
+        if bytes.len() < MIN_LEN {
+            return Err(SaltErr::TooShort);
+        } else if bytes.len() > MAX_LEN {
+            return Err(SaltErr::TooLong);
+        }
+        let mut rv = Salt {
+            bytes: [0; MAX_LEN],
+            len: bytes.len(),
+        };
+        rv.bytes[..bytes.len()].copy_from_slice(bytes);
+        Ok(rv)
+    }
+
+    /// A fresh random salt of `DEFAULT_LEN` bytes.
+    pub fn generate() -> io::Result<Salt> { Salt::generate_len(DEFAULT_LEN) }
+
+    /// A fresh random salt of `len` bytes, which must be between `MIN_LEN` and
+    /// `MAX_LEN`.
+    pub fn generate_len(len: usize) -> io::Result<Salt> {
+        Salt::generate_with(len, &mut OsRng)
+    }
+
+    /// Same as `Salt::generate_len`, but drawing the salt from `rng`.
+    pub fn generate_with(len: usize, rng: &mut dyn RngProvider)
+                         -> io::Result<Salt> {
+        assert!((MIN_LEN..=MAX_LEN).contains(&len));
+        let mut rv = Salt {
+            bytes: [0; MAX_LEN],
+            len,
+        };
+        rng.fill(&mut rv.bytes[..len])?;
+        Ok(rv)
+    }
+
+    /// Decodes a salt from the unpadded base64 used in encoded hashes.
+    pub fn from_b64(b64: &str) -> Result<Salt, SaltErr> {
+        match debase64_no_pad(b64.as_bytes()) {
+            Some(bytes) => Salt::new(&bytes),
+            None => Err(SaltErr::InvalidBase64),
+        }
+    }
+
+    /// Encodes this salt as unpadded base64, as in encoded hashes.
+    pub fn to_b64(&self) -> String {
+        String::from_utf8(base64_no_pad(self)).unwrap()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] { &self.bytes[..self.len] }
+}
+
+impl Deref for Salt {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] { self.as_bytes() }
+}
+
+impl AsRef<[u8]> for Salt {
+    fn as_ref(&self) -> &[u8] { self.as_bytes() }
+}
+
+impl PartialEq for Salt {
+    fn eq(&self, other: &Salt) -> bool { self.as_bytes() == other.as_bytes() }
+}
+
+impl Eq for Salt {}
+
+impl fmt::Debug for Salt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Salt({})", self.to_b64())
+    }
+}
+
+/// Formats as base64, like `Salt::to_b64`.
+impl fmt::Display for Salt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_b64())
+    }
+}
+
+impl str::FromStr for Salt {
+    type Err = SaltErr;
+    fn from_str(b64: &str) -> Result<Salt, SaltErr> { Salt::from_b64(b64) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DEFAULT_LEN, MAX_LEN, MIN_LEN, Salt, SaltErr};
+    use argon2::{Argon2, Variant};
+    use rng::test::Counter;
+    use verifier::Encoded;
+
+    #[test]
+    fn bounds() {
+        assert_eq!(Salt::new(&[0; MIN_LEN - 1]), Err(SaltErr::TooShort));
+        assert_eq!(Salt::new(&[0; MAX_LEN + 1]), Err(SaltErr::TooLong));
+        assert_eq!(&Salt::new(b"saltsalt").unwrap()[..], b"saltsalt");
+        assert_eq!(Salt::new(&[7; MAX_LEN]).unwrap().len(), MAX_LEN);
+    }
+
+    #[test]
+    fn generate() {
+        let (a, b) = (Salt::generate().unwrap(), Salt::generate().unwrap());
+        assert_eq!(a.len(), DEFAULT_LEN);
+        assert!(a != b);
+        assert_eq!(Salt::generate_len(32).unwrap().len(), 32);
+    }
+
+    #[test]
+    fn generate_with() {
+        let mut rng = Counter(0);
+        let s = Salt::generate_with(MIN_LEN, &mut rng).unwrap();
+        assert_eq!(s.as_bytes(), &[0, 1, 2, 3, 4, 5, 6, 7]);
+        let s = Salt::generate_with(MIN_LEN, &mut rng).unwrap();
+        assert_eq!(s.as_bytes(), &[8, 9, 10, 11, 12, 13, 14, 15]);
+    }
+
+    #[test]
+    fn base64() {
+        let s = Salt::new(b"sodium chloride").unwrap();
+        assert_eq!(s.to_b64(), "c29kaXVtIGNobG9yaWRl");
+        assert_eq!(s.to_string().parse::<Salt>(), Ok(s));
+        assert_eq!(Salt::from_b64("c29kaXVt"), Err(SaltErr::TooShort));
+        assert_eq!(Salt::from_b64("c29*aXVtIGNobG9yaWRl"),
+                   Err(SaltErr::InvalidBase64));
+    }
+
+    #[test]
+    fn as_salt_slice() {
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2i).unwrap();
+        let s = Salt::generate().unwrap();
+        let enc = Encoded::hash(a2, b"password", &s);
+        assert_eq!(enc.salt(), s.as_bytes());
+        assert!(enc.verify(b"password"));
+    }
+}
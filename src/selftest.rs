@@ -0,0 +1,114 @@
+//! Power-on self-test, for compliance regimes that require known-answer tests
+//! before a cryptographic module may be used. Enabled by the `self-test`
+//! feature.
+//!
+//! The first hash computed in a process, or an earlier explicit call to
+//! `power_on_self_test`, first checks Argon2d, Argon2i, and Argon2id against
+//! the test vectors of RFC 9106. If any check fails, the crate is poisoned:
+//! every later hashing call panics, since `Argon2::hash` has no way of
+//! reporting errors, and `status` reports the failure.
+
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::sync::Once;
+use std::sync::atomic::{AtomicBool, Ordering};
+use argon2::{Argon2, Variant};
+
+/// The self-test failed, so results of this crate can't be trusted.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct SelfTestFailed;
+
+const MESSAGE: &str = "Argon2 power-on self-test failed.";
+
+impl fmt::Display for SelfTestFailed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { f.write_str(MESSAGE) }
+}
+
+impl Error for SelfTestFailed {
+    fn description(&self) -> &str { MESSAGE }
+}
+
+// Section 5 of RFC 9106 (identical to the reference implementation's KATs):
+// 32 KiB, 3 passes, 4 lanes, 32-byte tags, version 0x13.
+const KATS: [(Variant, [u8; 32]); 3] =
+    [(Variant::Argon2d,
+      [0x51, 0x2b, 0x39, 0x1b, 0x6f, 0x11, 0x62, 0x97, 0x53, 0x71, 0xd3,
+       0x09, 0x19, 0x73, 0x42, 0x94, 0xf8, 0x68, 0xe3, 0xbe, 0x39, 0x84,
+       0xf3, 0xc1, 0xa1, 0x3a, 0x4d, 0xb9, 0xfa, 0xbe, 0x4a, 0xcb]),
+     (Variant::Argon2i,
+      [0xc8, 0x14, 0xd9, 0xd1, 0xdc, 0x7f, 0x37, 0xaa, 0x13, 0xf0, 0xd7,
+       0x7f, 0x24, 0x94, 0xbd, 0xa1, 0xc8, 0xde, 0x6b, 0x01, 0x6d, 0xd3,
+       0x88, 0xd2, 0x99, 0x52, 0xa4, 0xc4, 0x67, 0x2b, 0x6c, 0xe8]),
+     (Variant::Argon2id,
+      [0x0d, 0x64, 0x0d, 0xf5, 0x8d, 0x78, 0x76, 0x6c, 0x08, 0xc0, 0x37,
+       0xa3, 0x4a, 0x8b, 0x53, 0xc9, 0xd0, 0x1e, 0xf0, 0x45, 0x2d, 0x75,
+       0xb6, 0x5e, 0xb5, 0x25, 0x20, 0xe9, 0x6b, 0x01, 0xe6, 0x59])];
+
+static FIRST_RUN: Once = Once::new();
+static FAILED: AtomicBool = AtomicBool::new(false);
+
+thread_local!(static TESTING: Cell<bool> = const { Cell::new(false) });
+
+/// Runs the known-answer tests, even if they have run before, poisoning the
+/// crate if they fail. Returns the resulting `status`.
+pub fn power_on_self_test() -> Result<(), SelfTestFailed> {
+    let mut ran = false;
+    FIRST_RUN.call_once(|| {
+        run();
+        ran = true;
+    });
+    if !ran {
+        run();
+    }
+    status()
+}
+
+/// Whether any self-test so far has failed. Doesn't run one.
+pub fn status() -> Result<(), SelfTestFailed> {
+    match FAILED.load(Ordering::SeqCst) {
+        true => Err(SelfTestFailed),
+        false => Ok(()),
+    }
+}
+
+// Called before every hash: runs the self-test once per process and panics if
+// the crate is poisoned.
+pub(crate) fn ensure() {
+    if TESTING.with(|t| t.get()) {
+        return;
+    }
+    FIRST_RUN.call_once(run);
+    if let Err(e) = status() {
+        panic!("{}", e);
+    }
+}
+
+fn run() {
+    TESTING.with(|t| t.set(true));
+    let ok = KATS.iter().all(|&(variant, ref tag)| {
+        let a2 = Argon2::new(3, 4, 32, variant).unwrap();
+        let mut out = [0; 32];
+        a2.hash(&mut out, &[1; 32], &[2; 16], &[3; 8], &[4; 12]);
+        &out == tag
+    });
+    TESTING.with(|t| t.set(false));
+    if !ok {
+        FAILED.store(true, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{power_on_self_test, status};
+    use argon2::{Argon2, Variant};
+
+    #[test]
+    fn self_test_passes() {
+        let mut out = [0; 32];
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2i).unwrap();
+        a2.hash(&mut out, b"password", b"saltsalt", &[], &[]);
+        assert_eq!(status(), Ok(()));
+        assert_eq!(power_on_self_test(), Ok(()));
+    }
+}
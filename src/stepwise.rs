@@ -0,0 +1,160 @@
+//! Hashing a slice at a time, for event loops that must not block for the
+//! whole of a hash: single-threaded async runtimes, and browsers running
+//! wasm, where the alternative of a blocking thread pool isn't available.
+//!
+//! `Stepper::step` fills the next slice of the block matrix, a quarter of a
+//! pass, on the calling thread, and returns; `Stepper::finish` computes the
+//! tag once every slice is filled. `HashFuture` drives a `Stepper` as a
+//! `Future` that fills one slice per poll, and asks to be polled again after
+//! each, so that other tasks get to run in between. Either way, the tag is
+//! that of `Argon2::hash` on the same inputs.
+//!
+//! A slice of a hash with `m` KiB of memory fills `m / 4` KiB, on one thread
+//! whatever the number of lanes, so that is how long the event loop is held
+//! up at a time. Memory is allocated, and inputs are hashed into the initial
+//! state, when the `Stepper` is created.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use argon2::{Argon2, SLICES_PER_LANE};
+use low_level::Instance;
+
+/// An Argon2 run that is filled a slice at a time by `step`.
+pub struct Stepper {
+    inst: Instance,
+    slices: u32,
+}
+
+impl Stepper {
+    /// Starts hashing the given inputs, which are as in `Argon2::hash`, under
+    /// the parameters of `argon`, for a tag of `taglen` bytes.
+    pub fn new(argon: &Argon2, taglen: usize, p: &[u8], s: &[u8], k: &[u8],
+               x: &[u8])
+               -> Stepper {
+        let params = argon.to_params();
+        Stepper {
+            inst: Instance::new(params, taglen, p, s, k, x),
+            slices: params.passes() * SLICES_PER_LANE,
+        }
+    }
+
+    /// Fills the next slice of the block matrix, and returns whether the run
+    /// is now done. Does nothing once it is.
+    pub fn step(&mut self) -> bool {
+        if !self.inst.is_done() {
+            for seg in self.inst.segments() {
+                seg.fill();
+            }
+        }
+        self.inst.is_done()
+    }
+
+    /// The number of slices filled so far, and the total.
+    pub fn progress(&self) -> (u32, u32) {
+        let done = match self.inst.next_slice() {
+            Some((pass, slice)) => pass * SLICES_PER_LANE + slice,
+            None => self.slices,
+        };
+        (done, self.slices)
+    }
+
+    /// Whether every slice has been filled.
+    pub fn is_done(&self) -> bool { self.inst.is_done() }
+
+    /// Writes the tag to `out`, whose length must be the `taglen` passed to
+    /// `Stepper::new`. Panics unless `is_done()`.
+    pub fn finish(&self, out: &mut [u8]) { self.inst.finalize(out) }
+}
+
+/// A `Stepper` as a `Future` that fills one slice per poll, and resolves to
+/// the tag.
+pub struct HashFuture {
+    stepper: Stepper,
+    taglen: usize,
+}
+
+impl HashFuture {
+    /// Same as `Stepper::new`.
+    pub fn new(argon: &Argon2, taglen: usize, p: &[u8], s: &[u8], k: &[u8],
+               x: &[u8])
+               -> HashFuture {
+        HashFuture {
+            stepper: Stepper::new(argon, taglen, p, s, k, x),
+            taglen,
+        }
+    }
+
+    /// The `Stepper` being driven, e.g. for its `progress`.
+    pub fn stepper(&self) -> &Stepper { &self.stepper }
+}
+
+impl Future for HashFuture {
+    type Output = Vec<u8>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Vec<u8>> {
+        let this = self.get_mut();
+        if this.stepper.step() {
+            let mut tag = vec![0; this.taglen];
+            this.stepper.finish(&mut tag);
+            Poll::Ready(tag)
+        } else {
+            // nothing to wait for; yield to other tasks only.
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll, Waker};
+    use super::{HashFuture, Stepper};
+    use argon2::{Argon2, Variant};
+
+    fn expected(a2: &Argon2) -> [u8; 32] {
+        let mut out = [0; 32];
+        a2.hash(&mut out, b"password", b"somesalt", b"key", b"data");
+        out
+    }
+
+    #[test]
+    fn stepper() {
+        let a2 = Argon2::new(2, 2, 16, Variant::Argon2id).unwrap();
+        let mut st = Stepper::new(&a2, 32, b"password", b"somesalt", b"key",
+                                  b"data");
+        assert_eq!(st.progress(), (0, 8));
+        let mut steps = 0;
+        while !st.step() {
+            steps += 1;
+            assert_eq!(st.progress(), (steps, 8));
+        }
+        assert_eq!(steps, 7);
+        assert!(st.step() && st.is_done());
+        let mut out = [0; 32];
+        st.finish(&mut out);
+        assert_eq!(out, expected(&a2));
+    }
+
+    #[test]
+    fn future() {
+        let a2 = Argon2::new(3, 1, 8, Variant::Argon2i).unwrap();
+        let mut fut = HashFuture::new(&a2, 32, b"password", b"somesalt",
+                                      b"key", b"data");
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut polls = 1;
+        let tag = loop {
+            match Pin::new(&mut fut).poll(&mut cx) {
+                Poll::Ready(tag) => break tag,
+                Poll::Pending => polls += 1,
+            }
+        };
+        assert_eq!(polls, 12);
+        assert_eq!(&tag[..], &expected(&a2)[..]);
+
+        fn send<T: Send>(_: &T) {}
+        send(&fut);
+    }
+}
@@ -0,0 +1,426 @@
+//! Hashing in a short-lived child process, so that parameters too large to
+//! allocate for, or an allocator that aborts, cost the child rather than the
+//! service that asked. Unix only, with the `subprocess` feature.
+//!
+//! The child is an ordinary executable, by default the current one, that
+//! calls `serve` when `is_child` says it was started by a `Sandbox`:
+//!
+//! ```ignore
+//! fn main() {
+//!     if argon2rs::subprocess::is_child() {
+//!         argon2rs::subprocess::serve();
+//!     }
+//!     // ...
+//! }
+//! ```
+//!
+//! `serve` reads one request from stdin, answers it on stdout, and exits. A
+//! request is a byte naming the operation followed by its arguments:
+//!
+//! - `h`: passes, lanes, kib, variant, version, and output length, each a
+//!   little endian u32, then the password, salt, secret key, and associated
+//!   data, each preceded by its length as a little endian u32.
+//! - `v`: the encoded hash, then the password, each preceded by its length.
+//!
+//! The answer is a zero byte followed by the hash, or by a single byte that
+//! is 1 if the password matched, or else a nonzero byte if the request was
+//! invalid. A child that exits unsuccessfully has crashed, whatever it wrote.
+
+use std::env;
+use std::error::Error;
+use std::ffi::{CString, OsString};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{self, Command, ExitStatus, Stdio};
+use argon2::{Argon2, Variant, Version, wipe};
+use params::{Memory, Params};
+use verifier::Encoded;
+
+const CHILD_VAR: &str = "ARGON2RS_SUBPROCESS";
+const HASH: u8 = b'h';
+const VERIFY: u8 = b'v';
+const OK: u8 = 0;
+const INVALID: u8 = 1;
+
+/// Whether this process was started by a `Sandbox`, and should `serve`.
+pub fn is_child() -> bool { env::var_os(CHILD_VAR).is_some() }
+
+/// Answers the request on stdin, then exits: with status 0 if it could be
+/// read and answered, and 1 otherwise.
+pub fn serve() -> ! {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let rv = serve_on(&mut stdin.lock(), &mut stdout.lock());
+    process::exit(if rv.is_ok() { 0 } else { 1 })
+}
+
+fn serve_on(r: &mut dyn Read, w: &mut dyn Write) -> io::Result<()> {
+    let mut op = [0];
+    r.read_exact(&mut op)?;
+    let answer = match op[0] {
+        HASH => {
+            let mut nums = [0; 6];
+            for n in nums.iter_mut() {
+                *n = read_u32(r)?;
+            }
+            let mut args = [vec![], vec![], vec![], vec![]];
+            for arg in args.iter_mut() {
+                *arg = read_field(r)?;
+            }
+            let rv = hash(nums, &args);
+            for arg in args.iter_mut() {
+                wipe(arg);
+            }
+            rv
+        }
+        VERIFY => {
+            let enc = read_field(r)?;
+            let mut p = read_field(r)?;
+            let matched = match Encoded::from_u8(&enc) {
+                Ok(enc) => enc.verify(&p),
+                Err(_) => false,
+            };
+            wipe(&mut p);
+            Some(vec![OK, matched as u8])
+        }
+        _ => None,
+    };
+    let mut answer = answer.unwrap_or_else(|| vec![INVALID]);
+    let rv = w.write_all(&answer).and_then(|_| w.flush());
+    wipe(&mut answer);
+    rv
+}
+
+fn hash([passes, lanes, kib, variant, version, outlen]: [u32; 6],
+        args: &[Vec<u8>; 4])
+        -> Option<Vec<u8>> {
+    let variant = match variant {
+        0 => Variant::Argon2d,
+        1 => Variant::Argon2i,
+        2 => Variant::Argon2id,
+        _ => return None,
+    };
+    let version = match version {
+        0x10 => Version::_0x10,
+        0x13 => Version::_0x13,
+        _ => return None,
+    };
+    let params = Params::with_version(passes, lanes, kib, variant, version)
+                     .ok()?;
+    if outlen < 4 {
+        return None;
+    }
+    let mut rv = vec![0; 1 + outlen as usize];
+    rv[0] = OK;
+    Argon2::with_params(params).hash(&mut rv[1..], &args[0], &args[1],
+                                     &args[2], &args[3]);
+    Some(rv)
+}
+
+fn read_u32(r: &mut dyn Read) -> io::Result<u32> {
+    let mut n = [0; 4];
+    r.read_exact(&mut n)?;
+    Ok(u32::from_le_bytes(n))
+}
+
+fn read_field(r: &mut dyn Read) -> io::Result<Vec<u8>> {
+    let len = read_u32(r)? as usize;
+    let mut rv = vec![];
+    r.take(len as u64).read_to_end(&mut rv)?;
+    if rv.len() != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(rv)
+}
+
+fn put_field(buf: &mut Vec<u8>, field: &[u8]) {
+    buf.extend_from_slice(&(field.len() as u32).to_le_bytes());
+    buf.extend_from_slice(field);
+}
+
+/// Errors from `Sandbox`.
+#[derive(Debug)]
+pub enum SandboxErr {
+    /// The child couldn't be started or talked to.
+    Io(io::Error),
+    /// The child exited with the given status without answering, e.g. when
+    /// it ran out of memory.
+    Crashed(ExitStatus),
+    /// The child rejected the request, or answered it with something other
+    /// than an answer.
+    Protocol,
+}
+
+impl fmt::Display for SandboxErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SandboxErr::Io(ref e) => write!(f, "Hashing subprocess: {}", e),
+            SandboxErr::Crashed(status) => {
+                write!(f, "Hashing subprocess failed: {}.", status)
+            }
+            SandboxErr::Protocol => {
+                write!(f, "Hashing subprocess gave no valid answer.")
+            }
+        }
+    }
+}
+
+impl Error for SandboxErr {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            SandboxErr::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SandboxErr {
+    fn from(e: io::Error) -> SandboxErr { SandboxErr::Io(e) }
+}
+
+/// Runs each hash or verification in a fresh child process, optionally with
+/// its address space capped by `RLIMIT_AS` or inside a cgroup.
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    program: PathBuf,
+    args: Vec<OsString>,
+    max_memory: Option<Memory>,
+    cgroup: Option<PathBuf>,
+}
+
+impl Sandbox {
+    /// A sandbox whose children are the current executable, which must call
+    /// `serve` early in `main` (see the module docs).
+    pub fn new() -> io::Result<Sandbox> {
+        Ok(Sandbox::with_program(env::current_exe()?))
+    }
+
+    /// A sandbox whose children run `program`.
+    pub fn with_program<P: Into<PathBuf>>(program: P) -> Sandbox {
+        Sandbox {
+            program: program.into(),
+            args: vec![],
+            max_memory: None,
+            cgroup: None,
+        }
+    }
+
+    /// Adds an argument to the children's command line.
+    pub fn arg<S: Into<OsString>>(mut self, arg: S) -> Sandbox {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Caps each child's address space at `memory`. This counts everything
+    /// mapped, including the executable and a stack per thread, so leave
+    /// room beyond the block matrix.
+    pub fn max_memory(self, memory: Memory) -> Sandbox {
+        Sandbox { max_memory: Some(memory), ..self }
+    }
+
+    /// Moves each child into the cgroup (v2) at `path`, e.g.
+    /// "/sys/fs/cgroup/argon2", before it runs. The cgroup's limits, such as
+    /// `memory.max`, are for the caller to set up.
+    pub fn cgroup<P: Into<PathBuf>>(self, path: P) -> Sandbox {
+        Sandbox { cgroup: Some(path.into()), ..self }
+    }
+
+    /// Same as `Argon2::hash` with `a2.to_params()`, but in a child process.
+    pub fn hash(&self, a2: &Argon2, out: &mut [u8], p: &[u8], s: &[u8],
+                k: &[u8], x: &[u8])
+                -> Result<(), SandboxErr> {
+        let params = a2.to_params();
+        let mut req = vec![HASH];
+        for &n in [params.passes(), params.lanes(), params.kib(),
+                   params.variant() as u32, params.version() as u32,
+                   out.len() as u32]
+                      .iter() {
+            req.extend_from_slice(&n.to_le_bytes());
+        }
+        for arg in [p, s, k, x].iter() {
+            put_field(&mut req, arg);
+        }
+        let mut answer = self.run(&mut req)?;
+        let rv = if answer.len() == 1 + out.len() && answer[0] == OK {
+            out.clone_from_slice(&answer[1..]);
+            Ok(())
+        } else {
+            Err(SandboxErr::Protocol)
+        };
+        wipe(&mut answer);
+        rv
+    }
+
+    /// Same as `Encoded::from_u8(encoded)` followed by `Encoded::verify`,
+    /// but in a child process. Malformed hashes match no password.
+    pub fn verify(&self, encoded: &[u8], p: &[u8]) -> Result<bool, SandboxErr> {
+        let mut req = vec![VERIFY];
+        put_field(&mut req, encoded);
+        put_field(&mut req, p);
+        match &self.run(&mut req)?[..] {
+            [OK, matched] if *matched <= 1 => Ok(*matched == 1),
+            _ => Err(SandboxErr::Protocol),
+        }
+    }
+
+    // Sends `req` to a new child, wiping it afterwards, and returns the
+    // answer.
+    fn run(&self, req: &mut [u8]) -> Result<Vec<u8>, SandboxErr> {
+        let rv = self.exchange(req);
+        wipe(req);
+        rv
+    }
+
+    fn exchange(&self, req: &[u8]) -> Result<Vec<u8>, SandboxErr> {
+        let limit = self.max_memory.map(|m| m.bytes());
+        let procs = match self.cgroup {
+            Some(ref dir) => {
+                let path = dir.join("cgroup.procs");
+                let path = CString::new(path.as_os_str().as_bytes());
+                Some(path.map_err(|e| {
+                    io::Error::new(io::ErrorKind::InvalidInput, e)
+                })?)
+            }
+            None => None,
+        };
+        let mut cmd = Command::new(&self.program);
+        cmd.args(&self.args)
+           .env(CHILD_VAR, "1")
+           .stdin(Stdio::piped())
+           .stdout(Stdio::piped());
+        // only async-signal-safe calls between fork and exec, hence the
+        // `CString` made above.
+        unsafe {
+            cmd.pre_exec(move || {
+                if let Some(ref procs) = procs {
+                    join_cgroup(procs)?;
+                }
+                if let Some(bytes) = limit {
+                    let lim = libc::rlimit {
+                        rlim_cur: bytes as libc::rlim_t,
+                        rlim_max: bytes as libc::rlim_t,
+                    };
+                    if libc::setrlimit(libc::RLIMIT_AS, &lim) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+        let mut child = cmd.spawn()?;
+
+        // a child that stops reading early closes the pipe, but can't have
+        // answered, which is caught below, so `EPIPE` says nothing more.
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(req);
+        }
+        let mut answer = vec![];
+        let read = match child.stdout.take() {
+            Some(mut stdout) => stdout.read_to_end(&mut answer).map(|_| ()),
+            None => Ok(()),
+        };
+        let status = child.wait()?;
+        if !status.success() {
+            wipe(&mut answer);
+            return Err(SandboxErr::Crashed(status));
+        }
+        read?;
+        Ok(answer)
+    }
+}
+
+// Moves the calling process into the cgroup whose `cgroup.procs` file is
+// `procs`, by writing "0" to it.
+unsafe fn join_cgroup(procs: &CString) -> io::Result<()> {
+    let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let written = libc::write(fd, b"0".as_ptr() as *const libc::c_void, 1);
+    let rv = match written {
+        1 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    };
+    libc::close(fd);
+    rv
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use super::{Sandbox, SandboxErr, put_field, serve_on};
+    use argon2::{Argon2, Variant};
+    use verifier::Encoded;
+
+    fn serve(req: &[u8]) -> Vec<u8> {
+        let mut answer = vec![];
+        serve_on(&mut Cursor::new(req), &mut answer).unwrap();
+        answer
+    }
+
+    #[test]
+    fn protocol() {
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2id).unwrap();
+        let mut exp = [0; 16];
+        a2.hash(&mut exp, b"password", b"somesalt", b"key", b"data");
+        let mut req = vec![b'h'];
+        for &n in [1u32, 1, 8, 2, 0x13, 16].iter() {
+            req.extend_from_slice(&n.to_le_bytes());
+        }
+        for arg in [&b"password"[..], b"somesalt", b"key", b"data"].iter() {
+            put_field(&mut req, arg);
+        }
+        assert_eq!(serve(&req)[..], [&[0][..], &exp[..]].concat()[..]);
+        req[1] = 0;
+        assert_eq!(serve(&req), vec![1]);
+
+        let enc = Encoded::new(a2, b"password", b"somesalt", &[], &[]);
+        for &(ref enc, p, exp) in [(enc.to_u8(), b"password", 1),
+                                   (enc.to_u8(), b"passw0rd", 0),
+                                   (b"$argon2".to_vec(), b"password", 0)]
+                                      .iter() {
+            let mut req = vec![b'v'];
+            put_field(&mut req, enc);
+            put_field(&mut req, p);
+            assert_eq!(serve(&req), vec![0, exp]);
+        }
+
+        assert_eq!(serve(b"x"), vec![1]);
+        assert!(serve_on(&mut Cursor::new(b"v\x10\0\0\0$argon2"),
+                         &mut vec![])
+                    .is_err());
+    }
+
+    #[test]
+    fn crashed_child() {
+        let sandbox = Sandbox::with_program("/bin/sh")
+                          .arg("-c")
+                          .arg("kill -9 $$");
+        match sandbox.verify(b"$argon2i$m=8,t=1,p=1$c29tZXNhbHQ$AAAAAA",
+                             b"password") {
+            Err(SandboxErr::Crashed(status)) => assert!(!status.success()),
+            rv => panic!("{:?}", rv),
+        }
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2i).unwrap();
+        match sandbox.hash(&a2, &mut [0; 32], b"password", b"somesalt", &[],
+                           &[]) {
+            Err(SandboxErr::Crashed(_)) => {}
+            rv => panic!("{:?}", rv),
+        }
+    }
+
+    #[test]
+    fn no_answer() {
+        match Sandbox::with_program("/bin/true").verify(b"", b"") {
+            Err(SandboxErr::Protocol) => {}
+            rv => panic!("{:?}", rv),
+        }
+        match Sandbox::with_program("/nonexistent").verify(b"", b"") {
+            Err(SandboxErr::Io(_)) => {}
+            rv => panic!("{:?}", rv),
+        }
+    }
+}
@@ -0,0 +1,232 @@
+//! Counters and histograms for fleet monitoring, reported through the
+//! `metrics` facade. Enabled by the `metrics` feature.
+//!
+//! Nothing is recorded until the application installs a recorder, such as
+//! the one of `metrics-exporter-prometheus`; `describe` then documents the
+//! metrics below for it. Hashes are counted and timed as their block matrix
+//! is filled, so every hashing entry point is covered, as is verification.
+//! Verifications are counted where tags are compared: `Encoded::verify_dummy`
+//! and hashes rejected without hashing (e.g. ones made by
+//! `Encoded::wrap_legacy`) aren't counted.
+//!
+//! Lanes are timed separately, to catch scheduling imbalance: lanes wait for
+//! each other at every slice, so a single lane worker on an efficiency core or
+//! a busy CPU can double the latency of a multi-lane hash without any other
+//! sign. Lanes filled one after the other (by `Argon2::hash_fixed` and
+//! `trace`) aren't timed.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use metrics::Unit;
+use argon2::Variant;
+
+/// Counter of hashes computed, labelled by `variant` ("argon2d", "argon2i" or
+/// "argon2id").
+pub const HASHES: &str = "argon2_hashes_total";
+
+/// Counter of verifications, labelled by `result` ("success" or "failure").
+pub const VERIFICATIONS: &str = "argon2_verifications_total";
+
+/// Histogram of the time taken to fill the block matrix, in seconds, labelled
+/// by `variant`. Finalizing the tag adds a few microseconds.
+pub const HASH_DURATION: &str = "argon2_hash_duration_seconds";
+
+/// Counter of bytes of block matrix allocated. Matrices reused by
+/// `Argon2::with_pool` or `batch` are counted once.
+pub const MATRIX_BYTES: &str = "argon2_matrix_allocated_bytes_total";
+
+/// Histogram of the time each lane spent filling its segments over a hash, in
+/// seconds, labelled by `lane` ("0", "1", ...).
+pub const LANE_DURATION: &str = "argon2_lane_fill_seconds";
+
+/// Histogram, over hashes of several lanes, of the time spent filling by the
+/// slowest lane divided by that of the fastest. Near 1 when lanes are evenly
+/// scheduled.
+pub const LANE_IMBALANCE: &str = "argon2_lane_imbalance_ratio";
+
+/// Describes the metrics above to the installed recorder.
+pub fn describe() {
+    describe_counter!(HASHES, "Argon2 hashes computed.");
+    describe_counter!(VERIFICATIONS, "Argon2 hash verifications.");
+    describe_histogram!(HASH_DURATION, Unit::Seconds,
+                        "Time taken to fill Argon2 block matrices.");
+    describe_counter!(MATRIX_BYTES, Unit::Bytes,
+                      "Bytes of Argon2 block matrix allocated.");
+    describe_histogram!(LANE_DURATION, Unit::Seconds,
+                        "Time taken by each lane to fill its segments.");
+    describe_histogram!(LANE_IMBALANCE,
+                        "Slowest lane's fill time over the fastest's.");
+}
+
+fn variant_label(v: Variant) -> &'static str {
+    match v {
+        Variant::Argon2d => "argon2d",
+        Variant::Argon2i => "argon2i",
+        Variant::Argon2id => "argon2id",
+    }
+}
+
+pub(crate) fn hashed(variant: Variant, elapsed: Duration) {
+    let v = variant_label(variant);
+    counter!(HASHES, "variant" => v).increment(1);
+    histogram!(HASH_DURATION, "variant" => v).record(elapsed.as_secs_f64());
+}
+
+pub(crate) fn verified(ok: bool) {
+    let result = if ok { "success" } else { "failure" };
+    counter!(VERIFICATIONS, "result" => result).increment(1);
+}
+
+pub(crate) fn allocated(bytes: usize) {
+    counter!(MATRIX_BYTES).increment(bytes as u64);
+}
+
+// The time spent filling by each lane of a hash, in nanoseconds.
+pub(crate) struct LaneTimes(Vec<AtomicU64>);
+
+// Adds the time until it is dropped to its lane's.
+pub(crate) struct LaneTimer<'a> {
+    nanos: &'a AtomicU64,
+    start: Instant,
+}
+
+impl LaneTimes {
+    pub(crate) fn new(lanes: u32) -> LaneTimes {
+        LaneTimes((0..lanes).map(|_| AtomicU64::new(0)).collect())
+    }
+
+    pub(crate) fn start(&self, lane: u32) -> LaneTimer<'_> {
+        LaneTimer {
+            nanos: &self.0[lane as usize],
+            start: Instant::now(),
+        }
+    }
+
+    pub(crate) fn report(&self) {
+        let nanos: Vec<u64> = self.0
+                                  .iter()
+                                  .map(|n| n.load(Ordering::Relaxed))
+                                  .collect();
+        for (lane, &n) in nanos.iter().enumerate() {
+            histogram!(LANE_DURATION, "lane" => lane.to_string())
+                .record(n as f64 / 1e9);
+        }
+        let (min, max) = (nanos.iter().min(), nanos.iter().max());
+        if let (Some(&min), Some(&max)) = (min, max) {
+            if nanos.len() > 1 && min > 0 {
+                histogram!(LANE_IMBALANCE).record(max as f64 / min as f64);
+            }
+        }
+    }
+}
+
+impl<'a> Drop for LaneTimer<'a> {
+    fn drop(&mut self) {
+        let n = self.start.elapsed().as_nanos() as u64;
+        self.nanos.fetch_add(n, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+    use metrics::{Counter, Gauge, Histogram, HistogramFn, Key, KeyName,
+                  Metadata, Recorder, SharedString, Unit, with_local_recorder};
+    use super::{HASHES, HASH_DURATION, LANE_DURATION, LANE_IMBALANCE,
+                MATRIX_BYTES, VERIFICATIONS};
+    use argon2::{Argon2, Variant};
+    use verifier::Encoded;
+
+    // Keeps counter totals and histogram sample counts by name and labels.
+    #[derive(Default)]
+    struct Totals(Mutex<HashMap<String, Arc<AtomicU64>>>);
+
+    struct Samples(Arc<AtomicU64>);
+
+    impl HistogramFn for Samples {
+        fn record(&self, _: f64) { self.0.fetch_add(1, Ordering::SeqCst); }
+    }
+
+    impl Totals {
+        fn cell(&self, key: &Key) -> Arc<AtomicU64> {
+            let mut name = key.name().to_string();
+            for l in key.labels() {
+                name += &format!(",{}={}", l.key(), l.value());
+            }
+            self.0.lock().unwrap().entry(name).or_default().clone()
+        }
+
+        fn get(&self, name: &str) -> u64 {
+            self.0
+                .lock()
+                .unwrap()
+                .get(name)
+                .map_or(0, |c| c.load(Ordering::SeqCst))
+        }
+    }
+
+    impl Recorder for Totals {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>,
+                            _: SharedString) {
+        }
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>,
+                          _: SharedString) {
+        }
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>,
+                              _: SharedString) {
+        }
+        fn register_counter(&self, key: &Key, _: &Metadata) -> Counter {
+            Counter::from_arc(self.cell(key))
+        }
+        fn register_gauge(&self, _: &Key, _: &Metadata) -> Gauge {
+            Gauge::noop()
+        }
+        fn register_histogram(&self, key: &Key, _: &Metadata) -> Histogram {
+            Histogram::from_arc(Arc::new(Samples(self.cell(key))))
+        }
+    }
+
+    #[test]
+    fn recorded() {
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2id).unwrap();
+        // with `self-test`, the first hash in the process also hashes test
+        // vectors, which would be counted.
+        a2.hash(&mut [0; 32], b"password", b"somesalt", &[], &[]);
+
+        let totals = Totals::default();
+        with_local_recorder(&totals, || {
+            let enc = Encoded::new(a2, b"password", b"somesalt", &[], &[]);
+            assert!(enc.verify(b"password"));
+            assert!(!enc.verify(b"wrong"));
+        });
+        let hashes = format!("{},variant=argon2id", HASHES);
+        assert_eq!(totals.get(&hashes), 3);
+        assert_eq!(totals.get(&format!("{},variant=argon2id", HASH_DURATION)),
+                   3);
+        assert_eq!(totals.get(&format!("{},result=success", VERIFICATIONS)),
+                   1);
+        assert_eq!(totals.get(&format!("{},result=failure", VERIFICATIONS)),
+                   1);
+        assert_eq!(totals.get(MATRIX_BYTES), 3 * 8 * 1024);
+        assert_eq!(totals.get(&format!("{},lane=0", LANE_DURATION)), 3);
+        assert_eq!(totals.get(LANE_IMBALANCE), 0);
+    }
+
+    #[test]
+    fn lanes() {
+        let a2 = Argon2::new(1, 3, 24, Variant::Argon2d).unwrap();
+        a2.hash(&mut [0; 32], b"password", b"somesalt", &[], &[]);
+
+        let totals = Totals::default();
+        with_local_recorder(&totals, || {
+            a2.hash(&mut [0; 32], b"password", b"somesalt", &[], &[]);
+        });
+        for lane in 0..3 {
+            let name = format!("{},lane={}", LANE_DURATION, lane);
+            assert_eq!(totals.get(&name), 1);
+        }
+        assert_eq!(totals.get(LANE_IMBALANCE), 1);
+    }
+}
@@ -0,0 +1,329 @@
+//! Recording of Argon2's memory access pattern, for cache-timing research and
+//! visualization. Enabled by the `instrumentation` feature.
+//!
+//! `Argon2::hash_traced` computes the same hash as `Argon2::hash` while
+//! reporting, for each computed block, the coordinates of the block written
+//! and of the two blocks it was computed from. Coordinates are (lane, column)
+//! pairs into the block matrix, as in `argon2.rs`. The first two blocks of
+//! each lane are derived from H0 and not reported.
+//!
+//! `Argon2::hash_dumped` instead writes out the whole block matrix after each
+//! pass, in the format of the reference implementation's `genkat` tool, so
+//! that the first block where two implementations disagree can be found with
+//! `diff`.
+//!
+//! For analyses of the matrix itself, `Argon2::hash_inspected` passes a
+//! `MatrixView` to a callback after each pass, which iterates over lanes and
+//! columns without relying on how blocks are laid out in memory.
+//!
+//! `Argon2::hash_with_stats` condenses both into a `PassStats` per pass: how
+//! often each block was referenced, and how many of the matrix's bits are
+//! set. These are crude measures of diffusion, meant for teaching and for
+//! catching a broken backend (a stuck index generator, or a compression
+//! function that leaves blocks mostly zero), not for cryptanalysis.
+
+use std::io;
+use argon2::{Argon2, Recorder};
+use block::{ARGON2_BLOCK_BYTES, Matrix, Words};
+
+/// One block computation: `write` is set from `prev` and `reference` during
+/// pass `pass`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub pass: u32,
+    pub write: (u32, u32),
+    pub prev: (u32, u32),
+    pub reference: (u32, u32),
+}
+
+/// Receives the accesses of a traced hash, in the order that they happen.
+pub trait TraceSink {
+    fn record(&mut self, access: Access);
+}
+
+impl TraceSink for Vec<Access> {
+    fn record(&mut self, access: Access) { self.push(access); }
+}
+
+impl<'a, 'b> Recorder for &'a mut (dyn TraceSink + 'b) {
+    fn record(&mut self, pass: u32, write: (u32, u32), prev: (u32, u32),
+              reference: (u32, u32)) {
+        TraceSink::record(*self,
+                          Access {
+                              pass,
+                              write,
+                              prev,
+                              reference,
+                          });
+    }
+}
+
+/// Read-only view of the block matrix as of the end of a pass: `lane_count()`
+/// lanes of `lanelen()` blocks each. Blocks are given as their 128 words.
+#[derive(Clone, Copy)]
+pub struct MatrixView<'a>(&'a Matrix);
+
+impl<'a> MatrixView<'a> {
+    pub fn lane_count(&self) -> u32 { self.0.dims().0 }
+
+    pub fn lanelen(&self) -> u32 { self.0.dims().1 }
+
+    /// The block at column `col` of lane `lane`. Panics if out of range.
+    pub fn block(&self, lane: u32, col: u32) -> &'a Words {
+        self.0.lane(lane)[col as usize].as_words()
+    }
+
+    /// The lanes in order, each as an iterator over its blocks.
+    pub fn lanes(&self)
+                 -> impl Iterator<Item = impl Iterator<Item = &'a Words>> {
+        self.0.lanes().map(|lane| lane.iter().map(|b| b.as_words()))
+    }
+
+    /// The blocks of lane `lane`, from column 0 on. Panics if out of range.
+    pub fn lane(&self, lane: u32) -> impl Iterator<Item = &'a Words> {
+        self.0.lane(lane).iter().map(|b| b.as_words())
+    }
+
+    /// The blocks of column `col`, from lane 0 on. Panics if out of range.
+    pub fn column(&self, col: u32) -> impl Iterator<Item = &'a Words> {
+        self.0.column(col).map(|b| b.as_words())
+    }
+}
+
+/// Statistics of the block matrix as of the end of pass `pass`, for
+/// `Argon2::hash_with_stats`. Blocks are numbered lane by lane: block (lane,
+/// column) is number `lane * lanelen + column`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassStats {
+    pub pass: u32,
+    pub lanes: u32,
+    pub lanelen: u32,
+    /// How many times each block was used as a reference block during the
+    /// pass.
+    pub references: Vec<u32>,
+    /// The number of bits set in each lane. Each lane has `8192 * lanelen`
+    /// bits, about half of which should be set.
+    pub ones: Vec<u64>,
+}
+
+impl PassStats {
+    /// `histogram()[n]` is the number of blocks referenced `n` times during
+    /// the pass.
+    pub fn histogram(&self) -> Vec<u32> {
+        let max = self.references.iter().cloned().max().unwrap_or(0);
+        let mut rv = vec![0; max as usize + 1];
+        for &n in &self.references {
+            rv[n as usize] += 1;
+        }
+        rv
+    }
+
+    /// The fraction of the blocks of lane `lane` that were referenced at
+    /// least once during the pass. Panics if out of range.
+    pub fn coverage(&self, lane: u32) -> f64 {
+        let (start, len) = ((lane * self.lanelen) as usize,
+                            self.lanelen as usize);
+        let hit = self.references[start..start + len]
+                      .iter()
+                      .filter(|&&n| n > 0)
+                      .count();
+        hit as f64 / len as f64
+    }
+
+    /// The fraction of the bits of lane `lane` that are set. Panics if out of
+    /// range.
+    pub fn ones_fraction(&self, lane: u32) -> f64 {
+        let bits = self.lanelen as u64 * ARGON2_BLOCK_BYTES as u64 * 8;
+        self.ones[lane as usize] as f64 / bits as f64
+    }
+}
+
+// Collects the references of the current pass for `hash_with_stats`.
+struct StatsRecorder<F> {
+    refs: Vec<(u32, u32)>,
+    f: F,
+}
+
+impl<F: FnMut(&PassStats)> Recorder for StatsRecorder<F> {
+    fn record(&mut self, _: u32, _: (u32, u32), _: (u32, u32),
+              reference: (u32, u32)) {
+        self.refs.push(reference);
+    }
+
+    fn pass_done(&mut self, pass: u32, blocks: &Matrix) {
+        let (lanes, lanelen) = blocks.dims();
+        let mut references = vec![0; (lanes * lanelen) as usize];
+        for (lane, col) in self.refs.drain(..) {
+            references[(lane * lanelen + col) as usize] += 1;
+        }
+        let ones = blocks.lanes()
+                         .map(|lane| {
+                             lane.iter()
+                                 .flat_map(|b| b.as_u64())
+                                 .map(|w| w.count_ones() as u64)
+                                 .sum()
+                         })
+                         .collect();
+        (self.f)(&PassStats {
+            pass,
+            lanes,
+            lanelen,
+            references,
+            ones,
+        });
+    }
+}
+
+impl Argon2 {
+    /// Same as `Argon2::hash`, but reports every block computation to `sink`.
+    /// Lanes are filled one at a time rather than in parallel, so that the
+    /// order of accesses is deterministic: pass by pass, slice by slice, and
+    /// lane by lane within each slice. Expect this to be slow.
+    pub fn hash_traced(&self, out: &mut [u8], p: &[u8], s: &[u8], k: &[u8],
+                       x: &[u8], sink: &mut dyn TraceSink) {
+        let mut sink = sink;
+        self.hash_recorded(out, p, s, k, x, &mut sink);
+    }
+
+    /// Same as `Argon2::hash`, but writes the block matrix to `w` after each
+    /// pass, as `genkat` does: a line `" After pass N:"` preceded by an empty
+    /// line, then one line per 64-bit word of every block. Stops writing after
+    /// the first error, which is returned once hashing is done.
+    pub fn hash_dumped(&self, out: &mut [u8], p: &[u8], s: &[u8], k: &[u8],
+                       x: &[u8], w: &mut dyn io::Write)
+                       -> io::Result<()> {
+        let mut rv = Ok(());
+        self.hash_impl(out, p, s, k, x, |_| {}, |pass, blocks| {
+            if rv.is_ok() {
+                rv = writeln!(w, "\n After pass {}:", pass)
+                         .and_then(|_| blocks.dump(w));
+            }
+        });
+        rv
+    }
+
+    /// Same as `Argon2::hash`, but calls `f` with the number of each pass and
+    /// a view of the block matrix once that pass is done.
+    pub fn hash_inspected<F>(&self, out: &mut [u8], p: &[u8], s: &[u8],
+                             k: &[u8], x: &[u8], mut f: F)
+        where F: FnMut(u32, MatrixView)
+    {
+        self.hash_impl(out, p, s, k, x, |_| {}, |pass, blocks| {
+            f(pass, MatrixView(blocks))
+        });
+    }
+
+    /// Same as `Argon2::hash`, but calls `f` with statistics of each pass
+    /// once it is done. Lanes are filled one at a time, as for `hash_traced`.
+    pub fn hash_with_stats<F>(&self, out: &mut [u8], p: &[u8], s: &[u8],
+                              k: &[u8], x: &[u8], f: F)
+        where F: FnMut(&PassStats)
+    {
+        let mut rec = StatsRecorder { refs: vec![], f };
+        self.hash_recorded(out, p, s, k, x, &mut rec);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+    use std::io::Read;
+    use super::{Access, PassStats};
+    use argon2::{Argon2, Variant};
+
+    #[test]
+    fn traced_hash() {
+        for &v in [Variant::Argon2i, Variant::Argon2d].iter() {
+            let a2 = Argon2::new(2, 2, 32, v).unwrap();
+            let (mut exp, mut out) = ([0; 32], [0; 32]);
+            let mut trace: Vec<Access> = vec![];
+            a2.hash(&mut exp, b"password", b"saltsalt", &[], &[]);
+            a2.hash_traced(&mut out, b"password", b"saltsalt", &[], &[],
+                           &mut trace);
+            assert_eq!(out, exp);
+
+            // 2 passes over 2 lanes of 16 blocks, less 2 initial blocks each.
+            assert_eq!(trace.len(), 2 * 2 * 16 - 2 * 2);
+            assert_eq!(trace[0].write, (0, 2));
+            assert_eq!(trace[2].write, (1, 2));
+            for a in &trace {
+                let (lane, col) = a.write;
+                assert_eq!(a.prev, (lane, (col + 15) % 16));
+                assert!(a.reference != a.write && a.reference.1 < 16);
+            }
+            assert_eq!(trace.last().unwrap().write, (1, 15));
+        }
+    }
+
+    #[test]
+    fn inspected_hash() {
+        let a2 = Argon2::new(2, 3, 24, Variant::Argon2d).unwrap();
+        let (mut exp, mut out) = ([0; 32], [0; 32]);
+        a2.hash(&mut exp, b"password", b"saltsalt", &[], &[]);
+        let mut passes = vec![];
+        a2.hash_inspected(&mut out, b"password", b"saltsalt", &[], &[],
+                          |pass, m| {
+            assert_eq!((m.lane_count(), m.lanelen()), (3, 8));
+            assert_eq!(m.lanes().count(), 3);
+            for (l, lane) in m.lanes().enumerate() {
+                let lane: Vec<_> = lane.collect();
+                assert_eq!(lane.len(), 8);
+                let l = l as u32;
+                assert!(lane.iter().zip(m.lane(l)).all(|(a, b)| *a == b));
+                assert_eq!(lane[5], m.block(l, 5));
+            }
+            let col: Vec<_> = m.column(7).collect();
+            assert_eq!(col, vec![m.block(0, 7), m.block(1, 7), m.block(2, 7)]);
+            passes.push(pass);
+        });
+        assert_eq!(out, exp);
+        assert_eq!(passes, vec![0, 1]);
+    }
+
+    #[test]
+    fn dumped_hash() {
+        // the inputs of genkat, as in `argon2::tests::compare_kats`.
+        let a2 = Argon2::new(3, 4, 32, Variant::Argon2i).unwrap();
+        let mut out = [0; 32];
+        let mut dump = vec![];
+        a2.hash_dumped(&mut out, &[1; 32], &[2; 16], &[3; 8], &[4; 12],
+                       &mut dump)
+          .unwrap();
+        let dump = String::from_utf8(dump).unwrap();
+        let mut kat = String::new();
+        File::open("kats/0x13/argon2i").unwrap().read_to_string(&mut kat)
+                                                  .unwrap();
+        assert_eq!(dump.matches("After pass").count(), 3);
+        assert!(kat.contains(&dump[..]));
+    }
+
+    #[test]
+    fn pass_stats() {
+        let a2 = Argon2::new(2, 2, 32, Variant::Argon2id).unwrap();
+        let (mut exp, mut out) = ([0; 32], [0; 32]);
+        a2.hash(&mut exp, b"password", b"saltsalt", &[], &[]);
+        let mut stats: Vec<PassStats> = vec![];
+        a2.hash_with_stats(&mut out, b"password", b"saltsalt", &[], &[],
+                           |s| stats.push(s.clone()));
+        assert_eq!(out, exp);
+
+        assert_eq!(stats.iter().map(|s| s.pass).collect::<Vec<_>>(),
+                   vec![0, 1]);
+        for s in &stats {
+            assert_eq!((s.lanes, s.lanelen, s.references.len()), (2, 16, 32));
+            let hist = s.histogram();
+            assert_eq!(hist.iter().sum::<u32>(), 32);
+            // one reference per block computed.
+            let total: u32 = hist.iter()
+                                 .enumerate()
+                                 .map(|(n, &h)| n as u32 * h)
+                                 .sum();
+            assert_eq!(total, if s.pass == 0 { 28 } else { 32 });
+            for lane in 0..2 {
+                assert!(s.coverage(lane) > 0.0 && s.coverage(lane) <= 1.0);
+                let ones = s.ones_fraction(lane);
+                assert!(ones > 0.45 && ones < 0.55);
+            }
+        }
+    }
+}
@@ -2,32 +2,46 @@
 /// examples.
 
 use std::{fmt, str};
+use std::convert::TryFrom;
 use std::error::Error;
-use argon2::{Argon2, ParamErr, Variant, Version, defaults};
+use std::io::{self, Read};
+use argon2::{Argon2, ParamErr, Scratch, Unfinalized, Variant, Version,
+             defaults, wipe};
+use audit::{self, Timer, VerifyEvent};
+use kdf;
+use limits::{MAX_SECRET_LEN, MAX_TAG_LEN, MIN_SALT_LEN, MIN_TAG_LEN};
+use params::Params;
+use rng::{OsRng, RngProvider};
+use salt::{self, Salt};
 
-macro_rules! maybe {
-    ($e: expr) => {
-        match $e {
-            None => return None,
-            Some(v) => v,
-        }
-    };
-}
+// Base64 coding runs over salts, keys, and tags, so it avoids secret-dependent
+// branches and table lookups, after libsodium's `sodium_bin2base64`. The
+// helpers below return 0xff for true and 0 for false.
 
-const LUT64: &'static [u8; 64] =
-    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+fn ct_eq(x: u8, y: u8) -> u8 { (((x ^ y) as u16).wrapping_sub(1) >> 8) as u8 }
 
-fn lut(n: u8) -> u8 { LUT64[n as usize & 0x3f] }
+fn ct_lt(x: u8, y: u8) -> u8 { ((x as u16).wrapping_sub(y as u16) >> 8) as u8 }
 
-fn delut(c: u8) -> Option<u8> {
-    match c {
-        43 => Some(62),
-        47 => Some(63),
-        _ if 65 <= c && c <= 90 => Some(c - 65),
-        _ if 97 <= c && c <= 122 => Some(c - 71),
-        _ if 48 <= c && c <= 57 => Some(c + 4),
-        _ => None,
-    }
+fn ct_in(c: u8, lo: u8, hi: u8) -> u8 { !ct_lt(c, lo) & !ct_lt(hi, c) }
+
+fn lut(n: u8) -> u8 {
+    let n = n & 0x3f;
+    (ct_lt(n, 26) & n.wrapping_add(b'A')) |
+    (ct_in(n, 26, 51) & n.wrapping_add(b'a' - 26)) |
+    (ct_in(n, 52, 61) & n.wrapping_sub(52 - b'0')) |
+    (ct_eq(n, 62) & b'+') | (ct_eq(n, 63) & b'/')
+}
+
+// Returns 0xff for characters outside of the alphabet, so that callers can
+// accumulate validity over a whole string and check it once at the end.
+fn delut(c: u8) -> u8 {
+    let (upper, lower) = (ct_in(c, b'A', b'Z'), ct_in(c, b'a', b'z'));
+    let (digit, plus, slash) = (ct_in(c, b'0', b'9'), ct_eq(c, b'+'),
+                                ct_eq(c, b'/'));
+    let rv = (upper & c.wrapping_sub(b'A')) |
+             (lower & c.wrapping_sub(b'a' - 26)) |
+             (digit & c.wrapping_add(52 - b'0')) | (plus & 62) | (slash & 63);
+    rv | !(upper | lower | digit | plus | slash)
 }
 
 fn quad(n: &[u8]) -> [u8; 4] {
@@ -36,16 +50,23 @@ fn quad(n: &[u8]) -> [u8; 4] {
     [lut(n[0] >> 2), lut(b), lut(c), lut(n[2])]
 }
 
-fn triplet(n: &[u8]) -> Option<[u8; 3]> {
-    assert!(n.len() == 4);
-    let a = maybe!(delut(n[0]));
-    let b = maybe!(delut(n[1]));
-    let c = maybe!(delut(n[2]));
-    let d = maybe!(delut(n[3]));
-    Some([a << 2 | b >> 4, b << 4 | c >> 2, c << 6 | d])
+// Decodes up to four characters into `out`, returning the bitwise OR of their
+// sextets, which has its high bits set iff one of them was invalid.
+fn sextets(n: &[u8], out: &mut Vec<u8>) -> u8 {
+    let mut v = [0; 4];
+    let mut bad = 0;
+    for (v, &c) in v.iter_mut().zip(n) {
+        *v = delut(c);
+        bad |= *v;
+    }
+    let bytes = [v[0] << 2 | v[1] >> 4,
+                 v[1] << 4 | v[2] >> 2,
+                 v[2] << 6 | v[3]];
+    out.extend_from_slice(&bytes[..n.len() - 1]);
+    bad
 }
 
-fn base64_no_pad(bytes: &[u8]) -> Vec<u8> {
+pub(crate) fn base64_no_pad(bytes: &[u8]) -> Vec<u8> {
     let mut rv = vec![];
     let mut pos = 0;
     while pos + 3 <= bytes.len() {
@@ -63,43 +84,139 @@ fn base64_no_pad(bytes: &[u8]) -> Vec<u8> {
     rv
 }
 
-fn debase64_no_pad(bytes: &[u8]) -> Option<Vec<u8>> {
-    if bytes.len() % 4 != 1 && bytes.len() > 0 {
-        let mut rv = vec![];
-        let mut pos = 0;
-        while pos + 4 <= bytes.len() {
-            let s = maybe!(triplet(&bytes[pos..pos + 4]));
-            rv.extend_from_slice(&s);
-            pos += 4;
-        }
-
-        if bytes.len() - pos == 2 {
-            let a = maybe!(delut(bytes[pos]));
-            let b = maybe!(delut(bytes[pos + 1]));
-            rv.push(a << 2 | b >> 4);
-        } else if bytes.len() - pos == 3 {
-            let a = maybe!(delut(bytes[pos]));
-            let b = maybe!(delut(bytes[pos + 1]));
-            let c = maybe!(delut(bytes[pos + 2]));
-            rv.push(a << 2 | b >> 4);
-            rv.push(b << 4 | c >> 2);
-        }
-        Some(rv)
+// Whether the unused low bits of the last character of a valid, unpadded
+// base64 string are zero. Non-canonical strings decode to the same bytes as
+// their canonical counterparts.
+fn is_canonical(bytes: &[u8]) -> bool {
+    let last = bytes.last().map_or(0, |&c| delut(c));
+    match bytes.len() % 4 {
+        2 => last & 0x0f == 0,
+        3 => last & 0x03 == 0,
+        _ => true,
+    }
+}
+
+pub(crate) fn debase64_no_pad(bytes: &[u8]) -> Option<Vec<u8>> {
+    if bytes.len() % 4 != 1 && !bytes.is_empty() {
+        let mut rv = Vec::with_capacity(bytes.len() * 3 / 4);
+        let mut bad = 0;
+        for chunk in bytes.chunks(4) {
+            bad |= sextets(chunk, &mut rv);
+        }
+        if bad & 0xc0 == 0 { Some(rv) } else { None }
     } else {
         None
     }
 }
 
+const BINARY_FORMAT_V1: u8 = 1;
+// V1 plus a final field holding `Encoded::extra_params` as they appear in PHC
+// strings, e.g. `x=1,y=2`. Only written if there are any.
+const BINARY_FORMAT_V2: u8 = 2;
+
+// The size of `Encoded::to_bytes` before its variable-length fields: format,
+// variant, version, `m`, `t`, `p`, and the length prefixes of the salt, hash,
+// key id, associated data, and legacy scheme.
+const BINARY_FIXED_LEN: usize = 3 + 3 * 4 + 5 * 4;
+
+fn put_u32(out: &mut Vec<u8>, n: u32) {
+    out.extend_from_slice(&n.to_le_bytes());
+}
+
+// Reader for the binary form of `Encoded`. Errors carry byte offsets.
+struct BinReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BinReader<'a> {
+    fn byte(&mut self) -> Parsed<u8> {
+        match self.buf.get(self.pos) {
+            Some(&b) => {
+                self.pos += 1;
+                Ok(b)
+            }
+            None => Err(self.pos),
+        }
+    }
+
+    fn u32(&mut self) -> Parsed<u32> {
+        match self.buf.get(self.pos..self.pos + 4) {
+            Some(b) => {
+                self.pos += 4;
+                Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            }
+            None => Err(self.pos),
+        }
+    }
+
+    fn field(&mut self) -> Parsed<Vec<u8>> {
+        let start = self.pos;
+        let len = self.u32()?;
+        if len as usize > self.buf.len() - self.pos {
+            return Err(start);
+        }
+        let rv = self.buf[self.pos..self.pos + len as usize].to_vec();
+        self.pos += len as usize;
+        Ok(rv)
+    }
+}
+
+// Salt used by `Encoded::verify_dummy`. Its contents don't matter.
+const DUMMY_SALT: &[u8] = b"argon2rs dummy salt";
+
+// The parameters of PHC strings that this crate interprets. Any others are
+// kept as they are; see `Encoded::extra_params`.
+const KNOWN_PARAMS: &[&str] = &["v", "m", "t", "p", "keyid", "wrap", "data"];
+
+// Whether `name=value` can be kept as one of `Encoded::extra_params`.
+// For `json`, which gets them outside of any PHC string.
+#[cfg_attr(not(feature = "serde"), allow(dead_code))]
+pub(crate) fn is_extra_param(name: &str, value: &str) -> bool {
+    let pair = format!("{}={}", name, value);
+    let mut p = Parser {
+        enc: pair.as_bytes(),
+        pos: 0,
+        strict: false,
+        noncanonical: false,
+    };
+    p.read_extra().is_ok_and(|extra| extra.len() == 1)
+}
+
+// Legacy scheme names, like PHC parameter names, are restricted to
+// `[a-z0-9-]`.
+fn is_scheme_char(c: u8) -> bool {
+    c.is_ascii_lowercase() || c.is_ascii_digit() || c == b'-'
+}
+
+/// A legacy, typically weak, password hash whose outputs are to be wrapped
+/// inside Argon2 (cf. `Encoded::wrap_legacy`). Wrapping lets an existing table
+/// of, say, unsalted SHA-1 digests be upgraded in one pass without knowing any
+/// of the passwords: each digest is hashed as if it were the password, and at
+/// login the legacy hash is applied to the password before Argon2 is.
+pub trait LegacyHash {
+    /// A short name for this scheme, such as `"sha1"`, recorded in wrapped
+    /// encodings. Must be non-empty and consist only of `[a-z0-9-]`.
+    fn scheme(&self) -> &str;
+
+    /// Computes the legacy hash of `password`, in exactly the form that was
+    /// passed to `Encoded::wrap_legacy`.
+    fn digest(&self, password: &[u8]) -> Vec<u8>;
+}
+
 struct Parser<'a> {
     enc: &'a [u8],
     pos: usize,
+    // Whether to reject base64 fields with nonzero trailing bits, and whether
+    // that is what made parsing fail.
+    strict: bool,
+    noncanonical: bool,
 }
 
 type Parsed<T> = Result<T, usize>;
 
 impl<'a> Parser<'a> {
     fn expect(&mut self, exp: &[u8]) -> Parsed<()> {
-        assert!(self.pos < self.enc.len());
         if self.enc.len() - self.pos < exp.len() ||
            &self.enc[self.pos..self.pos + exp.len()] != exp {
             self.err()
@@ -110,7 +227,7 @@ impl<'a> Parser<'a> {
     }
 
     fn one_of(&mut self, chars: &[u8]) -> Parsed<u8> {
-        if self.enc.len() > 0 {
+        if self.pos < self.enc.len() {
             for &c in chars {
                 if c == self.enc[self.pos] {
                     self.pos += 1;
@@ -141,6 +258,56 @@ impl<'a> Parser<'a> {
         }
     }
 
+    fn read_scheme(&mut self) -> Parsed<String> {
+        let mut end = self.pos;
+        while end < self.enc.len() && is_scheme_char(self.enc[end]) {
+            end += 1;
+        }
+        match str::from_utf8(&self.enc[self.pos..end]) {
+            Ok(rv) if end > self.pos => {
+                self.pos = end;
+                Ok(rv.to_string())
+            }
+            _ => self.err(),
+        }
+    }
+
+    // The value of a parameter this crate doesn't know, which PHC strings
+    // restrict to `[a-zA-Z0-9/+.-]`.
+    fn read_value(&mut self) -> Parsed<String> {
+        let ok = |c: u8| c.is_ascii_alphanumeric() || b"/+.-".contains(&c);
+        let mut end = self.pos;
+        while end < self.enc.len() && ok(self.enc[end]) {
+            end += 1;
+        }
+        match str::from_utf8(&self.enc[self.pos..end]) {
+            Ok(rv) if end > self.pos => {
+                self.pos = end;
+                Ok(rv.to_string())
+            }
+            _ => self.err(),
+        }
+    }
+
+    // Comma-separated `name=value` pairs of parameters this crate doesn't
+    // know, up to the end of input. For the binary form.
+    fn read_extra(&mut self) -> Parsed<Vec<(String, String)>> {
+        let mut rv = vec![];
+        loop {
+            let start = self.pos;
+            let name = self.read_scheme()?;
+            if KNOWN_PARAMS.contains(&&name[..]) {
+                return Err(start);
+            }
+            self.expect(b"=")?;
+            rv.push((name, self.read_value()?));
+            if self.pos == self.enc.len() {
+                return Ok(rv);
+            }
+            self.expect(b",")?;
+        }
+    }
+
     fn read_version(&mut self) -> Parsed<Version> {
         self.read_u32()
             .and_then(|vers| match vers {
@@ -150,18 +317,26 @@ impl<'a> Parser<'a> {
                       })
     }
 
-    fn decode64_till(&mut self, stopchar: Option<&[u8]>) -> Parsed<Vec<u8>> {
-        let end = match stopchar {
+    // Decodes base64 up to (but excluding) the first of `stopchars`, or the end
+    // of input.
+    fn decode64_till(&mut self, stopchars: Option<&[u8]>) -> Parsed<Vec<u8>> {
+        let end = match stopchars {
             None => self.enc.len(),
-            Some(c) => {
+            Some(cs) => {
                 self.enc[self.pos..]
                     .iter()
-                    .take_while(|k| **k != c[0])
+                    .take_while(|k| !cs.contains(k))
                     .fold(0, |c, _| c + 1) + self.pos
             }
         };
         match debase64_no_pad(&self.enc[self.pos..end]) {
             None => self.err(),
+            Some(_) if self.strict &&
+                       !is_canonical(&self.enc[self.pos..end]) => {
+                self.noncanonical = true;
+                self.pos = end - 1;
+                self.err()
+            }
             Some(rv) => {
                 self.pos = end;
                 Ok(rv)
@@ -178,6 +353,9 @@ pub enum DecodeError {
     ParseError(usize),
     /// Invalid Argon2 parameters given in encoding
     InvalidParams(ParamErr),
+    /// Byte position of a base64 character with nonzero unused bits, which
+    /// only strict parsing rejects
+    NonCanonical(usize),
 }
 
 impl fmt::Display for DecodeError {
@@ -188,6 +366,9 @@ impl fmt::Display for DecodeError {
             InvalidParams(ref perr) => {
                 write!(f, "Invalid hash parameters given by encoded: {}", perr)
             }
+            NonCanonical(pos) => {
+                write!(f, "Non-canonical base64 at position {}", pos)
+            }
         }
     }
 }
@@ -197,18 +378,53 @@ impl Error for DecodeError {
         match *self {
             DecodeError::ParseError(_) => "Hash string parse error.",
             DecodeError::InvalidParams(ref perr) => perr.description(),
+            DecodeError::NonCanonical(_) => "Non-canonical base64 encoding.",
+        }
+    }
+}
+
+/// A scheme tag that a web framework puts in front of the PHC strings it
+/// stores, as recognized by `Encoded::from_u8_prefixed`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Prefix {
+    /// Django's `argon2`, as in `argon2$argon2id$v=19$m=102400,t=2,p=8$...`.
+    Django,
+}
+
+impl Prefix {
+    /// The tag itself, which precedes the PHC string with nothing between.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Prefix::Django => "argon2",
         }
     }
+
+    /// Puts this tag in front of `enc`, giving the form the framework stores.
+    /// The PHC string is laid out as the reference implementation does, with
+    /// `$` rather than `,` after the version, which is what the framework's
+    /// own parser expects.
+    pub fn apply(&self, enc: &Encoded) -> String {
+        let phc = String::from_utf8(enc.encode("$")).unwrap();
+        format!("{}{}", self.as_str(), phc)
+    }
 }
 
 /// Represents a single Argon2 hashing session. A hash session comprises of the
 /// hash algorithm parameters, salt, key, and data used to hash a given input.
+///
+/// This is what gets stored in a password database: create one with
+/// `Encoded::hash` (or `Encoded::new` for keyed hashes), store its
+/// `to_string()`, and later read it back with `Encoded::parse` and check login
+/// attempts with `Encoded::verify`. The accessors expose the individual fields
+/// for inspection, e.g. to decide whether a stored hash needs upgrading.
 pub struct Encoded {
-    params: Argon2,
-    hash: Vec<u8>,
-    salt: Vec<u8>,
-    key: Vec<u8>,
-    data: Vec<u8>,
+    pub(crate) params: Argon2,
+    pub(crate) hash: Vec<u8>,
+    pub(crate) salt: Vec<u8>,
+    pub(crate) key: Vec<u8>,
+    pub(crate) data: Vec<u8>,
+    pub(crate) wrap: Option<String>,
+    pub(crate) extra: Vec<(String, String)>,
 }
 
 macro_rules! try_unit {
@@ -228,20 +444,22 @@ type Packed = (Variant,
                Vec<u8>,
                Vec<u8>,
                Vec<u8>,
-               Vec<u8>);
+               Vec<u8>,
+               Option<String>,
+               Vec<(String, String)>);
 
 impl Encoded {
-    fn parse(encoded: &[u8]) -> Result<Packed, usize> {
-        let mut p = Parser {
-            enc: encoded,
-            pos: 0,
-        };
-
+    fn parse_u8(p: &mut Parser) -> Result<Packed, usize> {
         try_unit!(p.expect(b"$argon2"));
 
         let variant = match try!(p.one_of(b"di")) {
             v if v == 'd' as u8 => Variant::Argon2d,
-            v if v == 'i' as u8 => Variant::Argon2i,
+            v if v == 'i' as u8 => {
+                match p.expect(b"d") {
+                    Ok(()) => Variant::Argon2id,
+                    Err(_) => Variant::Argon2i,
+                }
+            }
             _ => unreachable!(),
         };
 
@@ -253,7 +471,8 @@ impl Encoded {
             Err(_) => Version::_0x10,
             Ok(()) => {
                 let vers = try!(p.read_version());
-                try_unit!(p.expect(b","));
+                // ours put a comma here, and the reference impl a `$`.
+                p.one_of(b",$")?;
                 vers
             }
         };
@@ -264,28 +483,45 @@ impl Encoded {
         try_unit!(p.expect(b",p="));
         let lanes = try!(p.read_u32());
 
-        let key = match p.expect(b",keyid=") {
-            Err(_) => vec![],
-            Ok(()) => try!(p.decode64_till(Some(b","))),
-        };
-
-        let data = match p.expect(b",data=") {
-            Ok(()) => try!(p.decode64_till(Some(b"$"))),
-            Err(_) => vec![],
-        };
+        // the optional parameters, in any order, each at most once.
+        let (mut key, mut wrap, mut data) = (None, None, None);
+        let mut extra = vec![];
+        while p.expect(b",").is_ok() {
+            let start = p.pos;
+            let name = p.read_scheme()?;
+            try_unit!(p.expect(b"="));
+            match &name[..] {
+                "keyid" if key.is_none() => {
+                    key = Some(p.decode64_till(Some(b",$"))?)
+                }
+                "wrap" if wrap.is_none() => wrap = Some(p.read_scheme()?),
+                "data" if data.is_none() => {
+                    data = Some(p.decode64_till(Some(b",$"))?)
+                }
+                n if KNOWN_PARAMS.contains(&n) => return Err(start),
+                _ => extra.push((name, p.read_value()?)),
+            }
+        }
+        let (key, data) = (key.unwrap_or_default(), data.unwrap_or_default());
 
         try_unit!(p.expect(b"$"));
+        let start = p.pos;
         let salt = try!(p.decode64_till(Some(b"$")));
+        // `Argon2::hash` would panic on it.
+        if salt.len() < MIN_SALT_LEN {
+            return Err(start);
+        }
         try_unit!(p.expect(b"$"));
         let hash = try!(p.decode64_till(None));
-        Ok((variant, vers, kib, passes, lanes, key, data, salt, hash))
+        Ok((variant, vers, kib, passes, lanes, key, data, salt, hash, wrap,
+            extra))
     }
 
-    /// Reconstruct a previous hash session from serialized bytes.
-    pub fn from_u8(encoded: &[u8]) -> Result<Self, DecodeError> {
-        match Self::parse(encoded) {
+    fn unpack(parsed: Result<Packed, usize>) -> Result<Self, DecodeError> {
+        match parsed {
             Err(pos) => Err(DecodeError::ParseError(pos)),
-            Ok((v, vers, kib, passes, lanes, key, data, salt, hash)) => {
+            Ok((v, vers, kib, passes, lanes, key, data, salt, hash, wrap,
+                extra)) => {
                 match Argon2::with_version(passes, lanes, kib, v, vers) {
                     Err(e) => Err(DecodeError::InvalidParams(e)),
                     Ok(a2) => {
@@ -295,6 +531,8 @@ impl Encoded {
                             salt: salt,
                             key: key,
                             data: data,
+                            wrap,
+                            extra,
                         })
                     }
                 }
@@ -302,13 +540,210 @@ impl Encoded {
         }
     }
 
+    /// Reconstruct a previous hash session from serialized bytes. Both the
+    /// `v=19,m=...` layout of `Encoded::to_u8` and the reference
+    /// implementation's `v=19$m=...` are accepted.
+    ///
+    /// Parsing never panics, whatever the input: truncated or garbled strings
+    /// fail with a `DecodeError`. The same holds for `Encoded::from_u8_strict`
+    /// and `Encoded::from_bytes`, and is fuzzed by the targets in `fuzz/`.
+    pub fn from_u8(encoded: &[u8]) -> Result<Self, DecodeError> {
+        let mut p = Parser {
+            enc: encoded,
+            pos: 0,
+            strict: false,
+            noncanonical: false,
+        };
+        Self::unpack(Self::parse_u8(&mut p))
+    }
+
+    /// Reads and parses an encoded hash from `r`, consuming at most `max_len`
+    /// bytes of it. Input longer than that fails with `InvalidData` rather
+    /// than being buffered, as do parse errors, which carry the
+    /// `DecodeError`. A single trailing newline is ignored, so that files
+    /// holding one hash can be read as-is.
+    pub fn from_reader<R: Read>(r: R, max_len: usize) -> io::Result<Self> {
+        let mut buf = vec![];
+        r.take(max_len as u64 + 1).read_to_end(&mut buf)?;
+        if buf.len() > max_len {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "encoded hash exceeds max_len"));
+        }
+        if buf.last() == Some(&b'\n') {
+            buf.pop();
+            if buf.last() == Some(&b'\r') {
+                buf.pop();
+            }
+        }
+        Self::from_u8(&buf)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Like `Encoded::from_u8`, but additionally rejects base64 fields whose
+    /// final character has nonzero unused bits, with
+    /// `DecodeError::NonCanonical`. Such fields decode to the same bytes as
+    /// their canonical forms, so lenient parsing maps several distinct strings
+    /// to one hash; strict parsing guarantees that `to_u8` round-trips the
+    /// base64 fields exactly.
+    pub fn from_u8_strict(encoded: &[u8]) -> Result<Self, DecodeError> {
+        let mut p = Parser {
+            enc: encoded,
+            pos: 0,
+            strict: true,
+            noncanonical: false,
+        };
+        match Self::parse_u8(&mut p) {
+            Err(pos) if p.noncanonical => Err(DecodeError::NonCanonical(pos)),
+            parsed => Self::unpack(parsed),
+        }
+    }
+
+    /// Like `Encoded::from_u8`, but also accepts hashes that carry a
+    /// framework's scheme tag, such as Django's `argon2$argon2i$v=19$...`,
+    /// returning the tag found alongside the hash. This eases moving an
+    /// existing user table to a service built on this crate: hashes verify as
+    /// they are, and `Prefix::apply` writes rehashed ones back in the
+    /// framework's form. passlib stores plain PHC strings, which need no tag
+    /// and parse with `None`. Error positions refer to `encoded` as given.
+    pub fn from_u8_prefixed(encoded: &[u8])
+                            -> Result<(Self, Option<Prefix>), DecodeError> {
+        let tag = Prefix::Django.as_str().as_bytes();
+        if !encoded.starts_with(tag) || encoded.get(tag.len()) != Some(&b'$') {
+            return Self::from_u8(encoded).map(|enc| (enc, None));
+        }
+        match Self::from_u8(&encoded[tag.len()..]) {
+            Ok(enc) => Ok((enc, Some(Prefix::Django))),
+            Err(DecodeError::ParseError(pos)) => {
+                Err(DecodeError::ParseError(pos + tag.len()))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Parses a stored hash string. Same as `Encoded::from_u8`, and as
+    /// `str::parse`.
+    pub fn parse(encoded: &str) -> Result<Self, DecodeError> {
+        Self::from_u8(encoded.as_bytes())
+    }
+
+    /// Serializes this hashing session into a compact binary form, for storage
+    /// where the textual encoding of `Encoded::to_u8` is wasteful. The layout
+    /// is a format version byte (1, or 2 if there are `extra_params`), the
+    /// variant (0 for Argon2d, 1 for Argon2i, 2 for Argon2id), the Argon2
+    /// version byte, then `m`, `t`, and `p`, and finally the salt, hash, key
+    /// id, associated data, wrapped legacy scheme name, and in format 2 the
+    /// extra parameters as in PHC strings (`x=1,y=2`), each prefixed by its
+    /// length. Numbers and lengths are little-endian `u32`s, so the size
+    /// depends only on the lengths of the fields: a hash without key id,
+    /// associated data, legacy scheme, or extra parameters takes 35 bytes plus
+    /// its salt and tag, e.g. 83 bytes for a 16-byte salt and a 32-byte tag.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let (var, m, t, p, vers) = self.params();
+        let format = match self.extra.len() {
+            0 => BINARY_FORMAT_V1,
+            _ => BINARY_FORMAT_V2,
+        };
+        let mut rv = Vec::with_capacity(BINARY_FIXED_LEN + self.salt.len() +
+                                        self.hash.len());
+        rv.extend_from_slice(&[format, var as u8, vers as u8]);
+        for &n in [m, t, p].iter() {
+            put_u32(&mut rv, n);
+        }
+        let wrap = self.wrap.as_ref().map(|w| w.as_bytes()).unwrap_or(&[]);
+        for &field in [&self.salt[..], &self.hash[..], &self.key[..],
+                       &self.data[..], wrap]
+                          .iter() {
+            put_u32(&mut rv, field.len() as u32);
+            rv.extend_from_slice(field);
+        }
+        if format == BINARY_FORMAT_V2 {
+            let extra = self.extra_u8();
+            put_u32(&mut rv, extra.len() as u32 - 1);
+            rv.extend_from_slice(&extra[1..]);
+        }
+        rv
+    }
+
+    /// Reconstructs a hashing session serialized by `Encoded::to_bytes`.
+    /// Parse errors report the offending byte offset.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Self::unpack(Self::parse_bytes(bytes))
+    }
+
+    fn parse_bytes(bytes: &[u8]) -> Result<Packed, usize> {
+        let mut r = BinReader { buf: bytes, pos: 0 };
+        let format = match r.byte()? {
+            f @ BINARY_FORMAT_V1 | f @ BINARY_FORMAT_V2 => f,
+            _ => return Err(0),
+        };
+        let variant = match r.byte()? {
+            0 => Variant::Argon2d,
+            1 => Variant::Argon2i,
+            2 => Variant::Argon2id,
+            _ => return Err(r.pos - 1),
+        };
+        let vers = match r.byte()? {
+            0x10 => Version::_0x10,
+            0x13 => Version::_0x13,
+            _ => return Err(r.pos - 1),
+        };
+        let kib = r.u32()?;
+        let passes = r.u32()?;
+        let lanes = r.u32()?;
+        let start = r.pos;
+        let salt = r.field()?;
+        if salt.len() < MIN_SALT_LEN {
+            return Err(start);
+        }
+        let hash = r.field()?;
+        let key = r.field()?;
+        let data = r.field()?;
+        let start = r.pos;
+        let wrap = match r.field()? {
+            ref w if w.is_empty() => None,
+            w => {
+                if !w.iter().all(|&c| is_scheme_char(c)) {
+                    return Err(start);
+                }
+                Some(w.iter().map(|&c| c as char).collect())
+            }
+        };
+        let extra = match format {
+            BINARY_FORMAT_V1 => vec![],
+            _ => {
+                let start = r.pos;
+                let field = r.field()?;
+                let offset = r.pos - field.len();
+                let mut p = Parser {
+                    enc: &field,
+                    pos: 0,
+                    strict: false,
+                    noncanonical: false,
+                };
+                if field.is_empty() {
+                    return Err(start);
+                }
+                p.read_extra().map_err(|pos| offset + pos)?
+            }
+        };
+        if r.pos != bytes.len() {
+            return Err(r.pos);
+        }
+        Ok((variant, vers, kib, passes, lanes, key, data, salt, hash, wrap,
+            extra))
+    }
+
     /// Serialize this hashing session into raw bytes that can later be
     /// recovered by `Encoded::from_u8`.
+    pub fn to_u8(&self) -> Vec<u8> { self.encode(",") }
+
+    // The PHC string with `vsep` after the version field.
     #[cfg_attr(rustfmt, rustfmt_skip)]
-    pub fn to_u8(&self) -> Vec<u8> {
+    fn encode(&self, vsep: &str) -> Vec<u8> {
         let vcode = |v| match v {
             Variant::Argon2i => "i",
             Variant::Argon2d => "d",
+            Variant::Argon2id => "id",
         };
         let b64 = |x| String::from_utf8(base64_no_pad(x)).unwrap()
 ;
@@ -316,14 +751,19 @@ impl Encoded {
             bytes if bytes.len() > 0 => format!(",keyid={}", bytes),
             _ => String::new(),
         };
+        let w_ = match self.wrap {
+            Some(ref scheme) => format!(",wrap={}", scheme),
+            None => String::new(),
+        };
         let x_ = match &b64(&self.data[..]) {
             bytes if bytes.len() > 0 => format!(",data={}", bytes),
             _ => String::new(),
         };
+        let e_ = String::from_utf8(self.extra_u8()).unwrap();
         let (var, m, t, p, vers) = self.params();
-        format!("$argon2{}$v={},m={},t={},p={}{}{}${}${}", vcode(var),
-                vers as usize, m, t, p, k_, x_, b64(&self.salt[..]),
-                b64(&self.hash))
+        format!("$argon2{}$v={}{}m={},t={},p={}{}{}{}{}${}${}", vcode(var),
+                vers as usize, vsep, m, t, p, k_, w_, x_, e_,
+                b64(&self.salt[..]), b64(&self.hash))
             .into_bytes()
     }
 
@@ -344,7 +784,17 @@ impl Encoded {
     /// Note that `p, s, k, x` must conform to the same length constraints
     /// dictated by `Argon2::hash`.
     pub fn new(argon: Argon2, p: &[u8], s: &[u8], k: &[u8], x: &[u8]) -> Self {
-        let mut out = vec![0 as u8; defaults::LENGTH];
+        Self::with_tag_len(argon, defaults::LENGTH, p, s, k, x)
+    }
+
+    /// Same as `Encoded::new`, but producing a hash output of `taglen` bytes
+    /// rather than `defaults::LENGTH`, subject to the same bounds as
+    /// `out.len()` in `Argon2::hash`. The length is implied by the encoded
+    /// hash, and verification recomputes a hash output of the same length.
+    pub fn with_tag_len(argon: Argon2, taglen: usize, p: &[u8], s: &[u8],
+                        k: &[u8], x: &[u8])
+                        -> Self {
+        let mut out = vec![0 as u8; taglen];
         argon.hash(&mut out[..], p, s, k, x);
         Encoded {
             params: argon,
@@ -352,9 +802,97 @@ impl Encoded {
             salt: s.iter().cloned().collect(),
             key: k.iter().cloned().collect(),
             data: x.iter().cloned().collect(),
+            wrap: None,
+            extra: vec![],
+        }
+    }
+
+    /// Wraps `digest`, the output of the legacy password hash named by
+    /// `scheme`, inside Argon2. Other parameters are the same as for
+    /// `Encoded::new`, with `digest` taking the place of the password. The
+    /// result records `scheme` in its encoding and can only be verified with
+    /// `Encoded::verify_wrapped`. See `LegacyHash` for details.
+    pub fn wrap_legacy(argon: Argon2, scheme: &str, digest: &[u8], s: &[u8],
+                       k: &[u8], x: &[u8])
+                       -> Self {
+        assert!(!scheme.is_empty() && scheme.bytes().all(is_scheme_char));
+        let mut rv = Self::new(argon, digest, s, k, x);
+        rv.wrap = Some(scheme.to_string());
+        rv
+    }
+
+    // Like `new`, but hashes with secret `k` while recording only `keyid` in
+    // the encoding. Cf. `KeyRing`.
+    pub(crate) fn with_keyid(argon: Argon2, p: &[u8], s: &[u8], k: &[u8],
+                             keyid: &[u8], x: &[u8])
+                             -> Self {
+        let mut rv = Self::new(argon, p, s, k, x);
+        rv.key = keyid.to_vec();
+        rv
+    }
+
+    /// Hashes password `p` with salt `s` and no secret key or associated data.
+    /// Shorthand for `Encoded::new(argon, p, s, &[], &[])`.
+    pub fn hash(argon: Argon2, p: &[u8], s: &[u8]) -> Self {
+        Self::new(argon, p, s, &[], &[])
+    }
+
+    /// Hashes password `p` under `params` with a fresh random salt of
+    /// `salt::DEFAULT_LEN` bytes, which is the usual way of creating a hash
+    /// for storage. Fails only if the system's random number generator (cf.
+    /// `rng::OsRng`) does.
+    pub fn generate(params: Params, p: &[u8]) -> io::Result<Self> {
+        Self::generate_with(params, p, &mut OsRng)
+    }
+
+    /// Same as `Encoded::generate`, but drawing the salt from `rng`.
+    pub fn generate_with(params: Params, p: &[u8], rng: &mut dyn RngProvider)
+                         -> io::Result<Self> {
+        let salt = Salt::generate_with(salt::DEFAULT_LEN, rng)?;
+        Ok(Self::hash(Argon2::with_params(params), p, &salt))
+    }
+
+    /// The `keyid` field of this encoding, empty if there is none. For hashes
+    /// made with `Encoded::new`, this is the secret key itself.
+    pub fn keyid(&self) -> &[u8] { &self.key[..] }
+
+    /// The salt of this hash.
+    pub fn salt(&self) -> &[u8] { &self.salt[..] }
+
+    /// The associated data of this hash, empty if there is none.
+    pub fn data(&self) -> &[u8] { &self.data[..] }
+
+    /// The raw hash output against which passwords are verified.
+    pub fn tag(&self) -> &[u8] { &self.hash[..] }
+
+    /// The parameters of the parsed string that this crate doesn't know, as
+    /// (name, value) pairs in their original order. Newer implementations may
+    /// record parameters that this one can't interpret; they are kept and
+    /// written out again by `Encoded::to_u8` (after the known ones), so that
+    /// passing a hash through this crate doesn't lose them. Verification
+    /// ignores them.
+    pub fn extra_params(&self) -> &[(String, String)] { &self.extra[..] }
+
+    // `extra_params` as in PHC strings, each preceded by a comma.
+    fn extra_u8(&self) -> Vec<u8> {
+        let mut rv = vec![];
+        for (name, value) in self.extra.iter() {
+            rv.push(b',');
+            rv.extend_from_slice(name.as_bytes());
+            rv.push(b'=');
+            rv.extend_from_slice(value.as_bytes());
         }
+        rv
     }
 
+    /// The hash algorithm parameters of this hash, as an `Argon2` ready to be
+    /// used for further hashing.
+    pub fn argon2(&self) -> &Argon2 { &self.params }
+
+    /// The Argon2 version this hash was made with. Hashes whose encoding
+    /// lacks a `v=` field are version 0x10.
+    pub fn version(&self) -> Version { self.params().4 }
+
     /// Same as `Encoded::new`, but with the default Argon2i hash algorithm
     /// parameters.
     pub fn default2i(p: &[u8], s: &[u8], k: &[u8], x: &[u8]) -> Self {
@@ -369,11 +907,168 @@ impl Encoded {
 
     /// Verifies password input against the hash that was previously created in
     /// this hashing session.
+    ///
+    /// Always returns `false` for hashes made by `Encoded::wrap_legacy`, which
+    /// must be checked with `Encoded::verify_wrapped` instead.
     pub fn verify(&self, p: &[u8]) -> bool {
-        let mut out = [0 as u8; defaults::LENGTH];
+        self.verify_with_secret(p, &self.key[..])
+    }
+
+    /// Verifies password input against a hash that wraps the legacy hash
+    /// `inner`, by computing `inner`'s digest of `p` and verifying that in
+    /// place of the password. Returns `false` if this hash wraps a different
+    /// scheme. Hashes that aren't wrapped at all are verified as usual, so that
+    /// rehashed accounts keep working.
+    pub fn verify_wrapped(&self, p: &[u8], inner: &dyn LegacyHash) -> bool {
+        match self.wrap {
+            None => self.verify(p),
+            Some(ref scheme) if scheme == inner.scheme() => {
+                let mut digest = inner.digest(p);
+                let rv = self.verify_raw(&digest, &self.key[..]);
+                wipe(&mut digest);
+                rv
+            }
+            Some(_) => self.rejected(),
+        }
+    }
+
+    /// The name of the legacy scheme wrapped by this hash, if any.
+    pub fn legacy_scheme(&self) -> Option<&str> {
+        self.wrap.as_ref().map(|s| &s[..])
+    }
+
+    // Like `verify`, but with a secret value that isn't the one recorded in
+    // the encoding. Cf. `KeyRing`.
+    pub(crate) fn verify_with_secret(&self, p: &[u8], k: &[u8]) -> bool {
+        match self.wrap {
+            None => self.verify_raw(p, k),
+            Some(_) => self.rejected(),
+        }
+    }
+
+    // Like `verify`, but hashing in `scratch`. Cf. `batch::verify_many`.
+    pub(crate) fn verify_in(&self, p: &[u8], scratch: &mut Scratch) -> bool {
+        match self.wrap {
+            None => self.verify_raw_in(p, &self.key[..], scratch),
+            Some(_) => self.rejected(),
+        }
+    }
+
+    fn verify_raw(&self, p: &[u8], k: &[u8]) -> bool {
+        self.verify_raw_in(p, k, &mut Scratch::new())
+    }
+
+    fn verify_raw_in(&self, p: &[u8], k: &[u8], scratch: &mut Scratch)
+                     -> bool {
+        // decoded hashes may have fields too short (or long) to have been
+        // produced by Argon2, which `hash_in` would panic on.
+        if !self.hashable(k) {
+            return self.rejected();
+        }
+        let timer = Timer::start();
+        let mut out = vec![0 as u8; self.hash.len()];
         let s = &self.salt[..];
-        self.params.hash(&mut out, p, s, &self.key[..], &self.data[..]);
-        constant_eq(&out, &self.hash)
+        self.params.hash_in(&mut out, p, s, k, &self.data[..], scratch);
+        let rv = constant_eq(&out, &self.hash);
+        #[cfg(feature = "metrics")]
+        ::telemetry::verified(rv);
+        self.audited(timer, true, rv)
+    }
+
+    // Whether this hash's fields, with secret `k`, are within the bounds that
+    // `Argon2::hash` asserts.
+    fn hashable(&self, k: &[u8]) -> bool {
+        self.hash.len() >= MIN_TAG_LEN && self.salt.len() >= MIN_SALT_LEN &&
+        k.len() <= MAX_SECRET_LEN
+    }
+
+    // Reports a verification of this hash to the `audit` hook, returning
+    // `success`.
+    fn audited(&self, timer: Timer, hashed: bool, success: bool) -> bool {
+        audit::report(&VerifyEvent {
+            params: self.params.to_params(),
+            keyid: &self.key,
+            legacy_scheme: self.legacy_scheme(),
+            success,
+            hashed,
+            duration: timer.elapsed(),
+        });
+        success
+    }
+
+    // Reports a verification of this hash that failed without hashing, and
+    // returns `false`.
+    pub(crate) fn rejected(&self) -> bool {
+        self.audited(Timer::start(), false, false)
+    }
+
+    /// Performs the same work as `verify` would on a hash created with `argon`,
+    /// but against a fixed, fake hash. Always returns `false`.
+    ///
+    /// Use this when a login names an account that doesn't exist: answering
+    /// immediately would reveal to a timing observer which accounts do exist.
+    /// For the same reason, it is reported to the `audit` hook as a failed
+    /// verification of a hash without a key id.
+    pub fn verify_dummy(argon: &Argon2, p: &[u8]) -> bool {
+        let timer = Timer::start();
+        let mut out = [0; defaults::LENGTH];
+        argon.hash(&mut out, p, DUMMY_SALT, &[], &[]);
+        // always false, but still paid for so that timing matches `verify`.
+        let _ = constant_eq(&out, &[0xff; defaults::LENGTH]);
+        audit::report(&VerifyEvent {
+            params: argon.to_params(),
+            keyid: &[],
+            legacy_scheme: None,
+            success: false,
+            hashed: true,
+            duration: timer.elapsed(),
+        });
+        false
+    }
+
+    /// Verifies `p` against `enc` if an encoded hash is present, and otherwise
+    /// falls back to `Encoded::verify_dummy` with default Argon2i parameters.
+    /// Applications that store hashes with non-default parameters should call
+    /// `verify_dummy` with their own parameters instead.
+    pub fn verify_opt(enc: Option<&Encoded>, p: &[u8]) -> bool {
+        match enc {
+            Some(enc) => enc.verify(p),
+            None => Self::verify_dummy(&Argon2::default(Variant::Argon2i), p),
+        }
+    }
+
+    /// Verifies `p` as `Encoded::verify` does and, if it matches, also writes a
+    /// key derived from the same Argon2 run to `out`, e.g. for decrypting data
+    /// protected by the password. One hash serves both purposes. The key is
+    /// independent of the stored hash, so the hash reveals nothing about it,
+    /// and equals the key that `kdf::derive_tag_and_key` derives from the same
+    /// password, salt, and parameters (if this hash has no key id or
+    /// associated data). `out.len()` must be between 4 and 2^32 - 1.
+    ///
+    /// Returns `false`, leaving `out` untouched, if `p` doesn't match.
+    pub fn verify_with_output(&self, p: &[u8], out: &mut [u8]) -> bool {
+        assert!(MIN_TAG_LEN <= out.len() && out.len() <= MAX_TAG_LEN);
+        if self.wrap.is_some() || !self.hashable(&self.key) {
+            return self.rejected();
+        }
+        let timer = Timer::start();
+        let state = self.params.hash_unfinalized(self.hash.len(), p,
+                                                 &self.salt, &self.key,
+                                                 &self.data);
+        let rv = state.verify(&self.hash);
+        if rv {
+            state.finalize_labeled(kdf::KEY_LABEL, out);
+        }
+        self.audited(timer, true, rv)
+    }
+
+    /// Checks the memory-hard state computed elsewhere (typically by a client,
+    /// cf. `Argon2::hash_unfinalized`) against the hash of this session. Only
+    /// the final H′ is computed here.
+    pub fn verify_unfinalized(&self, state: &Unfinalized) -> bool {
+        let timer = Timer::start();
+        let rv = state.verify(&self.hash);
+        self.audited(timer, true, rv)
     }
 
     /// Provides read-only access to the Argon2 parameters of this hash.
@@ -382,6 +1077,47 @@ impl Encoded {
     }
 }
 
+/// Compares everything but the hash output normally, and the hash output in
+/// constant time, so that `stored == recomputed` doesn't reveal how much of a
+/// tag matched.
+impl PartialEq for Encoded {
+    fn eq(&self, other: &Encoded) -> bool {
+        let tags_eq = constant_eq(&self.hash, &other.hash);
+        let rest_eq = self.params.to_params() == other.params.to_params() &&
+                      self.salt == other.salt &&
+                      self.key == other.key &&
+                      self.data == other.data &&
+                      self.wrap == other.wrap &&
+                      self.extra == other.extra;
+        tags_eq & rest_eq
+    }
+}
+
+impl Eq for Encoded {}
+
+/// Formats as the PHC-style string produced by `Encoded::to_u8`.
+impl fmt::Display for Encoded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(str::from_utf8(&self.to_u8()).unwrap())
+    }
+}
+
+/// Parses the PHC-style strings accepted by `Encoded::from_u8`, so that stored
+/// hashes can be read with `stored.parse::<Encoded>()?`.
+impl str::FromStr for Encoded {
+    type Err = DecodeError;
+
+    fn from_str(s: &str) -> Result<Self, DecodeError> {
+        Self::from_u8(s.as_bytes())
+    }
+}
+
+impl<'a> TryFrom<&'a str> for Encoded {
+    type Error = DecodeError;
+
+    fn try_from(s: &'a str) -> Result<Self, DecodeError> { s.parse() }
+}
+
 /// Compares two byte arrays for equality. Assumes that both are already of
 /// equal length.
 #[inline(never)]
@@ -397,7 +1133,10 @@ pub fn constant_eq(xs: &[u8], ys: &[u8]) -> bool {
 
 #[cfg(test)]
 mod test {
-    use super::{Encoded, base64_no_pad, debase64_no_pad};
+    use super::{Encoded, LegacyHash, base64_no_pad, debase64_no_pad, delut,
+                lut};
+    use argon2::{Argon2, Variant};
+    use kdf;
 
     const BASE64_CASES: [(&'static [u8], &'static [u8]); 5] =
         [(b"any carnal pleasure.", b"YW55IGNhcm5hbCBwbGVhc3VyZS4"),
@@ -422,6 +1161,53 @@ mod test {
         }
     }
 
+    #[test]
+    fn strict_base64() {
+        use super::DecodeError;
+
+        let canonical = ENCODED[2];
+        assert!(Encoded::from_u8_strict(canonical).is_ok());
+        // "cw" -> "cx" and "bA" -> "bB" differ only in unused bits.
+        let enc = ::std::str::from_utf8(canonical).unwrap();
+        let salt = enc.replace("ZXN0cw$", "ZXN0cx$");
+        let tag = enc.replace("LbA", "LbB");
+        for bad in [salt, tag].iter() {
+            let lenient = Encoded::from_u8(bad.as_bytes()).unwrap();
+            assert_eq!(&lenient.to_u8()[..], canonical);
+            let pos = bad.bytes().zip(enc.bytes()).position(|(a, b)| a != b);
+            assert_eq!(Encoded::from_u8_strict(bad.as_bytes()).err(),
+                       Some(DecodeError::NonCanonical(pos.unwrap())));
+        }
+    }
+
+    #[test]
+    fn test_from_reader() {
+        use std::io::ErrorKind;
+
+        let mut line = ENCODED[2].to_vec();
+        line.extend_from_slice(b"\r\n");
+        let v = Encoded::from_reader(&line[..], line.len()).unwrap();
+        assert!(v.verify(b"argon2i!"));
+        let err = Encoded::from_reader(&line[..], line.len() - 1).err();
+        assert_eq!(err.unwrap().kind(), ErrorKind::InvalidData);
+        let err = Encoded::from_reader(&b"$argon2q$"[..], 64).err();
+        assert_eq!(err.unwrap().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_lut() {
+        let alphabet = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
+                         abcdefghijklmnopqrstuvwxyz0123456789+/";
+        for (n, &c) in alphabet.iter().enumerate() {
+            assert_eq!(lut(n as u8), c);
+            assert_eq!(delut(c), n as u8);
+        }
+        for c in (0..=255u8).filter(|c| !alphabet.contains(c)) {
+            assert_eq!(delut(c), 0xff);
+        }
+        assert!(debase64_no_pad(b"YW55IGNhcm5h*GxlYXN1cmU").is_none());
+    }
+
     #[test]
     fn test_debase64_no_pad() {
         for &(exp, s) in BASE64_CASES.iter() {
@@ -438,6 +1224,283 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_str_conversions() {
+        use std::convert::TryFrom;
+        use super::DecodeError;
+
+        for &hash_string in &ENCODED[1..] {
+            let s = ::std::str::from_utf8(hash_string).unwrap();
+            let v: Encoded = s.parse().unwrap();
+            assert_eq!(v.to_string(), s);
+            assert_eq!(Encoded::try_from(s).unwrap().to_string(), s);
+        }
+        assert_eq!("$argon2q$".parse::<Encoded>().err(),
+                   Some(DecodeError::ParseError(7)));
+    }
+
+    #[test]
+    fn test_accessors() {
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2i).unwrap();
+        let enc = Encoded::hash(a2, b"pw", b"saltsalt");
+        let v = Encoded::parse(&enc.to_string()).unwrap();
+        assert!(v.verify(b"pw"));
+        assert_eq!(v.salt(), b"saltsalt");
+        assert_eq!(v.tag(), enc.tag());
+        assert_eq!(v.tag().len(), 32);
+        assert!(v.keyid().is_empty() && v.data().is_empty());
+        assert_eq!(v.argon2().params(), v.params());
+    }
+
+    #[test]
+    fn test_eq() {
+        let a2 = || Argon2::new(1, 1, 8, Variant::Argon2i).unwrap();
+        let enc = Encoded::hash(a2(), b"pw", b"saltsalt");
+        assert!(Encoded::parse(&enc.to_string()).unwrap() == enc);
+        assert!(Encoded::hash(a2(), b"pw2", b"saltsalt") != enc);
+        assert!(Encoded::hash(a2(), b"pw", b"saltsalt2") != enc);
+        assert!(Encoded::new(a2(), b"pw", b"saltsalt", &[], b"x") != enc);
+        let a2d = Argon2::new(1, 1, 8, Variant::Argon2d).unwrap();
+        assert!(Encoded::hash(a2d, b"pw", b"saltsalt") != enc);
+    }
+
+    #[test]
+    fn test_argon2id() {
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2id).unwrap();
+        let enc = Encoded::hash(a2, b"pw", b"saltsalt");
+        let s = enc.to_string();
+        assert!(s.starts_with("$argon2id$v=19,m=8,t=1,p=1$"));
+        let v = Encoded::parse(&s).unwrap();
+        assert_eq!(v.params().0, Variant::Argon2id);
+        assert!(v.verify(b"pw") && !v.verify(b"pW"));
+        assert!(Encoded::from_bytes(&enc.to_bytes()).unwrap() == enc);
+    }
+
+    #[test]
+    fn test_generate() {
+        use params::Params;
+        use salt::DEFAULT_LEN;
+
+        let params = Params::new(1, 1, 8, Variant::Argon2id).unwrap();
+        let a = Encoded::generate(params, b"pw").unwrap();
+        let b = Encoded::generate(params, b"pw").unwrap();
+        assert_eq!(a.salt().len(), DEFAULT_LEN);
+        assert!(a.salt() != b.salt());
+        assert!(a.verify(b"pw") && b.verify(b"pw") && !a.verify(b"pW"));
+        assert_eq!(a.argon2().to_params(), params);
+
+        let mut rng = ::rng::test::Counter(0);
+        let c = Encoded::generate_with(params, b"pw", &mut rng).unwrap();
+        assert_eq!(c.salt(), &(0..DEFAULT_LEN as u8).collect::<Vec<_>>()[..]);
+    }
+
+    #[test]
+    fn test_tag_len() {
+        let a2 = || Argon2::new(1, 1, 8, Variant::Argon2i).unwrap();
+        let enc = Encoded::with_tag_len(a2(), 64, b"pw", b"saltsalt", &[], &[]);
+        assert_eq!(enc.tag().len(), 64);
+        let mut exp = [0; 64];
+        a2().hash(&mut exp, b"pw", b"saltsalt", &[], &[]);
+        assert_eq!(enc.tag(), &exp[..]);
+
+        for v in [Encoded::parse(&enc.to_string()).unwrap(),
+                  Encoded::from_bytes(&enc.to_bytes()).unwrap()]
+                     .iter() {
+            assert!(v.verify(b"pw") && !v.verify(b"pW"));
+        }
+        // too short to verify, rather than panicking.
+        let short = "$argon2i$v=19,m=8,t=1,p=1$c2FsdHNhbHQ$AAAA";
+        assert!(!Encoded::parse(short).unwrap().verify(b"pw"));
+    }
+
+    #[test]
+    fn test_verify_opt() {
+        let v = Encoded::from_u8(ENCODED[0]).unwrap();
+        assert!(Encoded::verify_opt(Some(&v), b"argon2i!"));
+        assert!(!Encoded::verify_opt(Some(&v), b"nope"));
+        assert!(!Encoded::verify_opt(None, b"argon2i!"));
+
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2d).unwrap();
+        assert!(!Encoded::verify_dummy(&a2, b"argon2i!"));
+    }
+
+    #[test]
+    fn test_verify_unfinalized() {
+        let v = Encoded::from_u8(ENCODED[2]).unwrap();
+        let (var, kib, passes, lanes, _) = v.params();
+        let a2 = Argon2::new(passes, lanes, kib, var).unwrap();
+        let state = a2.hash_unfinalized(32, b"argon2i!", &v.salt, &[], &[]);
+        assert!(v.verify_unfinalized(&state));
+        let state = a2.hash_unfinalized(32, b"nope", &v.salt, &[], &[]);
+        assert!(!v.verify_unfinalized(&state));
+    }
+
+    #[test]
+    fn verify_with_output() {
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2id).unwrap();
+        let enc = Encoded::new(a2, b"passphrase", b"somesalt", &[], &[]);
+        let mut key = [0xaa; 32];
+        assert!(!enc.verify_with_output(b"passphrasf", &mut key));
+        assert_eq!(key, [0xaa; 32]);
+        assert!(enc.verify_with_output(b"passphrase", &mut key));
+        assert!(key[..] != enc.hash[..]);
+
+        let (mut tag, mut exp) = ([0; 32], [0; 32]);
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2id).unwrap();
+        kdf::derive_tag_and_key(&a2, b"passphrase", b"somesalt", &mut tag,
+                                &mut exp);
+        assert_eq!(key, exp);
+    }
+
+    #[test]
+    fn django_prefix() {
+        use super::{DecodeError, Prefix};
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2id).unwrap();
+        let enc = Encoded::new(a2, b"hunter2", b"somesalt", &[], &[]);
+        let django = Prefix::Django.apply(&enc);
+        assert!(django.starts_with("argon2$argon2id$v=19$m=8,t=1,p=1$"));
+        let (dec, prefix) = Encoded::from_u8_prefixed(django.as_bytes())
+                                .unwrap();
+        assert_eq!(prefix, Some(Prefix::Django));
+        assert!(dec == enc && dec.verify(b"hunter2"));
+
+        let phc = &django.as_bytes()[6..];
+        let (plain, prefix) = Encoded::from_u8_prefixed(phc).unwrap();
+        assert!(prefix.is_none() && plain == enc);
+        assert_eq!(Encoded::from_u8_prefixed(b"argon2$argon2x$").err(),
+                   Some(DecodeError::ParseError(13)));
+        assert_eq!(Encoded::from_u8_prefixed(b"argon2argon2i$").err(),
+                   Some(DecodeError::ParseError(0)));
+    }
+
+    #[test]
+    fn extra_params() {
+        use super::DecodeError::ParseError;
+        let newer = b"$argon2id$v=19,m=8,t=1,p=1,x=1,keyid=a2V5,y=+/.-Zz9\
+                      ,data=eA$c2FsdHNhbHQ$aGFzaA";
+        let enc = Encoded::from_u8(newer).unwrap();
+        assert_eq!(enc.keyid(), b"key");
+        assert_eq!(enc.data(), b"x");
+        let extra = [("x".to_string(), "1".to_string()),
+                     ("y".to_string(), "+/.-Zz9".to_string())];
+        assert_eq!(enc.extra_params(), &extra[..]);
+        assert_eq!(&enc.to_u8()[..],
+                   &b"$argon2id$v=19,m=8,t=1,p=1,keyid=a2V5,data=eA,x=1\
+                      ,y=+/.-Zz9$c2FsdHNhbHQ$aGFzaA"[..]);
+        let bin = enc.to_bytes();
+        assert_eq!(bin[0], 2);
+        assert!(Encoded::from_bytes(&bin).unwrap() == enc);
+        assert_eq!(Encoded::hash(Argon2::new(1, 1, 8, Variant::Argon2i)
+                                     .unwrap(),
+                                 b"pw", b"saltsalt")
+                       .to_bytes()[0],
+                   1);
+
+        let bad: &[(&[u8], usize)] =
+            &[(b"$argon2i$v=19,m=8,t=1,p=1,m=9$c2FsdHNhbHQ$aGFzaA", 26),
+              (b"$argon2i$v=19,m=8,t=1,p=1,data=eA,data=eA$c2FsdHNhbHQ$aGFzaA",
+               34),
+              (b"$argon2i$v=19,m=8,t=1,p=1,x=$c2FsdHNhbHQ$aGFzaA", 28),
+              (b"$argon2i$v=19,m=8,t=1,p=1,X=1$c2FsdHNhbHQ$aGFzaA", 26)];
+        for &(case, pos) in bad.iter() {
+            assert_eq!(Encoded::from_u8(case).err(), Some(ParseError(pos)));
+        }
+    }
+
+    #[test]
+    fn keyid_roundtrip() {
+        for &x in [&b""[..], &b"associated"[..]].iter() {
+            let a2 = Argon2::new(1, 1, 8, Variant::Argon2i).unwrap();
+            let enc = Encoded::new(a2, b"pw", b"saltsalt", b"keyid", x);
+            let v = Encoded::from_u8(&enc.to_u8()).unwrap();
+            assert_eq!(v.to_u8(), enc.to_u8());
+            assert!(v.verify(b"pw"));
+        }
+    }
+
+    struct Reversed;
+
+    impl LegacyHash for Reversed {
+        fn scheme(&self) -> &str { "rev" }
+        fn digest(&self, p: &[u8]) -> Vec<u8> {
+            p.iter().rev().cloned().collect()
+        }
+    }
+
+    #[test]
+    fn wrapped_legacy() {
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2i).unwrap();
+        let legacy = Reversed.digest(b"hunter2");
+        let enc = Encoded::wrap_legacy(a2, "rev", &legacy, b"saltsalt", b"k",
+                                       b"");
+        let s = String::from_utf8(enc.to_u8()).unwrap();
+        assert!(s.contains(",keyid=aw,wrap=rev$"));
+
+        let v = Encoded::from_u8(s.as_bytes()).unwrap();
+        assert_eq!(v.legacy_scheme(), Some("rev"));
+        assert!(v.verify_wrapped(b"hunter2", &Reversed));
+        assert!(!v.verify_wrapped(b"hunter3", &Reversed));
+        // never verifiable without the inner hash.
+        assert!(!v.verify(b"hunter2"));
+        assert!(!v.verify(&legacy));
+
+        let plain = Encoded::from_u8(ENCODED[0]).unwrap();
+        assert_eq!(plain.legacy_scheme(), None);
+        assert!(plain.verify_wrapped(b"argon2i!", &Reversed));
+    }
+
+    #[test]
+    fn binary_roundtrip() {
+        for &hash_string in ENCODED {
+            let v = Encoded::from_u8(hash_string).unwrap();
+            let bytes = v.to_bytes();
+            assert_eq!(bytes.len(),
+                       super::BINARY_FIXED_LEN + v.salt().len() +
+                       v.tag().len());
+            let w = Encoded::from_bytes(&bytes).unwrap();
+            assert_eq!(w.to_u8(), v.to_u8());
+            assert!(w.verify(b"argon2i!"));
+        }
+
+        let a2 = Argon2::new(1, 1, 8, Variant::Argon2d).unwrap();
+        let v = Encoded::wrap_legacy(a2, "rev", b"legacy", b"saltsalt", b"k",
+                                     b"data");
+        let w = Encoded::from_bytes(&v.to_bytes()).unwrap();
+        assert_eq!(w.to_u8(), v.to_u8());
+    }
+
+    #[test]
+    fn bad_binary() {
+        use super::DecodeError::*;
+        use argon2::ParamErr::*;
+        let good = Encoded::from_u8(ENCODED[2]).unwrap().to_bytes();
+        assert_eq!(&good[..15],
+                   &[1, 1, 0x13, 0, 0x10, 0, 0, 3, 0, 0, 0, 1, 0, 0, 0]);
+
+        let mut trailing = good.clone();
+        trailing.push(0);
+        let mut no_passes = good.clone();
+        no_passes[7] = 0;
+        let cases: &[(&[u8], super::DecodeError)] =
+            &[(&[], ParseError(0)),
+              (&[3], ParseError(0)),
+              (&[2], ParseError(1)),
+              (&[1, 3], ParseError(1)),
+              (&[1, 1, 0x11], ParseError(2)),
+              (&[1, 1, 0x13, 0, 0x10], ParseError(3)),
+              (&good[..40], ParseError(35)),
+              // ^ the hash's length prefix overruns the buffer.
+              (&trailing, ParseError(good.len())),
+              (&no_passes, InvalidParams(TooFewPasses)),
+              (&[1, 1, 0x13, 8, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 4, 0, 0, 0,
+                 b's', b'a', b'l', b't'],
+               ParseError(15))];
+              // ^ a salt shorter than 8 bytes.
+        for &(case, err) in cases.iter() {
+            assert_eq!(Encoded::from_bytes(case).err(), Some(err));
+        }
+    }
+
     #[test]
     fn bad_encoded() {
         use super::DecodeError::*;
@@ -446,13 +1509,21 @@ mod test {
             &[(b"$argon2y$v=19,m=4096", ParseError(7)),
               (b"$argon2i$v=19,m=-2,t=-4,p=-4$aaaaaaaa$ffffff", ParseError(16)),
               // ^ negative m is invalid.
-              (b"$argon2i$v=19,m=0,t=0,p=0$aaaaaaaa$ffffff*", ParseError(35)),
+              (b"$argon2i$v=19,m=0,t=0,p=0$aaaaaaaaaaa$ffffff*",
+               ParseError(38)),
               // ^ asterisk is invalid base64 char.
-              (b"$argon2i$v=19,m=0,t=0,p=0$aaaaaaaa$ffffff",
+              (b"$argon2i$v=19,m=0,t=0,p=0$aaaaaaaaaaa$ffffff",
                InvalidParams(TooFewPasses)),
               // ^ p = 0 is invalid.
-              (b"$argon2i$m", ParseError(9))];
+              (b"$argon2i$m", ParseError(9)),
               // ^ intentionally fail Encoded::expect with undersized input
+              (b"$argon2i$m=", ParseError(11)),
+              (b"$argon2id$v=19,m=64,t=1,p=1,keyid=", ParseError(34)),
+              (b"$argon2id$v=19,m=64,t=1,p=1,wrap=", ParseError(33)),
+              (b"$argon2i$v=19,m=8,t=1,p=1$c2FsdA$\
+                 AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA",
+               ParseError(26))];
+              // ^ a salt shorter than 8 bytes.
 
         for &(case, err) in cases.iter() {
             let v = Encoded::from_u8(case);
@@ -460,4 +1531,176 @@ mod test {
             assert_eq!(v.err().unwrap(), err);
         }
     }
+
+    #[test]
+    fn unhashable_fields() {
+        // a key id longer than a secret may be, which `verify` hashes with.
+        let long_key = format!("$argon2i$v=19,m=8,t=1,p=1,keyid={}\
+                                $c2FsdHNhbHQ${}",
+                               "A".repeat(44), "A".repeat(43));
+        let enc = Encoded::from_u8(long_key.as_bytes()).unwrap();
+        assert!(!enc.verify(b"password"));
+        assert!(!enc.verify_with_output(b"password", &mut [0; 32]));
+    }
+
+    mod prop {
+        use super::super::{Encoded, Packed, base64_no_pad, debase64_no_pad,
+                           is_canonical};
+        use base64::Engine;
+        use base64::alphabet::STANDARD;
+        use base64::engine::{DecodePaddingMode, GeneralPurpose,
+                             GeneralPurposeConfig};
+        use argon2::{Variant, Version};
+        use proptest::collection::vec;
+        use proptest::prelude::*;
+
+        // Encoded isn't Debug, so generate the fields of one instead.
+        fn packed() -> impl Strategy<Value = Packed> {
+            let v = prop_oneof![Just(Variant::Argon2i), Just(Variant::Argon2d),
+                                Just(Variant::Argon2id)];
+            let vers = prop_oneof![Just(Version::_0x10), Just(Version::_0x13)];
+            let wrap = proptest::option::of("[a-z0-9-]{1,16}");
+            // names starting with `x` can't be those of known parameters.
+            let extra = vec(("x[a-z0-9-]{0,8}", "[a-zA-Z0-9/+.-]{1,16}"), 0..3);
+            (1..0x00ffffffu32).prop_flat_map(move |lanes| {
+                (v.clone(), vers.clone(), 8 * lanes..=u32::MAX, 1..u32::MAX,
+                 Just(lanes), vec(any::<u8>(), 0..32),
+                 vec(any::<u8>(), 0..32), vec(any::<u8>(), 8..64),
+                 vec(any::<u8>(), 4..64), wrap.clone(), extra.clone())
+            })
+        }
+
+        // The reference codec for differential tests: standard alphabet, no
+        // padding, and nonzero trailing bits allowed, as in our lenient
+        // decoder.
+        fn reference() -> GeneralPurpose { reference_with(true) }
+
+        fn reference_with(trailing_bits: bool) -> GeneralPurpose {
+            let config = GeneralPurposeConfig::new()
+                             .with_encode_padding(false)
+                             .with_decode_padding_mode(DecodePaddingMode::
+                                                       RequireNone)
+                             .with_decode_allow_trailing_bits(trailing_bits);
+            GeneralPurpose::new(&STANDARD, config)
+        }
+
+        // Our decoder rejects empty input, which the reference accepts; all
+        // encoded fields are non-empty.
+        fn reference_decode(b64: &[u8]) -> Option<Vec<u8>> {
+            match reference().decode(b64) {
+                Ok(ref rv) if b64.is_empty() => {
+                    assert!(rv.is_empty());
+                    None
+                }
+                rv => rv.ok(),
+            }
+        }
+
+        #[test]
+        fn debase64_matches_reference_on_short_inputs() {
+            assert_eq!(debase64_no_pad(b""), reference_decode(b""));
+            for a in 0..256 {
+                assert_eq!(debase64_no_pad(&[a as u8]),
+                           reference_decode(&[a as u8]));
+                for b in 0..256 {
+                    let s = [a as u8, b as u8];
+                    assert_eq!(debase64_no_pad(&s), reference_decode(&s));
+                }
+            }
+        }
+
+        // Mostly valid base64 characters, with the occasional arbitrary byte.
+        fn b64ish() -> impl Strategy<Value = Vec<u8>> {
+            let alphabet = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
+                             abcdefghijklmnopqrstuvwxyz0123456789+/";
+            let c = prop_oneof![20 => proptest::sample::select(&alphabet[..]),
+                                1 => any::<u8>()];
+            vec(c, 0..128)
+        }
+
+        proptest! {
+            #[test]
+            fn base64_matches_reference(bytes in vec(any::<u8>(), 0..256)) {
+                let b64 = base64_no_pad(&bytes);
+                prop_assert_eq!(b64.clone(),
+                                reference().encode(&bytes).into_bytes());
+                if !bytes.is_empty() {
+                    prop_assert_eq!(debase64_no_pad(&b64), Some(bytes));
+                }
+            }
+
+            #[test]
+            fn debase64_matches_reference(b64 in b64ish()) {
+                let decoded = debase64_no_pad(&b64);
+                prop_assert_eq!(decoded.clone(), reference_decode(&b64));
+                if decoded.is_some() {
+                    let strict = reference_with(false).decode(&b64);
+                    prop_assert_eq!(is_canonical(&b64), strict.is_ok());
+                }
+            }
+
+            #[test]
+            fn round_trip(fields in packed()) {
+                let enc = Encoded::unpack(Ok(fields)).unwrap();
+                let s = enc.to_u8();
+                let dec = Encoded::from_u8(&s).unwrap();
+                prop_assert!(dec == enc);
+                prop_assert_eq!(dec.to_u8(), s.clone());
+                prop_assert!(Encoded::from_u8_strict(&s).unwrap() == enc);
+                prop_assert!(Encoded::from_bytes(&enc.to_bytes()).unwrap() ==
+                             enc);
+            }
+
+            #[test]
+            fn arbitrary_bytes_never_panic(s in vec(any::<u8>(), 0..256)) {
+                let _ = Encoded::from_u8(&s);
+                let _ = Encoded::from_u8_strict(&s);
+                let _ = Encoded::from_bytes(&s);
+            }
+
+            #[test]
+            fn truncations_fail(fields in packed()) {
+                let enc = Encoded::unpack(Ok(fields)).unwrap();
+                let (s, b) = (enc.to_u8(), enc.to_bytes());
+                // a hash cut short may still be valid base64, so stop short
+                // of the hash field there.
+                let hash_at = s.iter().rposition(|&c| c == b'$').unwrap();
+                for end in 0..hash_at + 1 {
+                    prop_assert!(Encoded::from_u8(&s[..end]).is_err());
+                    prop_assert!(Encoded::from_u8_strict(&s[..end]).is_err());
+                }
+                for end in 0..b.len() {
+                    prop_assert!(Encoded::from_bytes(&b[..end]).is_err());
+                }
+            }
+
+            #[test]
+            fn mutations_reencode_or_fail(fields in packed(),
+                                          edits in vec((any::<usize>(),
+                                                        any::<u8>(),
+                                                        0..3u8),
+                                                       1..8)) {
+                let enc = Encoded::unpack(Ok(fields)).unwrap();
+                let mut s = enc.to_u8();
+                for (i, b, op) in edits {
+                    let i = i % (s.len() + 1);
+                    match op {
+                        0 if i < s.len() => s[i] = b,
+                        1 if i < s.len() => { s.remove(i); }
+                        _ => s.insert(i, b),
+                    }
+                }
+                if let Ok(dec) = Encoded::from_u8(&s) {
+                    let re = dec.to_u8();
+                    let redec = Encoded::from_u8(&re).unwrap();
+                    prop_assert!(redec == dec);
+                    prop_assert_eq!(redec.to_u8(), re);
+                }
+                if let Ok(dec) = Encoded::from_u8_strict(&s) {
+                    prop_assert!(Encoded::from_u8(&dec.to_u8()).unwrap() ==
+                                 dec);
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,69 @@
+//! Hooks for testing that block matrices are wiped. Enabled by the
+//! `wipe_hooks` feature, which is meant for this crate's integration tests and
+//! for auditors, not for production builds.
+//!
+//! `on_release` installs a callback that sees every block matrix dropped on
+//! the current thread: its address and its bytes, just before they are handed
+//! back to the allocator. With `Flags::CLEAR_MEMORY`, which is the default,
+//! those bytes must all be zero, including when hashing panicked; `panic_next`
+//! makes the next hash on this thread panic once its matrix is filled, to
+//! check the latter.
+//!
+//! Hooks are per thread, so that tests running in parallel don't see each
+//! other's matrices. Matrices are dropped on the thread that hashed, or, for
+//! those kept by `Argon2::with_pool`, on the thread that drops the `Argon2`.
+
+use std::cell::{Cell, RefCell};
+
+/// A block matrix about to be freed.
+pub struct Release<'a> {
+    /// The address of the first block, to tell matrices apart.
+    pub addr: usize,
+    /// The whole matrix, `limits::BLOCK_BYTES` per block.
+    pub bytes: &'a [u8],
+    /// Whether the matrix was meant to be wiped.
+    pub wiped: bool,
+}
+
+impl<'a> Release<'a> {
+    /// Whether every byte is zero.
+    pub fn is_zero(&self) -> bool { self.bytes.iter().all(|&b| b == 0) }
+}
+
+type Hook = Box<dyn FnMut(&Release)>;
+
+thread_local! {
+    static HOOK: RefCell<Option<Hook>> = const { RefCell::new(None) };
+    static PANIC_NEXT: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Calls `hook` with every block matrix dropped on this thread from now on,
+/// replacing any previous hook.
+pub fn on_release<F>(hook: F)
+    where F: FnMut(&Release) + 'static
+{
+    HOOK.with(|h| *h.borrow_mut() = Some(Box::new(hook)));
+}
+
+/// Removes the hook of this thread, if any.
+pub fn clear() { HOOK.with(|h| *h.borrow_mut() = None); }
+
+/// Makes the next hash on this thread panic after filling its matrix.
+pub fn panic_next() { PANIC_NEXT.with(|p| p.set(true)); }
+
+pub(crate) fn release(addr: usize, bytes: &[u8], wiped: bool) {
+    // `try_borrow_mut` since a hook that drops a matrix would reenter.
+    HOOK.with(|h| {
+        if let Ok(mut h) = h.try_borrow_mut() {
+            if let Some(ref mut hook) = *h {
+                hook(&Release { addr, bytes, wiped });
+            }
+        }
+    });
+}
+
+pub(crate) fn maybe_panic() {
+    if PANIC_NEXT.with(|p| p.replace(false)) {
+        panic!("panic injected by wipe_hooks::panic_next");
+    }
+}
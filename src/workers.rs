@@ -1,46 +1,130 @@
-#[cfg(feature = "threaded")]
+//! Lane parallelism. `Workers::map` is the only synchronization point of a
+//! hash: it runs a closure once per lane, in parallel, and returns only after
+//! all of them have finished. Each invocation gets an aliased `&mut Matrix`;
+//! this is sound because a lane only writes its own segment of the current
+//! slice, and only reads segments finished before the last `map` returned.
+//!
+//! That argument can be checked mechanically. Building with `--cfg loom`
+//! swaps in an implementation on top of loom's threads, with every block
+//! access tracked by the `Matrix`, so that loom reports any read of a block
+//! that is concurrently written:
+//!
+//! ```sh
+//! RUSTFLAGS="--cfg loom" cargo test --release --lib loom
+//! ```
+//!
+//! The default implementation can likewise be run under ThreadSanitizer with
+//! `RUSTFLAGS="-Zsanitizer=thread"` on nightly.
+//!
+//! wasm32 has no way to spawn threads from Rust, so with the `wasm-threads`
+//! feature lanes are instead handed to Web Workers that share the module's
+//! memory, each of which has called `lane_worker`. See `bindings/wasm`.
+
+#[cfg(all(feature = "threaded", not(feature = "minimal"), not(loom),
+          not(all(target_arch = "wasm32", feature = "wasm-threads"))))]
 pub use self::threadpool::Workers;
 
-#[cfg(feature = "threaded")]
+#[cfg(all(feature = "threaded", not(feature = "minimal"), not(loom),
+          not(all(target_arch = "wasm32", feature = "wasm-threads"))))]
 mod threadpool {
-    extern crate scoped_threadpool;
     use block::Matrix;
+    use std::mem;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::mpsc::{Receiver, Sender, channel};
+    use std::thread::{self, JoinHandle};
+
+    type Fill = dyn Fn(&mut Matrix, u32) + Sync;
+
+    // A `map` in flight. Its borrows are extended to 'static and kept alive
+    // by waiting for every lane, even one that panicked, before `map`
+    // returns or unwinds.
+    struct Job(&'static Fill, *mut Matrix);
 
-    pub struct Workers(u32, Option<scoped_threadpool::Pool>);
+    unsafe impl Send for Job {}
+
+    // Long-lived threads for lanes 1 and up; lane 0 runs on the calling
+    // thread. Threads are spawned with `stack_size` bytes of stack if given,
+    // and the platform's default otherwise.
+    pub struct Workers {
+        jobs: Vec<Sender<Option<Job>>>,
+        done: Receiver<thread::Result<()>>,
+        threads: Vec<JoinHandle<()>>,
+    }
 
     impl Workers {
-        #[inline(always)]
-        pub fn new(lanes: u32) -> Workers {
-            match lanes {
-                1 => Workers(lanes, None),
-                n => Workers(lanes, Some(scoped_threadpool::Pool::new(n))),
+        pub fn new(lanes: u32, stack_size: Option<usize>) -> Workers {
+            let (done_tx, done) = channel();
+            let mut rv = Workers {
+                jobs: vec![],
+                done,
+                threads: vec![],
+            };
+            for lane in 1..lanes {
+                let (tx, rx) = channel::<Option<Job>>();
+                let done_tx = done_tx.clone();
+                let mut builder = thread::Builder::new()
+                                      .name(format!("argon2 lane {}", lane));
+                if let Some(size) = stack_size {
+                    builder = builder.stack_size(size);
+                }
+                let thread = builder.spawn(move || {
+                    while let Ok(Some(Job(f, m))) = rx.recv() {
+                        let rv = panic::catch_unwind(AssertUnwindSafe(|| {
+                            f(unsafe { &mut *m }, lane)
+                        }));
+                        let _ = done_tx.send(rv);
+                    }
+                });
+                rv.jobs.push(tx);
+                rv.threads.push(thread.expect("failed to spawn lane worker"));
             }
+            rv
         }
 
-        #[inline(always)]
         pub fn map<F>(&mut self, blocks: &mut Matrix, fill_slice: &F)
             where F: Fn(&mut Matrix, u32) + Sync
         {
-            match self {
-                &mut Workers(1, _) => fill_slice(blocks, 0),
-                &mut Workers(lanes, Some(ref mut pool)) => {
-                    pool.scoped(|sc| {
-                        for lane in 0..lanes {
-                            let m = unsafe { blocks.mut_ref() };
-                            sc.execute(move || fill_slice(m, lane));
-                        }
-                    })
-                }
-                _ => unreachable!(),
+            if self.jobs.is_empty() {
+                return fill_slice(blocks, 0);
+            }
+            let f: &(dyn Fn(&mut Matrix, u32) + Sync) = fill_slice;
+            let f: &'static Fill = unsafe { mem::transmute(f) };
+            for tx in &self.jobs {
+                tx.send(Some(Job(f, blocks))).unwrap();
+            }
+            let mut rv = panic::catch_unwind(AssertUnwindSafe(|| {
+                f(unsafe { blocks.mut_ref() }, 0)
+            }));
+            for _ in &self.jobs {
+                let lane = self.done.recv().unwrap();
+                rv = rv.and(lane);
+            }
+            if let Err(e) = rv {
+                panic::resume_unwind(e);
+            }
+        }
+    }
+
+    impl Drop for Workers {
+        fn drop(&mut self) {
+            for tx in &self.jobs {
+                let _ = tx.send(None);
+            }
+            for t in self.threads.drain(..) {
+                let _ = t.join();
             }
         }
     }
 }
 
-#[cfg(not(feature = "threaded"))]
+#[cfg(all(any(not(feature = "threaded"), feature = "minimal"), not(loom),
+          not(all(target_arch = "wasm32", feature = "wasm-threads",
+                  not(feature = "minimal")))))]
 pub use self::threaded::Workers;
 
-#[cfg(not(feature = "threaded"))]
+#[cfg(all(any(not(feature = "threaded"), feature = "minimal"), not(loom),
+          not(all(target_arch = "wasm32", feature = "wasm-threads",
+                  not(feature = "minimal")))))]
 mod threaded {
     use block::Matrix;
 
@@ -49,7 +133,7 @@ mod threaded {
 
     impl Workers {
         #[inline(always)]
-        pub fn new(lanes: u32) -> Workers { Workers(lanes) }
+        pub fn new(lanes: u32, _: Option<usize>) -> Workers { Workers(lanes) }
 
         #[inline(always)]
         pub fn map<F>(&mut self, blocks: &mut Matrix, fill_slice: &F)
@@ -61,3 +145,228 @@ mod threaded {
         }
     }
 }
+
+#[cfg(all(target_arch = "wasm32", feature = "wasm-threads",
+          not(feature = "minimal"), not(loom)))]
+pub use self::wasm::{Workers, lane_worker};
+
+#[cfg(all(target_arch = "wasm32", feature = "wasm-threads",
+          not(feature = "minimal"), not(loom)))]
+mod wasm {
+    use block::Matrix;
+    use std::arch::wasm32::{memory_atomic_notify, memory_atomic_wait32};
+    use std::cell::UnsafeCell;
+    use std::mem;
+    use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+    type Fill = dyn Fn(&mut Matrix, u32) + Sync;
+
+    // The `map` in flight, shared through linear memory. At most one `map`
+    // is posted at a time, under `LOCK`. Its lanes are claimed one by one by
+    // bumping the low half of `CLAIM`, whose high half counts `map`s so that a
+    // worker holding a stale value can't claim a lane of a later one. The low
+    // half is `DONE` between `map`s. `JOB` is only read by whoever claimed a
+    // lane of it, so it is never read while being rewritten.
+    struct Job(UnsafeCell<Option<(&'static Fill, *mut Matrix)>>);
+
+    unsafe impl Sync for Job {}
+
+    static JOB: Job = Job(UnsafeCell::new(None));
+    static LANES: AtomicU32 = AtomicU32::new(0);
+    static CLAIM: AtomicU64 = AtomicU64::new(DONE as u64);
+    // Lanes of the posted `map` that have yet to finish.
+    static PENDING: AtomicU32 = AtomicU32::new(0);
+    // Bumped for every `map`, for workers to wait on.
+    static POSTED: AtomicU32 = AtomicU32::new(0);
+    static LOCK: AtomicU32 = AtomicU32::new(0);
+
+    const DONE: u32 = u32::MAX;
+
+    fn wait(word: &AtomicU32, while_eq: u32) {
+        let ptr = word as *const AtomicU32 as *mut i32;
+        unsafe { memory_atomic_wait32(ptr, while_eq as i32, -1) };
+    }
+
+    fn notify(word: &AtomicU32) {
+        let ptr = word as *const AtomicU32 as *mut i32;
+        unsafe { memory_atomic_notify(ptr, u32::MAX) };
+    }
+
+    // Fills lanes of the posted `map`, if any, until none are left.
+    fn run_lanes() {
+        loop {
+            let claim = CLAIM.load(Ordering::Acquire);
+            let lane = claim as u32;
+            // `LANES` may be that of a later `map` by now, in which case the
+            // CAS fails.
+            if lane == DONE || lane >= LANES.load(Ordering::Relaxed) {
+                return;
+            }
+            if CLAIM.compare_exchange(claim, claim + 1, Ordering::AcqRel,
+                                      Ordering::Relaxed)
+                    .is_err() {
+                continue;
+            }
+            let (f, m) = unsafe { (*JOB.0.get()).unwrap() };
+            f(unsafe { &mut *m }, lane);
+            if PENDING.fetch_sub(1, Ordering::AcqRel) == 1 {
+                notify(&PENDING);
+            }
+        }
+    }
+
+    /// Runs lanes of multi-lane hashes forever. Call once on each Web Worker
+    /// that is to help; see `bindings/wasm`. The more workers, up to the
+    /// number of lanes less one, the more lanes are filled in parallel.
+    pub fn lane_worker() -> ! {
+        loop {
+            let posted = POSTED.load(Ordering::Acquire);
+            run_lanes();
+            wait(&POSTED, posted);
+        }
+    }
+
+    /// Holds the number of lanes.
+    pub struct Workers(u32);
+
+    impl Workers {
+        #[inline(always)]
+        pub fn new(lanes: u32, _: Option<usize>) -> Workers { Workers(lanes) }
+
+        // Posts the lanes for any idle workers, and fills lanes on the
+        // calling thread as well until all are claimed, so that this
+        // finishes even without workers. Must not be called on the browser's
+        // main thread, which may not wait.
+        pub fn map<F>(&mut self, blocks: &mut Matrix, fill_slice: &F)
+            where F: Fn(&mut Matrix, u32) + Sync
+        {
+            if self.0 == 1 {
+                return fill_slice(blocks, 0);
+            }
+            while LOCK.compare_exchange(0, 1, Ordering::Acquire,
+                                        Ordering::Relaxed)
+                      .is_err() {
+                wait(&LOCK, 1);
+            }
+
+            let f: &(dyn Fn(&mut Matrix, u32) + Sync) = fill_slice;
+            // not used past the wait for `PENDING` below.
+            let f: &'static Fill = unsafe { mem::transmute(f) };
+            unsafe { *JOB.0.get() = Some((f, blocks)) };
+            LANES.store(self.0, Ordering::Relaxed);
+            PENDING.store(self.0, Ordering::Relaxed);
+            let round = (CLAIM.load(Ordering::Relaxed) >> 32) + 1;
+            CLAIM.store(round << 32, Ordering::Release);
+            POSTED.fetch_add(1, Ordering::Release);
+            notify(&POSTED);
+
+            run_lanes();
+            loop {
+                let pending = PENDING.load(Ordering::Acquire);
+                if pending == 0 {
+                    break;
+                }
+                wait(&PENDING, pending);
+            }
+
+            CLAIM.store((round << 32) | DONE as u64, Ordering::Release);
+            unsafe { *JOB.0.get() = None };
+            LOCK.store(0, Ordering::Release);
+            notify(&LOCK);
+        }
+    }
+}
+
+#[cfg(loom)]
+pub use self::model::Workers;
+
+#[cfg(loom)]
+mod model {
+    use block::Matrix;
+    use loom::sync::mpsc::{Receiver, Sender, channel};
+    use loom::thread::{self, JoinHandle};
+    use std::mem;
+
+    type Fill = dyn Fn(&mut Matrix, u32) + Sync;
+
+    // A `map` in flight. loom has no scoped threads, so its borrows are
+    // extended to 'static and kept alive by waiting for every lane before
+    // `map` returns, just as a scoped pool would.
+    struct Job(&'static Fill, *mut Matrix);
+
+    unsafe impl Send for Job {}
+
+    // Long-lived threads for lanes 1 and up, like the threadpool's; lane 0
+    // runs on the calling thread. loom limits the number of threads per
+    // model, so spawning per `map` is out.
+    pub struct Workers {
+        jobs: Vec<Sender<Option<Job>>>,
+        done: Receiver<()>,
+        threads: Vec<JoinHandle<()>>,
+    }
+
+    impl Workers {
+        pub fn new(lanes: u32, _: Option<usize>) -> Workers {
+            let (done_tx, done) = channel();
+            let mut rv = Workers {
+                jobs: vec![],
+                done,
+                threads: vec![],
+            };
+            for lane in 1..lanes {
+                let (tx, rx) = channel::<Option<Job>>();
+                let done_tx = done_tx.clone();
+                rv.jobs.push(tx);
+                rv.threads.push(thread::spawn(move || {
+                    while let Some(Job(f, m)) = rx.recv().unwrap() {
+                        f(unsafe { &mut *m }, lane);
+                        done_tx.send(()).unwrap();
+                    }
+                }));
+            }
+            rv
+        }
+
+        pub fn map<F>(&mut self, blocks: &mut Matrix, fill_slice: &F)
+            where F: Fn(&mut Matrix, u32) + Sync
+        {
+            let f: &(dyn Fn(&mut Matrix, u32) + Sync) = fill_slice;
+            let f: &'static Fill = unsafe { mem::transmute(f) };
+            for tx in &self.jobs {
+                tx.send(Some(Job(f, blocks))).unwrap();
+            }
+            f(unsafe { blocks.mut_ref() }, 0);
+            for _ in &self.jobs {
+                self.done.recv().unwrap();
+            }
+        }
+    }
+
+    impl Drop for Workers {
+        fn drop(&mut self) {
+            for tx in &self.jobs {
+                tx.send(None).unwrap();
+            }
+            for t in self.threads.drain(..) {
+                t.join().unwrap();
+            }
+        }
+    }
+}
+
+#[cfg(all(test, loom))]
+mod test {
+    use argon2::{Argon2, Variant};
+    use loom;
+
+    #[test]
+    fn loom_lanes_are_race_free() {
+        loom::model(|| {
+            for &v in [Variant::Argon2i, Variant::Argon2d].iter() {
+                let a2 = Argon2::new(2, 2, 16, v).unwrap();
+                let mut out = [0; 8];
+                a2.hash(&mut out, b"password", b"saltsalt", &[], &[]);
+            }
+        });
+    }
+}
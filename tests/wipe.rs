@@ -0,0 +1,85 @@
+// checks, through the `wipe_hooks` feature, that block matrices are zero when
+// they are freed. run with
+//
+//     cargo test --features wipe_hooks --test wipe
+
+extern crate argon2rs;
+
+use std::cell::RefCell;
+use std::panic;
+use std::rc::Rc;
+use argon2rs::wipe_hooks::{self, Release};
+use argon2rs::{Argon2, Flags, FixedMatrix, Variant};
+
+// (wiped, is_zero, len) of each matrix released while `f` runs.
+fn releases<F: FnOnce()>(f: F) -> Vec<(bool, bool, usize)> {
+    // with `self-test`, the first hash in the process first hashes test
+    // vectors, whose matrices would show up here.
+    a2().hash(&mut [0; 32], b"password", b"somesalt", &[], &[]);
+    let seen = Rc::new(RefCell::new(vec![]));
+    let sink = seen.clone();
+    wipe_hooks::on_release(move |r: &Release| {
+        sink.borrow_mut().push((r.wiped, r.is_zero(), r.bytes.len()))
+    });
+    f();
+    wipe_hooks::clear();
+    let rv = seen.borrow().clone();
+    rv
+}
+
+fn a2() -> Argon2 { Argon2::new(1, 2, 16, Variant::Argon2id).unwrap() }
+
+// the bytes of `a2()`'s matrix, which in debug builds has a canary block after
+// each of its two lanes.
+const LEN: usize = (16 + 2 * cfg!(debug_assertions) as usize) * 1024;
+
+#[test]
+fn wiped_after_hash() {
+    let seen = releases(|| a2().hash(&mut [0; 32], b"password", b"somesalt",
+                                     &[], &[]));
+    assert_eq!(seen, vec![(true, true, LEN)]);
+}
+
+#[test]
+fn not_wiped_without_flag() {
+    // the hook would be toothless if unwiped memory looked the same.
+    let seen = releases(|| {
+        a2().with_flags(Flags::empty())
+            .hash(&mut [0; 32], b"password", b"somesalt", &[], &[])
+    });
+    assert_eq!(seen, vec![(false, false, LEN)]);
+}
+
+#[test]
+fn wiped_after_panic() {
+    let seen = releases(|| {
+        wipe_hooks::panic_next();
+        let r = panic::catch_unwind(|| {
+            a2().hash(&mut [0; 32], b"password", b"somesalt", &[], &[])
+        });
+        assert!(r.is_err());
+    });
+    assert_eq!(seen, vec![(true, true, LEN)]);
+}
+
+#[test]
+fn pooled_wiped_on_drop() {
+    let seen = releases(|| {
+        let a2 = a2().with_pool(1);
+        for _ in 0..3 {
+            a2.hash(&mut [0; 32], b"password", b"somesalt", &[], &[]);
+        }
+        drop(a2);
+    });
+    assert_eq!(seen, vec![(true, true, LEN)]);
+}
+
+#[test]
+fn fixed_wiped() {
+    let mut blocks = FixedMatrix::<16>::new();
+    let seen = releases(|| {
+        a2().hash_fixed(&mut [0; 32], b"password", b"somesalt", &[], &[],
+                        &mut blocks)
+    });
+    assert_eq!(seen, vec![(true, true, 16 * 1024)]);
+}
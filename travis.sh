@@ -4,6 +4,23 @@ cargo test
 # test single-threaded
 cargo test --no-default-features
 
+# check that block matrices are wiped when freed.
+cargo test --features wipe_hooks --test wipe
+
+# the criterion suite builds on any channel.
+cargo bench --bench scaling --no-run
+
+# timing leaks in the constant-time paths. Shared CI runners are too noisy
+# for this to gate the build, so retry a few times and only report.
+for try in 1 2 3
+do
+    cargo bench --bench dudect && break
+    if [ $try = 3 ]
+    then
+        echo "=== dudect: possible timing leak, not failing the build ==="
+    fi
+done
+
 # benches use `extern crate test` which requires nightly.
 if [ "$TRAVIS_RUST_VERSION" = "nightly" ]
 then